@@ -0,0 +1,489 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use serde::Serialize;
+
+// Every construct in the language is an expression, so the whole tree is made of `Node`s.
+// Operators such as `a + b` are represented as method calls (`a.+(b)`), like Ruby does.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Node {
+    Integer(i64),
+    Float(f64),
+    // Integer literals past 64 bits, as their digits and sign
+    Bignum(String),
+    Str(String),
+    // String literals with escapes that aren't valid UTF-8, such as `"\xff"`
+    Bytes(Vec<u8>),
+    // `"text #{code}"`, as the literal parts and the code, whose values are converted with
+    // `to_s` unless they are strings
+    InterpolatedString(Vec<Node>),
+    // `/source/options`
+    Regexp(String, String),
+    Symbol(String),
+    Nil,
+    True,
+    False,
+    SelfNode,
+    LocalVariable(String),
+    Constant(String),
+    // `Scope::Name`, or `::Name` at the top level when the scope is `None`
+    ScopedConstant(Option<Box<Node>>, String),
+    // `Name = value`, with the name a `Constant` or `ScopedConstant`
+    ConstantAssign(Box<Node>, Box<Node>),
+    LocalAssign(String, Box<Node>),
+    // `@name`, without the sigil
+    InstanceVariable(String),
+    InstanceVariableAssign(String, Box<Node>),
+    // `a, (b, *c) = value`, with several values on the right gathered into an `Array`.
+    // Assigning to `obj.attr` or `obj[key]` alone is a call to `attr=` or `[]=`.
+    MultipleAssign(Vec<Target>, Box<Node>),
+    OpAssign(OpAssign),
+    Call(Call),
+    Array(Vec<Node>),
+    Hash(Vec<HashElement>),
+    // `key: value` pairs at the end of an argument list, which pass keyword arguments where
+    // a braced `Hash` would be one more positional argument
+    KeywordHash(Vec<HashElement>),
+    // `start..end` or `start...end`, either end can be left out
+    Range(Option<Box<Node>>, Option<Box<Node>>, RangeKind),
+    // `*list` in array literals and argument lists
+    Splat(Box<Node>),
+    // `&block` or `&:name`, always the last argument. It becomes the call's block,
+    // with symbols converted through `Symbol#to_proc`.
+    BlockPass(Box<Node>),
+    // `->(x) { }` creates a lambda, which checks its arity and where `return` only
+    // leaves the lambda. `lambda { }`, `proc { }` and `Proc.new { }` are plain calls.
+    Lambda(Rc<Block>),
+    Def(Def),
+    Class(Class),
+    Module(Module),
+    // `class << target ... end`
+    SingletonClass(Box<Node>, Vec<Node>),
+    If(If),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Return(Option<Box<Node>>),
+    While(While),
+    For(For),
+    Break(Option<Box<Node>>),
+    Next(Option<Box<Node>>),
+    Redo,
+    Begin(Begin),
+    Retry,
+    Case(Case),
+    CaseIn(CaseIn),
+    // `yield a, b` calls the block given to the current method
+    Yield(Vec<Node>),
+    Super(Super),
+    // A statement that failed to parse, reported in the parser's diagnostics
+    Error,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum HashElement {
+    Pair(Node, Node),
+    // `**other`
+    DoubleSplat(Node),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub enum RangeKind {
+    // `..` includes the end
+    Inclusive,
+    // `...` excludes it
+    Exclusive,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Call {
+    pub receiver: Option<Box<Node>>,
+    pub method: String,
+    pub args: Vec<Node>,
+    // Shared with the closures created from it when the program runs
+    pub block: Option<Rc<Block>>,
+    #[serde(skip)]
+    pub line: Line,
+}
+
+// The line of the source a node is on, which backtraces show. Trees are compared by
+// their shape, so lines are always equal, and they aren't serialized.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Line(pub usize);
+
+impl PartialEq for Line {
+    fn eq(&self, _: &Line) -> bool {
+        true
+    }
+}
+
+// `super(args)` calls the next method of the same name in the ancestors. A bare `super`
+// has no `args` and passes along the arguments the current method was called with.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Super {
+    pub args: Option<Vec<Node>>,
+    pub block: Option<Rc<Block>>,
+    #[serde(skip)]
+    pub line: Line,
+}
+
+// Something that can be assigned to
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Target {
+    Local(String),
+    InstanceVariable(String),
+    // `receiver[args]`
+    Index(Box<Node>, Vec<Node>),
+    // `receiver.name`
+    Attribute(Box<Node>, String),
+    // `*rest`, or a bare `*` that discards the remaining values
+    Splat(Option<Box<Target>>),
+    // `(a, b)` destructures a single value
+    Nested(Vec<Target>),
+}
+
+// `target op= value`, where `op` is the operator without the `=`. The receiver and
+// index of the target are only evaluated once, and `||=`/`&&=` only assign when
+// the current value is falsy/truthy, so these can't be rewritten into plain calls.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct OpAssign {
+    pub target: Target,
+    pub operator: String,
+    pub value: Box<Node>,
+    #[serde(skip)]
+    pub line: Line,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Def {
+    // Receiver of a singleton method, as in `def self.create`
+    pub singleton: Option<Box<Node>>,
+    pub name: String,
+    pub params: Params,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Class {
+    // `Constant` or `ScopedConstant`
+    pub path: Box<Node>,
+    pub superclass: Option<Box<Node>>,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Module {
+    pub path: Box<Node>,
+    pub body: Vec<Node>,
+}
+
+// `unless`, the `if`/`unless` modifiers and the ternary operator are all parsed
+// into an `If`, with the branches swapped for `unless`. `elsif` is an `If` nested
+// as the only node of `else_body`.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct If {
+    pub condition: Box<Node>,
+    pub then_body: Vec<Node>,
+    pub else_body: Vec<Node>,
+}
+
+// `until cond` is parsed as `while !cond`. `do_while` is set for `begin ... end while cond`,
+// whose body runs once before the condition is checked.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct While {
+    pub condition: Box<Node>,
+    pub body: Vec<Node>,
+    pub do_while: bool,
+}
+
+// `for a, b in collection ... end`, the variables stay visible after the loop
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct For {
+    pub variables: Vec<String>,
+    pub iterable: Box<Node>,
+    pub body: Vec<Node>,
+}
+
+// `begin ... rescue ... else ... ensure ... end`. Method, class and `do` block bodies
+// with rescue clauses, and the `expr rescue fallback` modifier, are parsed into one too.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Begin {
+    pub body: Vec<Node>,
+    pub rescues: Vec<Rescue>,
+    pub else_body: Option<Vec<Node>>,
+    pub ensure_body: Option<Vec<Node>>,
+}
+
+// `rescue ArgumentError, TypeError => e`, no classes means `StandardError`
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Rescue {
+    pub classes: Vec<Node>,
+    pub variable: Option<String>,
+    pub body: Vec<Node>,
+}
+
+// `case subject when a, b then ... else ... end`, each condition is tested with `condition === subject`.
+// Without a subject the conditions are tested for truthiness instead.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Case {
+    pub subject: Option<Box<Node>>,
+    pub whens: Vec<When>,
+    pub else_body: Option<Vec<Node>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct When {
+    pub conditions: Vec<Node>,
+    pub body: Vec<Node>,
+}
+
+// `case subject in pattern if guard then ... else ... end`. Without an `else`,
+// a subject that matches no pattern raises `NoMatchingPatternError`.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct CaseIn {
+    pub subject: Box<Node>,
+    pub clauses: Vec<InClause>,
+    pub else_body: Option<Vec<Node>>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct InClause {
+    pub pattern: Pattern,
+    // `unless` guards are parsed as a negated `if` guard
+    pub guard: Option<Box<Node>>,
+    pub body: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Pattern {
+    // Literals, constants and ranges, matched with `===`
+    Value(Node),
+    // `^name` or `^(expression)`, matched with `===` against an existing value
+    Pin(Node),
+    // A bare name binds the matched value, `_` matches anything without binding it
+    Bind(String),
+    // `pattern | pattern`
+    Alternative(Vec<Pattern>),
+    // `pattern => name`
+    Capture(Box<Pattern>, String),
+    // `Const[a, *rest, b]` or `[a, *, b]`, `rest` is `Some(None)` for an anonymous splat
+    Array {
+        constant: Option<Node>,
+        pre: Vec<Pattern>,
+        rest: Option<Option<String>>,
+        post: Vec<Pattern>,
+    },
+    // `[*, pattern, *post]` finds the patterns anywhere in the array
+    Find {
+        constant: Option<Node>,
+        pre: Option<String>,
+        middle: Vec<Pattern>,
+        post: Option<String>,
+    },
+    // `Const(key:, other: pattern, **rest)`, a key without a pattern binds a local of the same name
+    Hash {
+        constant: Option<Node>,
+        pairs: Vec<(String, Option<Pattern>)>,
+        rest: Option<HashPatternRest>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum HashPatternRest {
+    // `**rest` collects the remaining keys
+    Named(String),
+    // `**nil` only matches hashes without other keys
+    Nil,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Block {
+    pub params: BlockParams,
+    pub body: Vec<Node>,
+}
+
+// A block either declares its parameters between pipes or uses the implicit
+// ones (`_1`..`_9` or `it`). A block without any parameters is `Explicit` with an empty list.
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum BlockParams {
+    Explicit(Params),
+    Numbered(u8),
+    It,
+}
+
+#[derive(Debug, PartialEq, Clone, Default, Serialize)]
+pub struct Params {
+    pub params: Vec<Param>,
+    // Block-local variables declared after `;`, as in `|x; tmp|`
+    pub locals: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum Param {
+    Required(String),
+    Destructure(Vec<Param>),
+    Optional(String, Node),
+    Rest(Option<String>),
+    RequiredKeyword(String),
+    OptionalKeyword(String, Node),
+    KeywordRest(Option<String>),
+    Block(Option<String>),
+}
+
+impl Call {
+    pub fn new(receiver: Option<Node>, method: &str, args: Vec<Node>, line: usize) -> Self {
+        Call {
+            receiver: receiver.map(Box::new),
+            method: method.to_string(),
+            args,
+            block: None,
+            line: Line(line),
+        }
+    }
+}
+
+impl Block {
+    // `_1`..`_9` and `it` are ordinary required parameters once the block runs
+    pub fn params(&self) -> Cow<'_, Params> {
+        let required = |names: Vec<String>| {
+            Cow::Owned(Params { params: names.into_iter().map(Param::Required).collect(), locals: Vec::new() })
+        };
+        match &self.params {
+            BlockParams::Explicit(params) => Cow::Borrowed(params),
+            BlockParams::Numbered(count) => required((1..=*count).map(|index| format!("_{}", index)).collect()),
+            BlockParams::It => required(vec!["it".to_string()]),
+        }
+    }
+}
+
+impl Param {
+    // The local a `*`, `**` or `&` parameter is bound to. Anonymous ones get a name no
+    // identifier can spell, so that a bare `super` can still pass them along.
+    pub fn rest_local(&self) -> Option<&str> {
+        match self {
+            Param::Rest(name) => Some(name.as_deref().unwrap_or("*")),
+            Param::KeywordRest(name) => Some(name.as_deref().unwrap_or("**")),
+            Param::Block(name) => Some(name.as_deref().unwrap_or("&")),
+            _ => None,
+        }
+    }
+}
+
+impl Params {
+    // Arguments that pass each parameter's current value along, as a bare `super` does
+    pub fn forwarded(&self) -> Vec<Node> {
+        let local = |name: &str| Node::LocalVariable(name.to_string());
+        let mut args = Vec::new();
+        let mut keywords = Vec::new();
+        let mut block = None;
+        for param in &self.params {
+            match param {
+                Param::Required(name) | Param::Optional(name, _) => args.push(local(name)),
+                Param::Destructure(params) => {
+                    let params = Params { params: params.clone(), locals: Vec::new() };
+                    args.push(Node::Array(params.forwarded()));
+                }
+                Param::RequiredKeyword(name) | Param::OptionalKeyword(name, _) => {
+                    keywords.push(HashElement::Pair(Node::Symbol(name.clone()), local(name)));
+                }
+                Param::Rest(_) | Param::KeywordRest(_) | Param::Block(_) => {
+                    let value = local(param.rest_local().expect("a rest or block parameter"));
+                    match param {
+                        Param::Rest(_) => args.push(Node::Splat(Box::new(value))),
+                        Param::KeywordRest(_) => keywords.push(HashElement::DoubleSplat(value)),
+                        _ => block = Some(Node::BlockPass(Box::new(value))),
+                    }
+                }
+            }
+        }
+        if !keywords.is_empty() {
+            args.push(Node::KeywordHash(keywords));
+        }
+        args.extend(block);
+        args
+    }
+}
+
+impl Target {
+    // Every local variable the target binds, in order
+    pub fn locals(&self) -> Vec<&str> {
+        match self {
+            Target::Local(name) => vec![name],
+            Target::Splat(Some(target)) => target.locals(),
+            Target::Nested(targets) => targets.iter().flat_map(Target::locals).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Begin {
+    pub fn has_clauses(&self) -> bool {
+        !self.rescues.is_empty() || self.else_body.is_some() || self.ensure_body.is_some()
+    }
+}
+
+impl Node {
+    // `A::B::C` as written in the source, for constant lookups and error messages
+    pub fn constant_path(&self) -> Option<String> {
+        match self {
+            Node::Constant(name) => Some(name.clone()),
+            Node::ScopedConstant(None, name) => Some(format!("::{}", name)),
+            Node::ScopedConstant(Some(scope), name) => Some(format!("{}::{}", scope.constant_path()?, name)),
+            _ => None,
+        }
+    }
+}
+
+impl Params {
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty() && self.locals.is_empty()
+    }
+
+    pub fn required_count(&self) -> usize {
+        self.params
+            .iter()
+            .filter(|param| matches!(param, Param::Required(_) | Param::Destructure(_)))
+            .count()
+    }
+
+    // Same convention as Ruby's `Method#arity`: the number of required arguments,
+    // or `-(required + 1)` when optional arguments are also accepted.
+    // Required keywords count as a single extra positional argument (the hash).
+    pub fn arity(&self) -> i32 {
+        let has = |predicate: fn(&Param) -> bool| self.params.iter().any(predicate);
+
+        let required_keywords = has(|param| matches!(param, Param::RequiredKeyword(_)));
+        let required = self.required_count() as i32 + required_keywords as i32;
+        let optional = has(|param| matches!(param, Param::Optional(..) | Param::Rest(_)))
+            || (!required_keywords && has(|param| matches!(param, Param::OptionalKeyword(..) | Param::KeywordRest(_))));
+
+        if optional {
+            -required - 1
+        } else {
+            required
+        }
+    }
+
+    // Every local variable the parameters bind, in declaration order
+    pub fn names(&self) -> Vec<&str> {
+        fn collect<'a>(params: &'a [Param], names: &mut Vec<&'a str>) {
+            for param in params {
+                match param {
+                    Param::Required(name)
+                    | Param::Optional(name, _)
+                    | Param::RequiredKeyword(name)
+                    | Param::OptionalKeyword(name, _) => names.push(name),
+                    Param::Rest(name) | Param::KeywordRest(name) | Param::Block(name) => {
+                        names.extend(name.as_deref())
+                    }
+                    Param::Destructure(inner) => collect(inner, names),
+                }
+            }
+        }
+
+        let mut names = Vec::new();
+        collect(&self.params, &mut names);
+        names.extend(self.locals.iter().map(String::as_str));
+        names
+    }
+}
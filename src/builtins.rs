@@ -0,0 +1,4904 @@
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::ops::{Add, Div, Mul, Sub};
+
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_traits::{FromPrimitive, Pow, Signed, ToPrimitive, Zero};
+use regex::bytes::{Captures, Match, Regex};
+
+use crate::runtime::{
+    Allocator, BlockFn, Encoding, ExceptionData, Executor, MethodBody, Missing, NativeFn, ObjectId, ObjectKind, Proc, ProcBody, RHash,
+    RRegexp, RString, Runtime, Unpacked, Unwind, Value, Visibility,
+};
+use crate::lexer::OPERATOR_METHODS;
+
+type NativeResult = Result<Value, Unwind>;
+
+// Defines the built-in classes and their methods, run once by `Runtime::new`
+pub fn define(runtime: &mut Runtime) {
+    define_exceptions(runtime);
+    define_kernel(runtime);
+    define_module(runtime);
+    define_singletons(runtime);
+    define_integer(runtime);
+    define_float(runtime);
+    define_string(runtime);
+    define_regexp(runtime);
+    define_encoding(runtime);
+    define_symbol(runtime);
+    define_enumerable(runtime);
+    define_array(runtime);
+    define_hash(runtime);
+    define_range(runtime);
+    define_proc(runtime);
+    define_gc(runtime);
+}
+
+fn define_exceptions(runtime: &mut Runtime) {
+    let exception = runtime.classes.exception;
+    let subclass = |runtime: &mut Runtime, name: &str, superclass: &str| {
+        let superclass = runtime.constant(runtime.classes.object, superclass).and_then(Value::object_id);
+        runtime.define_class(name, superclass.unwrap_or(exception), None, Allocator::Exception);
+    };
+
+    // Superclasses come before their subclasses
+    for (name, superclass) in [
+        ("ScriptError", "Exception"),
+        ("SystemStackError", "Exception"),
+        ("NotImplementedError", "ScriptError"),
+        ("StandardError", "Exception"),
+        ("ArgumentError", "StandardError"),
+        ("EncodingError", "StandardError"),
+        ("FiberError", "StandardError"),
+        ("IndexError", "StandardError"),
+        ("KeyError", "IndexError"),
+        ("StopIteration", "IndexError"),
+        ("LocalJumpError", "StandardError"),
+        ("NameError", "StandardError"),
+        ("NoMethodError", "NameError"),
+        ("RangeError", "StandardError"),
+        ("RegexpError", "StandardError"),
+        ("FloatDomainError", "RangeError"),
+        ("RuntimeError", "StandardError"),
+        ("FrozenError", "RuntimeError"),
+        ("TypeError", "StandardError"),
+        ("ZeroDivisionError", "StandardError"),
+        ("NoMatchingPatternError", "StandardError"),
+        ("NoMatchingPatternKeyError", "NoMatchingPatternError"),
+    ] {
+        subclass(runtime, name, superclass);
+    }
+
+    runtime.define_native(exception, "initialize", -1, exception_initialize);
+    runtime.define_native(exception, "message", 0, exception_message);
+    runtime.define_native(exception, "to_s", 0, exception_to_s);
+    runtime.define_native(exception, "inspect", 0, exception_inspect);
+    runtime.define_native(exception, "backtrace", 0, exception_backtrace);
+    runtime.define_native(exception, "exception", -1, exception_instance_exception);
+    runtime.define_singleton_native(exception, "exception", -1, exception_exception);
+}
+
+fn define_kernel(runtime: &mut Runtime) {
+    let basic_object = runtime.classes.basic_object;
+    runtime.define_native(basic_object, "initialize", 0, object_initialize);
+    runtime.define_native(basic_object, "==", 1, object_identical);
+    runtime.define_native(basic_object, "equal?", 1, object_identical);
+    runtime.define_native(basic_object, "!=", 1, object_not_equal);
+    runtime.define_native(basic_object, "!", 0, object_not);
+    runtime.define_native(basic_object, "__send__", -2, object_send);
+    runtime.define_native(basic_object, "instance_eval", 0, object_instance_eval);
+    runtime.define_native(basic_object, "instance_exec", -1, object_instance_exec);
+    runtime.define_native(basic_object, "method_missing", -2, object_method_missing);
+    runtime.set_visibility(basic_object, "method_missing", Visibility::Private).expect("just defined");
+
+    let kernel = runtime.classes.kernel;
+    runtime.define_native(kernel, "puts", -1, kernel_puts);
+    runtime.define_native(kernel, "print", -1, kernel_print);
+    runtime.define_native(kernel, "p", -1, kernel_p);
+    runtime.define_native(kernel, "raise", -1, kernel_raise);
+    runtime.define_native(kernel, "format", -2, kernel_format);
+    runtime.define_native(kernel, "Integer", 1, kernel_integer);
+    runtime.define_native(kernel, "Float", 1, kernel_float);
+    runtime.define_native(kernel, "sprintf", -2, kernel_format);
+    runtime.define_native(kernel, "lambda", 0, kernel_lambda);
+    runtime.define_native(kernel, "proc", 0, kernel_proc);
+    runtime.define_native(kernel, "block_given?", 0, kernel_block_given);
+    runtime.define_native(kernel, "loop", 0, kernel_loop);
+    runtime.define_native(kernel, "inspect", 0, object_inspect);
+    runtime.define_native(kernel, "to_s", 0, object_to_s);
+    runtime.define_native(kernel, "class", 0, object_class);
+    runtime.define_native(kernel, "singleton_class", 0, object_singleton_class);
+    runtime.define_native(kernel, "extend", -2, object_extend);
+    runtime.define_native(kernel, "eql?", 1, object_identical);
+    runtime.define_native(kernel, "===", 1, object_case_equal);
+    runtime.define_native(kernel, "=~", 1, object_match);
+    runtime.define_native(kernel, "!~", 1, object_not_match);
+    runtime.define_native(kernel, "nil?", 0, object_nil);
+    runtime.define_native(kernel, "is_a?", 1, object_is_a);
+    runtime.define_native(kernel, "kind_of?", 1, object_is_a);
+    runtime.define_native(kernel, "instance_of?", 1, object_instance_of);
+    runtime.define_native(kernel, "respond_to?", -2, object_respond_to);
+    runtime.define_native(kernel, "respond_to_missing?", 2, object_respond_to_missing);
+    runtime.define_native(kernel, "send", -2, object_send);
+    runtime.define_native(kernel, "public_send", -2, object_public_send);
+    runtime.define_native(kernel, "instance_variable_get", 1, object_instance_variable_get);
+    runtime.define_native(kernel, "instance_variable_set", 2, object_instance_variable_set);
+    runtime.define_native(kernel, "instance_variable_defined?", 1, object_instance_variable_defined);
+    runtime.define_native(kernel, "instance_variables", 0, object_instance_variables);
+    runtime.define_native(kernel, "freeze", 0, object_freeze);
+    runtime.define_native(kernel, "frozen?", 0, object_frozen);
+    runtime.define_native(kernel, "dup", 0, object_dup);
+    runtime.define_native(kernel, "object_id", 0, object_object_id);
+    runtime.define_native(kernel, "itself", 0, object_itself);
+    runtime.define_native(kernel, "tap", 0, object_tap);
+    runtime.define_native(kernel, "then", 0, object_then);
+}
+
+fn define_module(runtime: &mut Runtime) {
+    let module = runtime.classes.module;
+    runtime.define_native(module, "name", 0, module_name);
+    runtime.define_native(module, "to_s", 0, module_to_s);
+    runtime.define_native(module, "inspect", 0, module_to_s);
+    runtime.define_native(module, "===", 1, module_case_equal);
+    runtime.define_native(module, "method_defined?", 1, module_method_defined);
+    runtime.define_native(module, "include", -2, module_include);
+    runtime.define_native(module, "prepend", -2, module_prepend);
+    runtime.define_native(module, "include?", 1, module_include_p);
+    runtime.define_native(module, "ancestors", 0, module_ancestors);
+    runtime.define_native(module, "included_modules", 0, module_included_modules);
+    runtime.define_native(module, "<", 1, module_less_than);
+    runtime.define_native(module, "<=", 1, module_less_than_or_equal);
+    runtime.define_native(module, "define_method", -2, module_define_method);
+    runtime.define_native(module, "attr_reader", -1, module_attr_reader);
+    runtime.define_native(module, "attr_writer", -1, module_attr_writer);
+    runtime.define_native(module, "attr_accessor", -1, module_attr_accessor);
+    runtime.define_native(module, "class_eval", 0, module_class_eval);
+    runtime.define_native(module, "module_eval", 0, module_class_eval);
+    runtime.define_native(module, "class_exec", -1, module_class_exec);
+    runtime.define_native(module, "module_exec", -1, module_class_exec);
+    runtime.define_native(module, "public", -1, module_public);
+    runtime.define_native(module, "private", -1, module_private);
+    // Hooks called after a module is mixed in, with the class or object it went into
+    for hook in ["included", "extended", "prepended"] {
+        runtime.define_native(module, hook, 1, module_hook);
+    }
+
+    let class = runtime.classes.class;
+    runtime.define_native(class, "new", -1, class_new);
+    runtime.define_native(class, "allocate", 0, class_allocate);
+    runtime.define_native(class, "superclass", 0, class_superclass);
+}
+
+fn define_singletons(runtime: &mut Runtime) {
+    let nil = runtime.classes.nil;
+    runtime.define_native(nil, "to_s", 0, nil_to_s);
+    runtime.define_native(nil, "to_a", 0, nil_to_a);
+    runtime.define_native(nil, "inspect", 0, nil_inspect);
+    runtime.define_native(nil, "nil?", 0, nil_nil);
+
+    for class in [runtime.classes.true_class, runtime.classes.false_class] {
+        runtime.define_native(class, "to_s", 0, boolean_to_s);
+        runtime.define_native(class, "inspect", 0, boolean_to_s);
+    }
+
+    // The top level changes the visibility of methods on Object
+    let main = runtime.singleton_class(runtime.main).expect("main is a plain object");
+    runtime.define_native(main, "public", -1, main_public);
+    runtime.define_native(main, "private", -1, main_private);
+}
+
+fn main_public(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let object = Value::object(ex.runtime().classes.object);
+    change_visibility(ex, object, args, Visibility::Public)
+}
+
+fn main_private(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let object = Value::object(ex.runtime().classes.object);
+    change_visibility(ex, object, args, Visibility::Private)
+}
+
+// Exception
+
+fn exception_initialize(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let message = args.first().copied().unwrap_or(Value::NIL);
+    if let Some(ObjectKind::Exception(data)) = receiver.object_id().map(|id| &mut ex.runtime().object_mut(id).kind) {
+        data.message = message;
+    }
+    Ok(Value::NIL)
+}
+
+fn exception_message(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    ex.send(receiver, "to_s", &[], None)
+}
+
+fn exception_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let message = ex.runtime().exception_message(receiver);
+    Ok(ex.runtime().string(&message))
+}
+
+fn exception_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let class = runtime.class_name(runtime.real_class_of(receiver));
+    let message = runtime.exception_message(receiver);
+    let inspect = if message.is_empty() || message == class { class } else { format!("#<{}: {}>", class, message) };
+    Ok(runtime.string(&inspect))
+}
+
+fn exception_backtrace(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let backtrace = match runtime.kind(receiver) {
+        Some(ObjectKind::Exception(ExceptionData { backtrace: Some(backtrace), .. })) => backtrace.clone(),
+        _ => return Ok(Value::NIL),
+    };
+    let lines = backtrace.iter().map(|line| runtime.string(line)).collect();
+    Ok(runtime.array(lines))
+}
+
+fn exception_exception(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    ex.send(receiver, "new", args, None)
+}
+
+// What `raise exception, message` raises: the exception itself, or a copy of it with the
+// new message that hasn't been raised yet
+fn exception_instance_exception(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let message = match args {
+        [] => return Ok(receiver),
+        [message] if *message == receiver => return Ok(receiver),
+        [message] => *message,
+        _ => return Err(argument_count_error(ex, args.len(), "0..1")),
+    };
+    let runtime = ex.runtime();
+    let id = receiver.object_id().expect("exceptions are objects");
+    let (class, ivars) = (runtime.object(id).class, runtime.object(id).ivars.clone());
+    let copy = runtime.alloc(class, ObjectKind::Exception(ExceptionData { message, backtrace: None }));
+    runtime.object_mut(copy.object_id().expect("just allocated")).ivars = ivars;
+    Ok(copy)
+}
+
+// Kernel
+
+fn object_initialize(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::NIL)
+}
+
+fn kernel_puts(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    fn lines(ex: &mut dyn Executor, value: Value, output: &mut String) -> Result<(), Unwind> {
+        if let Some(elements) = ex.runtime().array_value(value).cloned() {
+            for element in elements {
+                lines(ex, element, output)?;
+            }
+            return Ok(());
+        }
+        let line = to_s(ex, value)?;
+        output.push_str(&line);
+        if !line.ends_with('\n') {
+            output.push('\n');
+        }
+        Ok(())
+    }
+
+    let mut output = String::new();
+    for &arg in args {
+        lines(ex, arg, &mut output)?;
+    }
+    if args.is_empty() {
+        output.push('\n');
+    }
+    ex.runtime().write_output(&output);
+    Ok(Value::NIL)
+}
+
+fn kernel_print(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let mut output = String::new();
+    for &arg in args {
+        output.push_str(&to_s(ex, arg)?);
+    }
+    ex.runtime().write_output(&output);
+    Ok(Value::NIL)
+}
+
+fn kernel_p(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let mut output = String::new();
+    for &arg in args {
+        output.push_str(&inspect(ex, arg)?);
+        output.push('\n');
+    }
+    ex.runtime().write_output(&output);
+
+    Ok(match args {
+        [] => Value::NIL,
+        [arg] => *arg,
+        args => ex.runtime().array(args.to_vec()),
+    })
+}
+
+fn kernel_format(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let format = expect_string(ex, args[0])?;
+    let text = sprintf(ex, &format, &args[1..])?;
+    Ok(ex.runtime().string(&text))
+}
+
+// Unlike `to_i`, strings must hold nothing but the integer, and floats are truncated
+fn kernel_integer(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let value = args[0];
+    if value == Value::NIL {
+        return Err(ex.runtime().error("TypeError", "can't convert nil into Integer"));
+    }
+    if let Unpacked::Float(value) = ex.runtime().unpack(value) {
+        return float_to_integer(ex, value.trunc());
+    }
+    if let Some(text) = ex.runtime().string_value(value).map(str::to_string) {
+        return match parse_integer(&text) {
+            Some(integer) => Ok(ex.runtime().integer(integer)),
+            None => Err(ex.runtime().error("ArgumentError", &format!("invalid value for Integer(): {}", quote(&text)))),
+        };
+    }
+    expect_bignum(ex, value)?;
+    Ok(value)
+}
+
+// Like `Integer()`, strings must hold nothing but the float
+fn kernel_float(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let value = args[0];
+    if value == Value::NIL {
+        return Err(ex.runtime().error("TypeError", "can't convert nil into Float"));
+    }
+    if let Some(text) = ex.runtime().string_value(value).map(str::to_string) {
+        let trimmed = text.trim();
+        let length = float_length(trimmed);
+        return match trimmed[..length].replace('_', "").parse() {
+            Ok(float) if length == trimmed.len() => Ok(ex.runtime().float(float)),
+            _ => Err(ex.runtime().error("ArgumentError", &format!("invalid value for Float(): {}", quote(&text)))),
+        };
+    }
+    match float_operand(ex.runtime(), value) {
+        Some(float) => Ok(ex.runtime().float(float)),
+        None => Err(conversion_error(ex, value, "Float")),
+    }
+}
+
+// `raise`, `raise "message"`, `raise Class`, `raise Class, "message"` or `raise exception`
+fn kernel_raise(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let exception = match args {
+        [] if ex.runtime().errinfo != Value::NIL => ex.runtime().errinfo,
+        [] => return Err(ex.runtime().error("RuntimeError", "unhandled exception")),
+        [message] if ex.runtime().string_value(*message).is_some() => {
+            let runtime = ex.runtime();
+            let runtime_error = runtime.constant(runtime.classes.object, "RuntimeError").unwrap_or(Value::NIL);
+            ex.send(runtime_error, "new", &[*message], None)?
+        }
+        [exception, rest @ ..] if rest.len() <= 1 => ex.send(*exception, "exception", rest, None)?,
+        _ => return Err(argument_count_error(ex, args.len(), "0..2")),
+    };
+
+    let runtime = ex.runtime();
+    if !runtime.is_a(exception, runtime.classes.exception) {
+        return Err(runtime.error("TypeError", "exception class/object expected"));
+    }
+    // Raising it again keeps where it was first raised
+    let backtrace = runtime.backtrace();
+    if let ObjectKind::Exception(data) = &mut runtime.object_mut(exception.object_id().expect("exceptions are objects")).kind {
+        data.backtrace.get_or_insert(backtrace);
+    }
+    Err(Unwind::Raise(exception))
+}
+
+fn kernel_lambda(ex: &mut dyn Executor, _: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "tried to create Proc object without a block")?;
+    let runtime = ex.runtime();
+    let body = match runtime.proc_value(block) {
+        Some(Proc { body, .. }) => body.clone(),
+        None => return Ok(block),
+    };
+    Ok(runtime.proc(body, true))
+}
+
+fn kernel_proc(ex: &mut dyn Executor, _: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    require_block(ex, block, "tried to create Proc object without a block")
+}
+
+fn kernel_block_given(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(ex.current_block().is_some()))
+}
+
+// Calls the block until it breaks, or raises `StopIteration`
+fn kernel_loop(ex: &mut dyn Executor, _: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    loop {
+        match ex.call_proc(block, &[]) {
+            Ok(_) => {}
+            Err(Unwind::Raise(exception)) if is_a_named(ex.runtime(), exception, "StopIteration") => return Ok(Value::NIL),
+            Err(unwind) => return Err(unwind),
+        }
+    }
+}
+
+fn object_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let Some(id) = receiver.object_id() else {
+        let description = ex.runtime().describe(receiver);
+        return Ok(ex.runtime().string(&description));
+    };
+    if receiver == ex.runtime().main {
+        return Ok(ex.runtime().string("main"));
+    }
+
+    let runtime = ex.runtime();
+    let class = runtime.class_name(runtime.real_class_of(receiver));
+    let ivars = ex.runtime().object(id).ivars.clone();
+    let mut parts = Vec::new();
+    for (name, value) in ivars {
+        parts.push(format!("@{}={}", name, inspect(ex, value)?));
+    }
+    let inspect = if parts.is_empty() { format!("#<{}>", class) } else { format!("#<{} {}>", class, parts.join(", ")) };
+    Ok(ex.runtime().string(&inspect))
+}
+
+fn object_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let description = ex.runtime().describe(receiver);
+    Ok(ex.runtime().string(&description))
+}
+
+fn object_class(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::object(ex.runtime().real_class_of(receiver)))
+}
+
+fn object_identical(_: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(receiver == args[0]))
+}
+
+fn object_not_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(!equal(ex, receiver, args[0])?))
+}
+
+// Nothing matches anything but strings, symbols and regexps
+fn object_match(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::NIL)
+}
+
+fn object_not_match(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(!ex.send(receiver, "=~", args, None)?.truthy()))
+}
+
+fn object_not(_: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(!receiver.truthy()))
+}
+
+fn object_case_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(equal(ex, receiver, args[0])?))
+}
+
+fn object_nil(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::FALSE)
+}
+
+fn object_is_a(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let class = expect_module(ex, args[0])?;
+    Ok(Value::from_bool(ex.runtime().is_a(receiver, class)))
+}
+
+fn object_instance_of(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let class = expect_module(ex, args[0])?;
+    Ok(Value::from_bool(ex.runtime().real_class_of(receiver) == class))
+}
+
+// Private methods only count with `include_all`. Methods `method_missing` handles count
+// when `respond_to_missing?` says so.
+fn object_respond_to(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let name = expect_name(ex, args[0])?;
+    let include_all = args.get(1).is_some_and(|value| value.truthy());
+    let runtime = ex.runtime();
+    match runtime.find_method(runtime.class_of(receiver), &name) {
+        Some(method) => Ok(Value::from_bool(include_all || method.visibility == Visibility::Public)),
+        None => {
+            let name = ex.runtime().symbol(&name);
+            let responds = ex.send(receiver, "respond_to_missing?", &[name, Value::from_bool(include_all)], None)?;
+            Ok(Value::from_bool(responds.truthy()))
+        }
+    }
+}
+
+fn object_respond_to_missing(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::FALSE)
+}
+
+// What calls nothing else handles end up in, raising the error for the missing method
+fn object_method_missing(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let name = expect_name(ex, args[0])?;
+    let missing = std::mem::replace(&mut ex.runtime().missing_reason, Missing::Undefined);
+    Err(ex.runtime().undefined_method(receiver, &name, missing))
+}
+
+// `send` reaches private methods too, `public_send` doesn't
+fn object_send(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let name = expect_name(ex, args[0])?;
+    ex.send(receiver, &name, &args[1..], block)
+}
+
+fn object_public_send(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let name = expect_name(ex, args[0])?;
+    let runtime = ex.runtime();
+    match runtime.find_method(runtime.class_of(receiver), &name) {
+        Some(method) if method.visibility == Visibility::Private => {
+            method_missing(ex, receiver, &name, &args[1..], block, Missing::Private)
+        }
+        _ => ex.send(receiver, &name, &args[1..], block),
+    }
+}
+
+// `self` is the receiver in the block, and `def` defines singleton methods
+fn object_instance_eval(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    object_instance_exec(ex, receiver, &[receiver], block)
+}
+
+fn object_instance_exec(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let Some(block) = block else {
+        return Err(ex.runtime().error("ArgumentError", "no block given"));
+    };
+    let definee = match ex.runtime().unpack(receiver) {
+        Unpacked::Object(_) => ex.runtime().singleton_class(receiver)?,
+        _ => ex.runtime().class_of(receiver),
+    };
+    ex.call_proc_as(block, receiver, definee, args)
+}
+
+// `@name`, with the `@`
+fn ivar_name(ex: &mut dyn Executor, value: Value) -> Result<String, Unwind> {
+    let name = expect_name(ex, value)?;
+    match name.strip_prefix('@') {
+        Some(bare) if bare.starts_with(|c: char| c.is_alphabetic() || c == '_') => Ok(bare.to_string()),
+        _ => Err(ex.runtime().error("NameError", &format!("'{}' is not allowed as an instance variable name", name))),
+    }
+}
+
+fn object_instance_variable_get(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let name = ivar_name(ex, args[0])?;
+    Ok(ex.runtime().ivar(receiver, &name))
+}
+
+fn object_instance_variable_set(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let name = ivar_name(ex, args[0])?;
+    set_ivar(ex, receiver, &name, args[1])?;
+    Ok(args[1])
+}
+
+fn object_instance_variable_defined(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let name = ivar_name(ex, args[0])?;
+    let runtime = ex.runtime();
+    let defined = receiver.object_id().is_some_and(|id| runtime.object(id).ivars.iter().any(|(ivar, _)| *ivar == name));
+    Ok(Value::from_bool(defined))
+}
+
+fn object_instance_variables(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let names: Vec<String> = match receiver.object_id() {
+        Some(id) => runtime.object(id).ivars.iter().map(|(name, _)| format!("@{}", name)).collect(),
+        None => Vec::new(),
+    };
+    let symbols = names.iter().map(|name| runtime.symbol(name)).collect();
+    Ok(runtime.array(symbols))
+}
+
+fn object_freeze(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    if let Some(id) = receiver.object_id() {
+        ex.runtime().object_mut(id).frozen = true;
+    }
+    Ok(receiver)
+}
+
+fn object_frozen(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(match receiver.object_id() {
+        Some(id) => ex.runtime().object(id).frozen,
+        None => true,
+    }))
+}
+
+// A shallow copy, never frozen
+fn object_dup(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let Some(id) = receiver.object_id() else {
+        return Ok(receiver);
+    };
+    let runtime = ex.runtime();
+    let kind = match &runtime.object(id).kind {
+        ObjectKind::Plain => ObjectKind::Plain,
+        ObjectKind::String(string) => ObjectKind::String(string.clone()),
+        ObjectKind::Regexp(regexp) => ObjectKind::Regexp(regexp.clone()),
+        ObjectKind::Array(elements) => ObjectKind::Array(elements.clone()),
+        ObjectKind::Hash(hash) => ObjectKind::Hash(hash.clone()),
+        ObjectKind::Range(start, end, exclusive) => ObjectKind::Range(*start, *end, *exclusive),
+        // Numbers are values, even when they're in the heap
+        ObjectKind::Bignum(_) | ObjectKind::Float(_) => return Ok(receiver),
+        _ => return Err(runtime.error("TypeError", &format!("can't dup {}", runtime.describe(receiver)))),
+    };
+    let ivars = runtime.object(id).ivars.clone();
+    let copy = runtime.alloc(runtime.real_class_of(receiver), kind);
+    runtime.object_mut(copy.object_id().expect("just allocated")).ivars = ivars;
+    Ok(copy)
+}
+
+fn object_object_id(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(ex.runtime().int(object_id(receiver)))
+}
+
+// The value's bits, so the slots of collected objects are reused and so are their ids
+pub fn object_id(value: Value) -> i64 {
+    value.bits() as i64
+}
+
+fn object_singleton_class(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::object(ex.runtime().singleton_class(receiver)?))
+}
+
+// `extend` includes the modules into the object's singleton class
+fn object_extend(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let singleton = ex.runtime().singleton_class(receiver)?;
+    for &module in args.iter().rev() {
+        let module_id = expect_module(ex, module)?;
+        ex.runtime().include_module(singleton, module_id)?;
+        ex.send(module, "extended", &[receiver], None)?;
+    }
+    Ok(receiver)
+}
+
+fn object_itself(_: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(receiver)
+}
+
+fn object_tap(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    yield_block(ex, block, &[receiver])?;
+    Ok(receiver)
+}
+
+fn object_then(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    yield_block(ex, block, &[receiver])
+}
+
+// Module and Class
+
+fn module_name(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let name = receiver.object_id().and_then(|id| runtime.class_value(id)).and_then(|class| class.name.clone());
+    Ok(match name {
+        Some(name) => runtime.string(&name),
+        None => Value::NIL,
+    })
+}
+
+fn module_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let name = ex.runtime().describe(receiver);
+    Ok(ex.runtime().string(&name))
+}
+
+fn module_case_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    Ok(Value::from_bool(ex.runtime().is_a(args[0], module)))
+}
+
+fn module_method_defined(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let name = expect_name(ex, args[0])?;
+    Ok(Value::from_bool(ex.runtime().find_method(module, &name).is_some()))
+}
+
+// `include A, B` includes `B` first, so `A` ends up before it in the ancestors
+fn module_include(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let target = expect_module(ex, receiver)?;
+    for &module in args.iter().rev() {
+        let module_id = expect_module(ex, module)?;
+        ex.runtime().include_module(target, module_id)?;
+        ex.send(module, "included", &[receiver], None)?;
+    }
+    Ok(receiver)
+}
+
+fn module_prepend(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let target = expect_module(ex, receiver)?;
+    for &module in args.iter().rev() {
+        let module_id = expect_module(ex, module)?;
+        ex.runtime().prepend_module(target, module_id)?;
+        ex.send(module, "prepended", &[receiver], None)?;
+    }
+    Ok(receiver)
+}
+
+fn module_include_p(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let target = expect_module(ex, receiver)?;
+    let module = expect_module(ex, args[0])?;
+    let runtime = ex.runtime();
+    let is_module = runtime.class_value(module).is_some_and(|class| class.is_module);
+    Ok(Value::from_bool(is_module && module != target && runtime.ancestors(target).contains(&module)))
+}
+
+fn module_ancestors(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let runtime = ex.runtime();
+    let ancestors = runtime.ancestors(module).into_iter().map(Value::object).collect();
+    Ok(runtime.array(ancestors))
+}
+
+fn module_included_modules(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let runtime = ex.runtime();
+    let modules = runtime
+        .ancestors(module)
+        .into_iter()
+        .filter(|&ancestor| runtime.class_value(ancestor).is_some_and(|class| class.is_module))
+        .map(Value::object)
+        .collect();
+    Ok(runtime.array(modules))
+}
+
+// `A < B` when `B` is among `A`'s ancestors, `nil` when they aren't related at all
+fn module_less_than(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if receiver == args[0] {
+        return Ok(Value::FALSE);
+    }
+    module_less_than_or_equal(ex, receiver, args, None)
+}
+
+fn module_less_than_or_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let Some(other) = args[0].object_id().filter(|&id| ex.runtime().class_value(id).is_some()) else {
+        return Err(ex.runtime().error("TypeError", "compared with non class/module"));
+    };
+    let runtime = ex.runtime();
+    Ok(if runtime.ancestors(module).contains(&other) {
+        Value::TRUE
+    } else if runtime.ancestors(other).contains(&module) {
+        Value::FALSE
+    } else {
+        Value::NIL
+    })
+}
+
+// `define_method(:name) { |args| ... }` or `define_method(:name, proc)`
+fn module_define_method(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let module = expect_module(ex, receiver)?;
+    let name = expect_name(ex, args[0])?;
+    let body = match (args.get(1), block) {
+        (Some(&body), _) if ex.runtime().proc_value(body).is_some() => body,
+        (Some(&body), _) => {
+            let runtime = ex.runtime();
+            let class = runtime.class_name(runtime.real_class_of(body));
+            return Err(ex.runtime().error("TypeError", &format!("wrong argument type {} (expected Proc/Method)", class)));
+        }
+        (None, Some(block)) => block,
+        (None, None) => return Err(ex.runtime().error("ArgumentError", "tried to create Proc object without a block")),
+    };
+    ex.runtime().add_method(module, &name, MethodBody::Proc(body));
+    Ok(ex.runtime().symbol(&name))
+}
+
+fn module_attr_reader(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    define_attributes(ex, receiver, args, true, false)
+}
+
+fn module_attr_writer(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    define_attributes(ex, receiver, args, false, true)
+}
+
+fn module_attr_accessor(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    define_attributes(ex, receiver, args, true, true)
+}
+
+// Returns the names of the methods it defined
+fn define_attributes(ex: &mut dyn Executor, receiver: Value, args: &[Value], reader: bool, writer: bool) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let mut defined = Vec::new();
+    for &arg in args {
+        let name = expect_name(ex, arg)?;
+        if !name.starts_with(|c: char| c.is_alphabetic() || c == '_') || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            return Err(ex.runtime().error("NameError", &format!("invalid attribute name '{}'", name)));
+        }
+        let runtime = ex.runtime();
+        if reader {
+            runtime.add_method(module, &name, MethodBody::AttrReader(name.clone()));
+            defined.push(runtime.symbol(&name));
+        }
+        if writer {
+            let setter = format!("{}=", name);
+            runtime.add_method(module, &setter, MethodBody::AttrWriter(name.clone()));
+            defined.push(runtime.symbol(&setter));
+        }
+    }
+    Ok(ex.runtime().array(defined))
+}
+
+// `self` is the module in the block, and `def` defines instance methods
+fn module_class_eval(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    module_class_exec(ex, receiver, &[receiver], block)
+}
+
+fn module_class_exec(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    let Some(block) = block else {
+        return Err(ex.runtime().error("ArgumentError", "no block given"));
+    };
+    ex.call_proc_as(block, receiver, module, args)
+}
+
+fn module_public(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    change_visibility(ex, receiver, args, Visibility::Public)
+}
+
+fn module_private(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    change_visibility(ex, receiver, args, Visibility::Private)
+}
+
+// Without names it applies to the methods defined next. Names can be given in an array,
+// as `private attr_accessor :a, :b` does.
+fn change_visibility(ex: &mut dyn Executor, receiver: Value, args: &[Value], visibility: Visibility) -> NativeResult {
+    let module = expect_module(ex, receiver)?;
+    if args.is_empty() {
+        ex.set_default_visibility(visibility);
+        return Ok(Value::NIL);
+    }
+    let names = match args {
+        [single] => splat(ex, *single)?,
+        _ => args.to_vec(),
+    };
+    for name in names {
+        let name = expect_name(ex, name)?;
+        ex.runtime().set_visibility(module, &name, visibility)?;
+    }
+    Ok(match args {
+        [single] => *single,
+        _ => ex.runtime().array(args.to_vec()),
+    })
+}
+
+fn module_hook(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::NIL)
+}
+
+fn class_new(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let instance = class_allocate(ex, receiver, &[], None)?;
+    ex.send(instance, "initialize", args, block)?;
+    Ok(instance)
+}
+
+fn class_allocate(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let class = expect_module(ex, receiver)?;
+    let runtime = ex.runtime();
+    let kind = match runtime.class_value(class).map(|class| class.allocator) {
+        Some(Allocator::Object) => ObjectKind::Plain,
+        Some(Allocator::String) => ObjectKind::String(RString::default()),
+        Some(Allocator::Array) => ObjectKind::Array(Vec::new()),
+        Some(Allocator::Hash) => ObjectKind::Hash(RHash::default()),
+        Some(Allocator::Exception) => ObjectKind::Exception(ExceptionData { message: Value::NIL, backtrace: None }),
+        _ => {
+            let message = format!("allocator undefined for {}", runtime.class_name(class));
+            return Err(runtime.error("TypeError", &message));
+        }
+    };
+    Ok(runtime.alloc(class, kind))
+}
+
+fn class_superclass(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let class = expect_module(ex, receiver)?;
+    let runtime = ex.runtime();
+    Ok(match runtime.class_value(class).and_then(|class| class.superclass) {
+        Some(superclass) => Value::object(superclass),
+        None => Value::NIL,
+    })
+}
+
+// nil, true and false
+
+fn nil_to_s(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(ex.runtime().string(""))
+}
+
+fn nil_to_a(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(ex.runtime().array(Vec::new()))
+}
+
+fn nil_inspect(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(ex.runtime().string("nil"))
+}
+
+fn nil_nil(_: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::TRUE)
+}
+
+fn boolean_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let text = if receiver.truthy() { "true" } else { "false" };
+    Ok(ex.runtime().string(text))
+}
+
+// Integer
+
+fn define_integer(runtime: &mut Runtime) {
+    let integer = runtime.classes.integer;
+    runtime.define_native(integer, "+", 1, integer_add);
+    runtime.define_native(integer, "-", 1, integer_sub);
+    runtime.define_native(integer, "*", 1, integer_mul);
+    runtime.define_native(integer, "/", 1, integer_div);
+    runtime.define_native(integer, "div", 1, integer_div);
+    runtime.define_native(integer, "%", 1, integer_mod);
+    runtime.define_native(integer, "modulo", 1, integer_mod);
+    runtime.define_native(integer, "divmod", 1, integer_divmod);
+    runtime.define_native(integer, "**", 1, integer_pow);
+    runtime.define_native(integer, "pow", -2, integer_pow);
+    runtime.define_native(integer, "<<", 1, integer_left_shift);
+    runtime.define_native(integer, ">>", 1, integer_right_shift);
+    runtime.define_native(integer, "&", 1, integer_and);
+    runtime.define_native(integer, "|", 1, integer_or);
+    runtime.define_native(integer, "^", 1, integer_xor);
+    runtime.define_native(integer, "-@", 0, integer_negate);
+    runtime.define_native(integer, "==", 1, numeric_equal);
+    runtime.define_native(integer, "===", 1, numeric_equal);
+    runtime.define_native(integer, "eql?", 1, integer_eql);
+    runtime.define_native(integer, "<", 1, numeric_less);
+    runtime.define_native(integer, "<=", 1, numeric_less_or_equal);
+    runtime.define_native(integer, ">", 1, numeric_greater);
+    runtime.define_native(integer, ">=", 1, numeric_greater_or_equal);
+    runtime.define_native(integer, "<=>", 1, numeric_compare);
+    runtime.define_native(integer, "abs", 0, integer_abs);
+    runtime.define_native(integer, "succ", 0, integer_succ);
+    runtime.define_native(integer, "next", 0, integer_succ);
+    runtime.define_native(integer, "pred", 0, integer_pred);
+    runtime.define_native(integer, "zero?", 0, integer_zero);
+    runtime.define_native(integer, "even?", 0, integer_even);
+    runtime.define_native(integer, "odd?", 0, integer_odd);
+    runtime.define_native(integer, "times", 0, integer_times);
+    runtime.define_native(integer, "upto", 1, integer_upto);
+    runtime.define_native(integer, "downto", 1, integer_downto);
+    runtime.define_native(integer, "to_i", 0, object_itself);
+    runtime.define_native(integer, "to_f", 0, integer_to_f);
+    runtime.define_native(integer, "chr", -1, integer_chr);
+    runtime.define_native(integer, "to_s", -1, integer_to_s);
+    runtime.define_native(integer, "inspect", 0, integer_to_s);
+}
+
+// Integers unpack to `i64`s while they fit in 64 bits and are bignums past that. Operations
+// try 64 bits first and redo the work on bignums when it overflows, and `Runtime::integer`
+// brings results that fit back down.
+fn bignum_of(runtime: &Runtime, value: Value) -> Option<BigInt> {
+    match runtime.unpack(value) {
+        Unpacked::Integer(value) => Some(BigInt::from(value)),
+        _ => runtime.bignum_value(value).cloned(),
+    }
+}
+
+fn receiver_bignum(ex: &mut dyn Executor, receiver: Value) -> BigInt {
+    bignum_of(ex.runtime(), receiver).expect("Integer methods are only called on integers")
+}
+
+fn expect_bignum(ex: &mut dyn Executor, value: Value) -> Result<BigInt, Unwind> {
+    match bignum_of(ex.runtime(), value) {
+        Some(value) => Ok(value),
+        None => Err(conversion_error(ex, value, "Integer")),
+    }
+}
+
+// `small` on 64-bit integers, or `big` when it overflows or either side is a bignum. With a
+// float on the right, the integer is converted and `float` does the operation.
+fn integer_arithmetic(
+    ex: &mut dyn Executor,
+    receiver: Value,
+    args: &[Value],
+    small: fn(i64, i64) -> Option<i64>,
+    big: fn(BigInt, BigInt) -> BigInt,
+    float: fn(f64, f64) -> f64,
+) -> NativeResult {
+    let runtime = ex.runtime();
+    if let (Unpacked::Integer(left), Unpacked::Integer(right)) = (runtime.unpack(receiver), runtime.unpack(args[0])) {
+        if let Some(result) = small(left, right) {
+            return Ok(runtime.int(result));
+        }
+    }
+    if let Unpacked::Float(right) = runtime.unpack(args[0]) {
+        let left = float_operand(runtime, receiver).expect("the receiver is an integer");
+        return Ok(runtime.float(float(left, right)));
+    }
+    let left = receiver_bignum(ex, receiver);
+    let right = expect_bignum(ex, args[0])?;
+    Ok(ex.runtime().integer(big(left, right)))
+}
+
+fn integer_add(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_arithmetic(ex, receiver, args, i64::checked_add, |left, right| left + right, f64::add)
+}
+
+fn integer_sub(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_arithmetic(ex, receiver, args, i64::checked_sub, |left, right| left - right, f64::sub)
+}
+
+fn integer_mul(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_arithmetic(ex, receiver, args, i64::checked_mul, |left, right| left * right, f64::mul)
+}
+
+// Division rounds towards negative infinity and the modulo takes the sign of the divisor
+fn floored_div_mod(left: i64, right: i64) -> Option<(i64, i64)> {
+    let (mut quotient, mut remainder) = (left.checked_div(right)?, left.checked_rem(right)?);
+    if remainder != 0 && (remainder < 0) != (right < 0) {
+        quotient -= 1;
+        remainder += right;
+    }
+    Some((quotient, remainder))
+}
+
+fn check_divisor(ex: &mut dyn Executor, divisor: Value) -> Result<(), Unwind> {
+    if divisor.as_fixnum() == Some(0) {
+        return Err(ex.runtime().error("ZeroDivisionError", "divided by 0"));
+    }
+    Ok(())
+}
+
+fn integer_div(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    check_divisor(ex, args[0])?;
+    let small = |left, right| floored_div_mod(left, right).map(|(quotient, _)| quotient);
+    integer_arithmetic(ex, receiver, args, small, |left, right| left.div_floor(&right), f64::div)
+}
+
+fn integer_mod(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    check_divisor(ex, args[0])?;
+    let small = |left, right| floored_div_mod(left, right).map(|(_, remainder)| remainder);
+    integer_arithmetic(ex, receiver, args, small, |left, right| left.mod_floor(&right), float_modulo)
+}
+
+// `[quotient, modulo]`, with a floored float quotient as an integer
+fn integer_divmod(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    check_divisor(ex, args[0])?;
+    if let Unpacked::Float(right) = ex.runtime().unpack(args[0]) {
+        let left = float_operand(ex.runtime(), receiver).expect("the receiver is an integer");
+        let quotient = float_to_integer(ex, (left / right).floor())?;
+        let modulo = ex.runtime().float(float_modulo(left, right));
+        return Ok(ex.runtime().array(vec![quotient, modulo]));
+    }
+    let (quotient, remainder) = receiver_bignum(ex, receiver).div_mod_floor(&expect_bignum(ex, args[0])?);
+    let runtime = ex.runtime();
+    let pair = vec![runtime.integer(quotient), runtime.integer(remainder)];
+    Ok(runtime.array(pair))
+}
+
+// A negative exponent gives a float, where Ruby would give a rational. With a modulus,
+// `pow(exponent, modulus)` reduces as it goes, and the result has the modulus's sign.
+fn integer_pow(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    if let Some(&modulus) = args.get(1) {
+        let exponent = expect_bignum(ex, args[0])?;
+        let modulus = expect_bignum(ex, modulus)?;
+        if exponent.is_negative() {
+            let message = "Integer#pow() 1st argument cannot be negative when 2nd argument specified";
+            return Err(ex.runtime().error("RangeError", message));
+        }
+        if modulus.is_zero() {
+            return Err(ex.runtime().error("ZeroDivisionError", "divided by 0"));
+        }
+        let result = receiver_bignum(ex, receiver).modpow(&exponent, &modulus);
+        return Ok(ex.runtime().integer(result));
+    }
+
+    let runtime = ex.runtime();
+    let base = float_operand(runtime, receiver).expect("the receiver is an integer");
+    match runtime.unpack(args[0]) {
+        Unpacked::Float(exponent) => return Ok(runtime.float(base.powf(exponent))),
+        Unpacked::Integer(..0) if base == 0.0 => return Err(runtime.error("ZeroDivisionError", "divided by 0")),
+        Unpacked::Integer(exponent @ ..0) => return Ok(runtime.float(base.powi(exponent.try_into().unwrap_or(i32::MIN)))),
+        _ => {}
+    }
+    let Ok(exponent) = u32::try_from(expect_integer(ex, args[0])?) else {
+        return Err(ex.runtime().error("ArgumentError", "exponent is too large"));
+    };
+    let runtime = ex.runtime();
+    if let Some(result) = integer_of(runtime, receiver).and_then(|base| base.checked_pow(exponent)) {
+        return Ok(runtime.int(result));
+    }
+    let result = receiver_bignum(ex, receiver).pow(exponent);
+    Ok(ex.runtime().integer(result))
+}
+
+// Shifting right rounds towards negative infinity, and a negative count shifts the other way
+fn integer_shift(ex: &mut dyn Executor, receiver: Value, count: Value, left: bool) -> NativeResult {
+    let count = expect_integer(ex, count)?;
+    let left = left == (count >= 0);
+    let Ok(count) = u32::try_from(count.unsigned_abs()) else {
+        if left {
+            return Err(ex.runtime().error("RangeError", "shift width too big"));
+        }
+        let negative = receiver_bignum(ex, receiver).is_negative();
+        return Ok(ex.runtime().int(if negative { -1 } else { 0 }));
+    };
+    let value = receiver_bignum(ex, receiver);
+    let result = if left { value << count } else { value >> count };
+    Ok(ex.runtime().integer(result))
+}
+
+fn integer_left_shift(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_shift(ex, receiver, args[0], true)
+}
+
+fn integer_right_shift(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_shift(ex, receiver, args[0], false)
+}
+
+// Bignums act as two's complement with infinitely many sign bits, like 64-bit integers do
+fn integer_bitwise(
+    ex: &mut dyn Executor,
+    receiver: Value,
+    argument: Value,
+    small: fn(i64, i64) -> i64,
+    big: fn(BigInt, BigInt) -> BigInt,
+) -> NativeResult {
+    let runtime = ex.runtime();
+    if let (Some(left), Some(right)) = (integer_of(runtime, receiver), integer_of(runtime, argument)) {
+        return Ok(runtime.int(small(left, right)));
+    }
+    let left = receiver_bignum(ex, receiver);
+    let right = expect_bignum(ex, argument)?;
+    Ok(ex.runtime().integer(big(left, right)))
+}
+
+fn integer_and(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_bitwise(ex, receiver, args[0], |left, right| left & right, |left, right| left & right)
+}
+
+fn integer_or(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_bitwise(ex, receiver, args[0], |left, right| left | right, |left, right| left | right)
+}
+
+fn integer_xor(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    integer_bitwise(ex, receiver, args[0], |left, right| left ^ right, |left, right| left ^ right)
+}
+
+fn integer_negate(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    if let Some(result) = integer_of(runtime, receiver).and_then(i64::checked_neg) {
+        return Ok(runtime.int(result));
+    }
+    let result = -receiver_bignum(ex, receiver);
+    Ok(ex.runtime().integer(result))
+}
+
+fn integer_abs(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    if let Some(result) = integer_of(runtime, receiver).and_then(i64::checked_abs) {
+        return Ok(runtime.int(result));
+    }
+    let result = receiver_bignum(ex, receiver).abs();
+    Ok(ex.runtime().integer(result))
+}
+
+// Unlike `==`, an integer is never `eql?` to a float
+fn integer_eql(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(Value::from_bool(bignum_of(runtime, args[0]).is_some() && numeric_order(runtime, receiver, args[0]) == Some(Ordering::Equal)))
+}
+
+fn integer_succ(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let one = ex.runtime().int(1);
+    integer_add(ex, receiver, &[one], None)
+}
+
+fn integer_pred(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let one = ex.runtime().int(1);
+    integer_sub(ex, receiver, &[one], None)
+}
+
+// Bignums are never zero
+fn integer_zero(_: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(receiver.as_fixnum() == Some(0)))
+}
+
+fn integer_even(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(receiver_bignum(ex, receiver).is_even()))
+}
+
+fn integer_odd(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(receiver_bignum(ex, receiver).is_odd()))
+}
+
+fn integer_times(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    for index in 0..expect_integer(ex, receiver)? {
+        let index = ex.runtime().int(index);
+        ex.call_proc(block, &[index])?;
+    }
+    Ok(receiver)
+}
+
+fn integer_upto(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let limit = expect_integer(ex, args[0])?;
+    for index in expect_integer(ex, receiver)?..=limit {
+        let index = ex.runtime().int(index);
+        ex.call_proc(block, &[index])?;
+    }
+    Ok(receiver)
+}
+
+fn integer_downto(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let limit = expect_integer(ex, args[0])?;
+    for index in (limit..=expect_integer(ex, receiver)?).rev() {
+        let index = ex.runtime().int(index);
+        ex.call_proc(block, &[index])?;
+    }
+    Ok(receiver)
+}
+
+fn integer_to_f(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let value = float_operand(runtime, receiver).expect("the receiver is an integer");
+    Ok(runtime.float(value))
+}
+
+// Codes past ASCII are bytes of a binary string, unless an encoding is given
+fn integer_chr(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let code = expect_integer(ex, receiver)?;
+    let encoding = match args.first() {
+        Some(&encoding) => Some(expect_encoding(ex, encoding)?),
+        None => None,
+    };
+    let c = u32::try_from(code).ok().and_then(char::from_u32);
+    let string = match (encoding, c) {
+        (Some(Encoding::Utf8), Some(c)) => RString::new(c.encode_utf8(&mut [0; 4])),
+        (_, Some(c)) if c.is_ascii() => RString::new(c.encode_utf8(&mut [0; 4])),
+        (None | Some(Encoding::Binary), _) if (0..=0xff).contains(&code) => RString::binary(vec![code as u8]),
+        _ => return Err(ex.runtime().error("RangeError", &format!("{} out of char range", code))),
+    };
+    Ok(ex.runtime().string_from(string))
+}
+
+fn integer_to_s(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let base = match args.first() {
+        Some(&base) => expect_integer(ex, base)?,
+        None => 10,
+    };
+    if !(2..=36).contains(&base) {
+        return Err(ex.runtime().error("ArgumentError", &format!("invalid radix {}", base)));
+    }
+
+    let Some(value) = integer_of(ex.runtime(), receiver) else {
+        let text = receiver_bignum(ex, receiver).to_str_radix(base as u32);
+        return Ok(ex.runtime().string(&text));
+    };
+    let mut magnitude = value.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        digits.push(std::char::from_digit((magnitude % base as u64) as u32, base as u32).expect("digit below base"));
+        magnitude /= base as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if value < 0 {
+        digits.push('-');
+    }
+    let text: String = digits.into_iter().rev().collect();
+    Ok(ex.runtime().string(&text))
+}
+
+fn integer_of(runtime: &Runtime, value: Value) -> Option<i64> {
+    match runtime.unpack(value) {
+        Unpacked::Integer(value) => Some(value),
+        _ => None,
+    }
+}
+
+// Float
+
+fn define_float(runtime: &mut Runtime) {
+    let float = runtime.classes.float;
+    runtime.define_native(float, "+", 1, float_add);
+    runtime.define_native(float, "-", 1, float_sub);
+    runtime.define_native(float, "*", 1, float_mul);
+    runtime.define_native(float, "/", 1, float_div);
+    runtime.define_native(float, "%", 1, float_mod);
+    runtime.define_native(float, "modulo", 1, float_mod);
+    runtime.define_native(float, "**", 1, float_pow);
+    runtime.define_native(float, "-@", 0, float_negate);
+    runtime.define_native(float, "==", 1, numeric_equal);
+    runtime.define_native(float, "===", 1, numeric_equal);
+    runtime.define_native(float, "eql?", 1, float_eql);
+    runtime.define_native(float, "<", 1, numeric_less);
+    runtime.define_native(float, "<=", 1, numeric_less_or_equal);
+    runtime.define_native(float, ">", 1, numeric_greater);
+    runtime.define_native(float, ">=", 1, numeric_greater_or_equal);
+    runtime.define_native(float, "<=>", 1, numeric_compare);
+    runtime.define_native(float, "abs", 0, float_abs);
+    runtime.define_native(float, "zero?", 0, float_zero);
+    runtime.define_native(float, "nan?", 0, float_nan);
+    runtime.define_native(float, "finite?", 0, float_finite);
+    runtime.define_native(float, "infinite?", 0, float_infinite);
+    runtime.define_native(float, "floor", 0, float_floor);
+    runtime.define_native(float, "ceil", 0, float_ceil);
+    runtime.define_native(float, "round", -1, float_round);
+    runtime.define_native(float, "truncate", 0, float_to_i);
+    runtime.define_native(float, "to_i", 0, float_to_i);
+    runtime.define_native(float, "to_f", 0, object_itself);
+    runtime.define_native(float, "to_s", 0, float_to_s);
+    runtime.define_native(float, "inspect", 0, float_to_s);
+    for (name, value) in [("INFINITY", f64::INFINITY), ("NAN", f64::NAN), ("EPSILON", f64::EPSILON)] {
+        let value = runtime.float(value);
+        runtime.set_constant(float, name, value);
+    }
+}
+
+fn float_of(runtime: &Runtime, receiver: Value) -> f64 {
+    match runtime.unpack(receiver) {
+        Unpacked::Float(value) => value,
+        _ => unreachable!("Float methods are only called on floats"),
+    }
+}
+
+// Integers and floats as floats, for operations mixing the two
+fn float_operand(runtime: &Runtime, value: Value) -> Option<f64> {
+    match runtime.unpack(value) {
+        Unpacked::Integer(value) => Some(value as f64),
+        Unpacked::Float(value) => Some(value),
+        _ => runtime.bignum_value(value).and_then(BigInt::to_f64),
+    }
+}
+
+// How two numbers compare, exactly between integers. `None` when either isn't a number or is NaN.
+fn numeric_order(runtime: &Runtime, left: Value, right: Value) -> Option<Ordering> {
+    if let (Some(left), Some(right)) = (integer_of(runtime, left), integer_of(runtime, right)) {
+        return Some(left.cmp(&right));
+    }
+    if let (Some(left), Some(right)) = (bignum_of(runtime, left), bignum_of(runtime, right)) {
+        return Some(left.cmp(&right));
+    }
+    float_operand(runtime, left)?.partial_cmp(&float_operand(runtime, right)?)
+}
+
+// `==` on Integer and Float
+fn numeric_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(numeric_order(ex.runtime(), receiver, args[0]) == Some(Ordering::Equal)))
+}
+
+// Comparing with NaN is false, and with anything but a number an error
+fn numeric_comparison(ex: &mut dyn Executor, receiver: Value, args: &[Value], test: fn(Ordering) -> bool) -> NativeResult {
+    let runtime = ex.runtime();
+    if float_operand(runtime, args[0]).is_none() {
+        return Err(comparison_error(ex, receiver, args[0]));
+    }
+    Ok(Value::from_bool(numeric_order(runtime, receiver, args[0]).is_some_and(test)))
+}
+
+fn numeric_less(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    numeric_comparison(ex, receiver, args, Ordering::is_lt)
+}
+
+fn numeric_less_or_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    numeric_comparison(ex, receiver, args, Ordering::is_le)
+}
+
+fn numeric_greater(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    numeric_comparison(ex, receiver, args, Ordering::is_gt)
+}
+
+fn numeric_greater_or_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    numeric_comparison(ex, receiver, args, Ordering::is_ge)
+}
+
+fn numeric_compare(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let order = numeric_order(runtime, receiver, args[0]);
+    Ok(order.map_or(Value::NIL, |order| runtime.int(order as i64)))
+}
+
+fn float_arithmetic(ex: &mut dyn Executor, receiver: Value, args: &[Value], operation: fn(f64, f64) -> f64) -> NativeResult {
+    let runtime = ex.runtime();
+    match float_operand(runtime, args[0]) {
+        Some(right) => Ok(runtime.float(operation(float_of(runtime, receiver), right))),
+        None => Err(conversion_error(ex, args[0], "Float")),
+    }
+}
+
+// Like integers, the result takes the sign of the divisor
+fn float_modulo(left: f64, right: f64) -> f64 {
+    let remainder = left % right;
+    if remainder != 0.0 && (remainder < 0.0) != (right < 0.0) {
+        remainder + right
+    } else {
+        remainder
+    }
+}
+
+fn float_add(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, f64::add)
+}
+
+fn float_sub(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, f64::sub)
+}
+
+fn float_mul(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, f64::mul)
+}
+
+fn float_div(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, f64::div)
+}
+
+fn float_mod(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, float_modulo)
+}
+
+fn float_pow(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    float_arithmetic(ex, receiver, args, f64::powf)
+}
+
+fn float_negate(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(runtime.float(-float_of(runtime, receiver)))
+}
+
+// Unlike `==`, an integer is never `eql?` to a float
+fn float_eql(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(Value::from_bool(matches!(runtime.unpack(args[0]), Unpacked::Float(right) if right == float_of(runtime, receiver))))
+}
+
+fn float_abs(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(runtime.float(float_of(runtime, receiver).abs()))
+}
+
+fn float_zero(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(float_of(ex.runtime(), receiver) == 0.0))
+}
+
+fn float_nan(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(float_of(ex.runtime(), receiver).is_nan()))
+}
+
+fn float_finite(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(float_of(ex.runtime(), receiver).is_finite()))
+}
+
+// `1` or `-1` for the infinities, `nil` otherwise
+fn float_infinite(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let value = float_of(runtime, receiver);
+    Ok(if value.is_infinite() { runtime.int(value.signum() as i64) } else { Value::NIL })
+}
+
+// Rounding to an integer fails for NaN and the infinities
+fn float_to_integer(ex: &mut dyn Executor, value: f64) -> NativeResult {
+    match BigInt::from_f64(value) {
+        Some(integer) => Ok(ex.runtime().integer(integer)),
+        None => Err(ex.runtime().error("FloatDomainError", &format_float(value))),
+    }
+}
+
+fn float_floor(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let value = float_of(ex.runtime(), receiver).floor();
+    float_to_integer(ex, value)
+}
+
+fn float_ceil(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let value = float_of(ex.runtime(), receiver).ceil();
+    float_to_integer(ex, value)
+}
+
+fn float_to_i(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let value = float_of(ex.runtime(), receiver).trunc();
+    float_to_integer(ex, value)
+}
+
+// Halves round away from zero. With a number of digits, the result stays a float.
+fn float_round(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let value = float_of(ex.runtime(), receiver);
+    match args.first() {
+        Some(&digits) => {
+            let digits = expect_integer(ex, digits)?;
+            let scale = 10f64.powi(digits.clamp(-400, 400) as i32);
+            Ok(ex.runtime().float((value * scale).round() / scale))
+        }
+        None => float_to_integer(ex, value.round()),
+    }
+}
+
+fn float_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let text = format_float(float_of(ex.runtime(), receiver));
+    Ok(ex.runtime().string(&text))
+}
+
+// The shortest digits that read back as the same float, in exponent form when the
+// exponent is below -4 or above 15, and always with a fractional part
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        return "NaN".to_string();
+    }
+    if value.is_infinite() {
+        return if value > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+    let scientific = format!("{:e}", value);
+    let (mantissa, exponent) = scientific.split_once('e').expect("exponent notation");
+    let exponent: i32 = exponent.parse().expect("exponent is a number");
+    if (-4..16).contains(&exponent) {
+        let decimal = value.to_string();
+        return if decimal.contains('.') { decimal } else { format!("{}.0", decimal) };
+    }
+    let mantissa = if mantissa.contains('.') { mantissa.to_string() } else { format!("{}.0", mantissa) };
+    format!("{}e{}{:02}", mantissa, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+// String
+
+fn define_string(runtime: &mut Runtime) {
+    let string = runtime.classes.string;
+    runtime.define_native(string, "initialize", -1, string_initialize);
+    runtime.define_native(string, "+", 1, string_add);
+    runtime.define_native(string, "*", 1, string_multiply);
+    runtime.define_native(string, "%", 1, string_format);
+    runtime.define_native(string, "<<", 1, string_append);
+    runtime.define_native(string, "concat", -1, string_concat);
+    runtime.define_native(string, "prepend", 1, string_prepend);
+    runtime.define_native(string, "insert", 2, string_insert);
+    runtime.define_native(string, "replace", 1, string_replace);
+    runtime.define_native(string, "clear", 0, string_clear);
+    runtime.define_native(string, "==", 1, string_equal);
+    runtime.define_native(string, "===", 1, string_equal);
+    runtime.define_native(string, "eql?", 1, string_equal);
+    runtime.define_native(string, "<=>", 1, string_compare);
+    runtime.define_native(string, "=~", 1, string_match_operator);
+    runtime.define_native(string, "match?", 1, string_match_p);
+    runtime.define_native(string, "[]", -2, string_index);
+    runtime.define_native(string, "slice", -2, string_index);
+    runtime.define_native(string, "[]=", -3, string_set_index);
+    runtime.define_native(string, "length", 0, string_length);
+    runtime.define_native(string, "size", 0, string_length);
+    runtime.define_native(string, "bytesize", 0, string_bytesize);
+    runtime.define_native(string, "empty?", 0, string_empty);
+    runtime.define_native(string, "encoding", 0, string_encoding);
+    runtime.define_native(string, "force_encoding", 1, string_force_encoding);
+    runtime.define_native(string, "b", 0, string_b);
+    runtime.define_native(string, "valid_encoding?", 0, string_valid_encoding);
+    runtime.define_native(string, "ascii_only?", 0, string_ascii_only);
+    runtime.define_native(string, "chars", 0, string_chars);
+    runtime.define_native(string, "bytes", 0, string_bytes);
+    runtime.define_native(string, "lines", 0, string_lines);
+    runtime.define_native(string, "each_char", 0, string_each_char);
+    runtime.define_native(string, "upcase", 0, string_upcase);
+    runtime.define_native(string, "downcase", 0, string_downcase);
+    runtime.define_native(string, "capitalize", 0, string_capitalize);
+    runtime.define_native(string, "swapcase", 0, string_swapcase);
+    runtime.define_native(string, "upcase!", 0, string_upcase_bang);
+    runtime.define_native(string, "downcase!", 0, string_downcase_bang);
+    runtime.define_native(string, "capitalize!", 0, string_capitalize_bang);
+    runtime.define_native(string, "swapcase!", 0, string_swapcase_bang);
+    runtime.define_native(string, "strip", 0, string_strip);
+    runtime.define_native(string, "lstrip", 0, string_lstrip);
+    runtime.define_native(string, "rstrip", 0, string_rstrip);
+    runtime.define_native(string, "strip!", 0, string_strip_bang);
+    runtime.define_native(string, "chomp", -1, string_chomp);
+    runtime.define_native(string, "chop", 0, string_chop);
+    runtime.define_native(string, "reverse", 0, string_reverse);
+    runtime.define_native(string, "ljust", -2, string_ljust);
+    runtime.define_native(string, "rjust", -2, string_rjust);
+    runtime.define_native(string, "center", -2, string_center);
+    runtime.define_native(string, "include?", 1, string_include);
+    runtime.define_native(string, "start_with?", -1, string_start_with);
+    runtime.define_native(string, "end_with?", -1, string_end_with);
+    runtime.define_native(string, "index", -2, string_index_of);
+    runtime.define_native(string, "split", -1, string_split);
+    runtime.define_native(string, "scan", 1, string_scan);
+    runtime.define_native(string, "sub", -2, string_sub);
+    runtime.define_native(string, "gsub", -2, string_gsub);
+    runtime.define_native(string, "sub!", -2, string_sub_bang);
+    runtime.define_native(string, "gsub!", -2, string_gsub_bang);
+    runtime.define_native(string, "ord", 0, string_ord);
+    runtime.define_native(string, "to_i", -1, string_to_i);
+    runtime.define_native(string, "to_f", 0, string_to_f);
+    runtime.define_native(string, "to_s", 0, object_itself);
+    runtime.define_native(string, "to_str", 0, object_itself);
+    runtime.define_native(string, "to_sym", 0, string_to_sym);
+    runtime.define_native(string, "intern", 0, string_to_sym);
+    runtime.define_native(string, "inspect", 0, string_inspect);
+}
+
+fn string_of(ex: &mut dyn Executor, receiver: Value) -> String {
+    ex.runtime().rstring_value(receiver).map(|string| string.text().into_owned()).unwrap_or_default()
+}
+
+// A copy of the string, so natives can call back into Ruby while they work on it
+fn rstring_of(ex: &mut dyn Executor, receiver: Value) -> RString {
+    ex.runtime().rstring_value(receiver).cloned().unwrap_or_default()
+}
+
+fn expect_rstring(ex: &mut dyn Executor, value: Value) -> Result<RString, Unwind> {
+    match ex.runtime().rstring_value(value) {
+        Some(string) => Ok(string.clone()),
+        None => Err(conversion_error(ex, value, "String")),
+    }
+}
+
+fn modify_string(ex: &mut dyn Executor, receiver: Value, change: impl FnOnce(&mut RString)) -> Result<(), Unwind> {
+    let id = receiver.object_id().expect("strings are objects");
+    check_frozen(ex, receiver)?;
+    if let ObjectKind::String(string) = &mut ex.runtime().object_mut(id).kind {
+        change(string);
+    }
+    Ok(())
+}
+
+// The encoding of two strings put together: either one's if the other is ASCII only
+fn compatible_encoding(ex: &mut dyn Executor, left: &RString, right: &RString) -> Result<Encoding, Unwind> {
+    if left.encoding == right.encoding || right.is_ascii() {
+        return Ok(left.encoding);
+    }
+    if left.is_ascii() {
+        return Ok(right.encoding);
+    }
+    let runtime = ex.runtime();
+    let class = runtime.constant(runtime.classes.encoding, "CompatibilityError").and_then(Value::object_id).expect("Encoding::CompatibilityError is defined");
+    let message = format!("incompatible character encodings: {} and {}", left.encoding.name(), right.encoding.name());
+    let message = runtime.string(&message);
+    Err(Unwind::Raise(runtime.exception(class, message)))
+}
+
+fn concatenate(ex: &mut dyn Executor, left: &RString, right: &RString) -> Result<RString, Unwind> {
+    let encoding = compatible_encoding(ex, left, right)?;
+    Ok(RString { bytes: [left.bytes.as_slice(), &right.bytes].concat(), encoding })
+}
+
+// A string made of some of the characters of another, in the same encoding
+fn substring(string: &RString, chars: &[&[u8]]) -> RString {
+    RString { bytes: chars.concat(), encoding: string.encoding }
+}
+
+fn string_initialize(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    if let Some(&initial) = args.first() {
+        let initial = expect_rstring(ex, initial)?;
+        modify_string(ex, receiver, |string| *string = initial)?;
+    }
+    Ok(Value::NIL)
+}
+
+fn string_add(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let right = expect_rstring(ex, args[0])?;
+    let left = rstring_of(ex, receiver);
+    let string = concatenate(ex, &left, &right)?;
+    Ok(ex.runtime().string_from(string))
+}
+
+// The longest string or array built from a size the program asks for. Bigger ones raise
+// `ArgumentError` rather than running out of memory.
+const MAX_LENGTH: usize = 1 << 28;
+
+fn string_multiply(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let times = expect_integer(ex, args[0])?;
+    if times < 0 {
+        return Err(ex.runtime().error("ArgumentError", "negative argument"));
+    }
+    let string = rstring_of(ex, receiver);
+    if string.bytes.len().checked_mul(times as usize).is_none_or(|length| length > MAX_LENGTH) {
+        return Err(ex.runtime().error("ArgumentError", "argument too big"));
+    }
+    Ok(ex.runtime().string_from(RString { bytes: string.bytes.repeat(times as usize), encoding: string.encoding }))
+}
+
+// `format % argument`, or `format % [arguments]`
+fn string_format(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let format = string_of(ex, receiver);
+    let args = ex.runtime().array_value(args[0]).cloned().unwrap_or_else(|| args.to_vec());
+    let text = sprintf(ex, &format, &args)?;
+    Ok(ex.runtime().string(&text))
+}
+
+// Appends a string, or the character with an integer's codepoint
+fn string_append(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let right = match integer_of(ex.runtime(), args[0]) {
+        Some(code) => match u32::try_from(code).ok().and_then(char::from_u32) {
+            Some(c) if rstring_of(ex, receiver).encoding == Encoding::Utf8 => RString::new(c.encode_utf8(&mut [0; 4])),
+            _ if (0..=0xff).contains(&code) => RString::binary(vec![code as u8]),
+            _ => return Err(ex.runtime().error("RangeError", &format!("{} out of char range", code))),
+        },
+        None => expect_rstring(ex, args[0])?,
+    };
+    let left = rstring_of(ex, receiver);
+    let joined = concatenate(ex, &left, &right)?;
+    modify_string(ex, receiver, |string| *string = joined)?;
+    Ok(receiver)
+}
+
+fn string_concat(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    // Copied first, so `s.concat(s, s)` appends the original twice
+    let mut strings = Vec::new();
+    for &arg in args {
+        strings.push(expect_rstring(ex, arg)?);
+    }
+    let mut joined = rstring_of(ex, receiver);
+    for string in &strings {
+        joined = concatenate(ex, &joined, string)?;
+    }
+    modify_string(ex, receiver, |string| *string = joined)?;
+    Ok(receiver)
+}
+
+fn string_prepend(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let left = expect_rstring(ex, args[0])?;
+    let right = rstring_of(ex, receiver);
+    let joined = concatenate(ex, &left, &right)?;
+    modify_string(ex, receiver, |string| *string = joined)?;
+    Ok(receiver)
+}
+
+// Inserts before the character at the index, or after it when it's negative
+fn string_insert(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let index = expect_integer(ex, args[0])?;
+    let other = expect_rstring(ex, args[1])?;
+    let string = rstring_of(ex, receiver);
+    let chars = string.chars();
+    let position = if index < 0 { chars.len() as i64 + index + 1 } else { index };
+    if position < 0 || position as usize > chars.len() {
+        let message = format!("index {} out of string", index);
+        return Err(ex.runtime().error("IndexError", &message));
+    }
+    let (before, after) = chars.split_at(position as usize);
+    let joined = concatenate(ex, &substring(&string, before), &other)?;
+    let joined = concatenate(ex, &joined, &substring(&string, after))?;
+    modify_string(ex, receiver, |string| *string = joined)?;
+    Ok(receiver)
+}
+
+fn string_replace(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let other = expect_rstring(ex, args[0])?;
+    modify_string(ex, receiver, |string| *string = other)?;
+    Ok(receiver)
+}
+
+fn string_clear(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    modify_string(ex, receiver, |string| string.bytes.clear())?;
+    Ok(receiver)
+}
+
+// Strings in different encodings are only equal when they're ASCII only
+fn string_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(Value::from_bool(match (runtime.rstring_value(receiver), runtime.rstring_value(args[0])) {
+        (Some(left), Some(right)) => left.bytes == right.bytes && (left.encoding == right.encoding || left.is_ascii()),
+        _ => false,
+    }))
+}
+
+fn string_compare(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let order = match (runtime.rstring_value(receiver), runtime.rstring_value(args[0])) {
+        (Some(left), Some(right)) => left.bytes.cmp(&right.bytes),
+        _ => return Ok(Value::NIL),
+    };
+    Ok(runtime.int(order as i64))
+}
+
+fn string_match_operator(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if ex.runtime().rstring_value(args[0]).is_some() {
+        return Err(ex.runtime().error("TypeError", "wrong argument type String (expected Regexp)"));
+    }
+    ex.send(args[0], "=~", &[receiver], None)
+}
+
+fn string_match_p(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let regex = expect_pattern(ex, args[0])?;
+    Ok(Value::from_bool(regex.is_match(&rstring_of(ex, receiver).bytes)))
+}
+
+// `s[index]`, `s[start, length]`, `s[range]`, `s["text"]`, `s[/pattern/]` and `s[/pattern/, group]`
+fn string_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let string = rstring_of(ex, receiver);
+    if let Some(regexp) = ex.runtime().regexp_value(args[0]) {
+        let regex = regexp.regex.clone();
+        let Some(captures) = regex.captures(&string.bytes) else {
+            return Ok(Value::NIL);
+        };
+        let group = match args.get(1) {
+            Some(&group) => capture_group(ex, &regex, &captures, group)?,
+            None => captures.get(0),
+        };
+        return Ok(match group {
+            Some(group) => ex.runtime().string_from(RString { bytes: group.as_bytes().to_vec(), encoding: string.encoding }),
+            None => Value::NIL,
+        });
+    }
+    if let (Some(other), None) = (ex.runtime().rstring_value(args[0]).cloned(), args.get(1)) {
+        let found = other.bytes.is_empty() || string.bytes.windows(other.bytes.len()).any(|window| window == other.bytes);
+        return Ok(if found { ex.runtime().string_from(other) } else { Value::NIL });
+    }
+
+    let chars = string.chars();
+    Ok(match selection(ex, args, chars.len())? {
+        Some(Selection::Single(index)) => ex.runtime().string_from(substring(&string, &chars[index..=index])),
+        Some(Selection::Span(start, length)) => ex.runtime().string_from(substring(&string, &chars[start..start + length])),
+        None => Value::NIL,
+    })
+}
+
+// `[]=` with the same selections as `[]`, replacing what they select
+fn string_set_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 3 {
+        return Err(argument_count_error(ex, args.len(), "2..3"));
+    }
+    let (selector, value) = args.split_at(args.len() - 1);
+    let value = expect_rstring(ex, value[0])?;
+    let string = rstring_of(ex, receiver);
+
+    let (start, end) = if let Some(regexp) = ex.runtime().regexp_value(selector[0]) {
+        let regex = regexp.regex.clone();
+        match regex.captures(&string.bytes) {
+            Some(captures) => {
+                let group = match selector.get(1) {
+                    Some(&group) => capture_group(ex, &regex, &captures, group)?,
+                    None => captures.get(0),
+                };
+                match group {
+                    Some(group) => (group.start(), group.end()),
+                    None => return Err(ex.runtime().error("IndexError", "regexp group not matched")),
+                }
+            }
+            None => return Err(ex.runtime().error("IndexError", "regexp not matched")),
+        }
+    } else if let (Some(other), 1) = (ex.runtime().rstring_value(selector[0]).cloned(), selector.len()) {
+        match find_bytes(&string.bytes, &other.bytes, 0) {
+            Some(start) => (start, start + other.bytes.len()),
+            None => return Err(ex.runtime().error("IndexError", "string not matched")),
+        }
+    } else {
+        let chars = string.chars();
+        let offset = |index: usize| chars[..index].iter().map(|c| c.len()).sum::<usize>();
+        match selection(ex, selector, chars.len())? {
+            Some(Selection::Single(index)) => (offset(index), offset(index + 1)),
+            Some(Selection::Span(start, length)) => (offset(start), offset(start + length)),
+            None => {
+                let message = format!("index {} out of string", describe_selector(ex, selector));
+                return Err(ex.runtime().error("IndexError", &message));
+            }
+        }
+    };
+
+    let before = RString { bytes: string.bytes[..start].to_vec(), encoding: string.encoding };
+    let after = RString { bytes: string.bytes[end..].to_vec(), encoding: string.encoding };
+    let joined = concatenate(ex, &before, &value)?;
+    let joined = concatenate(ex, &joined, &after)?;
+    modify_string(ex, receiver, |string| *string = joined)?;
+    Ok(args[args.len() - 1])
+}
+
+fn describe_selector(ex: &mut dyn Executor, selector: &[Value]) -> String {
+    selector.iter().map(|&value| ex.runtime().describe(value)).collect::<Vec<_>>().join(", ")
+}
+
+// A numbered or named group of a match, `None` when it didn't take part
+fn capture_group<'t>(ex: &mut dyn Executor, regex: &Regex, captures: &Captures<'t>, group: Value) -> Result<Option<Match<'t>>, Unwind> {
+    match integer_of(ex.runtime(), group) {
+        Some(index) => {
+            let index = if index < 0 { captures.len() as i64 + index } else { index };
+            Ok(usize::try_from(index).ok().and_then(|index| captures.get(index)))
+        }
+        None => {
+            let name = expect_name(ex, group)?;
+            if !regex.capture_names().any(|known| known == Some(name.as_str())) {
+                let message = format!("undefined group name reference: {}", name);
+                return Err(ex.runtime().error("IndexError", &message));
+            }
+            Ok(captures.name(&name))
+        }
+    }
+}
+
+fn string_length(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let length = rstring_of(ex, receiver).chars().len();
+    Ok(ex.runtime().int(length as i64))
+}
+
+fn string_bytesize(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let length = rstring_of(ex, receiver).bytes.len();
+    Ok(ex.runtime().int(length as i64))
+}
+
+fn string_empty(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(rstring_of(ex, receiver).bytes.is_empty()))
+}
+
+fn string_encoding(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let encoding = rstring_of(ex, receiver).encoding;
+    Ok(ex.runtime().encoding(encoding))
+}
+
+// Changes how the bytes are read, without changing them
+fn string_force_encoding(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let encoding = expect_encoding(ex, args[0])?;
+    modify_string(ex, receiver, |string| string.encoding = encoding)?;
+    Ok(receiver)
+}
+
+// A binary copy
+fn string_b(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    Ok(ex.runtime().string_from(RString::binary(string.bytes)))
+}
+
+fn string_valid_encoding(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(rstring_of(ex, receiver).is_valid()))
+}
+
+fn string_ascii_only(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(rstring_of(ex, receiver).is_ascii()))
+}
+
+fn string_chars(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let chars: Vec<Value> = string.chars().iter().map(|c| ex.runtime().string_from(substring(&string, &[c]))).collect();
+    Ok(ex.runtime().array(chars))
+}
+
+fn string_bytes(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let runtime = ex.runtime();
+    let bytes = string.bytes.iter().map(|&byte| runtime.int(byte as i64)).collect();
+    Ok(runtime.array(bytes))
+}
+
+// Each line keeps its `\n`
+fn string_lines(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let lines: Vec<Value> = string
+        .bytes
+        .split_inclusive(|&byte| byte == b'\n')
+        .map(|line| ex.runtime().string_from(RString { bytes: line.to_vec(), encoding: string.encoding }))
+        .collect();
+    Ok(ex.runtime().array(lines))
+}
+
+fn string_each_char(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let string = rstring_of(ex, receiver);
+    for c in string.chars() {
+        let c = ex.runtime().string_from(substring(&string, &[c]));
+        ex.call_proc(block, &[c])?;
+    }
+    Ok(receiver)
+}
+
+// Case mapping covers all of Unicode for valid UTF-8, and only ASCII letters otherwise
+fn map_case(string: &RString, unicode: fn(&str) -> String, ascii: fn(&mut [u8])) -> RString {
+    match string.as_str() {
+        Some(text) if string.encoding == Encoding::Utf8 => RString::new(&unicode(text)),
+        _ => {
+            let mut bytes = string.bytes.clone();
+            ascii(&mut bytes);
+            RString { bytes, encoding: string.encoding }
+        }
+    }
+}
+
+fn upcase(string: &RString) -> RString {
+    map_case(string, str::to_uppercase, <[u8]>::make_ascii_uppercase)
+}
+
+fn downcase(string: &RString) -> RString {
+    map_case(string, str::to_lowercase, <[u8]>::make_ascii_lowercase)
+}
+
+fn capitalize(string: &RString) -> RString {
+    let chars = string.chars();
+    let Some((first, rest)) = chars.split_first() else {
+        return string.clone();
+    };
+    let first = upcase(&substring(string, &[first]));
+    let rest = downcase(&substring(string, rest));
+    RString { bytes: [first.bytes, rest.bytes].concat(), encoding: string.encoding }
+}
+
+fn swapcase(string: &RString) -> RString {
+    let mut swapped = RString { bytes: Vec::new(), encoding: string.encoding };
+    for c in string.chars() {
+        let c = substring(string, &[c]);
+        let upper = upcase(&c);
+        swapped.bytes.extend(if upper == c { downcase(&c).bytes } else { upper.bytes });
+    }
+    swapped
+}
+
+// The copying version of a change
+fn changed_copy(ex: &mut dyn Executor, receiver: Value, change: fn(&RString) -> RString) -> NativeResult {
+    let string = change(&rstring_of(ex, receiver));
+    Ok(ex.runtime().string_from(string))
+}
+
+// The in-place version of a change, which returns `nil` when nothing changed
+fn change_in_place(ex: &mut dyn Executor, receiver: Value, change: fn(&RString) -> RString) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let changed = change(&string);
+    if changed == string {
+        return Ok(Value::NIL);
+    }
+    modify_string(ex, receiver, |string| *string = changed)?;
+    Ok(receiver)
+}
+
+fn string_upcase(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, upcase)
+}
+
+fn string_downcase(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, downcase)
+}
+
+fn string_capitalize(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, capitalize)
+}
+
+fn string_swapcase(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, swapcase)
+}
+
+fn string_upcase_bang(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    change_in_place(ex, receiver, upcase)
+}
+
+fn string_downcase_bang(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    change_in_place(ex, receiver, downcase)
+}
+
+fn string_capitalize_bang(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    change_in_place(ex, receiver, capitalize)
+}
+
+fn string_swapcase_bang(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    change_in_place(ex, receiver, swapcase)
+}
+
+// Whitespace as `strip` sees it, which includes NUL
+fn is_strip_space(byte: &u8) -> bool {
+    byte.is_ascii_whitespace() || *byte == b'\x0b' || *byte == 0
+}
+
+fn lstrip(string: &RString) -> RString {
+    let start = string.bytes.iter().position(|byte| !is_strip_space(byte)).unwrap_or(string.bytes.len());
+    RString { bytes: string.bytes[start..].to_vec(), encoding: string.encoding }
+}
+
+fn rstrip(string: &RString) -> RString {
+    let end = string.bytes.iter().rposition(|byte| !is_strip_space(byte)).map_or(0, |last| last + 1);
+    RString { bytes: string.bytes[..end].to_vec(), encoding: string.encoding }
+}
+
+fn strip(string: &RString) -> RString {
+    lstrip(&rstrip(string))
+}
+
+fn string_strip(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, strip)
+}
+
+fn string_lstrip(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, lstrip)
+}
+
+fn string_rstrip(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    changed_copy(ex, receiver, rstrip)
+}
+
+fn string_strip_bang(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    change_in_place(ex, receiver, strip)
+}
+
+// Without an argument, removes one line ending: `\n`, `\r\n` or `\r`
+fn string_chomp(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let mut string = rstring_of(ex, receiver);
+    let suffix = match args.first() {
+        Some(&suffix) => Some(expect_rstring(ex, suffix)?.bytes),
+        None => [b"\r\n".as_slice(), b"\n", b"\r"].into_iter().find(|ending| string.bytes.ends_with(ending)).map(<[u8]>::to_vec),
+    };
+    if let Some(suffix) = suffix.filter(|suffix| string.bytes.ends_with(suffix)) {
+        string.bytes.truncate(string.bytes.len() - suffix.len());
+    }
+    Ok(ex.runtime().string_from(string))
+}
+
+fn string_chop(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let chars = string.chars();
+    let keep = if string.bytes.ends_with(b"\r\n") { chars.len() - 2 } else { chars.len().saturating_sub(1) };
+    Ok(ex.runtime().string_from(substring(&string, &chars[..keep])))
+}
+
+fn string_reverse(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let mut chars = string.chars();
+    chars.reverse();
+    Ok(ex.runtime().string_from(substring(&string, &chars)))
+}
+
+// Pads to `width` characters with copies of the padding, split between the sides as `left`
+// says: 0 for `ljust`, 1 for `rjust`, and half of it for `center`
+fn justify(ex: &mut dyn Executor, receiver: Value, args: &[Value], left: fn(usize) -> usize) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let width = expect_integer(ex, args[0])?;
+    if width > MAX_LENGTH as i64 {
+        return Err(ex.runtime().error("ArgumentError", "argument too big"));
+    }
+    let padding = match args.get(1) {
+        Some(&padding) => expect_rstring(ex, padding)?,
+        None => RString::new(" "),
+    };
+    if padding.bytes.is_empty() {
+        return Err(ex.runtime().error("ArgumentError", "zero width padding"));
+    }
+    let string = rstring_of(ex, receiver);
+    let missing = (width.max(0) as usize).saturating_sub(string.chars().len());
+    let pad = |count: usize| -> Vec<u8> { padding.chars().into_iter().cycle().take(count).collect::<Vec<_>>().concat() };
+    let before = left(missing);
+    let bytes = [pad(before), string.bytes.clone(), pad(missing - before)].concat();
+    Ok(ex.runtime().string_from(RString { bytes, encoding: string.encoding }))
+}
+
+fn string_ljust(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    justify(ex, receiver, args, |_| 0)
+}
+
+fn string_rjust(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    justify(ex, receiver, args, |missing| missing)
+}
+
+fn string_center(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    justify(ex, receiver, args, |missing| missing / 2)
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if needle.is_empty() {
+        return (from <= haystack.len()).then_some(from);
+    }
+    haystack.get(from..)?.windows(needle.len()).position(|window| window == needle).map(|position| position + from)
+}
+
+fn string_include(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let other = expect_rstring(ex, args[0])?;
+    Ok(Value::from_bool(find_bytes(&rstring_of(ex, receiver).bytes, &other.bytes, 0).is_some()))
+}
+
+fn string_start_with(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    for &prefix in args {
+        let matched = match ex.runtime().regexp_value(prefix) {
+            Some(regexp) => regexp.regex.find(&string.bytes).is_some_and(|found| found.start() == 0),
+            None => string.bytes.starts_with(&expect_rstring(ex, prefix)?.bytes),
+        };
+        if matched {
+            return Ok(Value::TRUE);
+        }
+    }
+    Ok(Value::FALSE)
+}
+
+fn string_end_with(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    for &suffix in args {
+        if string.bytes.ends_with(&expect_rstring(ex, suffix)?.bytes) {
+            return Ok(Value::TRUE);
+        }
+    }
+    Ok(Value::FALSE)
+}
+
+// The character index of the first match at or after `start`
+fn string_index_of(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let string = rstring_of(ex, receiver);
+    let chars = string.chars();
+    let start = match args.get(1) {
+        Some(&start) => match array_position(expect_integer(ex, start)?, chars.len()).filter(|&start| start <= chars.len()) {
+            Some(start) => start,
+            None => return Ok(Value::NIL),
+        },
+        None => 0,
+    };
+    let from = chars[..start].iter().map(|c| c.len()).sum();
+    let regex = expect_pattern(ex, args[0])?;
+    Ok(match regex.find_at(&string.bytes, from) {
+        Some(found) => ex.runtime().int(string.char_index(found.start()) as i64),
+        None => Value::NIL,
+    })
+}
+
+// Without a pattern (or with " "), splits on runs of whitespace and ignores leading
+// whitespace. A regexp's groups are included in the result. A positive limit caps the
+// number of fields, and trailing empty fields are dropped unless there's a limit.
+fn string_split(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "0..2"));
+    }
+    let string = rstring_of(ex, receiver);
+    let limit = match args.get(1) {
+        Some(&limit) => expect_integer(ex, limit)?,
+        None => 0,
+    };
+    let pattern = args.first().copied().filter(|&pattern| pattern != Value::NIL);
+    let awk = match pattern {
+        None => true,
+        Some(pattern) => ex.runtime().rstring_value(pattern).is_some_and(|pattern| pattern.bytes == b" "),
+    };
+
+    let bytes = &string.bytes;
+    let mut fields: Vec<&[u8]> = Vec::new();
+    let full = |fields: &Vec<&[u8]>| limit > 0 && fields.len() as i64 == limit - 1;
+    if awk {
+        let mut rest = &bytes[bytes.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(bytes.len())..];
+        while !rest.is_empty() {
+            if full(&fields) {
+                fields.push(rest);
+                break;
+            }
+            let end = rest.iter().position(u8::is_ascii_whitespace).unwrap_or(rest.len());
+            fields.push(&rest[..end]);
+            rest = &rest[end..];
+            rest = &rest[rest.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(rest.len())..];
+        }
+    } else {
+        let regex = expect_pattern(ex, pattern.expect("not awk"))?;
+        let mut start = 0;
+        for captures in regex.captures_iter(bytes) {
+            let found = captures.get(0).expect("group 0 is the match");
+            // Empty matches split between characters, never before the first one
+            if found.is_empty() && (found.start() == start || !is_char_boundary(&string, found.start())) {
+                continue;
+            }
+            if found.start() == bytes.len() && found.is_empty() {
+                break;
+            }
+            if full(&fields) {
+                break;
+            }
+            fields.push(&bytes[start..found.start()]);
+            fields.extend(captures.iter().skip(1).flatten().map(|group| group.as_bytes()));
+            start = found.end();
+        }
+        fields.push(&bytes[start..]);
+    }
+    if limit == 0 {
+        while fields.last().is_some_and(|field| field.is_empty()) {
+            fields.pop();
+        }
+    }
+
+    let fields: Vec<Value> =
+        fields.into_iter().map(|field| ex.runtime().string_from(RString { bytes: field.to_vec(), encoding: string.encoding })).collect();
+    Ok(ex.runtime().array(fields))
+}
+
+fn is_char_boundary(string: &RString, offset: usize) -> bool {
+    string.encoding == Encoding::Binary || string.bytes.get(offset).is_none_or(|&byte| byte & 0xc0 != 0x80)
+}
+
+// Every match, or every match's groups when the pattern has any
+fn string_scan(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let regex = expect_pattern(ex, args[0])?;
+    let mut results = Vec::new();
+    for captures in regex.captures_iter(&string.bytes) {
+        let mut text = |group: Option<Match>| match group {
+            Some(group) => ex.runtime().string_from(RString { bytes: group.as_bytes().to_vec(), encoding: string.encoding }),
+            None => Value::NIL,
+        };
+        let result = if captures.len() == 1 {
+            text(captures.get(0))
+        } else {
+            let groups = captures.iter().skip(1).map(&mut text).collect();
+            ex.runtime().array(groups)
+        };
+        match block {
+            Some(block) => {
+                ex.call_proc(block, &[result])?;
+            }
+            None => results.push(result),
+        }
+    }
+    Ok(if block.is_some() { receiver } else { ex.runtime().array(results) })
+}
+
+// `sub` and `gsub`, with a replacement string (where `\0`, `\1`... and `\k<name>` stand
+// for the match and its groups), a hash looking up the matched text, or a block
+fn substitute(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>, global: bool) -> Result<Option<RString>, Unwind> {
+    if args.len() > 2 || (args.len() == 1 && block.is_none()) {
+        return Err(argument_count_error(ex, args.len(), if block.is_some() { "1..2" } else { "2" }));
+    }
+    let string = rstring_of(ex, receiver);
+    let regex = expect_pattern(ex, args[0])?;
+    let replacement = match args.get(1) {
+        Some(&replacement) if ex.runtime().hash_value(replacement).is_some() => None,
+        Some(&replacement) => Some(expect_rstring(ex, replacement)?),
+        None => None,
+    };
+
+    let mut result = Vec::new();
+    let mut last = 0;
+    let mut matched = false;
+    for captures in regex.captures_iter(&string.bytes) {
+        let found = captures.get(0).expect("group 0 is the match");
+        matched = true;
+        result.extend_from_slice(&string.bytes[last..found.start()]);
+        let text = RString { bytes: found.as_bytes().to_vec(), encoding: string.encoding };
+        match (&replacement, args.get(1)) {
+            (Some(replacement), _) => expand_replacement(&replacement.bytes, &regex, &captures, &mut result),
+            (None, Some(&hash)) => {
+                let key = ex.runtime().string_from(text);
+                let value = ex.send(hash, "[]", &[key], None)?;
+                result.extend(to_s(ex, value)?.into_bytes());
+            }
+            (None, None) => {
+                let text = ex.runtime().string_from(text);
+                let value = ex.call_proc(block.expect("checked above"), &[text])?;
+                result.extend(to_s(ex, value)?.into_bytes());
+            }
+        }
+        last = found.end();
+        if !global {
+            break;
+        }
+    }
+    if !matched {
+        return Ok(None);
+    }
+    result.extend_from_slice(&string.bytes[last..]);
+    Ok(Some(RString { bytes: result, encoding: string.encoding }))
+}
+
+fn expand_replacement(replacement: &[u8], regex: &Regex, captures: &Captures, result: &mut Vec<u8>) {
+    let mut bytes = replacement.iter().copied().peekable();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            result.push(byte);
+            continue;
+        }
+        let group = match bytes.peek() {
+            Some(digit @ b'0'..=b'9') => captures.get((digit - b'0') as usize),
+            Some(b'&') => captures.get(0),
+            Some(b'\\') => {
+                result.push(b'\\');
+                bytes.next();
+                continue;
+            }
+            Some(b'k') => {
+                let rest: Vec<u8> = bytes.clone().collect();
+                let name = rest.strip_prefix(b"k<").and_then(|rest| rest.iter().position(|&byte| byte == b'>').map(|end| &rest[..end]));
+                match name.and_then(|name| std::str::from_utf8(name).ok()).filter(|name| regex.capture_names().any(|known| known == Some(*name))) {
+                    Some(name) => {
+                        for _ in 0..name.len() + 2 {
+                            bytes.next();
+                        }
+                        captures.name(name)
+                    }
+                    None => {
+                        result.push(b'\\');
+                        continue;
+                    }
+                }
+            }
+            _ => {
+                result.push(b'\\');
+                continue;
+            }
+        };
+        bytes.next();
+        if let Some(group) = group {
+            result.extend_from_slice(group.as_bytes());
+        }
+    }
+}
+
+fn string_sub(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let string = match substitute(ex, receiver, args, block, false)? {
+        Some(string) => string,
+        None => rstring_of(ex, receiver),
+    };
+    Ok(ex.runtime().string_from(string))
+}
+
+fn string_gsub(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let string = match substitute(ex, receiver, args, block, true)? {
+        Some(string) => string,
+        None => rstring_of(ex, receiver),
+    };
+    Ok(ex.runtime().string_from(string))
+}
+
+fn string_sub_bang(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let Some(changed) = substitute(ex, receiver, args, block, false)? else {
+        return Ok(Value::NIL);
+    };
+    modify_string(ex, receiver, |string| *string = changed)?;
+    Ok(receiver)
+}
+
+fn string_gsub_bang(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let Some(changed) = substitute(ex, receiver, args, block, true)? else {
+        return Ok(Value::NIL);
+    };
+    modify_string(ex, receiver, |string| *string = changed)?;
+    Ok(receiver)
+}
+
+fn string_ord(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let string = rstring_of(ex, receiver);
+    let Some(first) = string.chars().first().map(|c| c.to_vec()) else {
+        return Err(ex.runtime().error("ArgumentError", "empty string"));
+    };
+    match (string.encoding, std::str::from_utf8(&first).ok().and_then(|c| c.chars().next())) {
+        (Encoding::Binary, _) => Ok(ex.runtime().int(first[0] as i64)),
+        (_, Some(c)) => Ok(ex.runtime().int(c as i64)),
+        (_, None) => Err(ex.runtime().error("ArgumentError", "invalid byte sequence in UTF-8")),
+    }
+}
+
+// Reads as much of an integer as there is at the start, after whitespace: `"12abc"` is
+// 12, `"abc"` is 0. The base's prefix (`0x` for 16...) is allowed.
+fn string_to_i(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let base = match args.first() {
+        Some(&base) => expect_integer(ex, base)?,
+        None => 10,
+    };
+    if !(2..=36).contains(&base) {
+        return Err(ex.runtime().error("ArgumentError", &format!("invalid radix {}", base)));
+    }
+    let text = string_of(ex, receiver);
+    let mut rest = text.trim_start();
+    let negative = rest.starts_with('-');
+    rest = rest.strip_prefix(['-', '+']).unwrap_or(rest);
+    let prefix = match base {
+        16 => Some("0x"),
+        8 => Some("0o"),
+        2 => Some("0b"),
+        _ => None,
+    };
+    if let Some(stripped) = prefix.and_then(|prefix| rest.get(..2).filter(|start| start.eq_ignore_ascii_case(prefix)).map(|_| &rest[2..])) {
+        rest = stripped;
+    }
+    let mut digits = String::new();
+    let mut chars = rest.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_digit(base as u32) => digits.push(c),
+            '_' if !digits.is_empty() && chars.peek().is_some_and(|next| next.is_digit(base as u32)) => {}
+            _ => break,
+        }
+    }
+    let value = BigInt::parse_bytes(digits.as_bytes(), base as u32).unwrap_or_default();
+    Ok(ex.runtime().integer(if negative { -value } else { value }))
+}
+
+// Like `to_i`, the longest float at the start
+fn string_to_f(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let text = string_of(ex, receiver);
+    let text = text.trim_start();
+    let end = float_length(text);
+    Ok(ex.runtime().float(text[..end].replace('_', "").parse().unwrap_or(0.0)))
+}
+
+// How much of the start of the text makes up a float, with underscores between digits
+fn float_length(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        let mut end = from;
+        while end < bytes.len() && (bytes[end].is_ascii_digit() || (bytes[end] == b'_' && end > from && bytes.get(end + 1).is_some_and(u8::is_ascii_digit))) {
+            end += 1;
+        }
+        end
+    };
+    let mut end = if text.starts_with(['-', '+']) { 1 } else { 0 };
+    end = digits(end);
+    if bytes.get(end) == Some(&b'.') && bytes.get(end + 1).is_some_and(u8::is_ascii_digit) {
+        end = digits(end + 1);
+    }
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(bytes.get(end + 1), Some(b'-' | b'+')));
+        if bytes.get(end + 1 + sign).is_some_and(u8::is_ascii_digit) {
+            end = digits(end + 1 + sign);
+        }
+    }
+    end
+}
+
+fn string_to_sym(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let text = string_of(ex, receiver);
+    Ok(ex.runtime().symbol(&text))
+}
+
+// Bytes that don't make a character, and every byte past ASCII in binary strings, are `\xNN`
+fn string_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let quoted = quote_string(&rstring_of(ex, receiver));
+    Ok(ex.runtime().string(&quoted))
+}
+
+// A double quoted literal for the string, with `\xNN` for the bytes that aren't characters
+pub fn quote_string(string: &RString) -> String {
+    let mut quoted = String::from("\"");
+    let chars = string.chars();
+    for (index, c) in chars.iter().enumerate() {
+        match std::str::from_utf8(c).ok().and_then(|c| c.chars().next()) {
+            Some(c) if c.is_ascii() || string.encoding == Encoding::Utf8 => {
+                let next = chars.get(index + 1).and_then(|next| next.first()).copied();
+                escape_char(c, next.map(char::from), string.encoding == Encoding::Utf8, &mut quoted);
+            }
+            _ => c.iter().for_each(|byte| quoted.push_str(&format!("\\x{:02X}", byte))),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// A double quoted literal for the text
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        escape_char(c, chars.peek().copied(), true, &mut quoted);
+    }
+    quoted.push('"');
+    quoted
+}
+
+// `#` only needs escaping where it would start an interpolation. Other unprintable
+// characters are `\uNNNN`, or `\xNN` outside of Unicode.
+fn escape_char(c: char, next: Option<char>, unicode: bool, quoted: &mut String) {
+    match c {
+        '"' => quoted.push_str("\\\""),
+        '\\' => quoted.push_str("\\\\"),
+        '\n' => quoted.push_str("\\n"),
+        '\t' => quoted.push_str("\\t"),
+        '\r' => quoted.push_str("\\r"),
+        '\u{0c}' => quoted.push_str("\\f"),
+        '\u{0b}' => quoted.push_str("\\v"),
+        '\u{08}' => quoted.push_str("\\b"),
+        '\u{07}' => quoted.push_str("\\a"),
+        '\u{1b}' => quoted.push_str("\\e"),
+        '#' if matches!(next, Some('{' | '$' | '@')) => quoted.push_str("\\#"),
+        c if c.is_control() && unicode => quoted.push_str(&format!("\\u{:04X}", c as u32)),
+        c if c.is_control() => quoted.push_str(&format!("\\x{:02X}", c as u32)),
+        c => quoted.push(c),
+    }
+}
+
+// A regexp to search with: a regexp's own, or one matching a string literally
+fn expect_pattern(ex: &mut dyn Executor, pattern: Value) -> Result<Regex, Unwind> {
+    if let Some(regexp) = ex.runtime().regexp_value(pattern) {
+        return Ok(regexp.regex.clone());
+    }
+    let Some(text) = ex.runtime().rstring_value(pattern).map(|string| string.text().into_owned()) else {
+        let runtime = ex.runtime();
+        let class = runtime.class_name(runtime.real_class_of(pattern));
+        return Err(runtime.error("TypeError", &format!("wrong argument type {} (expected Regexp)", class)));
+    };
+    Ok(Regex::new(&regex::escape(&text)).expect("escaped text is a valid pattern"))
+}
+
+// What `[]` selects from `length` characters or elements: the one at an index, or a span
+// from `[start, length]` or a range. `None` when that's out of bounds, which is `nil`.
+enum Selection {
+    Single(usize),
+    Span(usize, usize),
+}
+
+fn selection(ex: &mut dyn Executor, args: &[Value], length: usize) -> Result<Option<Selection>, Unwind> {
+    let (start, count) = match args {
+        [start, count] => (expect_integer(ex, *start)?, expect_integer(ex, *count)?),
+        [range] => match ex.runtime().kind(*range) {
+            Some(&ObjectKind::Range(start, end, exclusive)) => {
+                let start = if start == Value::NIL { 0 } else { expect_integer(ex, start)? };
+                let end = if end == Value::NIL { -1 } else { expect_integer(ex, end)? };
+                let Some(first) = array_position(start, length) else {
+                    return Ok(None);
+                };
+                let end = if end < 0 { length as i64 + end } else { end } + if exclusive { 0 } else { 1 };
+                (start, (end - first as i64).max(0))
+            }
+            _ => {
+                let index = expect_integer(ex, *range)?;
+                return Ok(array_position(index, length).filter(|&index| index < length).map(Selection::Single));
+            }
+        },
+        _ => unreachable!("callers check for one or two arguments"),
+    };
+    Ok(match array_position(start, length) {
+        Some(start) if start <= length && count >= 0 => Some(Selection::Span(start, (count as usize).min(length - start))),
+        _ => None,
+    })
+}
+
+// `format`: `%[flags][width][.precision]type`, where the flags are `-`, `+`, ` `, `0`
+// and `#`. `%{name}` and `%<name>...` take their values from a hash argument.
+pub fn sprintf(ex: &mut dyn Executor, format: &str, args: &[Value]) -> Result<String, Unwind> {
+    let mut output = String::new();
+    let mut next_arg = 0;
+    let mut chars = format.chars().peekable();
+    let mut arg = |ex: &mut dyn Executor, name: Option<&str>| -> Result<Value, Unwind> {
+        if let Some(name) = name {
+            let hash = args.first().copied().filter(|&hash| ex.runtime().hash_value(hash).is_some());
+            let Some(hash) = hash else {
+                return Err(ex.runtime().error("ArgumentError", "one hash required"));
+            };
+            let key = ex.runtime().symbol(name);
+            let runtime = ex.runtime();
+            let hash_key = runtime.hash_key(key);
+            return match runtime.hash_value(hash).and_then(|hash| hash.get(&hash_key)) {
+                Some(value) => Ok(value),
+                None => Err(runtime.error("KeyError", &format!("key<{}> not found", name))),
+            };
+        }
+        let value = args.get(next_arg).copied();
+        next_arg += 1;
+        value.ok_or_else(|| ex.runtime().error("ArgumentError", "too few arguments"))
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            output.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            output.push('%');
+            continue;
+        }
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+            let value = arg(ex, Some(&name))?;
+            output.push_str(&to_s(ex, value)?);
+            continue;
+        }
+
+        let mut spec = Spec::default();
+        let mut name = None;
+        loop {
+            match chars.peek() {
+                Some('-') => spec.left = true,
+                Some('+') => spec.plus = true,
+                Some(' ') => spec.space = true,
+                Some('0') => spec.zero = true,
+                Some('#') => spec.alternate = true,
+                Some('<') => {
+                    chars.next();
+                    name = Some(chars.by_ref().take_while(|&c| c != '>').collect::<String>());
+                    continue;
+                }
+                _ => break,
+            }
+            chars.next();
+        }
+        spec.width = match format_number(&mut chars) {
+            Some(width) => width,
+            None => return Err(ex.runtime().error("ArgumentError", "width too big")),
+        };
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            match format_number(&mut chars) {
+                Some(precision) => spec.precision = Some(precision),
+                None => return Err(ex.runtime().error("ArgumentError", "precision too big")),
+            }
+        }
+        let Some(kind) = chars.next() else {
+            return Err(ex.runtime().error("ArgumentError", "incomplete format specifier; use %% (double %) instead"));
+        };
+        let value = arg(ex, name.as_deref())?;
+        let formatted = match kind {
+            'd' | 'i' | 'u' => spec.number(format_integer(ex, value, 10)?, ""),
+            'x' => spec.number(format_integer(ex, value, 16)?, "0x"),
+            'X' => spec.number(format_integer(ex, value, 16)?.to_uppercase(), "0X"),
+            'o' => spec.number(format_integer(ex, value, 8)?, "0"),
+            'b' => spec.number(format_integer(ex, value, 2)?, "0b"),
+            'B' => spec.number(format_integer(ex, value, 2)?, "0B"),
+            'f' | 'e' | 'E' | 'g' | 'G' => {
+                let value = match float_operand(ex.runtime(), value) {
+                    Some(value) => value,
+                    None => return Err(conversion_error(ex, value, "Float")),
+                };
+                spec.float(value, kind)
+            }
+            's' | 'p' => {
+                let text = if kind == 's' { to_s(ex, value)? } else { inspect(ex, value)? };
+                let text = match spec.precision {
+                    Some(precision) => text.chars().take(precision).collect(),
+                    None => text,
+                };
+                spec.pad(text)
+            }
+            'c' => {
+                let c = match integer_of(ex.runtime(), value) {
+                    Some(code) => u32::try_from(code).ok().and_then(char::from_u32).map(String::from),
+                    None => ex.runtime().string_value(value).and_then(|text| text.chars().next()).map(String::from),
+                };
+                match c {
+                    Some(c) => spec.pad(c),
+                    None => return Err(ex.runtime().error("ArgumentError", "invalid character")),
+                }
+            }
+            kind => return Err(ex.runtime().error("ArgumentError", &format!("malformed format string - %{}", kind))),
+        };
+        output.push_str(&formatted);
+    }
+    Ok(output)
+}
+
+// A string holding a decimal integer, with underscores and surrounding whitespace allowed
+fn parse_integer(text: &str) -> Option<BigInt> {
+    text.trim().replace('_', "").parse().ok()
+}
+
+// The digits of an integer argument in a base, with its sign
+fn format_integer(ex: &mut dyn Executor, value: Value, base: u32) -> Result<String, Unwind> {
+    let value = match ex.runtime().unpack(value) {
+        Unpacked::Float(value) => {
+            let integer = float_to_integer(ex, value)?;
+            bignum_of(ex.runtime(), integer).expect("floats convert to integers")
+        }
+        _ => match ex.runtime().string_value(value).map(str::to_string) {
+            Some(text) => match parse_integer(&text) {
+                Some(value) => value,
+                None => return Err(ex.runtime().error("ArgumentError", &format!("invalid value for Integer(): {}", quote(&text)))),
+            },
+            None => match bignum_of(ex.runtime(), value) {
+                Some(value) => value,
+                None => return Err(conversion_error(ex, value, "Integer")),
+            },
+        },
+    };
+    Ok(value.to_str_radix(base))
+}
+
+#[derive(Default)]
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+impl Spec {
+    fn pad(&self, text: String) -> String {
+        let missing = self.width.saturating_sub(text.chars().count());
+        if self.left {
+            text + &" ".repeat(missing)
+        } else {
+            " ".repeat(missing) + &text
+        }
+    }
+
+    // Digits with their sign, and the base's prefix for `#`. Zero padding goes between
+    // the sign and the digits.
+    fn number(&self, digits: String, prefix: &str) -> String {
+        let (negative, digits) = match digits.strip_prefix('-') {
+            Some(digits) => (true, digits.to_string()),
+            None => (false, digits),
+        };
+        let digits = match self.precision {
+            Some(precision) => format!("{:0>width$}", digits, width = precision),
+            None => digits,
+        };
+        let sign = if negative { "-" } else if self.plus { "+" } else if self.space { " " } else { "" };
+        let prefix = if self.alternate { prefix } else { "" };
+        self.signed(sign, prefix, digits, self.precision.is_none())
+    }
+
+    fn float(&self, value: f64, kind: char) -> String {
+        let sign = if value.is_sign_negative() && !value.is_nan() { "-" } else if self.plus { "+" } else if self.space { " " } else { "" };
+        let value = value.abs();
+        if !value.is_finite() {
+            return self.signed(sign, "", if value.is_nan() { "NaN" } else { "Inf" }.to_string(), false);
+        }
+        let precision = self.precision.unwrap_or(6);
+        let digits = match kind {
+            'f' => format!("{:.*}", precision, value),
+            'e' | 'E' => exponent_form(value, precision, kind == 'E'),
+            _ => {
+                // `%g` is `%e` for exponents below -4 or from the precision up, `%f`
+                // otherwise, with `precision` significant digits and no trailing zeros
+                let precision = precision.max(1);
+                let exponent = format!("{:.*e}", precision - 1, value).split('e').nth(1).and_then(|exponent| exponent.parse::<i32>().ok()).unwrap_or(0);
+                let digits = if exponent < -4 || exponent >= precision as i32 {
+                    exponent_form(value, precision - 1, kind == 'G')
+                } else {
+                    format!("{:.*}", (precision as i32 - 1 - exponent) as usize, value)
+                };
+                if self.alternate {
+                    digits
+                } else {
+                    strip_fraction_zeros(&digits)
+                }
+            }
+        };
+        self.signed(sign, "", digits, true)
+    }
+
+    fn signed(&self, sign: &str, prefix: &str, digits: String, zero_pad: bool) -> String {
+        let length = sign.len() + prefix.len() + digits.len();
+        if self.zero && zero_pad && !self.left && length < self.width {
+            return format!("{}{}{}{}", sign, prefix, "0".repeat(self.width - length), digits);
+        }
+        self.pad(format!("{}{}{}", sign, prefix, digits))
+    }
+}
+
+// `1.5e+02`, as C writes it: a sign and at least two digits in the exponent
+// The digits of a width or precision, or `None` past `MAX_LENGTH`
+fn format_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<usize> {
+    let mut number: usize = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number.checked_mul(10)?.checked_add(digit as usize).filter(|&number| number <= MAX_LENGTH)?;
+        chars.next();
+    }
+    Some(number)
+}
+
+fn exponent_form(value: f64, precision: usize, upper: bool) -> String {
+    let formatted = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = formatted.split_once('e').expect("formatted with an exponent");
+    let exponent: i32 = exponent.parse().expect("exponents are integers");
+    let e = if upper { 'E' } else { 'e' };
+    format!("{}{}{}{:02}", mantissa, e, if exponent < 0 { '-' } else { '+' }, exponent.abs())
+}
+
+fn strip_fraction_zeros(digits: &str) -> String {
+    let (mantissa, exponent) = match digits.find(['e', 'E']) {
+        Some(position) => digits.split_at(position),
+        None => (digits, ""),
+    };
+    let mantissa = if mantissa.contains('.') { mantissa.trim_end_matches('0').trim_end_matches('.') } else { mantissa };
+    format!("{}{}", mantissa, exponent)
+}
+
+// Regexp
+
+fn define_regexp(runtime: &mut Runtime) {
+    let regexp = runtime.classes.regexp;
+    runtime.define_singleton_native(regexp, "new", -2, regexp_new);
+    runtime.define_singleton_native(regexp, "escape", 1, regexp_escape);
+    runtime.define_native(regexp, "source", 0, regexp_source);
+    runtime.define_native(regexp, "to_s", 0, regexp_to_s);
+    runtime.define_native(regexp, "inspect", 0, regexp_inspect);
+    runtime.define_native(regexp, "==", 1, regexp_equal);
+    runtime.define_native(regexp, "eql?", 1, regexp_equal);
+    runtime.define_native(regexp, "=~", 1, regexp_match_operator);
+    runtime.define_native(regexp, "===", 1, regexp_case_equal);
+    runtime.define_native(regexp, "match?", 1, regexp_match_p);
+}
+
+fn regexp_of(ex: &mut dyn Executor, receiver: Value) -> RRegexp {
+    ex.runtime().regexp_value(receiver).cloned().expect("Regexp methods are only called on regexps")
+}
+
+// `Regexp.new(source)`, with options as a string (`"mi"`), the `IGNORECASE`... flags, or
+// anything truthy for `i`
+fn regexp_new(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    if let Some(regexp) = ex.runtime().regexp_value(args[0]).cloned() {
+        return Ok(ex.runtime().regexp(regexp));
+    }
+    let source = expect_string(ex, args[0])?;
+    let options = match args.get(1).copied() {
+        None | Some(Value::NIL | Value::FALSE) => String::new(),
+        Some(options) => match (ex.runtime().unpack(options), ex.runtime().string_value(options)) {
+            (Unpacked::Integer(flags), _) => {
+                [(1, 'i'), (2, 'x'), (4, 'm')].iter().filter(|(flag, _)| flags & flag != 0).map(|(_, option)| option).collect()
+            }
+            (_, Some(options)) => options.to_string(),
+            (_, None) => "i".to_string(),
+        },
+    };
+    ex.runtime().new_regexp(&source, &options)
+}
+
+fn regexp_escape(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let text = expect_string(ex, args[0])?;
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\r' => escaped.push_str("\\r"),
+            ' ' => escaped.push_str("\\ "),
+            c if ".*?+^$|()[]{}\\/-".contains(c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    Ok(ex.runtime().string(&escaped))
+}
+
+fn regexp_source(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let source = regexp_of(ex, receiver).source;
+    Ok(ex.runtime().string(&source))
+}
+
+// `(?mi-x:source)`, which embeds in another pattern with the same meaning
+fn regexp_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let regexp = regexp_of(ex, receiver);
+    let off: String = "mix".chars().filter(|&option| !regexp.options.contains(option)).collect();
+    let text = format!("(?{}{}{}:{})", regexp.options, if off.is_empty() { "" } else { "-" }, off, regexp.source);
+    Ok(ex.runtime().string(&text))
+}
+
+fn regexp_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let regexp = regexp_of(ex, receiver);
+    let text = format!("/{}/{}", regexp.source.replace('/', "\\/"), regexp.options);
+    Ok(ex.runtime().string(&text))
+}
+
+fn regexp_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(Value::from_bool(match (runtime.regexp_value(receiver), runtime.regexp_value(args[0])) {
+        (Some(left), Some(right)) => left.source == right.source && left.options == right.options,
+        _ => false,
+    }))
+}
+
+// The character index of the first match, `nil` without one (or for `nil`)
+fn regexp_match_operator(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args[0] == Value::NIL {
+        return Ok(Value::NIL);
+    }
+    let string = expect_rstring(ex, args[0])?;
+    Ok(match regexp_of(ex, receiver).regex.find(&string.bytes) {
+        Some(found) => ex.runtime().int(string.char_index(found.start()) as i64),
+        None => Value::NIL,
+    })
+}
+
+// For `case`, where anything but a string (or symbol) just doesn't match
+fn regexp_case_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let text = match args[0].as_symbol() {
+        Some(symbol) => ex.runtime().symbol_name(symbol).as_bytes().to_vec(),
+        None => match ex.runtime().rstring_value(args[0]) {
+            Some(string) => string.bytes.clone(),
+            None => return Ok(Value::FALSE),
+        },
+    };
+    Ok(Value::from_bool(regexp_of(ex, receiver).regex.is_match(&text)))
+}
+
+fn regexp_match_p(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args[0] == Value::NIL {
+        return Ok(Value::FALSE);
+    }
+    let string = expect_rstring(ex, args[0])?;
+    Ok(Value::from_bool(regexp_of(ex, receiver).regex.is_match(&string.bytes)))
+}
+
+// Encoding
+
+fn define_encoding(runtime: &mut Runtime) {
+    let class = runtime.classes.encoding;
+    for encoding in [Encoding::Utf8, Encoding::Binary] {
+        let value = runtime.alloc(class, ObjectKind::Encoding(encoding));
+        runtime.object_mut(value.object_id().expect("just allocated")).frozen = true;
+        runtime.set_constant(class, encoding.constant(), value);
+    }
+    runtime.set_constant(class, "BINARY", runtime.encoding(Encoding::Binary));
+    let encoding_error = runtime.constant(runtime.classes.object, "EncodingError").and_then(Value::object_id).expect("EncodingError is defined");
+    runtime.define_class("CompatibilityError", encoding_error, Some(class), Allocator::Exception);
+    runtime.define_singleton_native(class, "find", 1, encoding_find);
+    runtime.define_native(class, "name", 0, encoding_name);
+    runtime.define_native(class, "to_s", 0, encoding_name);
+    runtime.define_native(class, "inspect", 0, encoding_inspect);
+}
+
+fn encoding_of(ex: &mut dyn Executor, receiver: Value) -> Encoding {
+    match ex.runtime().kind(receiver) {
+        Some(&ObjectKind::Encoding(encoding)) => encoding,
+        _ => unreachable!("Encoding methods are only called on encodings"),
+    }
+}
+
+// An encoding, or its name
+fn expect_encoding(ex: &mut dyn Executor, value: Value) -> Result<Encoding, Unwind> {
+    if let Some(&ObjectKind::Encoding(encoding)) = ex.runtime().kind(value) {
+        return Ok(encoding);
+    }
+    let name = expect_string(ex, value)?;
+    match Encoding::find(&name) {
+        Some(encoding) => Ok(encoding),
+        None => Err(ex.runtime().error("ArgumentError", &format!("unknown encoding name - {}", name))),
+    }
+}
+
+fn encoding_find(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let encoding = expect_encoding(ex, args[0])?;
+    Ok(ex.runtime().encoding(encoding))
+}
+
+fn encoding_name(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let name = encoding_of(ex, receiver).name();
+    Ok(ex.runtime().string(name))
+}
+
+fn encoding_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let text = format!("#<Encoding:{}>", encoding_of(ex, receiver).name());
+    Ok(ex.runtime().string(&text))
+}
+
+// Symbol
+
+fn define_symbol(runtime: &mut Runtime) {
+    let symbol = runtime.classes.symbol;
+    runtime.define_native(symbol, "to_s", 0, symbol_to_s);
+    runtime.define_native(symbol, "id2name", 0, symbol_to_s);
+    runtime.define_native(symbol, "name", 0, symbol_to_s);
+    runtime.define_native(symbol, "to_sym", 0, object_itself);
+    runtime.define_native(symbol, "to_proc", 0, symbol_to_proc);
+    runtime.define_native(symbol, "length", 0, symbol_length);
+    runtime.define_native(symbol, "size", 0, symbol_length);
+    runtime.define_native(symbol, "<=>", 1, symbol_compare);
+    runtime.define_native(symbol, "inspect", 0, symbol_inspect);
+}
+
+fn symbol_name(ex: &mut dyn Executor, receiver: Value) -> String {
+    match receiver.as_symbol() {
+        Some(symbol) => ex.runtime().symbol_name(symbol).to_string(),
+        None => unreachable!("Symbol methods are only called on symbols"),
+    }
+}
+
+fn symbol_to_s(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let name = symbol_name(ex, receiver);
+    Ok(ex.runtime().string(&name))
+}
+
+fn symbol_to_proc(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let name = symbol_name(ex, receiver);
+    Ok(ex.runtime().proc(ProcBody::Symbol(name), true))
+}
+
+fn symbol_length(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let length = symbol_name(ex, receiver).chars().count();
+    Ok(ex.runtime().int(length as i64))
+}
+
+fn symbol_compare(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args[0].as_symbol().is_none() {
+        return Ok(Value::NIL);
+    }
+    let order = symbol_name(ex, receiver).cmp(&symbol_name(ex, args[0]));
+    Ok(ex.runtime().int(order as i64))
+}
+
+fn symbol_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let inspect = inspect_symbol(&symbol_name(ex, receiver));
+    Ok(ex.runtime().string(&inspect))
+}
+
+// Variable names like `:@x` keep their sigil unquoted, as do operators and method names
+pub fn inspect_symbol(name: &str) -> String {
+    let bare = name.trim_start_matches('@');
+    let identifier = bare.trim_end_matches(['?', '!', '=']);
+    let plain = OPERATOR_METHODS.contains(&name)
+        || (!identifier.is_empty()
+            && !identifier.starts_with(|c: char| c.is_ascii_digit())
+            && identifier.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && bare.len() - identifier.len() <= 1
+            && (bare == name || bare.len() == identifier.len()));
+    if plain {
+        format!(":{}", name)
+    } else {
+        format!(":{}", quote(name))
+    }
+}
+
+// Enumerable
+
+// Methods for anything with an `each`. They visit what it yields as it's yielded, so
+// blocks run in step with it, and the ones that can stop early do, even when `each`
+// never ends.
+fn define_enumerable(runtime: &mut Runtime) {
+    let enumerable = runtime.define_module("Enumerable", None);
+    runtime.define_native(enumerable, "to_a", 0, enumerable_to_a);
+    runtime.define_native(enumerable, "entries", 0, enumerable_to_a);
+    runtime.define_native(enumerable, "map", 0, enumerable_map);
+    runtime.define_native(enumerable, "collect", 0, enumerable_map);
+    runtime.define_native(enumerable, "flat_map", 0, enumerable_flat_map);
+    runtime.define_native(enumerable, "select", 0, enumerable_select);
+    runtime.define_native(enumerable, "filter", 0, enumerable_select);
+    runtime.define_native(enumerable, "reject", 0, enumerable_reject);
+    runtime.define_native(enumerable, "partition", 0, enumerable_partition);
+    runtime.define_native(enumerable, "find", 0, enumerable_find);
+    runtime.define_native(enumerable, "detect", 0, enumerable_find);
+    runtime.define_native(enumerable, "reduce", -1, enumerable_reduce);
+    runtime.define_native(enumerable, "inject", -1, enumerable_reduce);
+    runtime.define_native(enumerable, "sum", -1, enumerable_sum);
+    runtime.define_native(enumerable, "count", -1, enumerable_count);
+    runtime.define_native(enumerable, "sort", 0, enumerable_sort);
+    runtime.define_native(enumerable, "sort_by", 0, enumerable_sort_by);
+    runtime.define_native(enumerable, "min", 0, enumerable_min);
+    runtime.define_native(enumerable, "max", 0, enumerable_max);
+    runtime.define_native(enumerable, "min_by", 0, enumerable_min_by);
+    runtime.define_native(enumerable, "max_by", 0, enumerable_max_by);
+    runtime.define_native(enumerable, "group_by", 0, enumerable_group_by);
+    runtime.define_native(enumerable, "each_with_index", 0, enumerable_each_with_index);
+    runtime.define_native(enumerable, "each_with_object", 1, enumerable_each_with_object);
+    runtime.define_native(enumerable, "zip", -1, enumerable_zip);
+    runtime.define_native(enumerable, "include?", 1, enumerable_include);
+    runtime.define_native(enumerable, "member?", 1, enumerable_include);
+    runtime.define_native(enumerable, "first", -1, enumerable_first);
+    runtime.define_native(enumerable, "take_while", 0, enumerable_take_while);
+    runtime.define_native(enumerable, "any?", -1, enumerable_any);
+    runtime.define_native(enumerable, "all?", -1, enumerable_all);
+    runtime.define_native(enumerable, "none?", -1, enumerable_none);
+    runtime.define_native(enumerable, "to_h", 0, enumerable_to_h);
+    for class in [runtime.classes.array, runtime.classes.hash, runtime.classes.range] {
+        runtime.include_module(class, enumerable).expect("Enumerable is a module");
+    }
+}
+
+// The slots of the array an enumeration keeps its state in: the method's block, its
+// argument, whether it was given one, and then what it has gathered so far
+const BLOCK: usize = 0;
+const ARGUMENT: usize = 1;
+const GIVEN: usize = 2;
+const GATHERED: usize = 3;
+
+fn enumeration(ex: &mut dyn Executor, block: Option<Value>, argument: Option<Value>) -> Value {
+    let slots = vec![block.unwrap_or(Value::NIL), argument.unwrap_or(Value::NIL), Value::from_bool(argument.is_some())];
+    ex.runtime().array(slots)
+}
+
+fn slot(ex: &mut dyn Executor, state: Value, index: usize) -> Value {
+    ex.runtime().array_value(state).map_or(Value::NIL, |slots| slots[index])
+}
+
+fn set_slot(ex: &mut dyn Executor, state: Value, index: usize, value: Value) {
+    let id = state.object_id().expect("arrays are objects");
+    if let ObjectKind::Array(slots) = &mut ex.runtime().object_mut(id).kind {
+        slots[index] = value;
+    }
+}
+
+fn gather(ex: &mut dyn Executor, state: Value, values: &[Value]) {
+    let id = state.object_id().expect("arrays are objects");
+    if let ObjectKind::Array(slots) = &mut ex.runtime().object_mut(id).kind {
+        slots.extend_from_slice(values);
+    }
+}
+
+// What's been gathered, rooted like `elements_of`
+fn gathered(ex: &mut dyn Executor, state: Value) -> Vec<Value> {
+    elements_of(ex, state).split_off(GATHERED)
+}
+
+// Runs `step` with the state on each value `each` yields, as it's yielded. Arrays skip
+// the call and use their elements directly. A step ends the iteration early by
+// returning `stop`, whose value is given back.
+fn visit(ex: &mut dyn Executor, receiver: Value, state: Value, step: BlockFn) -> Result<Option<Value>, Unwind> {
+    let visited = if ex.runtime().array_value(receiver).is_some() {
+        elements_of(ex, receiver).into_iter().try_for_each(|element| step(ex, state, &[element]).map(drop))
+    } else {
+        let block = ex.runtime().proc(ProcBody::Native(step, state), false);
+        ex.send(receiver, "each", &[], Some(block)).map(drop)
+    };
+    match visited {
+        Ok(()) => Ok(None),
+        Err(Unwind::Break(value, tag)) if tag.is_some() && tag == state.object_id() => Ok(Some(value)),
+        Err(unwind) => Err(unwind),
+    }
+}
+
+// A break to the `visit` of this state, like a block's to its call
+fn stop(state: Value, value: Value) -> Unwind {
+    Unwind::Break(value, state.object_id())
+}
+
+// One yielded value as it is, and several as an array of them
+fn yielded(ex: &mut dyn Executor, args: &[Value]) -> Value {
+    match args {
+        [value] => *value,
+        args => ex.runtime().array(args.to_vec()),
+    }
+}
+
+fn gather_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    gather(ex, state, &[element]);
+    Ok(Value::NIL)
+}
+
+// Gathers each element followed by the block's value for it
+fn keyed_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    gather(ex, state, &[element]);
+    let block = slot(ex, state, BLOCK);
+    let key = ex.call_proc(block, args)?;
+    gather(ex, state, &[key]);
+    Ok(Value::NIL)
+}
+
+// What `each` yields
+fn entries(ex: &mut dyn Executor, receiver: Value) -> Result<Vec<Value>, Unwind> {
+    if ex.runtime().array_value(receiver).is_some() {
+        return Ok(elements_of(ex, receiver));
+    }
+    let state = enumeration(ex, None, None);
+    visit(ex, receiver, state, gather_step)?;
+    Ok(gathered(ex, state))
+}
+
+// Calls the block, rooting what it returns, since only the caller holds it
+fn call_rooted(ex: &mut dyn Executor, block: Value, args: &[Value]) -> NativeResult {
+    let value = ex.call_proc(block, args)?;
+    ex.runtime().heap.root(value);
+    Ok(value)
+}
+
+// Each element with the block's value for it, calling the block as they're yielded
+fn keyed(ex: &mut dyn Executor, receiver: Value, block: Option<Value>) -> Result<Vec<(Value, Value)>, Unwind> {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let state = enumeration(ex, Some(block), None);
+    visit(ex, receiver, state, keyed_step)?;
+    Ok(gathered(ex, state).chunks(2).map(|pair| (pair[1], pair[0])).collect())
+}
+
+fn enumerable_to_a(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let elements = entries(ex, receiver)?;
+    Ok(ex.runtime().array(elements))
+}
+
+fn enumerable_map(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let mapped = keyed(ex, receiver, block)?.into_iter().map(|(key, _)| key).collect();
+    Ok(ex.runtime().array(mapped))
+}
+
+// Arrays the block returns are spread into the result, anything else is added as it is
+fn enumerable_flat_map(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let mut mapped = Vec::new();
+    for (value, _) in keyed(ex, receiver, block)? {
+        match ex.runtime().array_value(value) {
+            Some(elements) => mapped.extend_from_slice(elements),
+            None => mapped.push(value),
+        }
+    }
+    Ok(ex.runtime().array(mapped))
+}
+
+// The elements the block is truthy for, and the ones it isn't
+fn partition(ex: &mut dyn Executor, receiver: Value, block: Option<Value>) -> Result<(Vec<Value>, Vec<Value>), Unwind> {
+    let (selected, rejected): (Vec<_>, Vec<_>) = keyed(ex, receiver, block)?.into_iter().partition(|(key, _)| key.truthy());
+    let elements = |pairs: Vec<(Value, Value)>| pairs.into_iter().map(|(_, element)| element).collect();
+    Ok((elements(selected), elements(rejected)))
+}
+
+fn enumerable_select(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let (selected, _) = partition(ex, receiver, block)?;
+    Ok(ex.runtime().array(selected))
+}
+
+fn enumerable_reject(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let (_, rejected) = partition(ex, receiver, block)?;
+    Ok(ex.runtime().array(rejected))
+}
+
+fn enumerable_partition(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let (selected, rejected) = partition(ex, receiver, block)?;
+    let runtime = ex.runtime();
+    let pair = vec![runtime.array(selected), runtime.array(rejected)];
+    Ok(runtime.array(pair))
+}
+
+fn find_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let block = slot(ex, state, BLOCK);
+    if ex.call_proc(block, args)?.truthy() {
+        let element = yielded(ex, args);
+        return Err(stop(state, element));
+    }
+    Ok(Value::NIL)
+}
+
+fn enumerable_find(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let state = enumeration(ex, Some(block), None);
+    Ok(visit(ex, receiver, state, find_step)?.unwrap_or(Value::NIL))
+}
+
+// Folds each element into the memo, which is the first gathered value. Without a block
+// the argument names the method to fold with.
+fn reduce_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    let Some(&memo) = gathered(ex, state).first() else {
+        gather(ex, state, &[element]);
+        return Ok(Value::NIL);
+    };
+    let memo = match slot(ex, state, BLOCK) {
+        Value::NIL => {
+            let operator = slot(ex, state, ARGUMENT);
+            let operator = expect_name(ex, operator)?;
+            ex.send(memo, &operator, &[element], None)?
+        }
+        block => ex.call_proc(block, &[memo, element])?,
+    };
+    set_slot(ex, state, GATHERED, memo);
+    Ok(Value::NIL)
+}
+
+// `reduce(initial) { |memo, element| }`, or with a method name instead of the block.
+// Without an initial value the first element is the start, and nothing gives `nil`.
+fn enumerable_reduce(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let (initial, operator) = match (args, block) {
+        ([], _) => (None, None),
+        ([operator], None) => (None, Some(*operator)),
+        ([initial], Some(_)) => (Some(*initial), None),
+        ([initial, operator], _) => (Some(*initial), Some(*operator)),
+        _ => return Err(argument_count_error(ex, args.len(), "0..2")),
+    };
+    let state = match operator {
+        Some(operator) => {
+            expect_name(ex, operator)?;
+            enumeration(ex, None, Some(operator))
+        }
+        None => {
+            let block = require_block(ex, block, "no block given (yield)")?;
+            enumeration(ex, Some(block), None)
+        }
+    };
+    if let Some(initial) = initial {
+        gather(ex, state, &[initial]);
+    }
+    visit(ex, receiver, state, reduce_step)?;
+    Ok(gathered(ex, state).first().copied().unwrap_or(Value::NIL))
+}
+
+fn enumerable_sum(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let values = match block {
+        Some(_) => keyed(ex, receiver, block)?.into_iter().map(|(key, _)| key).collect(),
+        None => entries(ex, receiver)?,
+    };
+    let mut sum = match args.first() {
+        Some(&sum) => sum,
+        None => ex.runtime().int(0),
+    };
+    for value in values {
+        sum = ex.send(sum, "+", &[value], None)?;
+        ex.runtime().heap.root(sum);
+    }
+    Ok(sum)
+}
+
+// All the elements, the ones equal to the argument, or the ones the block is truthy for
+fn enumerable_count(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let count = match (args, block) {
+        ([], Some(_)) => keyed(ex, receiver, block)?.iter().filter(|(key, _)| key.truthy()).count(),
+        ([], None) => entries(ex, receiver)?.len(),
+        ([value], _) => {
+            let mut count = 0;
+            for element in entries(ex, receiver)? {
+                count += equal(ex, element, *value)? as usize;
+            }
+            count
+        }
+        _ => return Err(argument_count_error(ex, args.len(), "0..1")),
+    };
+    Ok(ex.runtime().int(count as i64))
+}
+
+// Orders the values by their keys, with `<=>` or the block. The sort is stable, and
+// the first failed comparison is raised once it's done.
+fn sort_by_keys(ex: &mut dyn Executor, pairs: &mut [(Value, Value)], block: Option<Value>) -> Result<(), Unwind> {
+    let mut failure = None;
+    pairs.sort_by(|&(left, _), &(right, _)| {
+        if failure.is_some() {
+            return Ordering::Equal;
+        }
+        let order = match block {
+            Some(block) => ex.call_proc(block, &[left, right]).map(|order| integer_of(ex.runtime(), order)),
+            None => compare(ex, left, right),
+        };
+        match order {
+            Ok(Some(order)) => order.cmp(&0),
+            Ok(None) => {
+                failure = Some(comparison_error(ex, left, right));
+                Ordering::Equal
+            }
+            Err(error) => {
+                failure = Some(error);
+                Ordering::Equal
+            }
+        }
+    });
+    failure.map_or(Ok(()), Err)
+}
+
+fn enumerable_sort(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let mut pairs: Vec<_> = entries(ex, receiver)?.into_iter().map(|element| (element, element)).collect();
+    sort_by_keys(ex, &mut pairs, block)?;
+    let sorted = pairs.into_iter().map(|(_, element)| element).collect();
+    Ok(ex.runtime().array(sorted))
+}
+
+fn enumerable_sort_by(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let mut pairs = keyed(ex, receiver, block)?;
+    sort_by_keys(ex, &mut pairs, None)?;
+    let sorted = pairs.into_iter().map(|(_, element)| element).collect();
+    Ok(ex.runtime().array(sorted))
+}
+
+// The element whose key sorts first, or last for `max`, with the earliest one winning ties
+fn extreme(ex: &mut dyn Executor, pairs: Vec<(Value, Value)>, max: bool) -> NativeResult {
+    let mut best: Option<(Value, Value)> = None;
+    for (key, element) in pairs {
+        let better = match best {
+            None => true,
+            Some((best_key, _)) => match compare(ex, key, best_key)? {
+                Some(order) => if max { order > 0 } else { order < 0 },
+                None => return Err(comparison_error(ex, key, best_key)),
+            },
+        };
+        if better {
+            best = Some((key, element));
+        }
+    }
+    Ok(best.map_or(Value::NIL, |(_, element)| element))
+}
+
+fn enumerable_min(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let pairs = entries(ex, receiver)?.into_iter().map(|element| (element, element)).collect();
+    extreme(ex, pairs, false)
+}
+
+fn enumerable_max(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let pairs = entries(ex, receiver)?.into_iter().map(|element| (element, element)).collect();
+    extreme(ex, pairs, true)
+}
+
+fn enumerable_min_by(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let pairs = keyed(ex, receiver, block)?;
+    extreme(ex, pairs, false)
+}
+
+fn enumerable_max_by(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let pairs = keyed(ex, receiver, block)?;
+    extreme(ex, pairs, true)
+}
+
+// A hash from the block's values to arrays of the elements that gave them, in order
+fn enumerable_group_by(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let groups = ex.runtime().hash(RHash::default());
+    for (key, element) in keyed(ex, receiver, block)? {
+        let hash_key = ex.runtime().hash_key(key);
+        match ex.runtime().hash_value(groups).and_then(|hash| hash.get(&hash_key)) {
+            Some(group) => array_push(ex, group, &[element], None)?,
+            None => {
+                let group = ex.runtime().array(vec![element]);
+                hash_set_index(ex, groups, &[key, group], None)?
+            }
+        };
+    }
+    Ok(groups)
+}
+
+fn each_with_index_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    let index = slot(ex, state, ARGUMENT);
+    if let Some(index) = integer_of(ex.runtime(), index) {
+        let next = ex.runtime().int(index + 1);
+        set_slot(ex, state, ARGUMENT, next);
+    }
+    let block = slot(ex, state, BLOCK);
+    ex.call_proc(block, &[element, index])
+}
+
+fn enumerable_each_with_index(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let index = ex.runtime().int(0);
+    let state = enumeration(ex, Some(block), Some(index));
+    visit(ex, receiver, state, each_with_index_step)?;
+    Ok(receiver)
+}
+
+fn each_with_object_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    let (block, object) = (slot(ex, state, BLOCK), slot(ex, state, ARGUMENT));
+    ex.call_proc(block, &[element, object])
+}
+
+fn enumerable_each_with_object(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let state = enumeration(ex, Some(block), Some(args[0]));
+    visit(ex, receiver, state, each_with_object_step)?;
+    Ok(args[0])
+}
+
+// Gathers each element with the ones at the same place in the arrays of the argument,
+// and yields them to the block if there is one
+fn zip_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    let index = gathered(ex, state).len();
+    let others = slot(ex, state, ARGUMENT);
+    let mut tuple = vec![element];
+    for other in elements_of(ex, others) {
+        let runtime = ex.runtime();
+        tuple.push(runtime.array_value(other).and_then(|other| other.get(index)).copied().unwrap_or(Value::NIL));
+    }
+    let tuple = ex.runtime().array(tuple);
+    gather(ex, state, &[tuple]);
+    match slot(ex, state, BLOCK) {
+        Value::NIL => Ok(Value::NIL),
+        block => ex.call_proc(block, &[tuple]),
+    }
+}
+
+// Arrays of each element with the ones at the same place in the arguments, padded with
+// `nil`. With a block they're yielded instead, and the result is `nil`.
+fn enumerable_zip(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let mut others = Vec::new();
+    for &other in args {
+        let runtime = ex.runtime();
+        if runtime.find_method(runtime.class_of(other), "each").is_none() {
+            let class = runtime.class_name(runtime.real_class_of(other));
+            return Err(runtime.error("TypeError", &format!("wrong argument type {} (must respond to :each)", class)));
+        }
+        let elements = entries(ex, other)?;
+        others.push(ex.runtime().array(elements));
+    }
+    let others = ex.runtime().array(others);
+    let state = enumeration(ex, block, Some(others));
+    visit(ex, receiver, state, zip_step)?;
+    let zipped = gathered(ex, state);
+    Ok(if block.is_some() { Value::NIL } else { ex.runtime().array(zipped) })
+}
+
+fn include_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    let value = slot(ex, state, ARGUMENT);
+    if equal(ex, element, value)? {
+        return Err(stop(state, Value::TRUE));
+    }
+    Ok(Value::NIL)
+}
+
+fn enumerable_include(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let state = enumeration(ex, None, Some(args[0]));
+    Ok(visit(ex, receiver, state, include_step)?.unwrap_or(Value::FALSE))
+}
+
+fn first_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let element = yielded(ex, args);
+    Err(stop(state, element))
+}
+
+// Gathers elements until there are as many as the argument
+fn take_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    gather_step(ex, state, args)?;
+    let count = gathered(ex, state).len() as i64;
+    let limit = slot(ex, state, ARGUMENT);
+    if integer_of(ex.runtime(), limit) == Some(count) {
+        return Err(stop(state, Value::NIL));
+    }
+    Ok(Value::NIL)
+}
+
+// The first element, or an array of the first `n`. Only as many as that are yielded.
+fn enumerable_first(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    match args {
+        [] => {
+            let state = enumeration(ex, None, None);
+            Ok(visit(ex, receiver, state, first_step)?.unwrap_or(Value::NIL))
+        }
+        [count] => {
+            let count = expect_integer(ex, *count)?;
+            if count < 0 {
+                return Err(ex.runtime().error("ArgumentError", "attempt to take negative size"));
+            }
+            let limit = ex.runtime().int(count);
+            let state = enumeration(ex, None, Some(limit));
+            if count > 0 {
+                visit(ex, receiver, state, take_step)?;
+            }
+            let taken = gathered(ex, state);
+            Ok(ex.runtime().array(taken))
+        }
+        _ => Err(argument_count_error(ex, args.len(), "0..1")),
+    }
+}
+
+// Gathers elements until the block is falsy for one
+fn take_while_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    let block = slot(ex, state, BLOCK);
+    if !ex.call_proc(block, args)?.truthy() {
+        return Err(stop(state, Value::NIL));
+    }
+    gather_step(ex, state, args)
+}
+
+fn enumerable_take_while(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let state = enumeration(ex, Some(block), None);
+    visit(ex, receiver, state, take_while_step)?;
+    let taken = gathered(ex, state);
+    Ok(ex.runtime().array(taken))
+}
+
+// Whether an element passes: `pattern === element` with an argument, the block's
+// value with a block, and the element itself otherwise
+fn passes(ex: &mut dyn Executor, state: Value, args: &[Value]) -> Result<bool, Unwind> {
+    let element = yielded(ex, args);
+    if slot(ex, state, GIVEN).truthy() {
+        let pattern = slot(ex, state, ARGUMENT);
+        return Ok(ex.send(pattern, "===", &[element], None)?.truthy());
+    }
+    match slot(ex, state, BLOCK) {
+        Value::NIL => Ok(element.truthy()),
+        block => Ok(ex.call_proc(block, args)?.truthy()),
+    }
+}
+
+fn any_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    match passes(ex, state, args)? {
+        true => Err(stop(state, Value::TRUE)),
+        false => Ok(Value::NIL),
+    }
+}
+
+fn all_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    match passes(ex, state, args)? {
+        true => Ok(Value::NIL),
+        false => Err(stop(state, Value::FALSE)),
+    }
+}
+
+fn none_step(ex: &mut dyn Executor, state: Value, args: &[Value]) -> NativeResult {
+    match passes(ex, state, args)? {
+        true => Err(stop(state, Value::FALSE)),
+        false => Ok(Value::NIL),
+    }
+}
+
+// Runs a predicate's step until it decides, which is `otherwise` if it never does
+fn predicate(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>, step: BlockFn, otherwise: bool) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let state = enumeration(ex, block, args.first().copied());
+    Ok(visit(ex, receiver, state, step)?.unwrap_or(Value::from_bool(otherwise)))
+}
+
+fn enumerable_any(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    predicate(ex, receiver, args, block, any_step, false)
+}
+
+fn enumerable_all(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    predicate(ex, receiver, args, block, all_step, true)
+}
+
+fn enumerable_none(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    predicate(ex, receiver, args, block, none_step, true)
+}
+
+// A hash from `[key, value]` pairs
+fn enumerable_to_h(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let hash = ex.runtime().hash(RHash::default());
+    for (index, element) in entries(ex, receiver)?.into_iter().enumerate() {
+        match ex.runtime().array_value(element).cloned().as_deref() {
+            Some(&[key, value]) => hash_set_index(ex, hash, &[key, value], None)?,
+            Some(pair) => {
+                let message = format!("wrong array length at {} (expected 2, was {})", index, pair.len());
+                return Err(ex.runtime().error("ArgumentError", &message));
+            }
+            None => {
+                let runtime = ex.runtime();
+                let class = runtime.class_name(runtime.real_class_of(element));
+                let message = format!("wrong element type {} at {} (expected array)", class, index);
+                return Err(runtime.error("TypeError", &message));
+            }
+        };
+    }
+    Ok(hash)
+}
+
+// Array
+
+fn define_array(runtime: &mut Runtime) {
+    let array = runtime.classes.array;
+    runtime.define_native(array, "initialize", -1, array_initialize);
+    runtime.define_native(array, "[]", -2, array_index);
+    runtime.define_native(array, "slice", -2, array_index);
+    runtime.define_native(array, "[]=", -3, array_set_index);
+    runtime.define_native(array, "<<", 1, array_push);
+    runtime.define_native(array, "push", -1, array_push);
+    runtime.define_native(array, "unshift", -1, array_unshift);
+    runtime.define_native(array, "pop", -1, array_pop);
+    runtime.define_native(array, "shift", -1, array_shift);
+    runtime.define_native(array, "length", 0, array_length);
+    runtime.define_native(array, "size", 0, array_length);
+    runtime.define_native(array, "empty?", 0, array_empty);
+    runtime.define_native(array, "last", -1, array_last);
+    runtime.define_native(array, "fetch", -2, array_fetch);
+    runtime.define_native(array, "dig", -2, dig);
+    runtime.define_native(array, "each", 0, array_each);
+    runtime.define_native(array, "flatten", -1, array_flatten);
+    runtime.define_native(array, "compact", 0, array_compact);
+    runtime.define_native(array, "reverse", 0, array_reverse);
+    runtime.define_native(array, "join", -1, array_join);
+    runtime.define_native(array, "+", 1, array_concat);
+    runtime.define_native(array, "|", 1, array_union);
+    runtime.define_native(array, "==", 1, array_equal);
+    runtime.define_native(array, "to_a", 0, object_itself);
+    runtime.define_native(array, "inspect", 0, array_inspect);
+    runtime.define_native(array, "to_s", 0, array_inspect);
+}
+
+// A copy, rooted in case the array loses its elements while they're visited
+pub fn elements_of(ex: &mut dyn Executor, receiver: Value) -> Vec<Value> {
+    let runtime = ex.runtime();
+    let elements = runtime.array_value(receiver).cloned().unwrap_or_default();
+    for &element in &elements {
+        runtime.heap.root(element);
+    }
+    elements
+}
+
+fn modify_array<T>(ex: &mut dyn Executor, receiver: Value, change: impl FnOnce(&mut Vec<Value>) -> T) -> Result<T, Unwind> {
+    check_frozen(ex, receiver)?;
+    let id = receiver.object_id().expect("arrays are objects");
+    match &mut ex.runtime().object_mut(id).kind {
+        ObjectKind::Array(elements) => Ok(change(elements)),
+        _ => unreachable!("Array methods are only called on arrays"),
+    }
+}
+
+fn array_initialize(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let (size, default) = match args {
+        [] => (0, Value::NIL),
+        [size] => (expect_integer(ex, *size)?, Value::NIL),
+        [size, default] => (expect_integer(ex, *size)?, *default),
+        _ => return Err(argument_count_error(ex, args.len(), "0..2")),
+    };
+    if size < 0 {
+        return Err(ex.runtime().error("ArgumentError", "negative array size"));
+    }
+    if size > MAX_LENGTH as i64 {
+        return Err(ex.runtime().error("ArgumentError", "array size too big"));
+    }
+    modify_array(ex, receiver, |elements| *elements = vec![default; size as usize])?;
+    Ok(Value::NIL)
+}
+
+// Negative indexes count from the end
+fn array_position(index: i64, length: usize) -> Option<usize> {
+    let position = if index < 0 { length as i64 + index } else { index };
+    (position >= 0).then_some(position as usize)
+}
+
+// An index, `start, length` or a range, as with strings
+fn array_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let elements = elements_of(ex, receiver);
+    Ok(match selection(ex, args, elements.len())? {
+        Some(Selection::Single(index)) => elements[index],
+        Some(Selection::Span(start, length)) => ex.runtime().array(elements[start..start + length].to_vec()),
+        None => Value::NIL,
+    })
+}
+
+// Assigning to `start, length` or a range replaces those elements with the value's, or
+// with the value itself when it isn't an array. Past the end the array is padded with `nil`.
+fn array_set_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let length = elements_of(ex, receiver).len();
+    let (start, count, value) = match args {
+        [index, value] => match ex.runtime().kind(*index) {
+            Some(&ObjectKind::Range(start, end, exclusive)) => {
+                let start = if start == Value::NIL { 0 } else { expect_integer(ex, start)? };
+                let end = if end == Value::NIL { -1 } else { expect_integer(ex, end)? };
+                let first = if start < 0 { length as i64 + start } else { start };
+                let end = if end < 0 { length as i64 + end } else { end } + if exclusive { 0 } else { 1 };
+                (start, Some((end - first).max(0)), *value)
+            }
+            _ => (expect_integer(ex, *index)?, None, *value),
+        },
+        [start, count, value] => (expect_integer(ex, *start)?, Some(expect_integer(ex, *count)?), *value),
+        _ => return Err(argument_count_error(ex, args.len(), "2..3")),
+    };
+    let Some(position) = array_position(start, length) else {
+        let message = format!("index {} too small for array; minimum: -{}", start, length);
+        return Err(ex.runtime().error("IndexError", &message));
+    };
+    let Some(count) = count else {
+        modify_array(ex, receiver, |elements| {
+            if position >= elements.len() {
+                elements.resize(position + 1, Value::NIL);
+            }
+            elements[position] = value;
+        })?;
+        return Ok(value);
+    };
+    if count < 0 {
+        return Err(ex.runtime().error("IndexError", &format!("negative length ({})", count)));
+    }
+    let replacement = ex.runtime().array_value(value).cloned().unwrap_or_else(|| vec![value]);
+    modify_array(ex, receiver, |elements| {
+        if position > elements.len() {
+            elements.resize(position, Value::NIL);
+        }
+        let end = (position + count as usize).min(elements.len());
+        elements.splice(position..end, replacement);
+    })?;
+    Ok(value)
+}
+
+fn array_push(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    modify_array(ex, receiver, |elements| elements.extend_from_slice(args))?;
+    Ok(receiver)
+}
+
+fn array_unshift(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    modify_array(ex, receiver, |elements| {
+        elements.splice(0..0, args.iter().copied());
+    })?;
+    Ok(receiver)
+}
+
+// How many elements `pop` and `shift` take, `None` for a single one
+fn take_count(ex: &mut dyn Executor, args: &[Value]) -> Result<Option<usize>, Unwind> {
+    match args {
+        [] => Ok(None),
+        [count] => match expect_integer(ex, *count)? {
+            count if count < 0 => Err(ex.runtime().error("ArgumentError", "negative array size")),
+            count => Ok(Some(count as usize)),
+        },
+        _ => Err(argument_count_error(ex, args.len(), "0..1")),
+    }
+}
+
+fn array_pop(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    match take_count(ex, args)? {
+        None => Ok(modify_array(ex, receiver, Vec::pop)?.unwrap_or(Value::NIL)),
+        Some(count) => {
+            let taken = modify_array(ex, receiver, |elements| elements.split_off(elements.len().saturating_sub(count)))?;
+            Ok(ex.runtime().array(taken))
+        }
+    }
+}
+
+fn array_shift(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    match take_count(ex, args)? {
+        None => {
+            let shifted = modify_array(ex, receiver, |elements| (!elements.is_empty()).then(|| elements.remove(0)))?;
+            Ok(shifted.unwrap_or(Value::NIL))
+        }
+        Some(count) => {
+            let taken = modify_array(ex, receiver, |elements| elements.drain(..count.min(elements.len())).collect())?;
+            Ok(ex.runtime().array(taken))
+        }
+    }
+}
+
+fn array_length(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let length = elements_of(ex, receiver).len();
+    Ok(ex.runtime().int(length as i64))
+}
+
+fn array_empty(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(elements_of(ex, receiver).is_empty()))
+}
+
+fn array_last(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let elements = elements_of(ex, receiver);
+    match take_count(ex, args)? {
+        None => Ok(elements.last().copied().unwrap_or(Value::NIL)),
+        Some(count) => Ok(ex.runtime().array(elements[elements.len().saturating_sub(count)..].to_vec())),
+    }
+}
+
+// Out of bounds it's the block's value for the index, the default, or an `IndexError`
+fn array_fetch(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let elements = elements_of(ex, receiver);
+    let index = expect_integer(ex, args[0])?;
+    if let Some(position) = array_position(index, elements.len()).filter(|&position| position < elements.len()) {
+        return Ok(elements[position]);
+    }
+    match (block, args.get(1)) {
+        (Some(block), _) => ex.call_proc(block, &[args[0]]),
+        (None, Some(&default)) => Ok(default),
+        (None, None) => {
+            let length = elements.len();
+            let message = format!("index {} outside of array bounds: {}...{}", index, -(length as i64), length);
+            Err(ex.runtime().error("IndexError", &message))
+        }
+    }
+}
+
+// Nested arrays are spread into this one, down to `depth` levels if it's given
+fn array_flatten(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let depth = match args {
+        [] => None,
+        [depth] => Some(expect_integer(ex, *depth)?).filter(|&depth| depth >= 0),
+        _ => return Err(argument_count_error(ex, args.len(), "0..1")),
+    };
+    let flattened = flatten(ex, receiver, depth, &mut Vec::new())?;
+    Ok(ex.runtime().array(flattened))
+}
+
+// `visiting` holds the arrays being flattened, to catch one that contains itself
+fn flatten(ex: &mut dyn Executor, array: Value, depth: Option<i64>, visiting: &mut Vec<Value>) -> Result<Vec<Value>, Unwind> {
+    if visiting.contains(&array) {
+        return Err(ex.runtime().error("ArgumentError", "tried to flatten recursive array"));
+    }
+    visiting.push(array);
+    let mut flattened = Vec::new();
+    for element in elements_of(ex, array) {
+        match ex.runtime().array_value(element) {
+            Some(_) if depth != Some(0) => flattened.extend(flatten(ex, element, depth.map(|depth| depth - 1), visiting)?),
+            _ => flattened.push(element),
+        }
+    }
+    visiting.pop();
+    Ok(flattened)
+}
+
+fn array_compact(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let elements = elements_of(ex, receiver).into_iter().filter(|&element| element != Value::NIL).collect();
+    Ok(ex.runtime().array(elements))
+}
+
+fn array_reverse(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let elements = elements_of(ex, receiver).into_iter().rev().collect();
+    Ok(ex.runtime().array(elements))
+}
+
+// `[]` with the first key, then `dig` on what it gives with the rest. Shared with `Hash`.
+fn dig(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let value = ex.send(receiver, "[]", &args[..1], None)?;
+    if args.len() == 1 || value == Value::NIL {
+        return Ok(value);
+    }
+    let runtime = ex.runtime();
+    let class = runtime.class_of(value);
+    if runtime.find_method(class, "dig").is_none() {
+        let class = runtime.class_name(runtime.real_class_of(value));
+        return Err(runtime.error("TypeError", &format!("{} does not have #dig method", class)));
+    }
+    ex.send(value, "dig", &args[1..], None)
+}
+
+// Iterates by index, so elements pushed by the block are visited too
+fn array_each(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let mut index = 0;
+    while let Some(&element) = ex.runtime().array_value(receiver).and_then(|elements| elements.get(index)) {
+        ex.call_proc(block, &[element])?;
+        index += 1;
+    }
+    Ok(receiver)
+}
+
+fn array_join(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let separator = match args.first() {
+        Some(&separator) => expect_string(ex, separator)?,
+        None => String::new(),
+    };
+    let mut parts = Vec::new();
+    for element in elements_of(ex, receiver) {
+        parts.push(match ex.runtime().array_value(element) {
+            Some(_) => {
+                let joined = array_join(ex, element, args, None)?;
+                string_of(ex, joined)
+            }
+            None => to_s(ex, element)?,
+        });
+    }
+    Ok(ex.runtime().string(&parts.join(&separator)))
+}
+
+fn array_concat(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let Some(right) = ex.runtime().array_value(args[0]).cloned() else {
+        return Err(conversion_error(ex, args[0], "Array"));
+    };
+    let mut elements = elements_of(ex, receiver);
+    elements.extend(right);
+    Ok(ex.runtime().array(elements))
+}
+
+// The elements of both, without duplicates, in the order they first appear
+fn array_union(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let Some(right) = ex.runtime().array_value(args[0]).cloned() else {
+        return Err(conversion_error(ex, args[0], "Array"));
+    };
+    let mut elements = elements_of(ex, receiver);
+    elements.extend(right);
+    let runtime = ex.runtime();
+    let mut seen = HashSet::new();
+    elements.retain(|&element| seen.insert(runtime.hash_key(element)));
+    Ok(runtime.array(elements))
+}
+
+fn array_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if receiver == args[0] {
+        return Ok(Value::TRUE);
+    }
+    let Some(right) = ex.runtime().array_value(args[0]).cloned() else {
+        return Ok(Value::FALSE);
+    };
+    let left = elements_of(ex, receiver);
+    if left.len() != right.len() {
+        return Ok(Value::FALSE);
+    }
+    // Arrays that contain themselves are equal as far as they've been compared
+    recursive(ex, "==", receiver, args[0], Value::TRUE, |ex| {
+        for (left, right) in left.into_iter().zip(right) {
+            if !equal(ex, left, right)? {
+                return Ok(Value::FALSE);
+            }
+        }
+        Ok(Value::TRUE)
+    })
+}
+
+fn array_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let inspect = recursive(ex, "inspect", receiver, receiver, "[...]".to_string(), |ex| {
+        let mut parts = Vec::new();
+        for element in elements_of(ex, receiver) {
+            parts.push(inspect(ex, element)?);
+        }
+        Ok(format!("[{}]", parts.join(", ")))
+    })?;
+    Ok(ex.runtime().string(&inspect))
+}
+
+// Hash
+
+fn define_hash(runtime: &mut Runtime) {
+    let hash = runtime.classes.hash;
+    runtime.define_native(hash, "initialize", -1, hash_initialize);
+    runtime.define_native(hash, "[]", 1, hash_index);
+    runtime.define_native(hash, "[]=", 2, hash_set_index);
+    runtime.define_native(hash, "store", 2, hash_set_index);
+    runtime.define_native(hash, "delete", 1, hash_delete);
+    runtime.define_native(hash, "key?", 1, hash_has_key);
+    runtime.define_native(hash, "has_key?", 1, hash_has_key);
+    runtime.define_native(hash, "include?", 1, hash_has_key);
+    runtime.define_native(hash, "member?", 1, hash_has_key);
+    runtime.define_native(hash, "fetch", -2, hash_fetch);
+    runtime.define_native(hash, "dig", -2, dig);
+    runtime.define_native(hash, "merge", -1, hash_merge);
+    runtime.define_native(hash, "merge!", -1, hash_update);
+    runtime.define_native(hash, "update", -1, hash_update);
+    runtime.define_native(hash, "transform_values", 0, hash_transform_values);
+    runtime.define_native(hash, "select", 0, hash_select);
+    runtime.define_native(hash, "filter", 0, hash_select);
+    runtime.define_native(hash, "reject", 0, hash_reject);
+    runtime.define_native(hash, "keys", 0, hash_keys);
+    runtime.define_native(hash, "values", 0, hash_values);
+    runtime.define_native(hash, "length", 0, hash_length);
+    runtime.define_native(hash, "size", 0, hash_length);
+    runtime.define_native(hash, "empty?", 0, hash_empty);
+    runtime.define_native(hash, "each", 0, hash_each);
+    runtime.define_native(hash, "each_pair", 0, hash_each);
+    runtime.define_native(hash, "to_a", 0, hash_to_a);
+    runtime.define_native(hash, "to_h", 0, object_itself);
+    runtime.define_native(hash, "==", 1, hash_equal);
+    runtime.define_native(hash, "inspect", 0, hash_inspect);
+    runtime.define_native(hash, "to_s", 0, hash_inspect);
+}
+
+// A copy, rooted like `elements_of`
+pub fn entries_of(ex: &mut dyn Executor, receiver: Value) -> Vec<(Value, Value)> {
+    let runtime = ex.runtime();
+    let entries = runtime.hash_value(receiver).map(|hash| hash.entries.clone()).unwrap_or_default();
+    for &(key, value) in &entries {
+        runtime.heap.root(key);
+        runtime.heap.root(value);
+    }
+    entries
+}
+
+fn modify_hash<T>(ex: &mut dyn Executor, receiver: Value, change: impl FnOnce(&mut RHash) -> T) -> Result<T, Unwind> {
+    check_frozen(ex, receiver)?;
+    let id = receiver.object_id().expect("hashes are objects");
+    match &mut ex.runtime().object_mut(id).kind {
+        ObjectKind::Hash(hash) => Ok(change(hash)),
+        _ => unreachable!("Hash methods are only called on hashes"),
+    }
+}
+
+fn hash_initialize(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 1 || (block.is_some() && !args.is_empty()) {
+        return Err(argument_count_error(ex, args.len(), if block.is_some() { "0" } else { "0..1" }));
+    }
+    let default = args.first().copied();
+    modify_hash(ex, receiver, |hash| {
+        hash.default = default;
+        hash.default_proc = block;
+    })?;
+    Ok(Value::NIL)
+}
+
+fn hash_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let key = runtime.hash_key(args[0]);
+    let hash = runtime.hash_value(receiver).expect("Hash methods are only called on hashes");
+    match (hash.get(&key), hash.default_proc) {
+        (Some(value), _) => Ok(value),
+        (None, Some(default_proc)) => ex.call_proc(default_proc, &[receiver, args[0]]),
+        (None, None) => Ok(hash.default.unwrap_or(Value::NIL)),
+    }
+}
+
+// String keys are copied and frozen, so changing the original doesn't change the hash
+fn hash_set_index(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let (mut key, value) = (args[0], args[1]);
+    if let Some(string) = ex.runtime().rstring_value(key).cloned() {
+        if !key.object_id().is_some_and(|id| ex.runtime().object(id).frozen) {
+            key = ex.runtime().string_from(string);
+            object_freeze(ex, key, &[], None)?;
+        }
+    }
+    let hash_key = ex.runtime().hash_key(key);
+    modify_hash(ex, receiver, |hash| hash.insert(hash_key, key, value))?;
+    Ok(value)
+}
+
+fn hash_delete(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let key = ex.runtime().hash_key(args[0]);
+    Ok(modify_hash(ex, receiver, |hash| hash.remove(&key))?.unwrap_or(Value::NIL))
+}
+
+fn hash_has_key(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let key = runtime.hash_key(args[0]);
+    Ok(Value::from_bool(runtime.hash_value(receiver).is_some_and(|hash| hash.get(&key).is_some())))
+}
+
+// A missing key gives the block's value for it, the default, or a `KeyError`
+fn hash_fetch(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let runtime = ex.runtime();
+    let key = runtime.hash_key(args[0]);
+    if let Some(value) = runtime.hash_value(receiver).and_then(|hash| hash.get(&key)) {
+        return Ok(value);
+    }
+    match (block, args.get(1)) {
+        (Some(block), _) => ex.call_proc(block, &[args[0]]),
+        (None, Some(&default)) => Ok(default),
+        (None, None) => {
+            let message = format!("key not found: {}", inspect(ex, args[0])?);
+            Err(ex.runtime().error("KeyError", &message))
+        }
+    }
+}
+
+// Adds the other hashes' entries, asking the block for the value of keys already there
+fn merge_into(ex: &mut dyn Executor, target: Value, others: &[Value], block: Option<Value>) -> Result<(), Unwind> {
+    for &other in others {
+        if ex.runtime().hash_value(other).is_none() {
+            return Err(conversion_error(ex, other, "Hash"));
+        }
+        for (key, mut value) in entries_of(ex, other) {
+            let hash_key = ex.runtime().hash_key(key);
+            let existing = ex.runtime().hash_value(target).and_then(|hash| hash.get(&hash_key));
+            if let (Some(block), Some(existing)) = (block, existing) {
+                value = call_rooted(ex, block, &[key, existing, value])?;
+            }
+            hash_set_index(ex, target, &[key, value], None)?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_merge(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let copy = ex.runtime().hash_value(receiver).cloned().unwrap_or_default();
+    let merged = ex.runtime().hash(copy);
+    merge_into(ex, merged, args, block)?;
+    Ok(merged)
+}
+
+fn hash_update(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    check_frozen(ex, receiver)?;
+    merge_into(ex, receiver, args, block)?;
+    Ok(receiver)
+}
+
+fn hash_transform_values(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let transformed = ex.runtime().hash(RHash::default());
+    for (key, value) in entries_of(ex, receiver) {
+        let value = call_rooted(ex, block, &[value])?;
+        hash_set_index(ex, transformed, &[key, value], None)?;
+    }
+    Ok(transformed)
+}
+
+// The entries the block is truthy for, or isn't, given the key and the value
+fn filter_entries(ex: &mut dyn Executor, receiver: Value, block: Option<Value>, keep: bool) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let filtered = ex.runtime().hash(RHash::default());
+    for (key, value) in entries_of(ex, receiver) {
+        if ex.call_proc(block, &[key, value])?.truthy() == keep {
+            hash_set_index(ex, filtered, &[key, value], None)?;
+        }
+    }
+    Ok(filtered)
+}
+
+fn hash_select(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    filter_entries(ex, receiver, block, true)
+}
+
+fn hash_reject(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    filter_entries(ex, receiver, block, false)
+}
+
+fn hash_keys(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let keys = entries_of(ex, receiver).into_iter().map(|(key, _)| key).collect();
+    Ok(ex.runtime().array(keys))
+}
+
+fn hash_values(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let values = entries_of(ex, receiver).into_iter().map(|(_, value)| value).collect();
+    Ok(ex.runtime().array(values))
+}
+
+fn hash_length(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let length = entries_of(ex, receiver).len();
+    Ok(ex.runtime().int(length as i64))
+}
+
+fn hash_empty(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(entries_of(ex, receiver).is_empty()))
+}
+
+// Each entry is yielded as a `[key, value]` pair, which blocks with two parameters destructure
+fn hash_each(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    for (key, value) in entries_of(ex, receiver) {
+        let pair = ex.runtime().array(vec![key, value]);
+        ex.call_proc(block, &[pair])?;
+    }
+    Ok(receiver)
+}
+
+fn hash_to_a(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    let entries = runtime.hash_value(receiver).map(|hash| hash.entries.clone()).unwrap_or_default();
+    let pairs = entries.into_iter().map(|(key, value)| runtime.array(vec![key, value])).collect();
+    Ok(runtime.array(pairs))
+}
+
+fn hash_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if receiver == args[0] {
+        return Ok(Value::TRUE);
+    }
+    if ex.runtime().hash_value(args[0]).is_none() {
+        return Ok(Value::FALSE);
+    }
+    let (left, right) = (entries_of(ex, receiver), entries_of(ex, args[0]));
+    if left.len() != right.len() {
+        return Ok(Value::FALSE);
+    }
+    recursive(ex, "==", receiver, args[0], Value::TRUE, |ex| {
+        for (key, value) in left {
+            let key = ex.runtime().hash_key(key);
+            let other = ex.runtime().hash_value(args[0]).and_then(|hash| hash.get(&key));
+            match other {
+                Some(other) if equal(ex, value, other)? => {}
+                _ => return Ok(Value::FALSE),
+            }
+        }
+        Ok(Value::TRUE)
+    })
+}
+
+fn hash_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let inspect = recursive(ex, "inspect", receiver, receiver, "{...}".to_string(), |ex| {
+        let mut parts = Vec::new();
+        for (key, value) in entries_of(ex, receiver) {
+            let value = inspect(ex, value)?;
+            parts.push(match key.as_symbol() {
+                Some(symbol) => {
+                    let name = ex.runtime().symbol_name(symbol).to_string();
+                    let key = inspect(ex, key)?;
+                    if key == format!(":{}", name) { format!("{}: {}", name, value) } else { format!("{}: {}", &key[1..], value) }
+                }
+                None => format!("{} => {}", inspect(ex, key)?, value),
+            });
+        }
+        Ok(if parts.is_empty() { "{}".to_string() } else { format!("{{{}}}", parts.join(", ")) })
+    })?;
+    Ok(ex.runtime().string(&inspect))
+}
+
+// Range
+
+fn define_range(runtime: &mut Runtime) {
+    let range = runtime.classes.range;
+    runtime.define_native(range, "first", -1, range_first);
+    runtime.define_native(range, "begin", 0, range_first);
+    runtime.define_native(range, "last", 0, range_last);
+    runtime.define_native(range, "end", 0, range_last);
+    runtime.define_native(range, "exclude_end?", 0, range_exclude_end);
+    runtime.define_native(range, "each", 0, range_each);
+    runtime.define_native(range, "to_a", 0, range_to_a);
+    runtime.define_native(range, "include?", 1, range_include);
+    runtime.define_native(range, "member?", 1, range_include);
+    runtime.define_native(range, "cover?", 1, range_include);
+    runtime.define_native(range, "===", 1, range_include);
+    runtime.define_native(range, "==", 1, range_equal);
+    runtime.define_native(range, "inspect", 0, range_inspect);
+    runtime.define_native(range, "to_s", 0, range_inspect);
+}
+
+fn range_of(ex: &mut dyn Executor, receiver: Value) -> (Value, Value, bool) {
+    match ex.runtime().kind(receiver) {
+        Some(ObjectKind::Range(start, end, exclusive)) => (*start, *end, *exclusive),
+        _ => unreachable!("Range methods are only called on ranges"),
+    }
+}
+
+// The start, or an array of the first `n` elements like `Enumerable#first`
+fn range_first(ex: &mut dyn Executor, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    match args {
+        [] => Ok(range_of(ex, receiver).0),
+        args => enumerable_first(ex, receiver, args, block),
+    }
+}
+
+fn range_last(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(range_of(ex, receiver).1)
+}
+
+fn range_exclude_end(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(range_of(ex, receiver).2))
+}
+
+// The integers in the range, which must start at one and can be endless. Either end can be
+// a bignum, and an exclusive end is turned into the last integer.
+fn integer_bounds(ex: &mut dyn Executor, receiver: Value) -> Result<(BigInt, Option<BigInt>), Unwind> {
+    let (start, end, exclusive) = range_of(ex, receiver);
+    let runtime = ex.runtime();
+    let Some(first) = bignum_of(runtime, start) else {
+        let class = runtime.class_name(runtime.real_class_of(start));
+        return Err(runtime.error("TypeError", &format!("can't iterate from {}", class)));
+    };
+    let last = match bignum_of(runtime, end) {
+        Some(end) if exclusive => Some(end - 1),
+        Some(end) => Some(end),
+        None => None,
+    };
+    Ok((first, last))
+}
+
+// Steps on 64-bit integers while they last and on bignums past them
+fn range_each(ex: &mut dyn Executor, receiver: Value, _: &[Value], block: Option<Value>) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    let (mut current, end) = integer_bounds(ex, receiver)?;
+    let small_end = match &end {
+        Some(end) => end.to_i64().or_else(|| end.is_positive().then_some(i64::MAX)),
+        None => Some(i64::MAX),
+    };
+    if let (Some(start), Some(small_end)) = (current.to_i64(), small_end) {
+        if start <= small_end {
+            for value in start..=small_end {
+                let value = ex.runtime().int(value);
+                ex.call_proc(block, &[value])?;
+            }
+            current = BigInt::from(small_end) + 1;
+        }
+    }
+    while end.as_ref().is_none_or(|end| &current <= end) {
+        let value = ex.runtime().integer(current.clone());
+        ex.call_proc(block, &[value])?;
+        current += 1;
+    }
+    Ok(receiver)
+}
+
+fn range_to_a(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let (mut current, Some(end)) = integer_bounds(ex, receiver)? else {
+        return Err(ex.runtime().error("RangeError", "cannot convert endless range to an array"));
+    };
+    let mut elements = Vec::new();
+    while current <= end {
+        elements.push(ex.runtime().integer(current.clone()));
+        current += 1;
+    }
+    Ok(ex.runtime().array(elements))
+}
+
+// Compares with `<=>`, so any comparable values work, and missing ends are unbounded
+fn range_include(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    let (start, end, exclusive) = range_of(ex, receiver);
+    let value = args[0];
+
+    if start != Value::NIL && !matches!(compare(ex, start, value)?, Some(order) if order <= 0) {
+        return Ok(Value::FALSE);
+    }
+    if end != Value::NIL {
+        match compare(ex, value, end)? {
+            Some(order) if order < 0 || (order == 0 && !exclusive) => {}
+            _ => return Ok(Value::FALSE),
+        }
+    }
+    Ok(Value::TRUE)
+}
+
+fn range_equal(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if !matches!(ex.runtime().kind(args[0]), Some(ObjectKind::Range(..))) {
+        return Ok(Value::FALSE);
+    }
+    let (left, right) = (range_of(ex, receiver), range_of(ex, args[0]));
+    Ok(Value::from_bool(left.2 == right.2 && equal(ex, left.0, right.0)? && equal(ex, left.1, right.1)?))
+}
+
+fn range_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let (start, end, exclusive) = range_of(ex, receiver);
+    let start = if start == Value::NIL { String::new() } else { inspect(ex, start)? };
+    let end = if end == Value::NIL { String::new() } else { inspect(ex, end)? };
+    let operator = if exclusive { "..." } else { ".." };
+    Ok(ex.runtime().string(&format!("{}{}{}", start, operator, end)))
+}
+
+// GC
+
+fn define_gc(runtime: &mut Runtime) {
+    let gc = runtime.define_module("GC", None);
+    let gc = runtime.singleton_class(Value::object(gc)).expect("modules have singleton classes");
+    runtime.define_native(gc, "start", 0, gc_start);
+    runtime.define_native(gc, "count", 0, gc_count);
+    runtime.define_native(gc, "stat", -1, gc_stat);
+    runtime.define_native(gc, "stress", 0, gc_stress);
+    runtime.define_native(gc, "stress=", 1, gc_set_stress);
+
+    let object_space = runtime.define_module("ObjectSpace", None);
+    let object_space = runtime.singleton_class(Value::object(object_space)).expect("modules have singleton classes");
+    runtime.define_native(object_space, "define_finalizer", -2, object_space_define_finalizer);
+    runtime.define_native(object_space, "undefine_finalizer", 1, object_space_undefine_finalizer);
+}
+
+fn gc_start(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    ex.collect_garbage();
+    Ok(Value::NIL)
+}
+
+fn gc_count(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(runtime.int(runtime.heap.stats.count as i64))
+}
+
+// All the counters in a hash, or the one named by a symbol
+fn gc_stat(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if args.len() > 1 {
+        return Err(argument_count_error(ex, args.len(), "0..1"));
+    }
+    let runtime = ex.runtime();
+    let heap = &runtime.heap;
+    let counters = [
+        ("count", heap.stats.count as i64),
+        ("heap_live_slots", heap.live_slots() as i64),
+        ("heap_free_slots", heap.free_slots() as i64),
+        ("total_allocated_objects", heap.stats.total_allocated as i64),
+        ("total_freed_objects", heap.stats.total_freed as i64),
+    ];
+    if let Some(&key) = args.first() {
+        let Some(symbol) = key.as_symbol() else {
+            let class = runtime.class_name(runtime.real_class_of(key));
+            return Err(runtime.error("TypeError", &format!("non-hash or symbol given: {}", class)));
+        };
+        let name = runtime.symbol_name(symbol);
+        return match counters.iter().find(|(counter, _)| *counter == name) {
+            Some(&(_, value)) => Ok(runtime.int(value)),
+            None => {
+                let message = format!("unknown key: {}", name);
+                Err(runtime.error("ArgumentError", &message))
+            }
+        };
+    }
+    let mut stat = RHash::default();
+    for (counter, value) in counters {
+        let key = runtime.symbol(counter);
+        let value = runtime.int(value);
+        stat.insert(runtime.hash_key(key), key, value);
+    }
+    Ok(runtime.hash(stat))
+}
+
+fn gc_stress(ex: &mut dyn Executor, _: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(ex.runtime().heap.stress))
+}
+
+fn gc_set_stress(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    ex.runtime().heap.stress = args[0].truthy();
+    Ok(args[0])
+}
+
+// `define_finalizer(object, proc)` or with a block. A finalizer that refers to its
+// object keeps it from ever being collected.
+fn object_space_define_finalizer(ex: &mut dyn Executor, _: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    if args.len() > 2 {
+        return Err(argument_count_error(ex, args.len(), "1..2"));
+    }
+    let Some(finalizer) = args.get(1).copied().or(block) else {
+        return Err(ex.runtime().error("ArgumentError", "tried to create Proc object without a block"));
+    };
+    let runtime = ex.runtime();
+    if runtime.proc_value(finalizer).is_none() && runtime.find_method(runtime.class_of(finalizer), "call").is_none() {
+        let class = runtime.class_name(runtime.real_class_of(finalizer));
+        return Err(runtime.error("ArgumentError", &format!("wrong type argument {} (should be callable)", class)));
+    }
+    let object = args[0];
+    let id = match runtime.unpack(object) {
+        Unpacked::Object(id) if !runtime.object(id).frozen => id,
+        unpacked => {
+            let class = runtime.class_name(runtime.real_class_of(object));
+            let (class, message) = match unpacked {
+                Unpacked::Object(_) => ("FrozenError", format!("can't modify frozen {}", class)),
+                _ => ("ArgumentError", format!("cannot define finalizer for {}", class)),
+            };
+            return Err(runtime.error(class, &message));
+        }
+    };
+    runtime.heap.define_finalizer(id, finalizer);
+    let zero = runtime.int(0);
+    Ok(runtime.array(vec![zero, finalizer]))
+}
+
+fn object_space_undefine_finalizer(ex: &mut dyn Executor, _: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    if let Some(id) = args[0].object_id() {
+        ex.runtime().heap.undefine_finalizer(id);
+    }
+    Ok(args[0])
+}
+
+// Proc
+
+fn define_proc(runtime: &mut Runtime) {
+    let proc = runtime.classes.proc;
+    runtime.define_singleton_native(proc, "new", 0, kernel_proc);
+    runtime.define_native(proc, "call", -1, proc_call);
+    runtime.define_native(proc, "()", -1, proc_call);
+    runtime.define_native(proc, "yield", -1, proc_call);
+    runtime.define_native(proc, "[]", -1, proc_call);
+    runtime.define_native(proc, "===", -1, proc_call);
+    runtime.define_native(proc, "to_proc", 0, object_itself);
+    runtime.define_native(proc, "lambda?", 0, proc_lambda);
+    runtime.define_native(proc, "arity", 0, proc_arity);
+    runtime.define_native(proc, "inspect", 0, proc_inspect);
+    runtime.define_native(proc, "to_s", 0, proc_inspect);
+}
+
+fn proc_call(ex: &mut dyn Executor, receiver: Value, args: &[Value], _: Option<Value>) -> NativeResult {
+    ex.call_proc(receiver, args)
+}
+
+fn proc_lambda(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    Ok(Value::from_bool(ex.runtime().proc_value(receiver).is_some_and(|proc| proc.lambda)))
+}
+
+fn proc_arity(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let runtime = ex.runtime();
+    Ok(runtime.int(match runtime.proc_value(receiver).map(|proc| &proc.body) {
+        Some(ProcBody::Ast(closure)) => closure.arity() as i64,
+        Some(ProcBody::Iseq(closure)) => closure.arity() as i64,
+        _ => -2,
+    }))
+}
+
+fn proc_inspect(ex: &mut dyn Executor, receiver: Value, _: &[Value], _: Option<Value>) -> NativeResult {
+    let lambda = ex.runtime().proc_value(receiver).is_some_and(|proc| proc.lambda);
+    Ok(ex.runtime().string(if lambda { "#<Proc (lambda)>" } else { "#<Proc>" }))
+}
+
+// Helpers shared by the methods above and the backends
+
+pub fn inspect(ex: &mut dyn Executor, value: Value) -> Result<String, Unwind> {
+    let inspected = ex.send(value, "inspect", &[], None)?;
+    Ok(match ex.runtime().rstring_value(inspected) {
+        Some(string) => string.text().into_owned(),
+        None => ex.runtime().describe(value),
+    })
+}
+
+pub fn to_s(ex: &mut dyn Executor, value: Value) -> Result<String, Unwind> {
+    if let Some(string) = ex.runtime().rstring_value(value) {
+        return Ok(string.text().into_owned());
+    }
+    let converted = ex.send(value, "to_s", &[], None)?;
+    Ok(match ex.runtime().rstring_value(converted) {
+        Some(string) => string.text().into_owned(),
+        None => ex.runtime().describe(value),
+    })
+}
+
+// A value interpolated into a string: strings as they are, anything else through `to_s`,
+// falling back to the default description when that doesn't return a string
+pub fn object_to_string(ex: &mut dyn Executor, value: Value) -> NativeResult {
+    if ex.runtime().rstring_value(value).is_some() {
+        return Ok(value);
+    }
+    let converted = ex.send(value, "to_s", &[], None)?;
+    if ex.runtime().rstring_value(converted).is_some() {
+        return Ok(converted);
+    }
+    let description = ex.runtime().describe(value);
+    Ok(ex.runtime().string(&description))
+}
+
+// The parts of an interpolated string, all strings, joined into a new one
+pub fn concat_strings(ex: &mut dyn Executor, parts: &[Value]) -> NativeResult {
+    let mut string = RString::new("");
+    for &part in parts {
+        let part = rstring_of(ex, part);
+        string = concatenate(ex, &string, &part)?;
+    }
+    Ok(ex.runtime().string_from(string))
+}
+
+// `==`, without a call for the values that can be compared directly
+// Runs `body` unless `method` is already running on the same receiver and argument further
+// up, giving `cycle` instead, so a collection that contains itself doesn't recurse forever
+fn recursive<T>(
+    ex: &mut dyn Executor,
+    method: &'static str,
+    receiver: Value,
+    argument: Value,
+    cycle: T,
+    body: impl FnOnce(&mut dyn Executor) -> Result<T, Unwind>,
+) -> Result<T, Unwind> {
+    let (Some(receiver), Some(argument)) = (receiver.object_id(), argument.object_id()) else {
+        return body(ex);
+    };
+    let key = (method, receiver, argument);
+    if !ex.runtime().recursion.insert(key) {
+        return Ok(cycle);
+    }
+    let result = body(ex);
+    ex.runtime().recursion.remove(&key);
+    result
+}
+
+pub fn equal(ex: &mut dyn Executor, left: Value, right: Value) -> Result<bool, Unwind> {
+    match ex.runtime().unpack(left) {
+        Unpacked::Object(_) => Ok(ex.send(left, "==", &[right], None)?.truthy()),
+        Unpacked::Integer(_) | Unpacked::Float(_) => Ok(numeric_order(ex.runtime(), left, right) == Some(Ordering::Equal)),
+        _ => Ok(left == right),
+    }
+}
+
+// `<=>` as an ordering, `None` when the values can't be compared
+pub fn compare(ex: &mut dyn Executor, left: Value, right: Value) -> Result<Option<i64>, Unwind> {
+    let runtime = ex.runtime();
+    match (integer_of(runtime, left), integer_of(runtime, right)) {
+        (Some(left), Some(right)) => Ok(Some(left.cmp(&right) as i64)),
+        _ => {
+            let order = ex.send(left, "<=>", &[right], None)?;
+            Ok(integer_of(ex.runtime(), order))
+        }
+    }
+}
+
+// `*value`: arrays are spread, `nil` is nothing, and anything with a `to_a` is converted
+pub fn splat(ex: &mut dyn Executor, value: Value) -> Result<Vec<Value>, Unwind> {
+    if value == Value::NIL {
+        return Ok(Vec::new());
+    }
+    if let Some(elements) = ex.runtime().array_value(value) {
+        return Ok(elements.clone());
+    }
+    let runtime = ex.runtime();
+    if runtime.find_method(runtime.class_of(value), "to_a").is_some() {
+        let converted = ex.send(value, "to_a", &[], None)?;
+        if let Some(elements) = ex.runtime().array_value(converted) {
+            return Ok(elements.clone());
+        }
+    }
+    Ok(vec![value])
+}
+
+// `&value` in a call: procs are passed as they are, anything else through `to_proc`
+pub fn block_argument(ex: &mut dyn Executor, value: Value) -> Result<Option<Value>, Unwind> {
+    if value == Value::NIL {
+        return Ok(None);
+    }
+    if ex.runtime().proc_value(value).is_some() {
+        return Ok(Some(value));
+    }
+    let converted = ex.send(value, "to_proc", &[], None)?;
+    if ex.runtime().proc_value(converted).is_none() {
+        let runtime = ex.runtime();
+        let class = runtime.class_name(runtime.real_class_of(value));
+        return Err(runtime.error("TypeError", &format!("wrong argument type {} (expected Proc)", class)));
+    }
+    Ok(Some(converted))
+}
+
+// The elements array patterns match against: arrays, or whatever `deconstruct` returns
+pub fn deconstruct(ex: &mut dyn Executor, value: Value) -> Result<Option<Vec<Value>>, Unwind> {
+    if let Some(elements) = ex.runtime().array_value(value) {
+        return Ok(Some(elements.clone()));
+    }
+    let runtime = ex.runtime();
+    if runtime.find_method(runtime.class_of(value), "deconstruct").is_none() {
+        return Ok(None);
+    }
+    let elements = ex.send(value, "deconstruct", &[], None)?;
+    Ok(ex.runtime().array_value(elements).cloned())
+}
+
+pub fn set_ivar(ex: &mut dyn Executor, object: Value, name: &str, value: Value) -> Result<(), Unwind> {
+    check_frozen(ex, object)?;
+    let runtime = ex.runtime();
+    match object.object_id() {
+        Some(id) => {
+            runtime.set_ivar(id, name, value);
+            Ok(())
+        }
+        None => {
+            let message = format!("can't modify frozen {}: {}", runtime.class_name(runtime.real_class_of(object)), runtime.describe(object));
+            Err(runtime.error("FrozenError", &message))
+        }
+    }
+}
+
+// Calls a native method with what it was given rooted, along with everything it
+// allocates. What it holds from other calls across more calls it roots itself.
+pub fn call_native(ex: &mut dyn Executor, function: NativeFn, receiver: Value, args: &[Value], block: Option<Value>) -> NativeResult {
+    let heap = &mut ex.runtime().heap;
+    let mark = heap.root_mark();
+    heap.root(receiver);
+    for &arg in args {
+        heap.root(arg);
+    }
+    heap.root(block.unwrap_or(Value::NIL));
+    let result = function(ex, receiver, args, block);
+    ex.runtime().heap.unroot_to(mark);
+    result
+}
+
+// Finalizers get the id of the collected object. What they raise is ignored.
+pub fn run_finalizers(ex: &mut dyn Executor, finalizers: Vec<(Value, ObjectId)>) {
+    for (proc, id) in finalizers {
+        let id = ex.runtime().int(object_id(Value::object(id)));
+        let _ = ex.call_proc(proc, &[id]);
+    }
+}
+
+// A call that found no method, or a private one it can't call. Classes that define
+// `method_missing` get to handle it, anything else raises.
+pub fn method_missing(ex: &mut dyn Executor, receiver: Value, name: &str, args: &[Value], block: Option<Value>, missing: Missing) -> NativeResult {
+    let runtime = ex.runtime();
+    let handler = runtime.find_method(runtime.class_of(receiver), "method_missing");
+    if handler.is_none_or(|handler| handler.owner == runtime.classes.basic_object) {
+        return Err(runtime.undefined_method(receiver, name, missing));
+    }
+    let mut arguments = vec![runtime.symbol(name)];
+    arguments.extend_from_slice(args);
+    runtime.missing_reason = missing;
+    let result = ex.send(receiver, "method_missing", &arguments, block);
+    ex.runtime().missing_reason = Missing::Undefined;
+    result
+}
+
+// `x=` and `[]=`, whose calls evaluate to the assigned value rather than what the method returns
+pub fn is_assignment_method(name: &str) -> bool {
+    name == "[]=" || (name.ends_with('=') && name.starts_with(|c: char| c.is_alphabetic() || c == '_'))
+}
+
+pub fn yield_block(ex: &mut dyn Executor, block: Option<Value>, args: &[Value]) -> NativeResult {
+    let block = require_block(ex, block, "no block given (yield)")?;
+    ex.call_proc(block, args)
+}
+
+fn require_block(ex: &mut dyn Executor, block: Option<Value>, message: &str) -> NativeResult {
+    block.ok_or_else(|| ex.runtime().error("LocalJumpError", message))
+}
+
+pub fn is_a_named(runtime: &Runtime, value: Value, class_name: &str) -> bool {
+    match runtime.constant(runtime.classes.object, class_name).and_then(Value::object_id) {
+        Some(class) => runtime.is_a(value, class),
+        None => false,
+    }
+}
+
+pub fn check_frozen(ex: &mut dyn Executor, value: Value) -> Result<(), Unwind> {
+    if value.object_id().is_some_and(|id| ex.runtime().object(id).frozen) {
+        let runtime = ex.runtime();
+        let class = runtime.class_name(runtime.real_class_of(value));
+        let message = format!("can't modify frozen {}: {}", class, inspect(ex, value)?);
+        return Err(ex.runtime().error("FrozenError", &message));
+    }
+    Ok(())
+}
+
+pub fn expect_integer(ex: &mut dyn Executor, value: Value) -> Result<i64, Unwind> {
+    match ex.runtime().unpack(value) {
+        Unpacked::Integer(value) => Ok(value),
+        _ if ex.runtime().bignum_value(value).is_some() => Err(ex.runtime().error("RangeError", "bignum too big to convert into 'long'")),
+        _ => Err(conversion_error(ex, value, "Integer")),
+    }
+}
+
+pub fn expect_string(ex: &mut dyn Executor, value: Value) -> Result<String, Unwind> {
+    match ex.runtime().rstring_value(value) {
+        Some(string) => Ok(string.text().into_owned()),
+        None => Err(conversion_error(ex, value, "String")),
+    }
+}
+
+// A method name given as a symbol or a string
+pub fn expect_name(ex: &mut dyn Executor, value: Value) -> Result<String, Unwind> {
+    match value.as_symbol() {
+        Some(symbol) => Ok(ex.runtime().symbol_name(symbol).to_string()),
+        None => match ex.runtime().string_value(value) {
+            Some(name) => Ok(name.to_string()),
+            None => {
+                let message = format!("{} is not a symbol nor a string", ex.runtime().describe(value));
+                Err(ex.runtime().error("TypeError", &message))
+            }
+        },
+    }
+}
+
+pub fn expect_module(ex: &mut dyn Executor, value: Value) -> Result<ObjectId, Unwind> {
+    match value.object_id() {
+        Some(id) if ex.runtime().class_value(id).is_some() => Ok(id),
+        _ => Err(ex.runtime().error("TypeError", "class or module required")),
+    }
+}
+
+pub fn argument_count_error(ex: &mut dyn Executor, given: usize, expected: &str) -> Unwind {
+    ex.runtime().argument_count_error(given, expected)
+}
+
+fn conversion_error(ex: &mut dyn Executor, value: Value, target: &str) -> Unwind {
+    let runtime = ex.runtime();
+    let source = match value {
+        Value::NIL => "nil".to_string(),
+        Value::TRUE => "true".to_string(),
+        Value::FALSE => "false".to_string(),
+        value => runtime.class_name(runtime.real_class_of(value)),
+    };
+    runtime.error("TypeError", &format!("no implicit conversion of {} into {}", source, target))
+}
+
+fn comparison_error(ex: &mut dyn Executor, left: Value, right: Value) -> Unwind {
+    let runtime = ex.runtime();
+    let left = runtime.class_name(runtime.real_class_of(left));
+    let right = match runtime.unpack(right) {
+        Unpacked::Nil | Unpacked::True | Unpacked::False | Unpacked::Integer(_) | Unpacked::Float(_) => runtime.describe(right),
+        _ => runtime.class_name(runtime.real_class_of(right)),
+    };
+    runtime.error("ArgumentError", &format!("comparison of {} with {} failed", left, right))
+}
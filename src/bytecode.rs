@@ -0,0 +1,483 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::rc::Rc;
+
+use crate::runtime::{Method, ObjectId};
+
+// The instructions of the VM's stack machine. Operands that aren't numbers index the
+// constant pools of the instruction sequence they belong to (`names`, `strings`, `call_infos`
+// and `children`), and jump targets are instruction indices.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    PutNil,
+    PutTrue,
+    PutFalse,
+    PutSelf,
+    PutInteger(i64),
+    PutFloat(f64),
+    // An integer literal past 64 bits, from its digits in the names
+    PutBignum(usize),
+    // A new string with the bytes of a string literal, since strings are mutable
+    PutString(usize),
+    // A new regexp from the source and options in the names
+    PutRegexp(usize, usize),
+    PutSymbol(usize),
+    // The top value as a string for interpolation, calling `to_s` unless it's one already
+    ObjToString,
+    // A new string joining the `n` strings on the top of the stack
+    ConcatStrings(usize),
+    Pop,
+    Dup,
+    // Pushes a copy of the value `n` below the top
+    TopN(usize),
+    // Drops `n` values under the top one, keeping the top
+    Adjust(usize),
+    // `level` is how many blocks out the variable was declared
+    GetLocal { index: usize, level: usize },
+    SetLocal { index: usize, level: usize },
+    GetInstanceVariable(usize),
+    SetInstanceVariable(usize),
+    // Looked up in the lexically enclosing modules, then in the ancestors of the innermost one
+    GetConstant(usize),
+    // `Scope::Name`, with the scope on the stack
+    GetScopedConstant(usize),
+    // `::Name`
+    GetTopConstant(usize),
+    // Defines the constant as the value on top, which stays there, in the scope under it for
+    // `ClassScope::Explicit`
+    SetConstant { name: usize, scope: ClassScope },
+    NewArray(usize),
+    // `*value` as a new array
+    SplatArray,
+    // Appends the top array to the one under it, which is always a new array
+    ConcatArray,
+    // The values a single value is destructured into: arrays stay arrays, anything else is wrapped in one
+    ToArray,
+    // Spreads an array over the stack for `before, *splat, after` targets, with the first target's value on top
+    ExpandArray { before: usize, after: usize, splat: bool },
+    // `n` key and value pairs
+    NewHash(usize),
+    // Adds the entries of the top hash (`**other`) to the new hash under it
+    MergeHash,
+    NewRange { exclusive: bool },
+    Not,
+    Send(usize),
+    // `super`, with `self` as the receiver. Without a block of its own it passes the
+    // current method's.
+    InvokeSuper(usize),
+    // `yield`, with the arguments in a single array when one of them was splatted
+    InvokeBlock { argc: usize, splat: bool, keywords: bool },
+    Lambda(usize),
+    DefineMethod { name: usize, body: usize },
+    // The receiver of `def target.name` is on the stack
+    DefineSingletonMethod { name: usize, body: usize },
+    DefineClass { kind: ClassKind, name: usize, body: usize, scope: ClassScope },
+    // `class << target`, with the target on the stack
+    DefineSingletonClass(usize),
+    Jump(usize),
+    BranchIf(usize),
+    BranchUnless(usize),
+    // Returns the top of the stack from the frame
+    Leave,
+    Throw(ThrowKind),
+    // Ends an `ensure` body that ran because the frame was unwinding, and carries on
+    // with that unwind. The operand is the handler's address, which identifies the ensure.
+    EndEnsure(usize),
+    // `$!`, the exception the innermost running `rescue` clause handles
+    GetErrinfo,
+    SetErrinfo,
+    CheckMatch(MatchKind),
+    // Whether the keyword parameter at this position among the keywords was passed,
+    // so the default value is only computed when it wasn't
+    CheckKeyword(usize),
+    // Whether the top is an array of exactly `length` elements, or at least `length` with `rest`
+    CheckLength { length: usize, rest: bool },
+    // The elements an array pattern matches against, or `nil` when the value has none
+    Deconstruct,
+    // A copy of the hash a hash pattern matches against, or `nil` when the value isn't a hash
+    DeconstructKeys,
+    // Removes a symbol key from the hash on top, pushing its value and whether it was there
+    DeleteKey(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassKind {
+    Class { superclass: bool },
+    Module,
+}
+
+// Where the constant a `class` or `module` or a constant assignment defines lives
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClassScope {
+    // The innermost enclosing module
+    Lexical,
+    // `class Scope::Name`, with the scope on the stack
+    Explicit,
+    // `class ::Name`
+    Top,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThrowKind {
+    Return,
+    Break,
+    Next,
+    Redo,
+    Retry,
+    Raise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchKind {
+    // `pattern === target`, with the pattern on top
+    Case,
+    // `*patterns`, matching when any of them does
+    Splat,
+    // `when *conditions` in a `case` without a subject, matching when any of them is truthy
+    SplatTruthy,
+}
+
+// Everything a `Send` needs to know about the call site besides the values on the stack
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallInfo {
+    pub method: String,
+    // The number of arguments above the receiver, not counting a block argument
+    pub argc: usize,
+    // The arguments are in a single array, because one of them was splatted
+    pub splat: bool,
+    // `&block` is on top of the arguments
+    pub block_arg: bool,
+    // The literal block's instruction sequence, among the children
+    pub block: Option<usize>,
+    // A bare identifier, which reads like a variable in errors
+    pub variable_like: bool,
+    // Without a receiver or to `self`, which can call private methods
+    pub fcall: bool,
+    // A bare `super`, passing the method's arguments
+    pub zsuper: bool,
+    // The last argument is the hash of the keyword arguments
+    pub keywords: bool,
+    // Where the call is, for backtraces
+    pub line: usize,
+    pub cache: CallCache,
+}
+
+// The methods a call site found for the classes of its last receivers, so sends to
+// the same classes skip the lookup. Everything in it is stale once the runtime's
+// method serial moves on.
+#[derive(Default)]
+pub struct CallCache {
+    serial: Cell<u64>,
+    entries: RefCell<Vec<(ObjectId, Rc<Method>)>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IseqKind {
+    Top,
+    Method,
+    Block,
+    Class,
+}
+
+// A region of instructions `[start, end)` that handles a kind of unwind by truncating
+// the stack to `depth` and jumping to `target`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CatchEntry {
+    pub kind: CatchKind,
+    pub start: usize,
+    pub end: usize,
+    pub target: usize,
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CatchKind {
+    // Pushes the exception for the rescue clauses to test
+    Rescue,
+    // Catches everything, runs the ensure body and resumes the unwind with `EndEnsure`
+    Ensure,
+    // Loops catch the `break`, `next` and `redo` thrown inside them, and rescue
+    // clauses the `retry`, when one crosses an `ensure` and can't be a plain jump
+    Break,
+    Next,
+    Redo,
+    Retry,
+}
+
+// How arguments are bound to parameters, in declaration order
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamSlot {
+    Required(usize),
+    Destructure(Vec<ParamTarget>),
+    Optional(usize),
+    Rest(Option<usize>),
+    Keyword { name: String, slot: usize, required: bool },
+    KeywordRest(Option<usize>),
+    Block(Option<usize>),
+}
+
+// What `(a, (b, *c))` parameters destructure into
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamTarget {
+    Local(usize),
+    Splat(Option<usize>),
+    Nested(Vec<ParamTarget>),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ParamLayout {
+    pub params: Vec<ParamSlot>,
+    // Same convention as `Params::arity`
+    pub arity: i32,
+    // Where to start running when `n` optional parameters were passed, so only the
+    // defaults of the others are computed
+    pub optional_entries: Vec<usize>,
+}
+
+// The compiled form of a program, method, block or class body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Iseq {
+    pub name: String,
+    pub kind: IseqKind,
+    pub instructions: Vec<Instruction>,
+    // Parameters first, then the other locals in the order they're assigned
+    pub locals: Vec<String>,
+    pub params: ParamLayout,
+    pub names: Vec<String>,
+    // The bytes of string literals, which escapes can make invalid UTF-8
+    pub strings: Vec<Vec<u8>>,
+    pub call_infos: Vec<CallInfo>,
+    pub children: Vec<Rc<Iseq>>,
+    // Inner regions come before the regions enclosing them
+    pub catch_table: Vec<CatchEntry>,
+    pub max_stack: usize,
+    // Where `redo` restarts a block, after the parameters' default values
+    pub body_start: usize,
+}
+
+impl CallInfo {
+    // A call with only positional arguments and an explicit receiver
+    pub fn new(method: &str, argc: usize) -> Self {
+        CallInfo {
+            method: method.to_string(),
+            argc,
+            splat: false,
+            block_arg: false,
+            block: None,
+            variable_like: false,
+            fcall: false,
+            zsuper: false,
+            keywords: false,
+            line: 0,
+            cache: CallCache::default(),
+        }
+    }
+}
+
+// Call sites that saw more receiver classes than this look the others up every time
+const POLYMORPHIC_LIMIT: usize = 4;
+
+impl CallCache {
+    pub fn lookup(&self, class: ObjectId, serial: u64) -> Option<Rc<Method>> {
+        if self.serial.get() != serial {
+            return None;
+        }
+        let entries = self.entries.borrow();
+        entries.iter().find(|(cached, _)| *cached == class).map(|(_, method)| method.clone())
+    }
+
+    pub fn fill(&self, class: ObjectId, serial: u64, method: Rc<Method>) {
+        let mut entries = self.entries.borrow_mut();
+        if self.serial.get() != serial {
+            entries.clear();
+            self.serial.set(serial);
+        }
+        if entries.len() < POLYMORPHIC_LIMIT {
+            entries.push((class, method));
+        }
+    }
+
+    // How many receiver classes it holds methods for
+    pub fn len(&self) -> usize {
+        self.entries.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Copies start out empty, and caches don't count when comparing sequences
+impl Clone for CallCache {
+    fn clone(&self) -> Self {
+        CallCache::default()
+    }
+}
+
+impl PartialEq for CallCache {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
+impl fmt::Debug for CallCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CallCache").field("serial", &self.serial.get()).field("classes", &self.len()).finish()
+    }
+}
+
+impl ParamLayout {
+    pub fn optional_count(&self) -> usize {
+        self.params.iter().filter(|param| matches!(param, ParamSlot::Optional(_))).count()
+    }
+}
+
+impl Iseq {
+    // A listing of the instructions, followed by the ones of every nested sequence
+    pub fn disassemble(&self) -> String {
+        let mut output = String::new();
+        self.write_listing(&mut output);
+        output
+    }
+
+    fn write_listing(&self, output: &mut String) {
+        output.push_str(&format!("== disasm: {} ({:?}, stack: {}) ==\n", self.name, self.kind, self.max_stack));
+        if !self.locals.is_empty() {
+            output.push_str(&format!("locals: {}\n", self.locals.join(", ")));
+        }
+        for entry in &self.catch_table {
+            output.push_str(&format!(
+                "catch {:?} {:04}..{:04} => {:04} (depth {})\n",
+                entry.kind, entry.start, entry.end, entry.target, entry.depth
+            ));
+        }
+        for (address, instruction) in self.instructions.iter().enumerate() {
+            output.push_str(&format!("{:04} {}\n", address, self.describe(*instruction)));
+        }
+        for child in &self.children {
+            output.push('\n');
+            child.write_listing(output);
+        }
+    }
+
+    // `argc: 1, splat, kw, &block, block: name, vcall`, the flags only when they're set
+    fn call_operands(&self, info: &CallInfo) -> String {
+        let mut text = format!("argc: {}", info.argc);
+        if info.splat {
+            text.push_str(", splat");
+        }
+        if info.keywords {
+            text.push_str(", kw");
+        }
+        if info.block_arg {
+            text.push_str(", &block");
+        }
+        if let Some(block) = info.block {
+            text.push_str(&format!(", block: {}", self.children[block].name));
+        }
+        if info.variable_like {
+            text.push_str(", vcall");
+        } else if info.fcall {
+            text.push_str(", fcall");
+        }
+        if info.zsuper {
+            text.push_str(", zsuper");
+        }
+        text
+    }
+
+    fn describe(&self, instruction: Instruction) -> String {
+        let name = |index: usize| &self.names[index];
+        let child = |index: usize| &self.children[index].name;
+        match instruction {
+            Instruction::PutNil => "putnil".to_string(),
+            Instruction::PutTrue => "puttrue".to_string(),
+            Instruction::PutFalse => "putfalse".to_string(),
+            Instruction::PutSelf => "putself".to_string(),
+            Instruction::PutInteger(value) => format!("putinteger {}", value),
+            Instruction::PutFloat(value) => format!("putfloat {:?}", value),
+            Instruction::PutBignum(index) => format!("putbignum {}", name(index)),
+            Instruction::PutString(index) => format!("putstring {:?}", String::from_utf8_lossy(&self.strings[index])),
+            Instruction::PutRegexp(source, options) => format!("putregexp /{}/{}", name(source), name(options)),
+            Instruction::PutSymbol(index) => format!("putsymbol :{}", name(index)),
+            Instruction::ObjToString => "objtostring".to_string(),
+            Instruction::ConcatStrings(count) => format!("concatstrings {}", count),
+            Instruction::Pop => "pop".to_string(),
+            Instruction::Dup => "dup".to_string(),
+            Instruction::TopN(n) => format!("topn {}", n),
+            Instruction::Adjust(n) => format!("adjust {}", n),
+            Instruction::GetLocal { index, level } => format!("getlocal {}@{}", self.local_name(index, level), level),
+            Instruction::SetLocal { index, level } => format!("setlocal {}@{}", self.local_name(index, level), level),
+            Instruction::GetInstanceVariable(index) => format!("getivar @{}", name(index)),
+            Instruction::SetInstanceVariable(index) => format!("setivar @{}", name(index)),
+            Instruction::GetConstant(index) => format!("getconstant {}", name(index)),
+            Instruction::GetScopedConstant(index) => format!("getscopedconstant {}", name(index)),
+            Instruction::GetTopConstant(index) => format!("gettopconstant {}", name(index)),
+            Instruction::SetConstant { name: constant, scope } => format!("setconstant {}, {:?}", name(constant), scope),
+            Instruction::NewArray(count) => format!("newarray {}", count),
+            Instruction::SplatArray => "splatarray".to_string(),
+            Instruction::ConcatArray => "concatarray".to_string(),
+            Instruction::ToArray => "toarray".to_string(),
+            Instruction::ExpandArray { before, after, splat } => {
+                format!("expandarray {}, {}{}", before, after, if splat { ", splat" } else { "" })
+            }
+            Instruction::NewHash(count) => format!("newhash {}", count),
+            Instruction::MergeHash => "mergehash".to_string(),
+            Instruction::NewRange { exclusive } => format!("newrange {}", if exclusive { "..." } else { ".." }),
+            Instruction::Not => "not".to_string(),
+            Instruction::Send(index) => {
+                let info = &self.call_infos[index];
+                format!("send :{}, {}", info.method, self.call_operands(info))
+            }
+            Instruction::InvokeSuper(index) => format!("invokesuper {}", self.call_operands(&self.call_infos[index])),
+            Instruction::InvokeBlock { argc, splat, keywords } => format!(
+                "invokeblock argc: {}{}{}",
+                argc,
+                if splat { ", splat" } else { "" },
+                if keywords { ", kw" } else { "" }
+            ),
+            Instruction::Lambda(index) => format!("lambda {}", child(index)),
+            Instruction::DefineMethod { name: method, body } => format!("definemethod :{}, {}", name(method), child(body)),
+            Instruction::DefineSingletonMethod { name: method, body } => {
+                format!("definesmethod :{}, {}", name(method), child(body))
+            }
+            Instruction::DefineClass { kind, name: class, body, scope } => {
+                let kind = match kind {
+                    ClassKind::Class { superclass: true } => "class, superclass",
+                    ClassKind::Class { superclass: false } => "class",
+                    ClassKind::Module => "module",
+                };
+                format!("defineclass {}, {}, {:?}, {}", name(class), kind, scope, child(body))
+            }
+            Instruction::DefineSingletonClass(body) => format!("definesingletonclass {}", child(body)),
+            Instruction::Jump(target) => format!("jump {:04}", target),
+            Instruction::BranchIf(target) => format!("branchif {:04}", target),
+            Instruction::BranchUnless(target) => format!("branchunless {:04}", target),
+            Instruction::Leave => "leave".to_string(),
+            Instruction::Throw(kind) => format!("throw {:?}", kind),
+            Instruction::EndEnsure(handler) => format!("endensure {:04}", handler),
+            Instruction::GetErrinfo => "geterrinfo".to_string(),
+            Instruction::SetErrinfo => "seterrinfo".to_string(),
+            Instruction::CheckMatch(kind) => format!("checkmatch {:?}", kind),
+            Instruction::CheckKeyword(index) => format!("checkkeyword {}", index),
+            Instruction::CheckLength { length, rest } => format!("checklength {}{}", length, if rest { "+" } else { "" }),
+            Instruction::Deconstruct => "deconstruct".to_string(),
+            Instruction::DeconstructKeys => "deconstructkeys".to_string(),
+            Instruction::DeleteKey(index) => format!("deletekey :{}", name(index)),
+        }
+    }
+
+    // Locals of enclosing scopes aren't known here, so those only show their slot
+    fn local_name(&self, index: usize, level: usize) -> String {
+        match (level, self.locals.get(index)) {
+            (0, Some(name)) => name.clone(),
+            _ => format!("#{}", index),
+        }
+    }
+}
+
+impl fmt::Display for Iseq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.disassemble())
+    }
+}
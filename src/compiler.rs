@@ -0,0 +1,1554 @@
+use std::rc::Rc;
+
+use crate::ast::{
+    Begin, Block, Call, Case, CaseIn, Class, Def, For, HashElement, HashPatternRest, Module, Node, OpAssign, Param,
+    Params, Pattern, RangeKind, Super, Target, While,
+};
+use crate::bytecode::{
+    CallInfo, CatchEntry, CatchKind, ClassKind, ClassScope, Instruction, Iseq, IseqKind, MatchKind,
+    ParamLayout, ParamSlot, ParamTarget, ThrowKind,
+};
+
+// Compiles a program into the instruction sequence of its top level. Programs with
+// syntax errors are never compiled, so this can't fail.
+pub fn compile(program: &[Node]) -> Rc<Iseq> {
+    let mut compiler = Compiler { builders: vec![Builder::new("<main>", IseqKind::Top)], line: 1 };
+    compiler.compile_params(&Params::default());
+    compiler.compile_body(program);
+    compiler.emit(Instruction::Leave);
+    Rc::new(compiler.builders.pop().expect("the top level is being compiled").finish())
+}
+
+// A jump target, resolved to an instruction index once the sequence is finished
+#[derive(Clone, Copy)]
+struct Label(usize);
+
+// Where `break`, `next` and `redo` inside a `while` or `for` go
+#[derive(Clone, Copy)]
+struct Loop {
+    break_label: Label,
+    next_label: Label,
+    redo_label: Label,
+    depth: usize,
+    ensures: usize,
+}
+
+// Where `retry` inside a rescue clause goes
+#[derive(Clone, Copy)]
+struct Retry {
+    label: Label,
+    depth: usize,
+    ensures: usize,
+}
+
+// An instruction sequence being compiled. Jump operands and catch entries hold label
+// numbers until `finish` replaces them with addresses.
+struct Builder {
+    name: String,
+    kind: IseqKind,
+    instructions: Vec<Instruction>,
+    locals: Vec<String>,
+    params: ParamLayout,
+    names: Vec<String>,
+    strings: Vec<Vec<u8>>,
+    call_infos: Vec<CallInfo>,
+    children: Vec<Rc<Iseq>>,
+    catch_table: Vec<CatchEntry>,
+    labels: Vec<Option<usize>>,
+    // The stack depth at each label, known from the jumps to it
+    label_depths: Vec<Option<usize>>,
+    depth: usize,
+    max_depth: usize,
+    loops: Vec<Loop>,
+    retries: Vec<Retry>,
+    // How many `ensure` bodies enclose the code being compiled. Jumping out of one has
+    // to run it, so `break` and friends are thrown instead.
+    ensures: usize,
+    optional_entries: Vec<Label>,
+    body_start: Option<Label>,
+    temporaries: usize,
+    // The arguments a bare `super` in a method passes along
+    forwarded: Vec<Node>,
+}
+
+struct Compiler {
+    // The sequence being compiled last, below it the ones it's nested in
+    builders: Vec<Builder>,
+    // The line of the call being compiled, which the calls emitted for it are on
+    line: usize,
+}
+
+impl Builder {
+    fn new(name: &str, kind: IseqKind) -> Self {
+        Builder {
+            name: name.to_string(),
+            kind,
+            instructions: Vec::new(),
+            locals: Vec::new(),
+            params: ParamLayout::default(),
+            names: Vec::new(),
+            strings: Vec::new(),
+            call_infos: Vec::new(),
+            children: Vec::new(),
+            catch_table: Vec::new(),
+            labels: Vec::new(),
+            label_depths: Vec::new(),
+            depth: 0,
+            max_depth: 0,
+            loops: Vec::new(),
+            retries: Vec::new(),
+            ensures: 0,
+            optional_entries: Vec::new(),
+            body_start: None,
+            temporaries: 0,
+            forwarded: Vec::new(),
+        }
+    }
+
+    fn finish(self) -> Iseq {
+        let labels = self.labels;
+        let address = |label: usize| labels[label].expect("every label is placed");
+        let instructions = self
+            .instructions
+            .into_iter()
+            .map(|instruction| match instruction {
+                Instruction::Jump(label) => Instruction::Jump(address(label)),
+                Instruction::BranchIf(label) => Instruction::BranchIf(address(label)),
+                Instruction::BranchUnless(label) => Instruction::BranchUnless(address(label)),
+                Instruction::EndEnsure(label) => Instruction::EndEnsure(address(label)),
+                instruction => instruction,
+            })
+            .collect();
+        let catch_table = self
+            .catch_table
+            .into_iter()
+            .map(|entry| CatchEntry {
+                start: address(entry.start),
+                end: address(entry.end),
+                target: address(entry.target),
+                ..entry
+            })
+            .collect();
+        let params = ParamLayout {
+            optional_entries: self.optional_entries.iter().map(|label| address(label.0)).collect(),
+            ..self.params
+        };
+
+        Iseq {
+            name: self.name,
+            kind: self.kind,
+            instructions,
+            locals: self.locals,
+            params,
+            names: self.names,
+            strings: self.strings,
+            call_infos: self.call_infos,
+            children: self.children,
+            catch_table,
+            max_stack: self.max_depth,
+            body_start: self.body_start.map_or(0, |label| address(label.0)),
+        }
+    }
+}
+
+impl Compiler {
+    fn current(&mut self) -> &mut Builder {
+        self.builders.last_mut().expect("something is being compiled")
+    }
+
+    fn depth(&mut self) -> usize {
+        self.current().depth
+    }
+
+    fn set_depth(&mut self, depth: usize) {
+        let builder = self.current();
+        builder.depth = depth;
+        builder.max_depth = builder.max_depth.max(depth);
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        let builder = self.current();
+        let effect = stack_effect(builder, instruction);
+        let depth = builder.depth as isize + effect;
+        debug_assert!(depth >= 0, "{:?} pops more than the stack holds", instruction);
+        builder.instructions.push(instruction);
+        self.set_depth(depth as usize);
+    }
+
+    fn label(&mut self) -> Label {
+        let builder = self.current();
+        builder.labels.push(None);
+        builder.label_depths.push(None);
+        Label(builder.labels.len() - 1)
+    }
+
+    // Places the label at the next instruction. Code that is only reached by jumping
+    // there continues at the depth the jumps left the stack at.
+    fn place(&mut self, label: Label) {
+        let builder = self.current();
+        builder.labels[label.0] = Some(builder.instructions.len());
+        if let Some(depth) = builder.label_depths[label.0] {
+            self.set_depth(depth);
+        }
+    }
+
+    // Places a label only reached through the catch table, which sets the depth itself
+    fn place_handler(&mut self, label: Label, depth: usize) {
+        self.place(label);
+        self.set_depth(depth);
+    }
+
+    fn jump(&mut self, instruction: fn(usize) -> Instruction, label: Label) {
+        self.emit(instruction(label.0));
+        let builder = self.current();
+        builder.label_depths[label.0] = Some(builder.depth);
+    }
+
+    fn catch(&mut self, kind: CatchKind, start: Label, end: Label, target: Label, depth: usize) {
+        let entry = CatchEntry { kind, start: start.0, end: end.0, target: target.0, depth };
+        self.current().catch_table.push(entry);
+    }
+
+    // After a jump or throw in the middle of an expression, the code that follows is
+    // compiled as if the expression had pushed its value like any other
+    fn transferred(&mut self, depth: usize) {
+        self.set_depth(depth + 1);
+    }
+
+    // Pops what the current expression pushed above `depth`
+    fn pop_to(&mut self, depth: usize) {
+        match self.depth() - depth {
+            0 => {}
+            1 => self.emit(Instruction::Pop),
+            extra => {
+                self.emit(Instruction::Adjust(extra - 1));
+                self.emit(Instruction::Pop);
+            }
+        }
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> usize {
+        let builder = self.current();
+        match builder.strings.iter().position(|string| string == bytes) {
+            Some(index) => index,
+            None => {
+                builder.strings.push(bytes.to_vec());
+                builder.strings.len() - 1
+            }
+        }
+    }
+
+    fn name(&mut self, text: &str) -> usize {
+        let builder = self.current();
+        match builder.names.iter().position(|name| name == text) {
+            Some(index) => index,
+            None => {
+                builder.names.push(text.to_string());
+                builder.names.len() - 1
+            }
+        }
+    }
+
+    fn send(&mut self, info: CallInfo) {
+        let index = self.call_info(info);
+        self.emit(Instruction::Send(index));
+    }
+
+    fn call_info(&mut self, info: CallInfo) -> usize {
+        let line = self.line;
+        let builder = self.current();
+        builder.call_infos.push(CallInfo { line, ..info });
+        builder.call_infos.len() - 1
+    }
+
+    // A call with plain positional arguments, already on the stack
+    fn call(&mut self, method: &str, argc: usize) {
+        self.send(CallInfo::new(method, argc));
+    }
+
+    // Locals
+
+    // The slot of a variable visible from the code being compiled, looking through
+    // the blocks it's nested in, and how many blocks out it is
+    fn resolve(&self, name: &str) -> Option<(usize, usize)> {
+        for (level, builder) in self.builders.iter().rev().enumerate() {
+            if let Some(index) = builder.locals.iter().position(|local| local == name) {
+                return Some((index, level));
+            }
+            if builder.kind != IseqKind::Block {
+                break;
+            }
+        }
+        None
+    }
+
+    // A visible variable, or a new one in the innermost scope
+    fn local(&mut self, name: &str) -> (usize, usize) {
+        match self.resolve(name) {
+            Some(local) => local,
+            None => (self.declare(name), 0),
+        }
+    }
+
+    // A variable of the innermost scope, shadowing outer ones (for parameters)
+    fn declare(&mut self, name: &str) -> usize {
+        let builder = self.current();
+        match builder.locals.iter().position(|local| local == name) {
+            Some(index) => index,
+            None => {
+                builder.locals.push(name.to_string());
+                builder.locals.len() - 1
+            }
+        }
+    }
+
+    // A hidden variable, whose name can't clash with the program's
+    fn temporary(&mut self) -> usize {
+        let builder = self.current();
+        builder.temporaries += 1;
+        let name = format!("#tmp{}", builder.temporaries);
+        self.declare(&name)
+    }
+
+    fn get_local(&mut self, (index, level): (usize, usize)) {
+        self.emit(Instruction::GetLocal { index, level });
+    }
+
+    fn set_local(&mut self, (index, level): (usize, usize)) {
+        self.emit(Instruction::SetLocal { index, level });
+    }
+
+    // Nested sequences
+
+    fn compile_child(&mut self, name: &str, kind: IseqKind, compile: impl FnOnce(&mut Self)) -> usize {
+        self.builders.push(Builder::new(name, kind));
+        compile(self);
+        self.emit(Instruction::Leave);
+        let child = self.builders.pop().expect("the child is being compiled").finish();
+        let builder = self.current();
+        builder.children.push(Rc::new(child));
+        builder.children.len() - 1
+    }
+
+    fn compile_block(&mut self, block: &Block) -> usize {
+        let owner = self.builders.iter().rev().find(|builder| builder.kind != IseqKind::Block);
+        let name = format!("block in {}", owner.map_or("<main>", |builder| builder.name.as_str()));
+        self.compile_child(&name, IseqKind::Block, |compiler| {
+            compiler.compile_params(&block.params());
+            compiler.compile_body(&block.body);
+        })
+    }
+
+    // Declares the parameters, then compiles the code computing their default values
+    fn compile_params(&mut self, params: &Params) {
+        let mut slots = Vec::new();
+        for param in &params.params {
+            slots.push(match param {
+                Param::Required(name) => ParamSlot::Required(self.declare(name)),
+                Param::Destructure(inner) => ParamSlot::Destructure(self.param_targets(inner)),
+                Param::Optional(name, _) => ParamSlot::Optional(self.declare(name)),
+                Param::Rest(_) => ParamSlot::Rest(param.rest_local().map(|name| self.declare(name))),
+                Param::RequiredKeyword(name) | Param::OptionalKeyword(name, _) => ParamSlot::Keyword {
+                    name: name.clone(),
+                    slot: self.declare(name),
+                    required: matches!(param, Param::RequiredKeyword(_)),
+                },
+                Param::KeywordRest(_) => ParamSlot::KeywordRest(param.rest_local().map(|name| self.declare(name))),
+                Param::Block(_) => ParamSlot::Block(param.rest_local().map(|name| self.declare(name))),
+            });
+        }
+        for local in &params.locals {
+            self.declare(local);
+        }
+
+        let mut entries = Vec::new();
+        for param in &params.params {
+            if let Param::Optional(name, default) = param {
+                let entry = self.label();
+                self.place(entry);
+                entries.push(entry);
+                self.compile_node(default);
+                let local = self.local(name);
+                self.set_local(local);
+            }
+        }
+        let entry = self.label();
+        self.place(entry);
+        entries.push(entry);
+
+        let keywords = params.params.iter().filter(|param| matches!(param, Param::RequiredKeyword(_) | Param::OptionalKeyword(..)));
+        for (index, param) in keywords.enumerate() {
+            if let Param::OptionalKeyword(name, default) = param {
+                let given = self.label();
+                self.emit(Instruction::CheckKeyword(index));
+                self.jump(Instruction::BranchIf, given);
+                self.compile_node(default);
+                let local = self.local(name);
+                self.set_local(local);
+                self.place(given);
+            }
+        }
+
+        let body_start = self.label();
+        self.place(body_start);
+        let builder = self.current();
+        builder.params = ParamLayout { params: slots, arity: params.arity(), optional_entries: Vec::new() };
+        builder.optional_entries = entries;
+        builder.body_start = Some(body_start);
+    }
+
+    // Destructuring parameters only hold names, splats and more destructuring
+    fn param_targets(&mut self, params: &[Param]) -> Vec<ParamTarget> {
+        params
+            .iter()
+            .filter_map(|param| match param {
+                Param::Destructure(inner) => Some(ParamTarget::Nested(self.param_targets(inner))),
+                Param::Rest(name) => Some(ParamTarget::Splat(name.as_deref().map(|name| self.declare(name)))),
+                Param::Required(name)
+                | Param::Optional(name, _)
+                | Param::RequiredKeyword(name)
+                | Param::OptionalKeyword(name, _) => Some(ParamTarget::Local(self.declare(name))),
+                Param::KeywordRest(name) | Param::Block(name) => {
+                    name.as_deref().map(|name| ParamTarget::Local(self.declare(name)))
+                }
+            })
+            .collect()
+    }
+
+    // Expressions. Every node leaves exactly one value on the stack.
+
+    fn compile_body(&mut self, nodes: &[Node]) {
+        match nodes.split_last() {
+            Some((last, rest)) => {
+                for node in rest {
+                    self.compile_node(node);
+                    self.emit(Instruction::Pop);
+                }
+                self.compile_node(last);
+            }
+            None => self.emit(Instruction::PutNil),
+        }
+    }
+
+    fn compile_optional(&mut self, node: &Option<Box<Node>>) {
+        match node {
+            Some(node) => self.compile_node(node),
+            None => self.emit(Instruction::PutNil),
+        }
+    }
+
+    fn compile_node(&mut self, node: &Node) {
+        match node {
+            Node::Integer(value) => self.emit(Instruction::PutInteger(*value)),
+            Node::Float(value) => self.emit(Instruction::PutFloat(*value)),
+            Node::Bignum(digits) => {
+                let index = self.name(digits);
+                self.emit(Instruction::PutBignum(index));
+            }
+            Node::Str(text) => {
+                let index = self.string(text.as_bytes());
+                self.emit(Instruction::PutString(index));
+            }
+            Node::Bytes(bytes) => {
+                let index = self.string(bytes);
+                self.emit(Instruction::PutString(index));
+            }
+            Node::InterpolatedString(parts) => {
+                for part in parts {
+                    self.compile_node(part);
+                    if !matches!(part, Node::Str(_) | Node::Bytes(_)) {
+                        self.emit(Instruction::ObjToString);
+                    }
+                }
+                self.emit(Instruction::ConcatStrings(parts.len()));
+            }
+            Node::Regexp(source, options) => {
+                let (source, options) = (self.name(source), self.name(options));
+                self.emit(Instruction::PutRegexp(source, options));
+            }
+            Node::Symbol(name) => {
+                let index = self.name(name);
+                self.emit(Instruction::PutSymbol(index));
+            }
+            Node::Nil => self.emit(Instruction::PutNil),
+            Node::True => self.emit(Instruction::PutTrue),
+            Node::False => self.emit(Instruction::PutFalse),
+            Node::SelfNode => self.emit(Instruction::PutSelf),
+            Node::LocalVariable(name) => {
+                let local = self.local(name);
+                self.get_local(local);
+            }
+            // Declared before the value, which can refer to it from a block
+            Node::LocalAssign(name, value) => {
+                let local = self.local(name);
+                self.compile_node(value);
+                self.emit(Instruction::Dup);
+                self.set_local(local);
+            }
+            Node::Constant(name) => {
+                let index = self.name(name);
+                self.emit(Instruction::GetConstant(index));
+            }
+            Node::ScopedConstant(scope, name) => {
+                let index = self.name(name);
+                match scope {
+                    Some(scope) => {
+                        self.compile_node(scope);
+                        self.emit(Instruction::GetScopedConstant(index));
+                    }
+                    None => self.emit(Instruction::GetTopConstant(index)),
+                }
+            }
+            Node::InstanceVariable(name) => {
+                let index = self.name(name);
+                self.emit(Instruction::GetInstanceVariable(index));
+            }
+            Node::ConstantAssign(path, value) => {
+                let (scope, name) = self.compile_definition_target(path);
+                self.compile_node(value);
+                let name = self.name(&name);
+                self.emit(Instruction::SetConstant { name, scope });
+            }
+            Node::InstanceVariableAssign(name, value) => {
+                self.compile_node(value);
+                self.emit(Instruction::Dup);
+                let index = self.name(name);
+                self.emit(Instruction::SetInstanceVariable(index));
+            }
+            Node::MultipleAssign(targets, value) => {
+                for target in targets {
+                    for name in target.locals() {
+                        self.local(name);
+                    }
+                }
+                self.compile_node(value);
+                self.emit(Instruction::Dup);
+                self.emit(Instruction::ToArray);
+                self.compile_destructure(targets);
+            }
+            Node::OpAssign(op_assign) => self.compile_op_assign(op_assign),
+            Node::Call(call) => self.compile_call(call),
+            Node::Array(elements) => {
+                let (count, splat) = self.compile_values(elements);
+                if !splat {
+                    self.emit(Instruction::NewArray(count));
+                }
+            }
+            Node::Hash(elements) | Node::KeywordHash(elements) => self.compile_hash(elements),
+            Node::Range(start, end, kind) => {
+                self.compile_optional(start);
+                self.compile_optional(end);
+                self.emit(Instruction::NewRange { exclusive: *kind == RangeKind::Exclusive });
+            }
+            Node::Splat(value) => {
+                self.compile_node(value);
+                self.emit(Instruction::SplatArray);
+            }
+            Node::BlockPass(value) => self.compile_node(value),
+            Node::Lambda(block) => {
+                let index = self.compile_block(block);
+                self.emit(Instruction::Lambda(index));
+            }
+            Node::Def(def) => self.compile_def(def),
+            Node::Class(class) => self.compile_class(class),
+            Node::Module(module) => self.compile_module(module),
+            Node::SingletonClass(target, body) => {
+                self.compile_node(target);
+                let index = self.compile_child("singleton class", IseqKind::Class, |compiler| compiler.compile_body(body));
+                self.emit(Instruction::DefineSingletonClass(index));
+            }
+            Node::If(node) => {
+                let (otherwise, end) = (self.label(), self.label());
+                self.compile_node(&node.condition);
+                self.jump(Instruction::BranchUnless, otherwise);
+                self.compile_body(&node.then_body);
+                self.jump(Instruction::Jump, end);
+                self.place(otherwise);
+                self.compile_body(&node.else_body);
+                self.place(end);
+            }
+            Node::And(left, right) | Node::Or(left, right) => {
+                let end = self.label();
+                self.compile_node(left);
+                self.emit(Instruction::Dup);
+                let branch = if matches!(node, Node::And(..)) { Instruction::BranchUnless } else { Instruction::BranchIf };
+                self.jump(branch, end);
+                self.emit(Instruction::Pop);
+                self.compile_node(right);
+                self.place(end);
+            }
+            Node::Not(value) => {
+                self.compile_node(value);
+                self.emit(Instruction::Not);
+            }
+            Node::Return(value) => {
+                let depth = self.depth();
+                self.compile_optional(value);
+                let builder = self.current();
+                if matches!(builder.kind, IseqKind::Method | IseqKind::Top) && builder.ensures == 0 {
+                    self.emit(Instruction::Leave);
+                } else {
+                    self.emit(Instruction::Throw(ThrowKind::Return));
+                }
+                self.transferred(depth);
+            }
+            Node::While(node) => self.compile_while(node),
+            Node::For(node) => self.compile_for(node),
+            Node::Break(value) => self.compile_break(value),
+            Node::Next(value) => self.compile_next(value),
+            Node::Redo => self.compile_redo(),
+            Node::Retry => self.compile_retry(),
+            Node::Begin(begin) => self.compile_begin(begin),
+            Node::Case(case) => self.compile_case(case),
+            Node::CaseIn(case) => self.compile_case_in(case),
+            Node::Yield(args) => {
+                let (argc, splat) = self.compile_values(args);
+                self.emit(Instruction::InvokeBlock { argc, splat, keywords: passes_keywords(args) });
+            }
+            Node::Super(node) => self.compile_super(node),
+            Node::Error => unreachable!("programs with syntax errors aren't compiled"),
+        }
+    }
+
+    // Pushes the values of an argument list or array literal, leaving out `&block`.
+    // With a splat among them, they're gathered in a single new array instead.
+    fn compile_values(&mut self, nodes: &[Node]) -> (usize, bool) {
+        let nodes: Vec<&Node> = nodes.iter().filter(|node| !matches!(node, Node::BlockPass(_))).collect();
+        if !nodes.iter().any(|node| matches!(node, Node::Splat(_))) {
+            for node in &nodes {
+                self.compile_node(node);
+            }
+            return (nodes.len(), false);
+        }
+
+        let mut gathered = false;
+        let mut pending = 0;
+        for node in nodes {
+            if let Node::Splat(value) = node {
+                if pending > 0 {
+                    self.emit(Instruction::NewArray(pending));
+                    if gathered {
+                        self.emit(Instruction::ConcatArray);
+                    }
+                    gathered = true;
+                    pending = 0;
+                }
+                self.compile_node(value);
+                self.emit(Instruction::SplatArray);
+                if gathered {
+                    self.emit(Instruction::ConcatArray);
+                }
+                gathered = true;
+            } else {
+                self.compile_node(node);
+                pending += 1;
+            }
+        }
+        if pending > 0 {
+            self.emit(Instruction::NewArray(pending));
+            self.emit(Instruction::ConcatArray);
+        }
+        (1, true)
+    }
+
+    fn compile_hash(&mut self, elements: &[HashElement]) {
+        let mut started = false;
+        let mut pairs = 0;
+        for element in elements {
+            match element {
+                HashElement::Pair(key, value) => {
+                    self.compile_node(key);
+                    self.compile_node(value);
+                    pairs += 1;
+                }
+                HashElement::DoubleSplat(value) => {
+                    self.flush_pairs(&mut started, &mut pairs);
+                    self.compile_node(value);
+                    self.emit(Instruction::MergeHash);
+                }
+            }
+        }
+        self.flush_pairs(&mut started, &mut pairs);
+    }
+
+    // Turns the pairs on the stack into a hash, merged into the one being built if there is one
+    fn flush_pairs(&mut self, started: &mut bool, pairs: &mut usize) {
+        if !*started {
+            self.emit(Instruction::NewHash(*pairs));
+            *started = true;
+        } else if *pairs > 0 {
+            self.emit(Instruction::NewHash(*pairs));
+            self.emit(Instruction::MergeHash);
+        }
+        *pairs = 0;
+    }
+
+    fn compile_call(&mut self, call: &Call) {
+        match &call.receiver {
+            Some(receiver) => self.compile_node(receiver),
+            None => self.emit(Instruction::PutSelf),
+        }
+        let (argc, splat, block_arg) = self.compile_arguments(&call.args);
+        let block = call.block.as_ref().map(|block| self.compile_block(block));
+        let variable_like = call.receiver.is_none() && call.args.is_empty() && call.block.is_none();
+        let fcall = matches!(call.receiver.as_deref(), None | Some(Node::SelfNode));
+        self.line = call.line.0;
+        let keywords = passes_keywords(&call.args);
+        self.send(CallInfo { splat, keywords, block_arg, block, variable_like, fcall, ..CallInfo::new(&call.method, argc) });
+    }
+
+    // Pushes the arguments of a call, then `&block` if it has one
+    fn compile_arguments(&mut self, args: &[Node]) -> (usize, bool, bool) {
+        let (argc, splat) = self.compile_values(args);
+        let block_arg = match args.last() {
+            Some(Node::BlockPass(value)) => {
+                self.compile_node(value);
+                true
+            }
+            _ => false,
+        };
+        (argc, splat, block_arg)
+    }
+
+    fn compile_super(&mut self, node: &Super) {
+        let forwarded;
+        let args = match &node.args {
+            Some(args) => args,
+            None => {
+                forwarded = self.forwarded_arguments();
+                &forwarded
+            }
+        };
+        self.emit(Instruction::PutSelf);
+        let (argc, splat, block_arg) = self.compile_arguments(args);
+        let block = node.block.as_ref().map(|block| self.compile_block(block));
+        let zsuper = node.args.is_none();
+        self.line = node.line.0;
+        let keywords = passes_keywords(args);
+        let index = self.call_info(CallInfo { splat, keywords, block_arg, block, zsuper, ..CallInfo::new("super", argc) });
+        self.emit(Instruction::InvokeSuper(index));
+    }
+
+    // What a bare `super` passes: the parameters of the method it's in, blocks included.
+    // Outside of methods it fails when it runs.
+    fn forwarded_arguments(&self) -> Vec<Node> {
+        for builder in self.builders.iter().rev() {
+            match builder.kind {
+                IseqKind::Block => continue,
+                IseqKind::Method => return builder.forwarded.clone(),
+                _ => break,
+            }
+        }
+        Vec::new()
+    }
+
+    // Assignment
+
+    // Assigns the elements of the array on top to the targets, consuming it
+    fn compile_destructure(&mut self, targets: &[Target]) {
+        let splat = targets.iter().position(|target| matches!(target, Target::Splat(_)));
+        let before = splat.unwrap_or(targets.len());
+        let after = splat.map_or(0, |splat| targets.len() - splat - 1);
+        self.emit(Instruction::ExpandArray { before, after, splat: splat.is_some() });
+        for target in targets {
+            self.compile_assign_target(target);
+        }
+    }
+
+    // Assigns the value on top to the target, consuming it
+    fn compile_assign_target(&mut self, target: &Target) {
+        match target {
+            Target::Local(name) => {
+                let local = self.local(name);
+                self.set_local(local);
+            }
+            Target::InstanceVariable(name) => {
+                let index = self.name(name);
+                self.emit(Instruction::SetInstanceVariable(index));
+            }
+            Target::Index(receiver, args) => {
+                self.compile_node(receiver);
+                let (argc, splat) = self.compile_values(args);
+                self.emit(Instruction::TopN(argc + 1));
+                self.compile_index_assign(argc, splat);
+                self.emit(Instruction::Pop);
+                self.emit(Instruction::Pop);
+            }
+            Target::Attribute(receiver, name) => {
+                self.compile_node(receiver);
+                self.emit(Instruction::TopN(1));
+                self.call(&format!("{}=", name), 1);
+                self.emit(Instruction::Pop);
+                self.emit(Instruction::Pop);
+            }
+            Target::Splat(Some(target)) => self.compile_assign_target(target),
+            Target::Splat(None) => self.emit(Instruction::Pop),
+            Target::Nested(targets) => {
+                self.emit(Instruction::ToArray);
+                self.compile_destructure(targets);
+            }
+        }
+    }
+
+    // `receiver[args] = value`, with the receiver, the arguments and the value on the stack
+    fn compile_index_assign(&mut self, argc: usize, splat: bool) {
+        if splat {
+            self.emit(Instruction::NewArray(1));
+            self.emit(Instruction::ConcatArray);
+            self.send(CallInfo { splat: true, ..CallInfo::new("[]=", 1) });
+        } else {
+            self.call("[]=", argc + 1);
+        }
+    }
+
+    // The receiver and index of the target are evaluated once, and left on the stack
+    // under the current value for the assignment
+    fn compile_op_assign(&mut self, op_assign: &OpAssign) {
+        let (short, end) = (self.label(), self.label());
+        match &op_assign.target {
+            Target::Local(name) => {
+                let local = self.local(name);
+                self.get_local(local);
+                self.compile_operation(op_assign, short);
+                self.emit(Instruction::Dup);
+                self.set_local(local);
+                self.place(short);
+            }
+            Target::InstanceVariable(name) => {
+                let index = self.name(name);
+                self.emit(Instruction::GetInstanceVariable(index));
+                self.compile_operation(op_assign, short);
+                self.emit(Instruction::Dup);
+                self.emit(Instruction::SetInstanceVariable(index));
+                self.place(short);
+            }
+            Target::Index(receiver, args) => {
+                self.compile_node(receiver);
+                let (argc, splat) = self.compile_values(args);
+                for _ in 0..=argc {
+                    self.emit(Instruction::TopN(argc));
+                }
+                self.line = op_assign.line.0;
+                self.send(CallInfo { splat, ..CallInfo::new("[]", argc) });
+                self.compile_operation(op_assign, short);
+                self.compile_index_assign(argc, splat);
+                self.place_short_circuit(op_assign, short, end, argc + 1);
+            }
+            Target::Attribute(receiver, name) => {
+                self.compile_node(receiver);
+                self.emit(Instruction::Dup);
+                self.line = op_assign.line.0;
+                self.call(name, 0);
+                self.compile_operation(op_assign, short);
+                self.call(&format!("{}=", name), 1);
+                self.place_short_circuit(op_assign, short, end, 1);
+            }
+            Target::Splat(_) | Target::Nested(_) => unreachable!("the parser only allows single targets in `op=`"),
+        }
+    }
+
+    // Where `||=` and `&&=` end up without assigning, dropping the receiver and index under the current value
+    fn place_short_circuit(&mut self, op_assign: &OpAssign, short: Label, end: Label, kept: usize) {
+        if is_short_circuit(&op_assign.operator) {
+            self.jump(Instruction::Jump, end);
+            self.place(short);
+            self.emit(Instruction::Adjust(kept));
+            self.place(end);
+        }
+    }
+
+    // Turns the current value on top into the new one. `||=` and `&&=` jump to `short`
+    // with the current value when there's nothing to assign.
+    fn compile_operation(&mut self, op_assign: &OpAssign, short: Label) {
+        match op_assign.operator.as_str() {
+            operator if is_short_circuit(operator) => {
+                self.emit(Instruction::Dup);
+                let branch = if operator == "||" { Instruction::BranchIf } else { Instruction::BranchUnless };
+                self.jump(branch, short);
+                self.emit(Instruction::Pop);
+                self.compile_node(&op_assign.value);
+                self.line = op_assign.line.0;
+            }
+            operator => {
+                self.compile_node(&op_assign.value);
+                self.line = op_assign.line.0;
+                self.call(operator, 1);
+            }
+        }
+    }
+
+    // Definitions
+
+    fn compile_def(&mut self, def: &Def) {
+        if let Some(target) = &def.singleton {
+            self.compile_node(target);
+        }
+        let body = self.compile_child(&def.name, IseqKind::Method, |compiler| {
+            compiler.current().forwarded = def.params.forwarded();
+            compiler.compile_params(&def.params);
+            compiler.compile_body(&def.body);
+        });
+        let name = self.name(&def.name);
+        match def.singleton {
+            Some(_) => self.emit(Instruction::DefineSingletonMethod { name, body }),
+            None => self.emit(Instruction::DefineMethod { name, body }),
+        }
+    }
+
+    // Pushes the scope of a `class` or `module` path or an assigned constant if it has one,
+    // and returns the constant's name
+    fn compile_definition_target(&mut self, path: &Node) -> (ClassScope, String) {
+        match path {
+            Node::Constant(name) => (ClassScope::Lexical, name.clone()),
+            Node::ScopedConstant(Some(scope), name) => {
+                self.compile_node(scope);
+                (ClassScope::Explicit, name.clone())
+            }
+            Node::ScopedConstant(None, name) => (ClassScope::Top, name.clone()),
+            _ => unreachable!("class and module paths and assigned constants are constants"),
+        }
+    }
+
+    fn compile_class(&mut self, class: &Class) {
+        let (scope, name) = self.compile_definition_target(&class.path);
+        if let Some(superclass) = &class.superclass {
+            self.compile_node(superclass);
+        }
+        let body = self.compile_child(&format!("<class:{}>", name), IseqKind::Class, |compiler| {
+            compiler.compile_body(&class.body);
+        });
+        let kind = ClassKind::Class { superclass: class.superclass.is_some() };
+        let name = self.name(&name);
+        self.emit(Instruction::DefineClass { kind, name, body, scope });
+    }
+
+    fn compile_module(&mut self, module: &Module) {
+        let (scope, name) = self.compile_definition_target(&module.path);
+        let body = self.compile_child(&format!("<module:{}>", name), IseqKind::Class, |compiler| {
+            compiler.compile_body(&module.body);
+        });
+        let name = self.name(&name);
+        self.emit(Instruction::DefineClass { kind: ClassKind::Module, name, body, scope });
+    }
+
+    // Control flow
+
+    fn compile_while(&mut self, node: &While) {
+        let depth = self.depth();
+        let (condition, body, end, done) = (self.label(), self.label(), self.label(), self.label());
+        let ensures = self.current().ensures;
+        self.current().loops.push(Loop { break_label: done, next_label: condition, redo_label: body, depth, ensures });
+
+        if node.do_while {
+            self.jump(Instruction::Jump, body);
+        }
+        self.place(condition);
+        self.compile_node(&node.condition);
+        self.jump(Instruction::BranchUnless, end);
+        self.place(body);
+        self.compile_body(&node.body);
+        self.emit(Instruction::Pop);
+        self.jump(Instruction::Jump, condition);
+        self.place(end);
+        self.emit(Instruction::PutNil);
+        self.place(done);
+
+        self.current().loops.pop();
+        self.catch_loop(condition, end, Loop { break_label: done, next_label: condition, redo_label: body, depth, ensures });
+    }
+
+    fn catch_loop(&mut self, start: Label, end: Label, target: Loop) {
+        self.catch(CatchKind::Break, start, end, target.break_label, target.depth);
+        self.catch(CatchKind::Next, start, end, target.next_label, target.depth);
+        self.catch(CatchKind::Redo, start, end, target.redo_label, target.depth);
+    }
+
+    // Iterates over `to_a` by index, with the variables in the enclosing scope
+    fn compile_for(&mut self, node: &For) {
+        let depth = self.depth();
+        let variables: Vec<_> = node.variables.iter().map(|variable| self.local(variable)).collect();
+        let (iterable, elements, index) = (self.temporary(), self.temporary(), self.temporary());
+        let (next, body, end, done) = (self.label(), self.label(), self.label(), self.label());
+
+        self.compile_node(&node.iterable);
+        self.emit(Instruction::Dup);
+        self.set_local((iterable, 0));
+        self.call("to_a", 0);
+        self.emit(Instruction::ToArray);
+        self.set_local((elements, 0));
+        self.emit(Instruction::PutInteger(0));
+        self.set_local((index, 0));
+
+        let ensures = self.current().ensures;
+        let target = Loop { break_label: done, next_label: next, redo_label: body, depth, ensures };
+        self.current().loops.push(target);
+
+        self.place(next);
+        self.get_local((index, 0));
+        self.get_local((elements, 0));
+        self.call("length", 0);
+        self.call("<", 1);
+        self.jump(Instruction::BranchUnless, end);
+        self.get_local((elements, 0));
+        self.get_local((index, 0));
+        self.call("[]", 1);
+        self.get_local((index, 0));
+        self.emit(Instruction::PutInteger(1));
+        self.call("+", 1);
+        self.set_local((index, 0));
+        if let [variable] = variables[..] {
+            self.set_local(variable);
+        } else {
+            self.emit(Instruction::ToArray);
+            self.emit(Instruction::ExpandArray { before: variables.len(), after: 0, splat: false });
+            for variable in variables {
+                self.set_local(variable);
+            }
+        }
+        self.place(body);
+        self.compile_body(&node.body);
+        self.emit(Instruction::Pop);
+        self.jump(Instruction::Jump, next);
+        self.place(end);
+        self.get_local((iterable, 0));
+        self.place(done);
+
+        self.current().loops.pop();
+        self.catch_loop(next, end, target);
+    }
+
+    fn compile_break(&mut self, value: &Option<Box<Node>>) {
+        let depth = self.depth();
+        self.compile_optional(value);
+        let builder = self.current();
+        match builder.loops.last().copied().filter(|target| target.ensures == builder.ensures) {
+            Some(target) => {
+                let extra = self.depth() - 1 - target.depth;
+                if extra > 0 {
+                    self.emit(Instruction::Adjust(extra));
+                }
+                self.jump(Instruction::Jump, target.break_label);
+            }
+            None => self.emit(Instruction::Throw(ThrowKind::Break)),
+        }
+        self.transferred(depth);
+    }
+
+    fn compile_next(&mut self, value: &Option<Box<Node>>) {
+        let depth = self.depth();
+        self.compile_optional(value);
+        let builder = self.current();
+        let (kind, ensures) = (builder.kind, builder.ensures);
+        match builder.loops.last().copied() {
+            Some(target) if target.ensures == ensures => {
+                self.pop_to(target.depth);
+                self.jump(Instruction::Jump, target.next_label);
+            }
+            // Leaves the block with the value
+            None if kind == IseqKind::Block && ensures == 0 => self.emit(Instruction::Leave),
+            _ => self.emit(Instruction::Throw(ThrowKind::Next)),
+        }
+        self.transferred(depth);
+    }
+
+    fn compile_redo(&mut self) {
+        let depth = self.depth();
+        let builder = self.current();
+        let (kind, ensures, body_start) = (builder.kind, builder.ensures, builder.body_start);
+        match builder.loops.last().copied() {
+            Some(target) if target.ensures == ensures => {
+                self.pop_to(target.depth);
+                self.jump(Instruction::Jump, target.redo_label);
+            }
+            None if kind == IseqKind::Block && ensures == 0 => {
+                self.pop_to(0);
+                let body_start = body_start.expect("block parameters are compiled first");
+                self.jump(Instruction::Jump, body_start);
+            }
+            _ => {
+                self.emit(Instruction::PutNil);
+                self.emit(Instruction::Throw(ThrowKind::Redo));
+            }
+        }
+        self.transferred(depth);
+    }
+
+    fn compile_retry(&mut self) {
+        let depth = self.depth();
+        let builder = self.current();
+        match builder.retries.last().copied().filter(|target| target.ensures == builder.ensures) {
+            Some(target) => {
+                self.pop_to(target.depth);
+                self.jump(Instruction::Jump, target.label);
+            }
+            None => {
+                self.emit(Instruction::PutNil);
+                self.emit(Instruction::Throw(ThrowKind::Retry));
+            }
+        }
+        self.transferred(depth);
+    }
+
+    // The body is covered by a rescue entry whose handler tests the clauses in order and
+    // rethrows the exception if none matches. An ensure entry covers everything but the
+    // ensure body, which is compiled twice: once for leaving normally, once for unwinding.
+    fn compile_begin(&mut self, begin: &Begin) {
+        if !begin.has_clauses() {
+            self.compile_body(&begin.body);
+            return;
+        }
+
+        let depth = self.depth();
+        if begin.ensure_body.is_some() {
+            self.current().ensures += 1;
+        }
+        // `$!` goes back to what it was once the `begin` is done
+        let errinfo = (!begin.rescues.is_empty()).then(|| self.temporary());
+        if let Some(errinfo) = errinfo {
+            self.emit(Instruction::GetErrinfo);
+            self.set_local((errinfo, 0));
+        }
+        let (start, body_end) = (self.label(), self.label());
+        self.place(start);
+        self.compile_body(&begin.body);
+        self.place(body_end);
+
+        if begin.rescues.is_empty() {
+            if let Some(else_body) = &begin.else_body {
+                self.emit(Instruction::Pop);
+                self.compile_body(else_body);
+            }
+        } else {
+            let (handler, handler_end, done) = (self.label(), self.label(), self.label());
+            if let Some(else_body) = &begin.else_body {
+                self.emit(Instruction::Pop);
+                self.compile_body(else_body);
+            }
+            self.jump(Instruction::Jump, done);
+
+            self.place_handler(handler, depth + 1);
+            let ensures = self.current().ensures;
+            self.current().retries.push(Retry { label: start, depth, ensures });
+            let clauses: Vec<Label> = begin.rescues.iter().map(|_| self.label()).collect();
+            for (rescue, &clause) in begin.rescues.iter().zip(&clauses) {
+                if rescue.classes.is_empty() {
+                    self.emit(Instruction::Dup);
+                    let index = self.name("StandardError");
+                    self.emit(Instruction::GetTopConstant(index));
+                    self.emit(Instruction::CheckMatch(MatchKind::Case));
+                    self.jump(Instruction::BranchIf, clause);
+                }
+                for class in &rescue.classes {
+                    self.emit(Instruction::Dup);
+                    self.compile_match_candidate(class, MatchKind::Splat);
+                    self.jump(Instruction::BranchIf, clause);
+                }
+            }
+            self.emit(Instruction::Throw(ThrowKind::Raise));
+            for (rescue, clause) in begin.rescues.iter().zip(clauses) {
+                self.place(clause);
+                self.emit(Instruction::Dup);
+                self.emit(Instruction::SetErrinfo);
+                match &rescue.variable {
+                    Some(variable) => {
+                        let local = self.local(variable);
+                        self.set_local(local);
+                    }
+                    None => self.emit(Instruction::Pop),
+                }
+                self.compile_body(&rescue.body);
+                self.jump(Instruction::Jump, done);
+            }
+            self.place(handler_end);
+            self.current().retries.pop();
+
+            self.catch(CatchKind::Rescue, start, body_end, handler, depth);
+            self.catch(CatchKind::Retry, handler, handler_end, start, depth);
+            self.place(done);
+        }
+        if let Some(errinfo) = errinfo {
+            self.get_local((errinfo, 0));
+            self.emit(Instruction::SetErrinfo);
+        }
+
+        if let Some(ensure_body) = &begin.ensure_body {
+            self.current().ensures -= 1;
+            let (ensure_start, handler, done) = (self.label(), self.label(), self.label());
+            self.place(ensure_start);
+            self.compile_body(ensure_body);
+            self.emit(Instruction::Pop);
+            self.jump(Instruction::Jump, done);
+
+            self.place_handler(handler, depth);
+            self.compile_body(ensure_body);
+            self.emit(Instruction::Pop);
+            self.emit(Instruction::EndEnsure(handler.0));
+            self.place(done);
+            self.catch(CatchKind::Ensure, start, ensure_start, handler, depth);
+        }
+    }
+
+    // Pushes a `when` condition or rescued class and tests it against the value under it.
+    // `splat` is how a `*list` of them is tested.
+    fn compile_match_candidate(&mut self, candidate: &Node, splat: MatchKind) {
+        match candidate {
+            Node::Splat(value) => {
+                self.compile_node(value);
+                self.emit(Instruction::CheckMatch(splat));
+            }
+            candidate => {
+                self.compile_node(candidate);
+                self.emit(Instruction::CheckMatch(MatchKind::Case));
+            }
+        }
+    }
+
+    fn compile_case(&mut self, case: &Case) {
+        let end = self.label();
+        let bodies: Vec<Label> = case.whens.iter().map(|_| self.label()).collect();
+        if let Some(subject) = &case.subject {
+            self.compile_node(subject);
+        }
+
+        for (when, &body) in case.whens.iter().zip(&bodies) {
+            for condition in &when.conditions {
+                if case.subject.is_some() {
+                    self.emit(Instruction::Dup);
+                    self.compile_match_candidate(condition, MatchKind::Splat);
+                } else if let Node::Splat(value) = condition {
+                    self.compile_node(value);
+                    self.emit(Instruction::CheckMatch(MatchKind::SplatTruthy));
+                } else {
+                    self.compile_node(condition);
+                }
+                self.jump(Instruction::BranchIf, body);
+            }
+        }
+        if case.subject.is_some() {
+            self.emit(Instruction::Pop);
+        }
+        match &case.else_body {
+            Some(else_body) => self.compile_body(else_body),
+            None => self.emit(Instruction::PutNil),
+        }
+        self.jump(Instruction::Jump, end);
+
+        for (when, body) in case.whens.iter().zip(bodies) {
+            self.place(body);
+            if case.subject.is_some() {
+                self.emit(Instruction::Pop);
+            }
+            self.compile_body(&when.body);
+            self.jump(Instruction::Jump, end);
+        }
+        self.place(end);
+    }
+
+    fn compile_case_in(&mut self, case: &CaseIn) {
+        let end = self.label();
+        self.compile_node(&case.subject);
+        for clause in &case.clauses {
+            let fail = self.label();
+            self.emit(Instruction::Dup);
+            self.compile_pattern(&clause.pattern, fail);
+            if let Some(guard) = &clause.guard {
+                self.compile_node(guard);
+                self.jump(Instruction::BranchUnless, fail);
+            }
+            self.emit(Instruction::Pop);
+            self.compile_body(&clause.body);
+            self.jump(Instruction::Jump, end);
+            self.place(fail);
+        }
+
+        match &case.else_body {
+            Some(else_body) => {
+                self.emit(Instruction::Pop);
+                self.compile_body(else_body);
+            }
+            None => {
+                // raise NoMatchingPatternError, subject.inspect
+                self.emit(Instruction::PutSelf);
+                let index = self.name("NoMatchingPatternError");
+                self.emit(Instruction::GetTopConstant(index));
+                self.emit(Instruction::TopN(2));
+                self.call("inspect", 0);
+                self.call("raise", 2);
+                self.emit(Instruction::Adjust(1));
+            }
+        }
+        self.place(end);
+    }
+
+    // Matches the value on top against the pattern, consuming it. When the value doesn't
+    // match, jumps to `fail` with the stack as it was under the value.
+    fn compile_pattern(&mut self, pattern: &Pattern, fail: Label) {
+        match pattern {
+            Pattern::Value(node) | Pattern::Pin(node) => {
+                self.compile_node(node);
+                self.emit(Instruction::CheckMatch(MatchKind::Case));
+                self.jump(Instruction::BranchUnless, fail);
+            }
+            Pattern::Bind(name) => {
+                let local = self.local(name);
+                self.set_local(local);
+            }
+            Pattern::Alternative(patterns) => {
+                let matched = self.label();
+                for pattern in patterns {
+                    let next = self.label();
+                    self.emit(Instruction::Dup);
+                    self.compile_pattern(pattern, next);
+                    self.emit(Instruction::Pop);
+                    self.jump(Instruction::Jump, matched);
+                    self.place(next);
+                }
+                self.emit(Instruction::Pop);
+                self.jump(Instruction::Jump, fail);
+                self.place(matched);
+            }
+            Pattern::Capture(pattern, name) => {
+                let stubs = self.pattern_stubs(1);
+                self.emit(Instruction::Dup);
+                self.compile_pattern(pattern, stubs[1]);
+                let local = self.local(name);
+                self.set_local(local);
+                self.place_stubs(&stubs, fail);
+            }
+            Pattern::Array { constant, pre, rest, post } => {
+                let count = pre.len() + post.len() + rest.is_some() as usize;
+                let stubs = self.pattern_stubs(count.max(1));
+                self.compile_pattern_constant(constant, stubs[1]);
+                self.emit(Instruction::Deconstruct);
+                self.emit(Instruction::Dup);
+                self.emit(Instruction::CheckLength { length: pre.len() + post.len(), rest: rest.is_some() });
+                self.jump(Instruction::BranchUnless, stubs[1]);
+                self.emit(Instruction::ExpandArray { before: pre.len(), after: post.len(), splat: rest.is_some() });
+
+                let mut remaining = count;
+                for pattern in pre {
+                    remaining -= 1;
+                    self.compile_pattern(pattern, stubs[remaining]);
+                }
+                if let Some(rest) = rest {
+                    remaining -= 1;
+                    match rest {
+                        Some(name) => {
+                            let local = self.local(name);
+                            self.set_local(local);
+                        }
+                        None => self.emit(Instruction::Pop),
+                    }
+                }
+                for pattern in post {
+                    remaining -= 1;
+                    self.compile_pattern(pattern, stubs[remaining]);
+                }
+                self.place_stubs(&stubs, fail);
+            }
+            Pattern::Find { constant, pre, middle, post } => self.compile_find_pattern(constant, pre, middle, post, fail),
+            Pattern::Hash { constant, pairs, rest } => {
+                let stubs = self.pattern_stubs(2);
+                self.compile_pattern_constant(constant, stubs[1]);
+                self.emit(Instruction::DeconstructKeys);
+                self.emit(Instruction::Dup);
+                self.jump(Instruction::BranchUnless, stubs[1]);
+                for (key, pattern) in pairs {
+                    let index = self.name(key);
+                    self.emit(Instruction::DeleteKey(index));
+                    self.jump(Instruction::BranchUnless, stubs[2]);
+                    match pattern {
+                        Some(pattern) => self.compile_pattern(pattern, stubs[1]),
+                        None => {
+                            let local = self.local(key);
+                            self.set_local(local);
+                        }
+                    }
+                }
+                match rest {
+                    Some(HashPatternRest::Named(name)) => {
+                        let local = self.local(name);
+                        self.set_local(local);
+                    }
+                    Some(HashPatternRest::Nil) => {
+                        self.call("empty?", 0);
+                        self.jump(Instruction::BranchUnless, stubs[0]);
+                    }
+                    None => self.emit(Instruction::Pop),
+                }
+                self.place_stubs(&stubs, fail);
+            }
+        }
+    }
+
+    // `Const(...)` patterns first test `Const === value`, keeping the value
+    fn compile_pattern_constant(&mut self, constant: &Option<Node>, fail: Label) {
+        if let Some(constant) = constant {
+            self.emit(Instruction::Dup);
+            self.compile_node(constant);
+            self.emit(Instruction::CheckMatch(MatchKind::Case));
+            self.jump(Instruction::BranchUnless, fail);
+        }
+    }
+
+    // Failure labels for a pattern that keeps values of its own on the stack: `stubs[n]`
+    // pops `n` of them before going on to the pattern's own failure label
+    fn pattern_stubs(&mut self, count: usize) -> Vec<Label> {
+        (0..=count).map(|_| self.label()).collect()
+    }
+
+    // Ends the pattern's successful path and places its failure stubs after it
+    fn place_stubs(&mut self, stubs: &[Label], fail: Label) {
+        let matched = self.label();
+        self.jump(Instruction::Jump, matched);
+        let used: Vec<bool> = stubs.iter().map(|stub| self.current().label_depths[stub.0].is_some()).collect();
+        let Some(highest) = used.iter().rposition(|&used| used) else {
+            self.place(matched);
+            return;
+        };
+        for count in (1..=highest).rev() {
+            self.place(stubs[count]);
+            self.emit(Instruction::Pop);
+        }
+        self.place(stubs[0]);
+        self.jump(Instruction::Jump, fail);
+        self.place(matched);
+    }
+
+    // `[*pre, a, b, *post]` tries the middle patterns at every position of the
+    // deconstructed array, keeping the array and the position in hidden variables
+    fn compile_find_pattern(&mut self, constant: &Option<Node>, pre: &Option<String>, middle: &[Pattern], post: &Option<String>, fail: Label) {
+        let stubs = self.pattern_stubs(1);
+        self.compile_pattern_constant(constant, stubs[1]);
+        self.emit(Instruction::Deconstruct);
+        self.emit(Instruction::Dup);
+        self.emit(Instruction::CheckLength { length: middle.len(), rest: true });
+        self.jump(Instruction::BranchUnless, stubs[1]);
+        let (elements, start) = (self.temporary(), self.temporary());
+        self.set_local((elements, 0));
+        self.emit(Instruction::PutInteger(0));
+        self.set_local((start, 0));
+
+        let (attempt, next, matched) = (self.label(), self.label(), self.label());
+        self.place(attempt);
+        self.get_local((start, 0));
+        self.get_local((elements, 0));
+        self.call("length", 0);
+        self.emit(Instruction::PutInteger(middle.len() as i64));
+        self.call("-", 1);
+        self.call("<=", 1);
+        self.jump(Instruction::BranchUnless, stubs[0]);
+        for (offset, pattern) in middle.iter().enumerate() {
+            self.get_local((elements, 0));
+            self.get_local((start, 0));
+            self.emit(Instruction::PutInteger(offset as i64));
+            self.call("+", 1);
+            self.call("[]", 1);
+            self.compile_pattern(pattern, next);
+        }
+        if let Some(pre) = pre {
+            self.get_local((elements, 0));
+            self.emit(Instruction::PutInteger(0));
+            self.get_local((start, 0));
+            self.call("[]", 2);
+            let local = self.local(pre);
+            self.set_local(local);
+        }
+        if let Some(post) = post {
+            self.get_local((elements, 0));
+            self.get_local((start, 0));
+            self.emit(Instruction::PutInteger(middle.len() as i64));
+            self.call("+", 1);
+            self.get_local((elements, 0));
+            self.call("length", 0);
+            self.call("[]", 2);
+            let local = self.local(post);
+            self.set_local(local);
+        }
+        self.jump(Instruction::Jump, matched);
+
+        self.place(next);
+        self.get_local((start, 0));
+        self.emit(Instruction::PutInteger(1));
+        self.call("+", 1);
+        self.set_local((start, 0));
+        self.jump(Instruction::Jump, attempt);
+        self.place(matched);
+        self.place_stubs(&stubs, fail);
+    }
+}
+
+// The call site ends its arguments with keyword arguments
+fn passes_keywords(args: &[Node]) -> bool {
+    args.iter().any(|arg| matches!(arg, Node::KeywordHash(_)))
+}
+
+fn is_short_circuit(operator: &str) -> bool {
+    operator == "||" || operator == "&&"
+}
+
+// How many values an instruction leaves on the stack, minus how many it takes
+fn stack_effect(builder: &Builder, instruction: Instruction) -> isize {
+    match instruction {
+        Instruction::PutNil
+        | Instruction::PutTrue
+        | Instruction::PutFalse
+        | Instruction::PutSelf
+        | Instruction::PutInteger(_)
+        | Instruction::PutFloat(_)
+        | Instruction::PutBignum(_)
+        | Instruction::PutString(_)
+        | Instruction::PutRegexp(..)
+        | Instruction::PutSymbol(_)
+        | Instruction::Dup
+        | Instruction::TopN(_)
+        | Instruction::GetLocal { .. }
+        | Instruction::GetInstanceVariable(_)
+        | Instruction::GetConstant(_)
+        | Instruction::GetTopConstant(_)
+        | Instruction::Lambda(_)
+        | Instruction::DefineMethod { .. }
+        | Instruction::GetErrinfo
+        | Instruction::CheckKeyword(_) => 1,
+        Instruction::Pop
+        | Instruction::SetLocal { .. }
+        | Instruction::SetInstanceVariable(_)
+        | Instruction::ConcatArray
+        | Instruction::MergeHash
+        | Instruction::NewRange { .. }
+        | Instruction::BranchIf(_)
+        | Instruction::BranchUnless(_)
+        | Instruction::Leave
+        | Instruction::SetErrinfo
+        | Instruction::Throw(_) => -1,
+        Instruction::GetScopedConstant(_)
+        | Instruction::ObjToString
+        | Instruction::SplatArray
+        | Instruction::ToArray
+        | Instruction::Not
+        | Instruction::DefineSingletonMethod { .. }
+        | Instruction::DefineSingletonClass(_)
+        | Instruction::Jump(_)
+        | Instruction::EndEnsure(_)
+        | Instruction::CheckLength { .. }
+        | Instruction::Deconstruct
+        | Instruction::DeconstructKeys => 0,
+        Instruction::Adjust(count) => -(count as isize),
+        Instruction::NewArray(count) | Instruction::ConcatStrings(count) => 1 - count as isize,
+        Instruction::ExpandArray { before, after, splat } => (before + after + splat as usize) as isize - 1,
+        Instruction::NewHash(pairs) => 1 - 2 * pairs as isize,
+        Instruction::Send(index) | Instruction::InvokeSuper(index) => {
+            let info = &builder.call_infos[index];
+            -(info.argc as isize) - info.block_arg as isize
+        }
+        Instruction::InvokeBlock { argc, .. } => 1 - argc as isize,
+        Instruction::SetConstant { scope, .. } => -((scope == ClassScope::Explicit) as isize),
+        Instruction::DefineClass { kind, scope, .. } => {
+            let superclass = matches!(kind, ClassKind::Class { superclass: true });
+            1 - superclass as isize - (scope == ClassScope::Explicit) as isize
+        }
+        Instruction::CheckMatch(MatchKind::SplatTruthy) => 0,
+        Instruction::CheckMatch(_) => -1,
+        Instruction::DeleteKey(_) => 2,
+    }
+}
@@ -0,0 +1,230 @@
+use crate::runtime::{MethodBody, Object, ObjectId, ObjectKind, ProcBody, Value};
+
+// Allocations before the first collection. After that, collections happen once the
+// heap has grown by as many objects as survived the last one.
+const INITIAL_THRESHOLD: usize = 10_000;
+
+// The runtime's objects, freed by a precise mark-and-sweep collector. Collections only
+// run at safe points, where the backend knows where all of its values are: allocating
+// past the threshold (or at all, in stress mode) asks for one, and the backend runs it
+// between instructions or statements.
+pub struct Heap {
+    slots: Vec<Option<Object>>,
+    // Slots of collected objects, reused before the heap grows
+    free: Vec<ObjectId>,
+    // Values only held in Rust variables, by native methods and the backends. Scopes
+    // are marked by the length of the stack, and new objects are rooted in the scope
+    // they were allocated in.
+    roots: Vec<Value>,
+    // Procs called with the id of their object once it's collected
+    finalizers: Vec<(ObjectId, Value)>,
+    // Collects at every safe point after an allocation, to find values that aren't rooted
+    pub stress: bool,
+    pending: bool,
+    allocated_since: usize,
+    threshold: usize,
+    pub stats: GcStats,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GcStats {
+    // Collections so far
+    pub count: u64,
+    pub total_allocated: u64,
+    pub total_freed: u64,
+}
+
+// The values a collection starts marking from, given by the runtime and the backend
+#[derive(Default)]
+pub struct Tracer {
+    gray: Vec<ObjectId>,
+}
+
+// What a collection freed that the runtime has to act on
+pub struct Sweep {
+    // Finalizers to call, with the objects they were defined for
+    pub finalizers: Vec<(Value, ObjectId)>,
+    // Class ids can be reused, which would make inline caches find the wrong methods
+    pub freed_classes: bool,
+}
+
+impl Tracer {
+    pub fn mark(&mut self, value: Value) {
+        if let Some(id) = value.object_id() {
+            self.gray.push(id);
+        }
+    }
+
+    pub fn mark_id(&mut self, id: ObjectId) {
+        self.gray.push(id);
+    }
+
+    pub fn mark_all(&mut self, values: impl IntoIterator<Item = Value>) {
+        for value in values {
+            self.mark(value);
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            slots: Vec::new(),
+            free: Vec::new(),
+            roots: Vec::new(),
+            finalizers: Vec::new(),
+            stress: false,
+            pending: false,
+            allocated_since: 0,
+            threshold: INITIAL_THRESHOLD,
+            stats: GcStats::default(),
+        }
+    }
+}
+
+impl Heap {
+    pub fn alloc(&mut self, object: Object) -> ObjectId {
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.slots[id.index()] = Some(object);
+                id
+            }
+            None => {
+                self.slots.push(Some(object));
+                ObjectId::from_index(self.slots.len() - 1)
+            }
+        };
+        self.roots.push(Value::object(id));
+        self.stats.total_allocated += 1;
+        self.allocated_since += 1;
+        if self.stress || self.allocated_since >= self.threshold {
+            self.pending = true;
+        }
+        id
+    }
+
+    pub fn get(&self, id: ObjectId) -> &Object {
+        self.slots[id.index()].as_ref().expect("objects in use aren't collected")
+    }
+
+    pub fn get_mut(&mut self, id: ObjectId) -> &mut Object {
+        self.slots[id.index()].as_mut().expect("objects in use aren't collected")
+    }
+
+    pub fn contains(&self, id: ObjectId) -> bool {
+        self.slots.get(id.index()).is_some_and(Option::is_some)
+    }
+
+    pub fn live_slots(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn free_slots(&self) -> usize {
+        self.free.len()
+    }
+
+    // Whether the next safe point should collect
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    // Rooting
+
+    pub fn root(&mut self, value: Value) {
+        if value.object_id().is_some() {
+            self.roots.push(value);
+        }
+    }
+
+    // Where the current scope starts, to drop its roots with `unroot_to` once it ends
+    pub fn root_mark(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub fn unroot_to(&mut self, mark: usize) {
+        self.roots.truncate(mark);
+    }
+
+    pub fn define_finalizer(&mut self, id: ObjectId, proc: Value) {
+        self.finalizers.push((id, proc));
+    }
+
+    pub fn undefine_finalizer(&mut self, id: ObjectId) {
+        self.finalizers.retain(|&(object, _)| object != id);
+    }
+
+    // Collection
+
+    // Marks everything reachable from the tracer's values, the root stack and the
+    // finalizers, and frees the rest
+    pub fn collect(&mut self, mut tracer: Tracer) -> Sweep {
+        tracer.mark_all(self.roots.iter().copied());
+        tracer.mark_all(self.finalizers.iter().map(|&(_, proc)| proc));
+
+        let mut marks = vec![false; self.slots.len()];
+        while let Some(id) = tracer.gray.pop() {
+            if std::mem::replace(&mut marks[id.index()], true) {
+                continue;
+            }
+            trace_object(self.get(id), &mut tracer);
+        }
+
+        let mut sweep = Sweep { finalizers: Vec::new(), freed_classes: false };
+        for (index, marked) in marks.into_iter().enumerate() {
+            if marked || self.slots[index].is_none() {
+                continue;
+            }
+            let object = self.slots[index].take().expect("checked above");
+            sweep.freed_classes |= matches!(object.kind, ObjectKind::Class(_));
+            self.free.push(ObjectId::from_index(index));
+            self.stats.total_freed += 1;
+        }
+        let (finalized, kept): (Vec<_>, Vec<_>) =
+            std::mem::take(&mut self.finalizers).into_iter().partition(|&(id, _)| self.slots[id.index()].is_none());
+        self.finalizers = kept;
+        sweep.finalizers = finalized.into_iter().map(|(id, proc)| (proc, id)).collect();
+
+        self.stats.count += 1;
+        self.pending = false;
+        self.allocated_since = 0;
+        self.threshold = INITIAL_THRESHOLD.max(self.live_slots());
+        sweep
+    }
+}
+
+fn trace_object(object: &Object, tracer: &mut Tracer) {
+    tracer.mark_id(object.class);
+    tracer.mark_all(object.ivars.iter().map(|&(_, value)| value));
+    match &object.kind {
+        ObjectKind::Plain | ObjectKind::String(_) | ObjectKind::Regexp(_) | ObjectKind::Encoding(_) | ObjectKind::Bignum(_) | ObjectKind::Float(_) => {}
+        ObjectKind::Array(elements) => tracer.mark_all(elements.iter().copied()),
+        ObjectKind::Hash(hash) => {
+            tracer.mark_all(hash.entries.iter().flat_map(|&(key, value)| [key, value]));
+            tracer.mark_all(hash.default);
+            tracer.mark_all(hash.default_proc);
+        }
+        ObjectKind::Range(start, end, _) => tracer.mark_all([*start, *end]),
+        ObjectKind::Proc(proc) => match &proc.body {
+            ProcBody::Ast(closure) => closure.trace(tracer),
+            ProcBody::Iseq(closure) => closure.trace(tracer),
+            ProcBody::Symbol(_) => {}
+            ProcBody::Native(_, state) => tracer.mark(*state),
+        },
+        ObjectKind::Class(class) => {
+            tracer.mark_all(class.superclass.map(Value::object));
+            tracer.mark_all(class.attached);
+            for method in class.methods.values() {
+                trace_method_body(&method.body, tracer);
+            }
+            tracer.mark_all(class.constants.values().copied());
+            tracer.mark_all(class.includes.iter().chain(&class.prepends).map(|&module| Value::object(module)));
+        }
+        ObjectKind::Exception(data) => tracer.mark(data.message),
+    }
+}
+
+pub fn trace_method_body(body: &MethodBody, tracer: &mut Tracer) {
+    if let MethodBody::Proc(proc) = body {
+        tracer.mark(*proc);
+    }
+}
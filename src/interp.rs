@@ -0,0 +1,1285 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::{
+    Begin, Block, Call, Case, CaseIn, Class, Def, For, HashElement, HashPatternRest, Module, Node,
+    OpAssign, Param, Params, Pattern, RangeKind, Super, Target, While,
+};
+use crate::builtins;
+use crate::gc::{self, Tracer};
+use crate::parser::Parser;
+use crate::runtime::{
+    EvalError, Executor, Method, MethodBody, Missing, ObjectId, ProcBody, RHash, RString, Runtime, Unwind, Value,
+    Visibility,
+};
+
+// Deeper recursion raises `SystemStackError` instead of overflowing the native stack
+const MAX_DEPTH: usize = 10_000;
+
+// A method defined with `def`
+pub struct AstMethod {
+    pub def: Def,
+    // The modules the `def` is nested in, for constant lookups
+    nesting: Rc<Vec<ObjectId>>,
+}
+
+// A block together with the variables and `self` of the place it was written
+pub struct Closure {
+    pub block: Rc<Block>,
+    env: Env,
+    context: Rc<Context>,
+}
+
+// What method bodies, class bodies and the top level run with. Blocks share the
+// context they were created in, except lambdas which get their own `home`.
+#[derive(Clone)]
+struct Context {
+    self_value: Value,
+    // Where `def` adds methods
+    definee: ObjectId,
+    // The lexically enclosing modules, innermost last
+    nesting: Rc<Vec<ObjectId>>,
+    block: Option<Value>,
+    // The running method, which `super` continues the lookup from
+    method: Option<Rc<Method>>,
+    // Identifies the activation that `return` leaves
+    home: usize,
+    // What `def` makes methods, changed by `private` and `public` without arguments
+    visibility: Cell<Visibility>,
+}
+
+type Env = Rc<RefCell<Scope>>;
+
+#[derive(Default)]
+struct Scope {
+    vars: HashMap<String, Value>,
+    // Blocks see the variables of the scope they were created in
+    parent: Option<Env>,
+}
+
+// Something `op=` reads and then writes, with the receiver and index already evaluated
+enum Place<'a> {
+    Local(&'a str),
+    InstanceVariable(&'a str),
+    Index(Value, Vec<Value>),
+    Attribute(Value, &'a str),
+}
+
+// Evaluates the AST directly, one node at a time
+pub struct Interpreter {
+    runtime: Runtime,
+    // Innermost last, so native methods can find the block of their caller
+    contexts: Vec<Rc<Context>>,
+    // The variables of the running methods, blocks and bodies, innermost last
+    envs: Vec<Env>,
+    // Homes of the running methods and lambdas, which are the only ones `return` can leave
+    homes: Vec<usize>,
+    next_home: usize,
+}
+
+impl Closure {
+    pub fn arity(&self) -> i32 {
+        self.block.params().arity()
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        trace_env(&self.env, tracer);
+        self.context.trace(tracer);
+    }
+}
+
+impl Context {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.self_value);
+        tracer.mark_id(self.definee);
+        tracer.mark_all(self.nesting.iter().map(|&module| Value::object(module)));
+        tracer.mark_all(self.block);
+        if let Some(method) = &self.method {
+            gc::trace_method_body(&method.body, tracer);
+        }
+    }
+}
+
+fn trace_env(env: &Env, tracer: &mut Tracer) {
+    let scope = env.borrow();
+    tracer.mark_all(scope.vars.values().copied());
+    if let Some(parent) = &scope.parent {
+        trace_env(parent, tracer);
+    }
+}
+
+fn new_env(parent: Option<Env>) -> Env {
+    Rc::new(RefCell::new(Scope { vars: HashMap::new(), parent }))
+}
+
+fn lookup(env: &Env, name: &str) -> Option<Value> {
+    let scope = env.borrow();
+    match scope.vars.get(name) {
+        Some(&value) => Some(value),
+        None => scope.parent.as_ref().and_then(|parent| lookup(parent, name)),
+    }
+}
+
+// Assigns to the variable where it's visible, or creates it in the innermost scope
+fn assign(env: &Env, name: &str, value: Value) {
+    fn assign_existing(env: &Env, name: &str, value: Value) -> bool {
+        let mut scope = env.borrow_mut();
+        if let Some(slot) = scope.vars.get_mut(name) {
+            *slot = value;
+            return true;
+        }
+        let parent = scope.parent.clone();
+        drop(scope);
+        parent.is_some_and(|parent| assign_existing(&parent, name, value))
+    }
+
+    if !assign_existing(env, name, value) {
+        declare(env, name, value);
+    }
+}
+
+// Creates the variable in the innermost scope, shadowing outer ones (for parameters)
+fn declare(env: &Env, name: &str, value: Value) {
+    env.borrow_mut().vars.insert(name.to_string(), value);
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Interpreter::new()
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Interpreter { runtime: Runtime::new(), contexts: Vec::new(), envs: Vec::new(), homes: Vec::new(), next_home: 0 }
+    }
+
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    // Parses and runs a program, returning the value of its last statement
+    pub fn eval(&mut self, source: &str) -> Result<Value, EvalError> {
+        let program = Parser::new(source).parse_program().map_err(EvalError::Syntax)?;
+
+        let context = Rc::new(Context {
+            self_value: self.runtime.main,
+            definee: self.runtime.classes.object,
+            nesting: Rc::default(),
+            block: None,
+            method: None,
+            home: self.new_home(),
+            visibility: Cell::new(Visibility::Public),
+        });
+        let env = new_env(None);
+        let result = self.in_context(&context, &env, Some("<main>"), true, |interp| interp.eval_body(&program, &env, &context));
+
+        match result {
+            Ok(value) => Ok(value),
+            Err(Unwind::Return(value, home)) if home == context.home => Ok(value),
+            Err(unwind) => {
+                let exception = match self.runtime.jump_error(unwind) {
+                    Unwind::Raise(exception) => exception,
+                    _ => unreachable!("jump errors are exceptions"),
+                };
+                Err(self.runtime.uncaught(exception))
+            }
+        }
+    }
+
+    fn new_home(&mut self) -> usize {
+        self.next_home += 1;
+        self.next_home
+    }
+
+    // Runs with `context` and `env` as the innermost ones. A `frame` shows in backtraces,
+    // and an `activation` (methods, lambdas) is something `return` can leave.
+    fn in_context(
+        &mut self,
+        context: &Rc<Context>,
+        env: &Env,
+        frame: Option<&str>,
+        activation: bool,
+        run: impl FnOnce(&mut Self) -> Result<Value, Unwind>,
+    ) -> Result<Value, Unwind> {
+        if self.contexts.len() >= MAX_DEPTH {
+            return Err(self.runtime.error("SystemStackError", "stack level too deep"));
+        }
+
+        self.contexts.push(context.clone());
+        self.envs.push(env.clone());
+        if let Some(frame) = frame {
+            self.runtime.push_frame(frame);
+        }
+        if activation {
+            self.homes.push(context.home);
+        }
+        let mark = self.runtime.heap.root_mark();
+        let result = run(self);
+        self.runtime.heap.unroot_to(mark);
+        if activation {
+            self.homes.pop();
+        }
+        if frame.is_some() {
+            self.runtime.frames.pop();
+        }
+        self.envs.pop();
+        self.contexts.pop();
+        result
+    }
+
+    // Each statement's temporaries are rooted until it's done, and collections happen in between
+    fn eval_body(&mut self, nodes: &[Node], env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let mut value = Value::NIL;
+        for node in nodes {
+            let mark = self.runtime.heap.root_mark();
+            value = self.eval_node(node, env, context)?;
+            self.runtime.heap.unroot_to(mark);
+            if self.runtime.heap.pending() {
+                self.runtime.heap.root(value);
+                self.collect_garbage();
+                self.runtime.heap.unroot_to(mark);
+            }
+        }
+        Ok(value)
+    }
+
+    fn eval_optional(&mut self, node: &Option<Box<Node>>, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        match node {
+            Some(node) => self.eval_node(node, env, context),
+            None => Ok(Value::NIL),
+        }
+    }
+
+    // Values are rooted until the statement they're part of is done
+    fn eval_node(&mut self, node: &Node, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let value = self.evaluate(node, env, context)?;
+        self.runtime.heap.root(value);
+        Ok(value)
+    }
+
+    fn evaluate(&mut self, node: &Node, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        match node {
+            Node::Integer(value) => Ok(self.runtime.int(*value)),
+            Node::Float(value) => Ok(self.runtime.float(*value)),
+            Node::Bignum(digits) => Ok(self.runtime.integer(digits.parse().expect("bignum literals are digits"))),
+            Node::Str(text) => Ok(self.runtime.string(text)),
+            Node::Bytes(bytes) => Ok(self.runtime.string_from(RString::utf8(bytes.clone()))),
+            Node::InterpolatedString(parts) => {
+                let mut strings = Vec::new();
+                for part in parts {
+                    let value = self.eval_node(part, env, context)?;
+                    strings.push(builtins::object_to_string(self, value)?);
+                }
+                builtins::concat_strings(self, &strings)
+            }
+            Node::Regexp(source, options) => self.runtime.new_regexp(source, options),
+            Node::Symbol(name) => Ok(self.runtime.symbol(name)),
+            Node::Nil => Ok(Value::NIL),
+            Node::True => Ok(Value::TRUE),
+            Node::False => Ok(Value::FALSE),
+            Node::SelfNode => Ok(context.self_value),
+            // Declared by an assignment that hasn't run, as in `x = 1 if false`
+            Node::LocalVariable(name) => Ok(lookup(env, name).unwrap_or(Value::NIL)),
+            Node::LocalAssign(name, value) => {
+                let value = self.eval_node(value, env, context)?;
+                assign(env, name, value);
+                Ok(value)
+            }
+            Node::Constant(name) => self.runtime.lookup_constant(&context.nesting, name),
+            Node::ScopedConstant(scope, name) => {
+                let module = match scope {
+                    Some(scope) => {
+                        let scope = self.eval_node(scope, env, context)?;
+                        builtins::expect_module(self, scope)?
+                    }
+                    None => self.runtime.classes.object,
+                };
+                self.runtime.scoped_constant(module, name)
+            }
+            Node::InstanceVariable(name) => Ok(self.runtime.ivar(context.self_value, name)),
+            Node::ConstantAssign(path, value) => {
+                let (module, name) = self.definition_target(path, env, context)?;
+                let value = self.eval_node(value, env, context)?;
+                self.runtime.set_constant(module, &name, value);
+                Ok(value)
+            }
+            Node::InstanceVariableAssign(name, value) => {
+                let value = self.eval_node(value, env, context)?;
+                builtins::set_ivar(self, context.self_value, name, value)?;
+                Ok(value)
+            }
+            Node::MultipleAssign(targets, value) => {
+                let value = self.eval_node(value, env, context)?;
+                let values = self.to_array(value);
+                self.destructure(targets, &values, env, context)?;
+                Ok(value)
+            }
+            Node::OpAssign(op_assign) => self.eval_op_assign(op_assign, env, context),
+            Node::Call(call) => self.eval_call(call, env, context),
+            Node::Array(elements) => {
+                let (elements, _) = self.eval_arguments(elements, env, context)?;
+                Ok(self.runtime.array(elements))
+            }
+            Node::Hash(elements) | Node::KeywordHash(elements) => self.eval_hash(elements, env, context),
+            Node::Range(start, end, kind) => {
+                let start = self.eval_optional(start, env, context)?;
+                let end = self.eval_optional(end, env, context)?;
+                Ok(self.runtime.range(start, end, *kind == RangeKind::Exclusive))
+            }
+            Node::Splat(value) => {
+                let value = self.eval_node(value, env, context)?;
+                let elements = builtins::splat(self, value)?;
+                Ok(self.runtime.array(elements))
+            }
+            Node::BlockPass(value) => self.eval_node(value, env, context),
+            Node::Lambda(block) => Ok(self.closure(block, env, context, true)),
+            Node::Def(def) => self.eval_def(def, env, context),
+            Node::Class(class) => self.eval_class(class, env, context),
+            Node::Module(module) => self.eval_module(module, env, context),
+            Node::SingletonClass(target, body) => {
+                let target = self.eval_node(target, env, context)?;
+                let singleton = self.runtime.singleton_class(target)?;
+                self.eval_module_body(singleton, body, "singleton class", context)
+            }
+            Node::If(node) => {
+                if self.eval_node(&node.condition, env, context)?.truthy() {
+                    self.eval_body(&node.then_body, env, context)
+                } else {
+                    self.eval_body(&node.else_body, env, context)
+                }
+            }
+            Node::And(left, right) => {
+                let left = self.eval_node(left, env, context)?;
+                if left.truthy() {
+                    self.eval_node(right, env, context)
+                } else {
+                    Ok(left)
+                }
+            }
+            Node::Or(left, right) => {
+                let left = self.eval_node(left, env, context)?;
+                if left.truthy() {
+                    Ok(left)
+                } else {
+                    self.eval_node(right, env, context)
+                }
+            }
+            Node::Not(value) => Ok(Value::from_bool(!self.eval_node(value, env, context)?.truthy())),
+            Node::Return(value) => {
+                let value = self.eval_optional(value, env, context)?;
+                // A proc whose method already returned has nowhere to return to
+                if !self.homes.contains(&context.home) {
+                    return Err(self.runtime.error("LocalJumpError", "unexpected return"));
+                }
+                Err(Unwind::Return(value, context.home))
+            }
+            Node::While(node) => self.eval_while(node, env, context),
+            Node::For(node) => self.eval_for(node, env, context),
+            Node::Break(value) => Err(Unwind::Break(self.eval_optional(value, env, context)?, None)),
+            Node::Next(value) => Err(Unwind::Next(self.eval_optional(value, env, context)?)),
+            Node::Redo => Err(Unwind::Redo),
+            Node::Retry => Err(Unwind::Retry),
+            Node::Begin(begin) => self.eval_begin(begin, env, context),
+            Node::Case(case) => self.eval_case(case, env, context),
+            Node::CaseIn(case) => self.eval_case_in(case, env, context),
+            Node::Yield(args) => {
+                let (args, _) = self.eval_arguments(args, env, context)?;
+                let Some(block) = context.block else {
+                    return Err(self.runtime.error("LocalJumpError", "no block given (yield)"));
+                };
+                self.call_block(block, &args, None)
+            }
+            Node::Super(node) => self.eval_super(node, env, context),
+            Node::Error => unreachable!("programs with syntax errors aren't run"),
+        }
+    }
+
+    // Calls
+
+    fn eval_call(&mut self, call: &Call, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let receiver = match &call.receiver {
+            Some(receiver) => self.eval_node(receiver, env, context)?,
+            None => context.self_value,
+        };
+        let (args, block_arg) = self.eval_arguments(&call.args, env, context)?;
+        let literal_block = call.block.as_ref().map(|block| self.closure(block, env, context, false));
+
+        // A bare identifier that isn't a local reads like a variable, which shows in the error
+        let variable_like = call.receiver.is_none() && call.args.is_empty() && call.block.is_none();
+        // Private methods are only called without a receiver, or with `self`
+        let fcall = matches!(call.receiver.as_deref(), None | Some(Node::SelfNode));
+        let block = literal_block.or(block_arg);
+        self.runtime.set_line(call.line.0);
+        let result = match self.runtime.find_method(self.runtime.class_of(receiver), &call.method) {
+            Some(method) if method.visibility == Visibility::Private && !fcall => {
+                builtins::method_missing(self, receiver, &call.method, &args, block, Missing::Private)
+            }
+            Some(method) => self.invoke(receiver, &method, &args, block),
+            None => {
+                let missing = if variable_like { Missing::VariableLike } else { Missing::Undefined };
+                builtins::method_missing(self, receiver, &call.method, &args, block, missing)
+            }
+        };
+
+        match (result, literal_block) {
+            // `break` in the block leaves the call it was given to
+            (Err(Unwind::Break(value, Some(tag))), Some(block)) if block.object_id() == Some(tag) => Ok(value),
+            (Ok(_), _) if builtins::is_assignment_method(&call.method) => Ok(args.last().copied().unwrap_or(Value::NIL)),
+            (result, _) => result,
+        }
+    }
+
+    // A bare `super` passes the current values of the method's parameters, and the
+    // method's block unless it's given one of its own
+    fn eval_super(&mut self, node: &Super, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        self.runtime.set_line(node.line.0);
+        let Some(method) = context.method.clone() else {
+            return Err(self.runtime.no_super_method(context.self_value, None));
+        };
+        let (args, block_arg) = match (&node.args, &method.body) {
+            (Some(args), _) => self.eval_arguments(args, env, context)?,
+            (None, MethodBody::Ast(ast)) => self.eval_arguments(&ast.def.params.forwarded(), env, context)?,
+            (None, MethodBody::Proc(_)) => return Err(self.runtime.implicit_super_in_define_method()),
+            (None, _) => unreachable!("only methods defined from the AST or blocks run in the interpreter"),
+        };
+        let literal_block = node.block.as_ref().map(|block| self.closure(block, env, context, false));
+
+        self.runtime.set_line(node.line.0);
+        let receiver = context.self_value;
+        let Some(target) = self.runtime.find_super_method(self.runtime.class_of(receiver), &method) else {
+            return Err(self.runtime.no_super_method(receiver, Some(&method)));
+        };
+        let result = self.invoke(receiver, &target, &args, literal_block.or(block_arg).or(context.block));
+        match (result, literal_block) {
+            (Err(Unwind::Break(value, Some(tag))), Some(block)) if block.object_id() == Some(tag) => Ok(value),
+            (result, _) => result,
+        }
+    }
+
+    // Calls from the interpreter itself, which can call private methods
+    fn call_method(&mut self, receiver: Value, name: &str, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        match self.runtime.find_method(self.runtime.class_of(receiver), name) {
+            Some(method) => self.invoke(receiver, &method, args, block),
+            None => builtins::method_missing(self, receiver, name, args, block, Missing::Undefined),
+        }
+    }
+
+    fn invoke(&mut self, receiver: Value, method: &Rc<Method>, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        match &method.body {
+            MethodBody::Native(function, arity) => {
+                self.runtime.check_arity(*arity, args.len())?;
+                builtins::call_native(self, *function, receiver, args, block)
+            }
+            MethodBody::Ast(ast) => {
+                let context = Rc::new(Context {
+                    self_value: receiver,
+                    definee: method.owner,
+                    nesting: ast.nesting.clone(),
+                    block,
+                    method: Some(method.clone()),
+                    home: self.new_home(),
+                    visibility: Cell::new(Visibility::Public),
+                });
+                let env = new_env(None);
+                let result = self.in_context(&context, &env, Some(&method.name), true, |interp| {
+                    interp.bind_params(&ast.def.params, args, block, true, &env, &context)?;
+                    interp.eval_body(&ast.def.body, &env, &context)
+                });
+                match result {
+                    Err(Unwind::Return(value, home)) if home == context.home => Ok(value),
+                    result => result,
+                }
+            }
+            // Runs like a lambda, with the receiver as `self`
+            MethodBody::Proc(proc) => {
+                let Some(closure) = self.ast_closure(*proc) else {
+                    return self.call_block(*proc, args, block);
+                };
+                let context = Rc::new(Context {
+                    self_value: receiver,
+                    definee: method.owner,
+                    block,
+                    method: Some(method.clone()),
+                    home: self.new_home(),
+                    visibility: Cell::new(Visibility::Public),
+                    ..(*closure.context).clone()
+                });
+                self.run_closure(*proc, &closure, context, true, Some(&method.name), args, block)
+            }
+            MethodBody::AttrReader(name) => {
+                self.runtime.check_arity(0, args.len())?;
+                Ok(self.runtime.ivar(receiver, name))
+            }
+            MethodBody::AttrWriter(name) => {
+                self.runtime.check_arity(1, args.len())?;
+                builtins::set_ivar(self, receiver, name, args[0])?;
+                Ok(args[0])
+            }
+            MethodBody::Iseq(_) => unreachable!("compiled methods only exist in the VM's runtime"),
+        }
+    }
+
+    // Evaluates call arguments, spreading splats. `&block` is returned separately.
+    fn eval_arguments(&mut self, nodes: &[Node], env: &Env, context: &Rc<Context>) -> Result<(Vec<Value>, Option<Value>), Unwind> {
+        let mut args = Vec::new();
+        let mut block = None;
+        for node in nodes {
+            match node {
+                Node::Splat(value) => {
+                    let value = self.eval_node(value, env, context)?;
+                    args.extend(builtins::splat(self, value)?);
+                }
+                Node::BlockPass(value) => {
+                    let value = self.eval_node(value, env, context)?;
+                    block = builtins::block_argument(self, value)?;
+                }
+                node => args.push(self.eval_node(node, env, context)?),
+            }
+        }
+        if nodes.iter().any(|node| matches!(node, Node::KeywordHash(_))) {
+            self.runtime.pass_keywords(&mut args);
+        }
+        Ok((args, block))
+    }
+
+    // The values a single value is destructured into
+    fn to_array(&self, value: Value) -> Vec<Value> {
+        match self.runtime.array_value(value) {
+            Some(elements) => elements.clone(),
+            None => vec![value],
+        }
+    }
+
+    fn eval_hash(&mut self, elements: &[HashElement], env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let mut hash = RHash::default();
+        for element in elements {
+            match element {
+                HashElement::Pair(key, value) => {
+                    let key = self.eval_node(key, env, context)?;
+                    let value = self.eval_node(value, env, context)?;
+                    // String keys are frozen, like `Hash#[]=` does
+                    if let (Some(id), Some(_)) = (key.object_id(), self.runtime.rstring_value(key)) {
+                        self.runtime.object_mut(id).frozen = true;
+                    }
+                    hash.insert(self.runtime.hash_key(key), key, value);
+                }
+                HashElement::DoubleSplat(value) => {
+                    let value = self.eval_node(value, env, context)?;
+                    let Some(other) = self.runtime.hash_value(value) else {
+                        let class = self.runtime.class_name(self.runtime.real_class_of(value));
+                        return Err(self.runtime.error("TypeError", &format!("no implicit conversion of {} into Hash", class)));
+                    };
+                    for (key, value) in other.entries.clone() {
+                        hash.insert(self.runtime.hash_key(key), key, value);
+                    }
+                }
+            }
+        }
+        Ok(self.runtime.hash(hash))
+    }
+
+    // Blocks
+
+    fn closure(&mut self, block: &Rc<Block>, env: &Env, context: &Rc<Context>, lambda: bool) -> Value {
+        let closure = Closure { block: block.clone(), env: env.clone(), context: context.clone() };
+        self.runtime.proc(ProcBody::Ast(Rc::new(closure)), lambda)
+    }
+
+    fn call_block(&mut self, proc: Value, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        let (body, lambda) = match self.runtime.proc_value(proc) {
+            Some(value) => (value.body.clone(), value.lambda),
+            None => return self.call_method(proc, "call", args, block),
+        };
+        let closure = match body {
+            ProcBody::Ast(closure) => closure,
+            // `&:name` calls `name` on the first argument with the rest
+            ProcBody::Symbol(name) => {
+                let Some((&receiver, rest)) = args.split_first() else {
+                    return Err(self.runtime.error("ArgumentError", "no receiver given"));
+                };
+                return self.call_method(receiver, &name, rest, block);
+            }
+            ProcBody::Native(function, state) => return function(self, state, args),
+            ProcBody::Iseq(_) => unreachable!("compiled blocks only exist in the VM's runtime"),
+        };
+
+        let context = if lambda {
+            Rc::new(Context { home: self.new_home(), ..(*closure.context).clone() })
+        } else {
+            closure.context.clone()
+        };
+        self.run_closure(proc, &closure, context, lambda, None, args, block)
+    }
+
+    // The closure of a block written in the program, not made from a symbol
+    fn ast_closure(&self, proc: Value) -> Option<Rc<Closure>> {
+        match &self.runtime.proc_value(proc)?.body {
+            ProcBody::Ast(closure) => Some(closure.clone()),
+            _ => None,
+        }
+    }
+
+    // Runs a block's body in `context`, which is its own or one with another `self`.
+    // Lambdas are activations `return` leaves, and so are methods defined from blocks.
+    #[allow(clippy::too_many_arguments)]
+    fn run_closure(
+        &mut self,
+        proc: Value,
+        closure: &Closure,
+        context: Rc<Context>,
+        lambda: bool,
+        frame: Option<&str>,
+        args: &[Value],
+        block: Option<Value>,
+    ) -> Result<Value, Unwind> {
+        let env = new_env(Some(closure.env.clone()));
+        let params = closure.block.params();
+        let result = self.in_context(&context, &env, frame, lambda, |interp| {
+            interp.bind_params(&params, args, block, lambda, &env, &context)?;
+            loop {
+                match interp.eval_body(&closure.block.body, &env, &context) {
+                    Err(Unwind::Redo) => continue,
+                    Err(Unwind::Next(value)) => return Ok(value),
+                    result => return result,
+                }
+            }
+        });
+
+        match result {
+            Err(Unwind::Return(value, home)) if lambda && home == context.home => Ok(value),
+            Err(Unwind::Break(value, None)) if lambda => Ok(value),
+            // Tagged with the proc, so the call the block was given to can stop
+            Err(Unwind::Break(value, None)) => Err(Unwind::Break(value, proc.object_id())),
+            result => result,
+        }
+    }
+
+    // Binds arguments to parameters. Methods and lambdas are `strict` about the number of
+    // arguments, procs ignore extra ones, fill missing ones with `nil` and spread a single array.
+    fn bind_params(
+        &mut self,
+        params: &Params,
+        args: &[Value],
+        block: Option<Value>,
+        strict: bool,
+        env: &Env,
+        context: &Rc<Context>,
+    ) -> Result<(), Unwind> {
+        let mut args = args.to_vec();
+        let required = params.required_count();
+        let optional = params.params.iter().filter(|param| matches!(param, Param::Optional(..))).count();
+        let rest = params.params.iter().any(|param| matches!(param, Param::Rest(_)));
+        let takes_keywords = params.params.iter().any(|param| {
+            matches!(param, Param::RequiredKeyword(_) | Param::OptionalKeyword(..) | Param::KeywordRest(_))
+        });
+
+        let mut keywords = self.runtime.take_keywords(&mut args, takes_keywords);
+
+        if strict {
+            if args.len() < required || (!rest && args.len() > required + optional) {
+                let expected = if rest {
+                    format!("{}+", required)
+                } else if optional > 0 {
+                    format!("{}..{}", required, required + optional)
+                } else {
+                    required.to_string()
+                };
+                return Err(builtins::argument_count_error(self, args.len(), &expected));
+            }
+        } else {
+            let positional = required + optional;
+            if args.len() == 1 && (positional > 1 || (rest && positional > 0)) {
+                if let Some(elements) = self.runtime.array_value(args[0]) {
+                    args = elements.clone();
+                }
+            }
+            if args.len() < required {
+                args.resize(required, Value::NIL);
+            }
+            if !rest {
+                args.truncate(required + optional);
+            }
+        }
+
+        let optional_given = optional.min(args.len() - required);
+        let rest_length = args.len() - required - optional_given;
+        let (mut position, mut optional_seen) = (0, 0);
+        for param in &params.params {
+            match param {
+                Param::Required(name) => {
+                    declare(env, name, args[position]);
+                    position += 1;
+                }
+                Param::Destructure(inner) => {
+                    let values = self.to_array(args[position]);
+                    let inner = Params { params: inner.clone(), locals: Vec::new() };
+                    self.bind_params(&inner, &values, None, false, env, context)?;
+                    position += 1;
+                }
+                Param::Optional(name, default) => {
+                    let value = if optional_seen < optional_given {
+                        position += 1;
+                        args[position - 1]
+                    } else {
+                        self.eval_node(default, env, context)?
+                    };
+                    optional_seen += 1;
+                    declare(env, name, value);
+                }
+                Param::Rest(_) => {
+                    let elements = args[position..position + rest_length].to_vec();
+                    position += rest_length;
+                    let array = self.runtime.array(elements);
+                    declare(env, param.rest_local().expect("a rest"), array);
+                }
+                Param::RequiredKeyword(name) | Param::OptionalKeyword(name, _) => {
+                    let key = self.runtime.symbol(name);
+                    let value = match keywords.iter().position(|&(candidate, _)| candidate == key) {
+                        Some(index) => keywords.remove(index).1,
+                        None => match param {
+                            Param::OptionalKeyword(_, default) => self.eval_node(default, env, context)?,
+                            _ => return Err(self.runtime.error("ArgumentError", &format!("missing keyword: :{}", name))),
+                        },
+                    };
+                    declare(env, name, value);
+                }
+                Param::KeywordRest(_) => {
+                    let mut hash = RHash::default();
+                    for (key, value) in keywords.drain(..) {
+                        hash.insert(self.runtime.hash_key(key), key, value);
+                    }
+                    let hash = self.runtime.hash(hash);
+                    declare(env, param.rest_local().expect("a keyword rest"), hash);
+                }
+                Param::Block(_) => declare(env, param.rest_local().expect("a block"), block.unwrap_or(Value::NIL)),
+            }
+        }
+
+        if let Some(&(key, _)) = keywords.first() {
+            let message = format!("unknown keyword: {}", self.runtime.describe(key));
+            return Err(self.runtime.error("ArgumentError", &message));
+        }
+        for local in &params.locals {
+            declare(env, local, Value::NIL);
+        }
+        Ok(())
+    }
+
+    // Assignment
+
+    fn destructure(&mut self, targets: &[Target], values: &[Value], env: &Env, context: &Rc<Context>) -> Result<(), Unwind> {
+        let value_at = |index: usize| values.get(index).copied().unwrap_or(Value::NIL);
+        let Some(splat) = targets.iter().position(|target| matches!(target, Target::Splat(_))) else {
+            for (index, target) in targets.iter().enumerate() {
+                self.assign_target(target, value_at(index), env, context)?;
+            }
+            return Ok(());
+        };
+
+        // Targets after the splat take the last values, the splat gets what's left in between
+        let after = targets.len() - splat - 1;
+        let middle_end = values.len().saturating_sub(after).max(splat);
+        for (index, target) in targets[..splat].iter().enumerate() {
+            self.assign_target(target, value_at(index), env, context)?;
+        }
+        if let Target::Splat(Some(target)) = &targets[splat] {
+            let middle = values.get(splat..middle_end).unwrap_or_default().to_vec();
+            let array = self.runtime.array(middle);
+            self.assign_target(target, array, env, context)?;
+        }
+        for (index, target) in targets[splat + 1..].iter().enumerate() {
+            self.assign_target(target, value_at(middle_end + index), env, context)?;
+        }
+        Ok(())
+    }
+
+    fn assign_target(&mut self, target: &Target, value: Value, env: &Env, context: &Rc<Context>) -> Result<(), Unwind> {
+        match target {
+            Target::Local(name) => assign(env, name, value),
+            Target::InstanceVariable(name) => builtins::set_ivar(self, context.self_value, name, value)?,
+            Target::Index(receiver, args) => {
+                let receiver = self.eval_node(receiver, env, context)?;
+                let (mut args, _) = self.eval_arguments(args, env, context)?;
+                args.push(value);
+                self.call_method(receiver, "[]=", &args, None)?;
+            }
+            Target::Attribute(receiver, name) => {
+                let receiver = self.eval_node(receiver, env, context)?;
+                self.call_method(receiver, &format!("{}=", name), &[value], None)?;
+            }
+            Target::Splat(Some(target)) => self.assign_target(target, value, env, context)?,
+            Target::Splat(None) => {}
+            Target::Nested(targets) => {
+                let values = self.to_array(value);
+                self.destructure(targets, &values, env, context)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn eval_op_assign(&mut self, op_assign: &OpAssign, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let place = match &op_assign.target {
+            Target::Local(name) => Place::Local(name),
+            Target::InstanceVariable(name) => Place::InstanceVariable(name),
+            Target::Index(receiver, args) => {
+                let receiver = self.eval_node(receiver, env, context)?;
+                let (args, _) = self.eval_arguments(args, env, context)?;
+                Place::Index(receiver, args)
+            }
+            Target::Attribute(receiver, name) => Place::Attribute(self.eval_node(receiver, env, context)?, name),
+            Target::Splat(_) | Target::Nested(_) => unreachable!("the parser only allows single targets in `op=`"),
+        };
+
+        self.runtime.set_line(op_assign.line.0);
+        let current = match &place {
+            Place::Local(name) => lookup(env, name).unwrap_or(Value::NIL),
+            Place::InstanceVariable(name) => self.runtime.ivar(context.self_value, name),
+            Place::Index(receiver, args) => self.call_method(*receiver, "[]", args, None)?,
+            Place::Attribute(receiver, name) => self.call_method(*receiver, name, &[], None)?,
+        };
+        let value = match op_assign.operator.as_str() {
+            "||" if current.truthy() => return Ok(current),
+            "&&" if !current.truthy() => return Ok(current),
+            "||" | "&&" => self.eval_node(&op_assign.value, env, context)?,
+            operator => {
+                let right = self.eval_node(&op_assign.value, env, context)?;
+                self.runtime.set_line(op_assign.line.0);
+                self.call_method(current, operator, &[right], None)?
+            }
+        };
+
+        self.runtime.set_line(op_assign.line.0);
+        match place {
+            Place::Local(name) => assign(env, name, value),
+            Place::InstanceVariable(name) => builtins::set_ivar(self, context.self_value, name, value)?,
+            Place::Index(receiver, mut args) => {
+                args.push(value);
+                self.call_method(receiver, "[]=", &args, None)?;
+            }
+            Place::Attribute(receiver, name) => {
+                self.call_method(receiver, &format!("{}=", name), &[value], None)?;
+            }
+        }
+        Ok(value)
+    }
+
+    // Definitions
+
+    fn eval_def(&mut self, def: &Def, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let owner = match &def.singleton {
+            Some(target) => {
+                let target = self.eval_node(target, env, context)?;
+                self.runtime.singleton_class(target)?
+            }
+            None => context.definee,
+        };
+        let method = AstMethod { def: def.clone(), nesting: context.nesting.clone() };
+        self.runtime.add_method(owner, &def.name, MethodBody::Ast(Rc::new(method)));
+        if def.singleton.is_none() && context.visibility.get() == Visibility::Private {
+            self.runtime.set_visibility(owner, &def.name, Visibility::Private)?;
+        }
+        Ok(self.runtime.symbol(&def.name))
+    }
+
+    // The module a `class` or `module` path or a constant assignment defines its constant in,
+    // and the constant's name
+    fn definition_target(&mut self, path: &Node, env: &Env, context: &Rc<Context>) -> Result<(ObjectId, String), Unwind> {
+        match path {
+            Node::Constant(name) => {
+                Ok((context.nesting.last().copied().unwrap_or(self.runtime.classes.object), name.clone()))
+            }
+            Node::ScopedConstant(Some(scope), name) => {
+                let scope = self.eval_node(scope, env, context)?;
+                Ok((builtins::expect_module(self, scope)?, name.clone()))
+            }
+            Node::ScopedConstant(None, name) => Ok((self.runtime.classes.object, name.clone())),
+            _ => unreachable!("class and module paths and assigned constants are constants"),
+        }
+    }
+
+    fn eval_class(&mut self, class: &Class, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let (namespace, name) = self.definition_target(&class.path, env, context)?;
+        let superclass = match &class.superclass {
+            Some(superclass) => Some(self.eval_node(superclass, env, context)?),
+            None => None,
+        };
+        let class_id = self.runtime.open_class(namespace, &name, superclass)?;
+        let frame = format!("<class:{}>", name);
+        self.eval_module_body(class_id, &class.body, &frame, context)
+    }
+
+    fn eval_module(&mut self, module: &Module, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let (namespace, name) = self.definition_target(&module.path, env, context)?;
+        let module_id = self.runtime.open_module(namespace, &name)?;
+        let frame = format!("<module:{}>", name);
+        self.eval_module_body(module_id, &module.body, &frame, context)
+    }
+
+    // Class, module and singleton class bodies run with the module as `self`, in a new scope
+    fn eval_module_body(&mut self, module: ObjectId, body: &[Node], frame: &str, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let nesting = context.nesting.iter().copied().chain([module]).collect();
+        let context = Rc::new(Context {
+            self_value: Value::object(module),
+            definee: module,
+            nesting: Rc::new(nesting),
+            block: None,
+            method: None,
+            home: self.new_home(),
+            visibility: Cell::new(Visibility::Public),
+        });
+        let env = new_env(None);
+        self.in_context(&context, &env, Some(frame), false, |interp| interp.eval_body(body, &env, &context))
+    }
+
+    // Control flow
+
+    fn eval_while(&mut self, node: &While, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let mut skip_condition = node.do_while;
+        let mark = self.runtime.heap.root_mark();
+        loop {
+            self.runtime.heap.unroot_to(mark);
+            if !skip_condition && !self.eval_node(&node.condition, env, context)?.truthy() {
+                return Ok(Value::NIL);
+            }
+            skip_condition = false;
+            match self.eval_body(&node.body, env, context) {
+                Ok(_) | Err(Unwind::Next(_)) => {}
+                Err(Unwind::Break(value, None)) => return Ok(value),
+                // Runs the body again without checking the condition
+                Err(Unwind::Redo) => skip_condition = true,
+                Err(unwind) => return Err(unwind),
+            }
+        }
+    }
+
+    // Iterates over `to_a`, with the variables in the enclosing scope
+    fn eval_for(&mut self, node: &For, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let iterable = self.eval_node(&node.iterable, env, context)?;
+        let collection = self.call_method(iterable, "to_a", &[], None)?;
+        self.runtime.heap.root(collection);
+        let elements = self.to_array(collection);
+
+        for element in elements {
+            match &node.variables[..] {
+                [variable] => assign(env, variable, element),
+                variables => {
+                    let values = self.to_array(element);
+                    for (index, variable) in variables.iter().enumerate() {
+                        assign(env, variable, values.get(index).copied().unwrap_or(Value::NIL));
+                    }
+                }
+            }
+            loop {
+                match self.eval_body(&node.body, env, context) {
+                    Ok(_) | Err(Unwind::Next(_)) => break,
+                    Err(Unwind::Redo) => continue,
+                    Err(Unwind::Break(value, None)) => return Ok(value),
+                    Err(unwind) => return Err(unwind),
+                }
+            }
+        }
+        Ok(iterable)
+    }
+
+    fn eval_begin(&mut self, begin: &Begin, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let errinfo = self.runtime.errinfo;
+        let result = loop {
+            let body = self.eval_body(&begin.body, env, context);
+            self.root_result(&body);
+            let result = match body {
+                Err(Unwind::Raise(exception)) => match self.find_rescue(begin, exception, env, context) {
+                    Ok(Some(index)) => {
+                        let rescue = &begin.rescues[index];
+                        if let Some(variable) = &rescue.variable {
+                            assign(env, variable, exception);
+                        }
+                        self.runtime.errinfo = exception;
+                        match self.eval_body(&rescue.body, env, context) {
+                            // Runs the whole `begin` again
+                            Err(Unwind::Retry) => continue,
+                            result => result,
+                        }
+                    }
+                    Ok(None) => Err(Unwind::Raise(exception)),
+                    Err(unwind) => Err(unwind),
+                },
+                Ok(value) => match &begin.else_body {
+                    Some(else_body) => self.eval_body(else_body, env, context),
+                    None => Ok(value),
+                },
+                result => result,
+            };
+            break result;
+        };
+        self.runtime.errinfo = errinfo;
+
+        // `ensure` doesn't change the value, unless it leaves some other way itself
+        if let Some(ensure_body) = &begin.ensure_body {
+            self.root_result(&result);
+            self.eval_body(ensure_body, env, context)?;
+        }
+        result
+    }
+
+    // Keeps the value or exception alive while the clauses that follow run
+    fn root_result(&mut self, result: &Result<Value, Unwind>) {
+        let value = match *result {
+            Ok(value) => Some(value),
+            Err(unwind) => unwind.value(),
+        };
+        self.runtime.heap.root(value.unwrap_or(Value::NIL));
+    }
+
+    // The index of the first rescue clause whose classes match the exception
+    fn find_rescue(&mut self, begin: &Begin, exception: Value, env: &Env, context: &Rc<Context>) -> Result<Option<usize>, Unwind> {
+        for (index, rescue) in begin.rescues.iter().enumerate() {
+            let classes = match &rescue.classes[..] {
+                [] => vec![Value::object(self.runtime.classes.standard_error)],
+                classes => self.eval_arguments(classes, env, context)?.0,
+            };
+            for class in classes {
+                if self.call_method(class, "===", &[exception], None)?.truthy() {
+                    return Ok(Some(index));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn eval_case(&mut self, case: &Case, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let subject = match &case.subject {
+            Some(subject) => Some(self.eval_node(subject, env, context)?),
+            None => None,
+        };
+        for when in &case.whens {
+            for condition in &when.conditions {
+                let candidates = match condition {
+                    Node::Splat(value) => {
+                        let value = self.eval_node(value, env, context)?;
+                        builtins::splat(self, value)?
+                    }
+                    condition => vec![self.eval_node(condition, env, context)?],
+                };
+                for candidate in candidates {
+                    let matched = match subject {
+                        Some(subject) => self.call_method(candidate, "===", &[subject], None)?.truthy(),
+                        None => candidate.truthy(),
+                    };
+                    if matched {
+                        return self.eval_body(&when.body, env, context);
+                    }
+                }
+            }
+        }
+        match &case.else_body {
+            Some(else_body) => self.eval_body(else_body, env, context),
+            None => Ok(Value::NIL),
+        }
+    }
+
+    fn eval_case_in(&mut self, case: &CaseIn, env: &Env, context: &Rc<Context>) -> Result<Value, Unwind> {
+        let subject = self.eval_node(&case.subject, env, context)?;
+        for clause in &case.clauses {
+            if !self.pattern_matches(&clause.pattern, subject, env, context)? {
+                continue;
+            }
+            let guarded = match &clause.guard {
+                Some(guard) => self.eval_node(guard, env, context)?.truthy(),
+                None => true,
+            };
+            if guarded {
+                return self.eval_body(&clause.body, env, context);
+            }
+        }
+        match &case.else_body {
+            Some(else_body) => self.eval_body(else_body, env, context),
+            None => {
+                let message = builtins::inspect(self, subject)?;
+                Err(self.runtime.error("NoMatchingPatternError", &message))
+            }
+        }
+    }
+
+    fn pattern_matches(&mut self, pattern: &Pattern, value: Value, env: &Env, context: &Rc<Context>) -> Result<bool, Unwind> {
+        match pattern {
+            Pattern::Value(node) | Pattern::Pin(node) => {
+                let pattern = self.eval_node(node, env, context)?;
+                Ok(self.call_method(pattern, "===", &[value], None)?.truthy())
+            }
+            Pattern::Bind(name) => {
+                assign(env, name, value);
+                Ok(true)
+            }
+            Pattern::Alternative(patterns) => {
+                for pattern in patterns {
+                    if self.pattern_matches(pattern, value, env, context)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            Pattern::Capture(pattern, name) => {
+                let matched = self.pattern_matches(pattern, value, env, context)?;
+                if matched {
+                    assign(env, name, value);
+                }
+                Ok(matched)
+            }
+            Pattern::Array { constant, pre, rest, post } => {
+                if !self.constant_matches(constant, value, env, context)? {
+                    return Ok(false);
+                }
+                let Some(elements) = builtins::deconstruct(self, value)? else {
+                    return Ok(false);
+                };
+                let fits = match rest {
+                    Some(_) => elements.len() >= pre.len() + post.len(),
+                    None => elements.len() == pre.len(),
+                };
+                if !fits {
+                    return Ok(false);
+                }
+                let post_start = elements.len() - post.len();
+                for (pattern, &element) in pre.iter().zip(&elements).chain(post.iter().zip(&elements[post_start..])) {
+                    if !self.pattern_matches(pattern, element, env, context)? {
+                        return Ok(false);
+                    }
+                }
+                if let Some(Some(name)) = rest {
+                    let middle = self.runtime.array(elements[pre.len()..post_start].to_vec());
+                    assign(env, name, middle);
+                }
+                Ok(true)
+            }
+            Pattern::Find { constant, pre, middle, post } => {
+                if !self.constant_matches(constant, value, env, context)? {
+                    return Ok(false);
+                }
+                let Some(elements) = builtins::deconstruct(self, value)? else {
+                    return Ok(false);
+                };
+                if middle.len() > elements.len() {
+                    return Ok(false);
+                }
+                'start: for start in 0..=elements.len() - middle.len() {
+                    for (pattern, &element) in middle.iter().zip(&elements[start..]) {
+                        if !self.pattern_matches(pattern, element, env, context)? {
+                            continue 'start;
+                        }
+                    }
+                    for (name, range) in [(pre, 0..start), (post, start + middle.len()..elements.len())] {
+                        if let Some(name) = name {
+                            let array = self.runtime.array(elements[range].to_vec());
+                            assign(env, name, array);
+                        }
+                    }
+                    return Ok(true);
+                }
+                Ok(false)
+            }
+            Pattern::Hash { constant, pairs, rest } => {
+                if !self.constant_matches(constant, value, env, context)? {
+                    return Ok(false);
+                }
+                let Some(mut entries) = self.runtime.hash_value(value).map(|hash| hash.entries.clone()) else {
+                    return Ok(false);
+                };
+                for (key, pattern) in pairs {
+                    let key_value = self.runtime.symbol(key);
+                    let Some(index) = entries.iter().position(|&(candidate, _)| candidate == key_value) else {
+                        return Ok(false);
+                    };
+                    let (_, element) = entries.remove(index);
+                    let matched = match pattern {
+                        Some(pattern) => self.pattern_matches(pattern, element, env, context)?,
+                        None => {
+                            assign(env, key, element);
+                            true
+                        }
+                    };
+                    if !matched {
+                        return Ok(false);
+                    }
+                }
+                match rest {
+                    Some(HashPatternRest::Named(name)) => {
+                        let mut remaining = RHash::default();
+                        for (key, value) in entries {
+                            remaining.insert(self.runtime.hash_key(key), key, value);
+                        }
+                        let remaining = self.runtime.hash(remaining);
+                        assign(env, name, remaining);
+                        Ok(true)
+                    }
+                    Some(HashPatternRest::Nil) => Ok(entries.is_empty()),
+                    None => Ok(true),
+                }
+            }
+        }
+    }
+
+    fn constant_matches(&mut self, constant: &Option<Node>, value: Value, env: &Env, context: &Rc<Context>) -> Result<bool, Unwind> {
+        match constant {
+            Some(constant) => {
+                let constant = self.eval_node(constant, env, context)?;
+                Ok(self.call_method(constant, "===", &[value], None)?.truthy())
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+impl Executor for Interpreter {
+    fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    fn send(&mut self, receiver: Value, method: &str, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        self.call_method(receiver, method, args, block)
+    }
+
+    fn call_proc(&mut self, proc: Value, args: &[Value]) -> Result<Value, Unwind> {
+        self.call_block(proc, args, None)
+    }
+
+    fn current_block(&mut self) -> Option<Value> {
+        self.contexts.last().and_then(|context| context.block)
+    }
+
+    // Blocks keep their variables and where `return` goes, lambdas keep returning from themselves
+    fn call_proc_as(&mut self, proc: Value, self_value: Value, definee: ObjectId, args: &[Value]) -> Result<Value, Unwind> {
+        let Some(closure) = self.ast_closure(proc) else {
+            return self.call_block(proc, args, None);
+        };
+        let lambda = self.runtime.proc_value(proc).is_some_and(|value| value.lambda);
+        let home = if lambda { self.new_home() } else { closure.context.home };
+        let context = Rc::new(Context {
+            self_value,
+            definee,
+            home,
+            visibility: Cell::new(Visibility::Public),
+            ..(*closure.context).clone()
+        });
+        self.run_closure(proc, &closure, context, lambda, None, args, None)
+    }
+
+    fn set_default_visibility(&mut self, visibility: Visibility) {
+        if let Some(context) = self.contexts.last() {
+            context.visibility.set(visibility);
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        let mut tracer = Tracer::default();
+        for context in &self.contexts {
+            context.trace(&mut tracer);
+        }
+        for env in &self.envs {
+            trace_env(env, &mut tracer);
+        }
+        let finalizers = self.runtime.collect_garbage(tracer);
+        builtins::run_finalizers(self, finalizers);
+    }
+}
@@ -1,9 +1,53 @@
-use crate::token::Token;
+use serde::Serialize;
+
+use crate::parser::is_keyword;
+use crate::token::{StringPart, Token};
+
+// Keywords that end a value, so a `/` after them divides
+const VALUE_ENDING_KEYWORDS: [&str; 5] = ["end", "self", "nil", "true", "false"];
+
+// Operators that can be method names, longest first so the lexer can match prefixes
+pub(crate) const OPERATOR_METHODS: [&str; 27] = [
+    "[]=", "===", "<=>", "[]", "**", "==", "=~", "!=", "!~", "<=", ">=", "<<", ">>", "+@", "-@", "+", "-", "*", "/", "%",
+    "<", ">", "!", "&", "|", "^", "~",
+];
+
+// A token together with whether whitespace came right before it, which decides
+// between `foo -1` (a call with a negative argument) and `foo - 1` (a subtraction),
+// and where it is in the source
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Lexeme {
+    pub token: Token,
+    pub space_before: bool,
+    pub span: Span,
+    // Every part of an interpolated string. Its `Interpolation` token only has the text
+    // before the first `#{}` and that code.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<StringPart>,
+}
+
+// Lines and columns count from 1, the end is exclusive
+#[derive(Debug, PartialEq, Clone, Copy, Serialize)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub end_line: usize,
+    pub end_column: usize,
+}
 
 pub struct Lexer<'a> {
+    input: &'a str,
     position: usize,
+    line: usize,
+    column: usize,
     chars: std::str::Chars<'a>,
     current_char: Option<char>,
+    // The last token other than whitespace, and whether whitespace followed it, which
+    // tell a regexp literal from a division
+    previous: Token,
+    space_after_previous: bool,
+    // The parts of the interpolated string just read, until its lexeme takes them
+    string_parts: Vec<StringPart>,
 }
 
 impl<'a> Lexer<'a> {
@@ -11,18 +55,41 @@ impl<'a> Lexer<'a> {
         let mut chars = input.chars();
         let current_char = chars.next();
         Lexer {
+            input,
             position: 0,
+            line: 1,
+            column: 1,
             chars,
             current_char,
+            previous: Token::BreakLine,
+            space_after_previous: false,
+            string_parts: Vec::new(),
         }
     }
 
     fn advance(&mut self) {
+        if self.current_char == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         self.current_char = self.chars.next();
         self.position += 1;
     }
 
     pub fn next_token(&mut self) -> Token {
+        let token = self.scan_token();
+        if token == Token::WhiteSpace {
+            self.space_after_previous = true;
+        } else {
+            self.previous = token.clone();
+            self.space_after_previous = false;
+        }
+        token
+    }
+
+    fn scan_token(&mut self) -> Token {
         if let Some(ch) = self.current_char {
             match ch {
                 ':' => {
@@ -37,17 +104,61 @@ impl<'a> Lexer<'a> {
                     self.advance();
                     Token::RightBrace
                 },
+                '|' => {
+                    self.advance();
+                    self.resolve_pipe()
+                },
+                '[' => {
+                    self.advance();
+                    Token::LeftBracket
+                },
+                ']' => {
+                    self.advance();
+                    Token::RightBracket
+                },
+                '^' => {
+                    self.advance();
+                    Token::Caret
+                },
+                '?' => {
+                    self.advance();
+                    Token::Question
+                },
+                '!' => {
+                    self.advance();
+                    self.resolve_not()
+                },
+                '.' => {
+                    self.advance();
+                    self.resolve_dot()
+                },
+                ';' => {
+                    self.advance();
+                    Token::Semicolon
+                },
                 '*' => {
                     self.advance();
-                    Token::Asterisk
+                    self.resolve_asterisk()
+                },
+                '&' => {
+                    self.advance();
+                    self.resolve_ampersand()
                 },
                 '/' => {
+                    let regexp = self.at_regexp();
                     self.advance();
-                    Token::Slash
+                    if regexp {
+                        return self.read_regexp();
+                    }
+                    self.operator_or_assign(Token::Slash, "/")
                 },
                 '%' => {
                     self.advance();
-                    Token::Percent
+                    self.operator_or_assign(Token::Percent, "%")
+                },
+                '@' => {
+                    self.advance();
+                    self.read_instance_variable()
                 },
                 '=' => {
                     self.advance();
@@ -87,13 +198,13 @@ impl<'a> Lexer<'a> {
                 }
                 '+' => {
                     self.advance();
-                    Token::Plus
+                    self.operator_or_assign(Token::Plus, "+")
                 }
                 '-' => {
                     self.advance();
-                    Token::Minus
+                    self.resolve_minus()
                 }
-                c if c.is_alphabetic() => {
+                c if c.is_alphabetic() || c == '_' => {
                     self.advance();
                     self.read_identifier(c)
                 }
@@ -111,12 +222,114 @@ impl<'a> Lexer<'a> {
         }
     }
 
+    // Like `next_token`, but folds `WhiteSpace` tokens into the token that follows them
+    pub fn next_lexeme(&mut self) -> Lexeme {
+        let mut space_before = false;
+        loop {
+            let (line, column) = (self.line, self.column);
+            let token = self.next_token();
+            if token == Token::WhiteSpace {
+                space_before = true;
+                continue;
+            }
+            let span = Span { line, column, end_line: self.line, end_column: self.column };
+            return Lexeme { token, space_before, span, parts: std::mem::take(&mut self.string_parts) };
+        }
+    }
+
+    // Every lexeme up to and including `Eof`
+    pub fn tokenize(mut self) -> Vec<Lexeme> {
+        let mut lexemes = Vec::new();
+        loop {
+            let lexeme = self.next_lexeme();
+            let eof = lexeme.token == Token::Eof;
+            lexemes.push(lexeme);
+            if eof {
+                return lexemes;
+            }
+        }
+    }
+
+    fn resolve_dot(&mut self) -> Token {
+        match self.current_char {
+            Some('.') => {
+                self.advance();
+                match self.current_char {
+                    Some('.') => {
+                        self.advance();
+                        Token::DotDotDot
+                    }
+                    _ => Token::DotDot,
+                }
+            }
+            _ => Token::Dot,
+        }
+    }
+
+    fn resolve_pipe(&mut self) -> Token {
+        match self.current_char {
+            Some('|') => {
+                self.advance();
+                self.operator_or_assign(Token::PipePipe, "||")
+            }
+            _ => Token::Pipe,
+        }
+    }
+
+    fn resolve_ampersand(&mut self) -> Token {
+        match self.current_char {
+            Some('&') => {
+                self.advance();
+                self.operator_or_assign(Token::AmpersandAmpersand, "&&")
+            }
+            _ => Token::Ampersand,
+        }
+    }
+
+    fn resolve_not(&mut self) -> Token {
+        match self.current_char {
+            Some('=') => {
+                self.advance();
+                Token::NotEqual
+            }
+            Some('~') => {
+                self.advance();
+                Token::NotMatch
+            }
+            _ => Token::Not,
+        }
+    }
+
+    fn resolve_minus(&mut self) -> Token {
+        match self.current_char {
+            Some('>') => {
+                self.advance();
+                Token::Lambda
+            }
+            _ => self.operator_or_assign(Token::Minus, "-"),
+        }
+    }
+
+    fn resolve_asterisk(&mut self) -> Token {
+        match self.current_char {
+            Some('*') => {
+                self.advance();
+                self.operator_or_assign(Token::AsteriskAsterisk, "**")
+            }
+            _ => self.operator_or_assign(Token::Asterisk, "*"),
+        }
+    }
+
     fn resolve_greater(&mut self) -> Token {
         match self.current_char {
             Some('=') => {
                 self.advance();
                 Token::GreaterThanOrEqual
             }
+            Some('>') => {
+                self.advance();
+                self.operator_or_assign(Token::ShiftRight, ">>")
+            }
             _ => Token::GreaterThan,  // Não avança se não for '='
         }
     }
@@ -125,12 +338,31 @@ impl<'a> Lexer<'a> {
         match self.current_char {
             Some('=') => {
                 self.advance();
+                if self.current_char == Some('>') {
+                    self.advance();
+                    return Token::Spaceship;
+                }
                 Token::LessThanOrEqual
             }
+            Some('<') => {
+                self.advance();
+                self.operator_or_assign(Token::ShiftLeft, "<<")
+            }
             _ => Token::LessThan,  // Não avança se não for '='
         }
     }
 
+    // `op=` is a single token, so `a += 1` never reaches the parser as `a + (= 1)`
+    fn operator_or_assign(&mut self, operator: Token, name: &str) -> Token {
+        match self.current_char {
+            Some('=') => {
+                self.advance();
+                Token::OperatorAssign(name.to_string())
+            }
+            _ => operator,
+        }
+    }
+
     fn resolve_equal(&mut self) -> Token {
         match self.current_char {
             Some('=') => {
@@ -147,21 +379,209 @@ impl<'a> Lexer<'a> {
                 self.advance();
                 Token::Arrow
             }
+            Some('~') => {
+                self.advance();
+                Token::Match
+            }
             _ => Token::Equal,
         }
     }
 
+    // Double quoted strings take the usual escapes and `#{code}`, single quoted ones only
+    // `\\` and `\'`. Escapes can spell out bytes that aren't valid UTF-8, which are kept as
+    // `Bytes`.
     fn read_string(&mut self, quote: char) -> Token {
-        let mut text = String::new();
+        let mut bytes = Vec::new();
+        let mut parts = Vec::new();
+        let mut first_code = None;
         while let Some(ch) = self.current_char {
-            if ch == quote {
-                self.advance();
-                break;
+            self.advance();
+            match ch {
+                ch if ch == quote => break,
+                '#' if quote == '"' && self.current_char == Some('{') => {
+                    self.advance();
+                    let start = self.offset();
+                    let (code, end) = self.read_interpolated_code();
+                    first_code.get_or_insert_with(|| self.input[start..end].trim().to_string());
+                    parts.push(StringPart::Text(std::mem::take(&mut bytes)));
+                    parts.push(StringPart::Code(code));
+                }
+                '\\' if quote == '"' => self.read_escape(&mut bytes),
+                '\\' if self.current_char == Some(quote) || self.current_char == Some('\\') => {
+                    bytes.push(self.current_char.expect("just checked") as u8);
+                    self.advance();
+                }
+                ch => bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes()),
+            }
+        }
+        let Some(first_code) = first_code else {
+            return match String::from_utf8(bytes) {
+                Ok(text) => Token::Text(text),
+                Err(error) => Token::Bytes(error.into_bytes()),
+            };
+        };
+        parts.push(StringPart::Text(bytes));
+        let first_text = match &parts[0] {
+            StringPart::Text(text) => String::from_utf8_lossy(text).into_owned(),
+            StringPart::Code(_) => unreachable!("parts start with text"),
+        };
+        self.string_parts = parts;
+        Token::Interpolation(first_text, first_code)
+    }
+
+    // The lexemes of the code in `#{}` up to its closing brace, which becomes their `Eof`,
+    // and the offset where the code ends
+    fn read_interpolated_code(&mut self) -> (Vec<Lexeme>, usize) {
+        let mut lexemes = Vec::new();
+        let mut depth = 0;
+        loop {
+            let end = self.offset();
+            let mut lexeme = self.next_lexeme();
+            match lexeme.token {
+                Token::LeftBrace => depth += 1,
+                Token::RightBrace if depth > 0 => depth -= 1,
+                Token::RightBrace => lexeme.token = Token::Eof,
+                _ => {}
+            }
+            let last = lexeme.token == Token::Eof;
+            lexemes.push(lexeme);
+            if last {
+                return (lexemes, end);
+            }
+        }
+    }
+
+    // The byte offset of the current character
+    fn offset(&self) -> usize {
+        self.input.len() - self.chars.as_str().len() - self.current_char.map_or(0, char::len_utf8)
+    }
+
+    fn read_escape(&mut self, bytes: &mut Vec<u8>) {
+        let Some(ch) = self.current_char else { return };
+        self.advance();
+        let byte = match ch {
+            'n' => b'\n',
+            't' => b'\t',
+            'r' => b'\r',
+            's' => b' ',
+            'e' => 0x1b,
+            'a' => 0x07,
+            'b' => 0x08,
+            'f' => 0x0c,
+            'v' => 0x0b,
+            '0'..='7' => {
+                let mut code = ch.to_digit(8).expect("octal digit");
+                for _ in 0..2 {
+                    match self.current_char.and_then(|c| c.to_digit(8)) {
+                        Some(digit) => code = code * 8 + digit,
+                        None => break,
+                    }
+                    self.advance();
+                }
+                code as u8
+            }
+            'x' => {
+                let digits = self.read_hex_digits(2);
+                u8::from_str_radix(&digits, 16).unwrap_or(b'x')
+            }
+            'u' => {
+                let code = if self.current_char == Some('{') {
+                    self.advance();
+                    let digits = self.read_hex_digits(6);
+                    if self.current_char == Some('}') {
+                        self.advance();
+                    }
+                    digits
+                } else {
+                    self.read_hex_digits(4)
+                };
+                let c = u32::from_str_radix(&code, 16).ok().and_then(char::from_u32).unwrap_or(char::REPLACEMENT_CHARACTER);
+                bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                return;
             }
-            text.push(ch);
+            '\n' => return,
+            ch => {
+                bytes.extend_from_slice(ch.encode_utf8(&mut [0; 4]).as_bytes());
+                return;
+            }
+        };
+        bytes.push(byte);
+    }
+
+    fn read_hex_digits(&mut self, max: usize) -> String {
+        let mut digits = String::new();
+        while let Some(ch) = self.current_char.filter(|ch| ch.is_ascii_hexdigit() && digits.len() < max) {
+            digits.push(ch);
             self.advance();
         }
-        Token::Text(text)
+        digits
+    }
+
+    // A `/` starts a regexp where a value is expected: after an operator or a keyword,
+    // and after a method name it's separated from, as in `puts /x/` (but not `a / b`).
+    // The regexp also has to end on the same line.
+    fn at_regexp(&self) -> bool {
+        let expects_value = match &self.previous {
+            Token::Identifier(name) if is_keyword(name) => !VALUE_ENDING_KEYWORDS.contains(&name.as_str()),
+            Token::Identifier(_) => self.space_after_previous && !matches!(self.chars.clone().next(), Some(' ' | '=') | None),
+            Token::Number(_)
+            | Token::Float(_)
+            | Token::Bignum(_)
+            | Token::Text(_)
+            | Token::Bytes(_)
+            | Token::Interpolation(..)
+            | Token::Regexp(..)
+            | Token::Symbol(_)
+            | Token::InstanceVariable(_)
+            | Token::RightParenthesis
+            | Token::RightBracket
+            | Token::RightBrace => false,
+            _ => true,
+        };
+        expects_value && self.regexp_ends_on_line()
+    }
+
+    fn regexp_ends_on_line(&self) -> bool {
+        let mut rest = self.chars.clone();
+        loop {
+            match rest.next() {
+                Some('/') => return true,
+                Some('\\') => {
+                    rest.next();
+                }
+                Some('\n') | None => return false,
+                Some(_) => {}
+            }
+        }
+    }
+
+    // The source is kept as written, but for `\/`, followed by the options
+    fn read_regexp(&mut self) -> Token {
+        let mut source = String::new();
+        while let Some(ch) = self.current_char {
+            self.advance();
+            match ch {
+                '/' => break,
+                '\\' if self.current_char == Some('/') => {
+                    source.push('/');
+                    self.advance();
+                }
+                '\\' => {
+                    source.push('\\');
+                    if let Some(escaped) = self.current_char {
+                        source.push(escaped);
+                        self.advance();
+                    }
+                }
+                ch => source.push(ch),
+            }
+        }
+        let mut options = String::new();
+        while let Some(ch) = self.current_char.filter(|ch| matches!(ch, 'i' | 'm' | 'x')) {
+            options.push(ch);
+            self.advance();
+        }
+        Token::Regexp(source, options)
     }
 
     fn read_identifier(&mut self, first_char: char) -> Token {
@@ -169,13 +589,21 @@ impl<'a> Lexer<'a> {
         identifier.push(first_char);
 
         while let Some(ch) = self.current_char {
-            if !ch.is_alphabetic() {
+            if !ch.is_alphanumeric() && ch != '_' {
                 break;
             }
             identifier.push(ch);
             self.advance();
         }
 
+        // Predicate and bang methods (`empty?`, `save!`), but not `a != b`
+        if let Some(ch) = self.current_char {
+            if (ch == '?' || ch == '!') && self.chars.clone().next() != Some('=') {
+                identifier.push(ch);
+                self.advance();
+            }
+        }
+
         Token::Identifier(identifier)
     }
 
@@ -183,42 +611,155 @@ impl<'a> Lexer<'a> {
         let mut number = String::new();
         number.push(first_char);
 
+        self.read_digits(&mut number);
+
+        // `1.5` and `1e3` are floats, but `1.times` calls a method on an integer
+        let mut float = false;
+        if self.current_char == Some('.') && self.chars.clone().next().is_some_and(|ch| ch.is_ascii_digit()) {
+            float = true;
+            number.push('.');
+            self.advance();
+            self.read_digits(&mut number);
+        }
+        if matches!(self.current_char, Some('e' | 'E')) {
+            let mut rest = self.chars.clone();
+            let exponent = match rest.next() {
+                Some(sign @ ('+' | '-')) if rest.next().is_some_and(|ch| ch.is_ascii_digit()) => Some(Some(sign)),
+                Some(ch) if ch.is_ascii_digit() => Some(None),
+                _ => None,
+            };
+            if let Some(sign) = exponent {
+                float = true;
+                number.push('e');
+                self.advance();
+                if let Some(sign) = sign {
+                    number.push(sign);
+                    self.advance();
+                }
+                self.read_digits(&mut number);
+            }
+        }
+
+        if float {
+            return Token::Float(number.parse::<f64>().unwrap_or(0.0));
+        }
+        match number.parse::<i64>() {
+            Ok(value) => Token::Number(value),
+            Err(_) => Token::Bignum(number),
+        }
+    }
+
+    // Underscores can separate digits, as in `1_000_000`
+    fn read_digits(&mut self, number: &mut String) {
         while let Some(ch) = self.current_char {
-            if !ch.is_numeric() {
+            if ch == '_' && self.chars.clone().next().is_some_and(|next| next.is_ascii_digit()) {
+                self.advance();
+                continue;
+            }
+            if !ch.is_ascii_digit() {
                 break;
             }
             number.push(ch);
             self.advance();
         }
-
-        Token::Number(number.parse::<i32>().unwrap_or(0))
     }
 
     // Should resolve the token for colon or symbol
     // If the next char is a letter, it should be a symbol
+    // If it is another colon, it is the scope operator (`A::B`)
     // Otherwise, it should be a colon
     fn resolve_colon_or_symbol(&mut self) -> Token {
         match self.current_char {
-            Some(ch) if ch.is_alphabetic() => {
+            Some(':') => {
+                self.advance();
+                Token::ColonColon
+            }
+            Some(ch) if ch.is_alphabetic() || ch == '_' => {
                 self.advance();
                 self.read_symbol(ch)
             }
-            _ => Token::Colon,
+            // `:"with spaces"`
+            Some(quote @ ('"' | '\'')) => {
+                self.advance();
+                match self.read_string(quote) {
+                    Token::Text(text) => Token::Symbol(text),
+                    Token::Bytes(bytes) => Token::Symbol(String::from_utf8_lossy(&bytes).into_owned()),
+                    token => token,
+                }
+            }
+            // `:@name`
+            Some('@') if self.peek_char().is_some_and(|ch| ch.is_alphabetic() || ch == '_') => {
+                self.advance();
+                match self.read_instance_variable() {
+                    Token::InstanceVariable(name) => Token::Symbol(format!("@{}", name)),
+                    token => token,
+                }
+            }
+            _ => match self.operator_symbol() {
+                Some(operator) => Token::Symbol(operator.to_string()),
+                None => Token::Colon,
+            },
         }
     }
 
+    // `:+`, `:<=>`, `:[]=` and the other operators that can be method names
+    fn operator_symbol(&mut self) -> Option<&'static str> {
+        let ahead: String = self.current_char.into_iter().chain(self.chars.clone().take(2)).collect();
+        let operator = OPERATOR_METHODS.into_iter().find(|operator| ahead.starts_with(operator))?;
+        for _ in 0..operator.len() {
+            self.advance();
+        }
+        Some(operator)
+    }
+
+    // The character after the current one
+    fn peek_char(&self) -> Option<char> {
+        self.chars.clone().next()
+    }
+
+    fn read_instance_variable(&mut self) -> Token {
+        let mut name = String::new();
+
+        while let Some(ch) = self.current_char {
+            if !ch.is_alphanumeric() && ch != '_' {
+                break;
+            }
+            name.push(ch);
+            self.advance();
+        }
+
+        if name.is_empty() {
+            return Token::Illegal("@".to_string());
+        }
+        Token::InstanceVariable(name)
+    }
+
     fn read_symbol(&mut self, first_char: char) -> Token {
         let mut symbol = String::new();
         symbol.push(first_char);
 
         while let Some(ch) = self.current_char {
-            if !ch.is_alphabetic() {
+            if !ch.is_alphanumeric() && ch != '_' {
                 break;
             }
             symbol.push(ch);
             self.advance();
         }
 
+        // `:empty?`, `:save!` and `:name=`, but not `:a==b`, `:a!=b` or `{:a=>1}`
+        let next = self.peek_char();
+        match self.current_char {
+            Some(ch @ ('?' | '!')) if next != Some('=') => {
+                symbol.push(ch);
+                self.advance();
+            }
+            Some('=') if !matches!(next, Some('=' | '~' | '>')) => {
+                symbol.push('=');
+                self.advance();
+            }
+            _ => {}
+        }
+
         Token::Symbol(symbol)
     }
 }
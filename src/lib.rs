@@ -1,2 +1,17 @@
 pub mod token;
-pub mod lexer;
\ No newline at end of file
+pub mod lexer;
+pub mod llvm;
+pub mod ast;
+pub mod builtins;
+pub mod bytecode;
+pub mod compiler;
+pub mod gc;
+pub mod interp;
+pub mod parser;
+pub mod resolver;
+pub mod runtime;
+pub mod sexp;
+pub mod unparser;
+pub mod visitor;
+pub mod vm;
+pub mod word;
\ No newline at end of file
@@ -1,18 +1,23 @@
 use inkwell::builder::Builder;
 use inkwell::context::Context;
 use inkwell::execution_engine::{ExecutionEngine, JitFunction};
-use inkwell::module::Module;
-use inkwell::OptimizationLevel;
+use inkwell::module::{Linkage, Module};
+use inkwell::values::{FunctionValue, IntValue};
+use inkwell::{IntPredicate, OptimizationLevel};
 
-use std::error::Error;
+use crate::runtime::{Executor, Unwind, Value};
+use crate::word;
 
 /// Convenience type alias for the `sum` function.
 ///
 /// Calling this is innately `unsafe` because there's no guarantee it doesn't
 /// do `unsafe` operations internally.
-type SumFunc = unsafe extern "C" fn(u64, u64, u64) -> u64;
+pub type SumFunc = unsafe extern "C" fn(Value, Value, Value) -> Value;
 
-struct CodeGen<'ctx> {
+// Emits functions over `Value`s, tagged as `word` describes, which callers pass as they
+// are. What compiled code can't handle (anything but fixnums and flonums, for now) makes
+// it return `Value::UNDEF`, and the caller falls back to sending the method.
+pub struct CodeGen<'ctx> {
     context: &'ctx Context,
     module: Module<'ctx>,
     builder: Builder<'ctx>,
@@ -20,9 +25,16 @@ struct CodeGen<'ctx> {
 }
 
 impl<'ctx> CodeGen<'ctx> {
-    fn jit_compile_sum(&self) -> Option<JitFunction<SumFunc>> {
-        let i64_type = self.context.i64_type();
-        let fn_type = i64_type.fn_type(&[i64_type.into(), i64_type.into(), i64_type.into()], false);
+    pub fn new(context: &'ctx Context) -> Result<Self, String> {
+        let module = context.create_module("chimiaguin");
+        let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None).map_err(|error| error.to_string())?;
+        Ok(CodeGen { context, module, builder: context.create_builder(), execution_engine })
+    }
+
+    pub fn jit_compile_sum(&self) -> Option<JitFunction<'ctx, SumFunc>> {
+        let word_type = self.context.i64_type();
+        let add = self.compile_add();
+        let fn_type = word_type.fn_type(&[word_type.into(), word_type.into(), word_type.into()], false);
         let function = self.module.add_function("sum", fn_type, None);
         let basic_block = self.context.append_basic_block(function, "entry");
 
@@ -32,36 +44,126 @@ impl<'ctx> CodeGen<'ctx> {
         let y = function.get_nth_param(1)?.into_int_value();
         let z = function.get_nth_param(2)?.into_int_value();
 
-        let sum = self.builder.build_int_add(x, y, "sum").unwrap();
-        let sum = self.builder.build_int_add(sum, z, "sum").unwrap();
+        // `UNDEF` is neither a fixnum nor a flonum, so it goes through the second addition
+        let sum = self.builder.build_call(add, &[x.into(), y.into()], "sum").ok()?.try_as_basic_value().left()?;
+        let sum = self.builder.build_call(add, &[sum.into(), z.into()], "sum").ok()?.try_as_basic_value().left()?;
 
-        self.builder.build_return(Some(&sum)).unwrap();
+        self.builder.build_return(Some(&sum)).ok()?;
 
         unsafe { self.execution_engine.get_function("sum").ok() }
     }
-}
 
-fn main() -> Result<(), Box<dyn Error>> {
-    let context = Context::create();
-    let module = context.create_module("sum");
-    let execution_engine = module.create_jit_execution_engine(OptimizationLevel::None)?;
-    let codegen = CodeGen {
-        context: &context,
-        module,
-        builder: context.create_builder(),
-        execution_engine,
-    };
-
-    let sum = codegen.jit_compile_sum().ok_or("Unable to JIT compile `sum`")?;
-
-    let x = 1u64;
-    let y = 2u64;
-    let z = 3u64;
-
-    unsafe {
-        println!("{} + {} + {} = {}", x, y, z, sum.call(x, y, z));
-        assert_eq!(sum.call(x, y, z), x + y + z);
+    // `Integer#+` and `Float#+` on two immediates of the same kind
+    fn compile_add(&self) -> FunctionValue<'ctx> {
+        let word_type = self.context.i64_type();
+        let function = self.module.add_function("word_add", word_type.fn_type(&[word_type.into(), word_type.into()], false), Some(Linkage::Private));
+        let entry = self.context.append_basic_block(function, "entry");
+        let fixnums = self.context.append_basic_block(function, "fixnums");
+        let not_fixnums = self.context.append_basic_block(function, "not_fixnums");
+        let flonums = self.context.append_basic_block(function, "flonums");
+        let undef = self.context.append_basic_block(function, "undef");
+
+        let left = function.get_nth_param(0).expect("two parameters").into_int_value();
+        let right = function.get_nth_param(1).expect("two parameters").into_int_value();
+        let builder = &self.builder;
+
+        builder.position_at_end(entry);
+        let both = builder.build_and(left, right, "both").unwrap();
+        let is_fixnum = self.has_tag(both, word::FIXNUM_FLAG, word::FIXNUM_FLAG);
+        builder.build_conditional_branch(is_fixnum, fixnums, not_fixnums).unwrap();
+
+        // `(2a + 1) - 1 + (2b + 1)` is `2(a + b) + 1`, which overflows exactly when the sum isn't a fixnum
+        builder.position_at_end(fixnums);
+        let untagged = builder.build_xor(left, self.word(word::FIXNUM_FLAG), "untagged").unwrap();
+        let add_with_overflow = self.sadd_with_overflow();
+        let result = builder.build_call(add_with_overflow, &[untagged.into(), right.into()], "result").unwrap();
+        let result = result.try_as_basic_value().left().expect("returns a struct").into_struct_value();
+        let sum = builder.build_extract_value(result, 0, "sum").unwrap().into_int_value();
+        let overflow = builder.build_extract_value(result, 1, "overflow").unwrap().into_int_value();
+        let sum = builder.build_select(overflow, self.word(Value::UNDEF.bits()), sum, "sum").unwrap();
+        builder.build_return(Some(&sum)).unwrap();
+
+        builder.position_at_end(not_fixnums);
+        let left_flonum = self.has_tag(left, word::FLONUM_MASK, word::FLONUM_FLAG);
+        let right_flonum = self.has_tag(right, word::FLONUM_MASK, word::FLONUM_FLAG);
+        let is_flonum = builder.build_and(left_flonum, right_flonum, "is_flonum").unwrap();
+        builder.build_conditional_branch(is_flonum, flonums, undef).unwrap();
+
+        builder.position_at_end(flonums);
+        let float_type = self.context.f64_type();
+        let left = builder.build_bit_cast(self.decode_flonum(left), float_type, "left").unwrap().into_float_value();
+        let right = builder.build_bit_cast(self.decode_flonum(right), float_type, "right").unwrap().into_float_value();
+        let sum = builder.build_float_add(left, right, "sum").unwrap();
+        let bits = builder.build_bit_cast(sum, word_type, "bits").unwrap().into_int_value();
+        builder.build_return(Some(&self.encode_flonum(bits))).unwrap();
+
+        builder.position_at_end(undef);
+        builder.build_return(Some(&self.word(Value::UNDEF.bits()))).unwrap();
+        function
+    }
+
+    fn word(&self, bits: u64) -> IntValue<'ctx> {
+        self.context.i64_type().const_int(bits, false)
+    }
+
+    fn has_tag(&self, value: IntValue<'ctx>, mask: u64, tag: u64) -> IntValue<'ctx> {
+        let masked = self.builder.build_and(value, self.word(mask), "masked").unwrap();
+        self.builder.build_int_compare(IntPredicate::EQ, masked, self.word(tag), "has_tag").unwrap()
     }
 
-    Ok(())
-}
\ No newline at end of file
+    fn rotate(&self, value: IntValue<'ctx>, left: u64) -> IntValue<'ctx> {
+        let high = self.builder.build_left_shift(value, self.word(left), "high").unwrap();
+        let low = self.builder.build_right_shift(value, self.word(64 - left), false, "low").unwrap();
+        self.builder.build_or(high, low, "rotated").unwrap()
+    }
+
+    // The float's bits, as `word::decode_flonum` does it
+    fn decode_flonum(&self, value: IntValue<'ctx>) -> IntValue<'ctx> {
+        let builder = &self.builder;
+        let sign = builder.build_right_shift(value, self.word(63), false, "sign").unwrap();
+        let top = builder.build_int_sub(self.word(2), sign, "top").unwrap();
+        let rest = builder.build_and(value, self.word(!word::FLONUM_MASK), "rest").unwrap();
+        let rotated = self.rotate(builder.build_or(top, rest, "bits").unwrap(), 61);
+        let is_zero = builder.build_int_compare(IntPredicate::EQ, value, self.word(Value::flonum(0.0).expect("zero is a flonum").bits()), "is_zero").unwrap();
+        builder.build_select(is_zero, self.word(0), rotated, "bits").unwrap().into_int_value()
+    }
+
+    // The flonum for a float's bits, or `UNDEF` when it would have to be boxed
+    fn encode_flonum(&self, bits: IntValue<'ctx>) -> IntValue<'ctx> {
+        let builder = &self.builder;
+        let exponent = builder.build_right_shift(bits, self.word(60), false, "exponent").unwrap();
+        let exponent = builder.build_and(exponent, self.word(0x7), "exponent").unwrap();
+        // Exponents starting with `011` or `100`, the ones whose rotated bits fit
+        let biased = builder.build_int_sub(exponent, self.word(3), "biased").unwrap();
+        let in_range = builder.build_int_compare(IntPredicate::ULE, biased, self.word(1), "in_range").unwrap();
+        let ambiguous = builder.build_int_compare(IntPredicate::EQ, bits, self.word(0x3000_0000_0000_0000), "ambiguous").unwrap();
+        let not_ambiguous = builder.build_not(ambiguous, "not_ambiguous").unwrap();
+        let in_range = builder.build_and(in_range, not_ambiguous, "in_range").unwrap();
+        let rotated = builder.build_and(self.rotate(bits, 3), self.word(!0x01), "rotated").unwrap();
+        let flonum = builder.build_or(rotated, self.word(word::FLONUM_FLAG), "flonum").unwrap();
+
+        let is_zero = builder.build_int_compare(IntPredicate::EQ, bits, self.word(0), "is_zero").unwrap();
+        let zero = self.word(Value::flonum(0.0).expect("zero is a flonum").bits());
+        let other = builder.build_select(is_zero, zero, self.word(Value::UNDEF.bits()), "other").unwrap().into_int_value();
+        builder.build_select(in_range, flonum, other, "flonum").unwrap().into_int_value()
+    }
+
+    fn sadd_with_overflow(&self) -> FunctionValue<'ctx> {
+        let name = "llvm.sadd.with.overflow.i64";
+        self.module.get_function(name).unwrap_or_else(|| {
+            let word_type = self.context.i64_type();
+            let result_type = self.context.struct_type(&[word_type.into(), self.context.bool_type().into()], false);
+            self.module.add_function(name, result_type.fn_type(&[word_type.into(), word_type.into()], false), None)
+        })
+    }
+}
+
+// Adds three values with compiled code, or by sending `+` when it returns `UNDEF`
+pub fn sum(ex: &mut dyn Executor, function: &JitFunction<SumFunc>, values: [Value; 3]) -> Result<Value, Unwind> {
+    let result = unsafe { function.call(values[0], values[1], values[2]) };
+    if result != Value::UNDEF {
+        return Ok(result);
+    }
+    let partial = ex.send(values[0], "+", &[values[1]], None)?;
+    ex.send(partial, "+", &[values[2]], None)
+}
@@ -1,3 +1,71 @@
-fn main(){
-    println!("Hello, world!");
-}
\ No newline at end of file
+use std::io::Read;
+use std::process::ExitCode;
+use std::{env, fs, io, thread};
+
+use chimiaguin::interp::Interpreter;
+use chimiaguin::parser::Parser;
+use chimiaguin::resolver::ConstantTree;
+use chimiaguin::runtime::{EvalError, Runtime};
+use chimiaguin::vm::{self, Vm};
+
+// Deeply recursive scripts need more than the default stack of the main thread
+const STACK_SIZE: usize = 512 * 1024 * 1024;
+
+// Runs the script given as the first argument, or read from stdin. `--vm` runs it
+// compiled to bytecode instead of walking the tree, `--dump` prints the bytecode,
+// `--constants` the classes and modules it declares, `--stats` reports how well the VM's
+// inline caches did, and `--gc-stress` collects garbage after every allocation.
+fn main() -> ExitCode {
+    let (flags, paths): (Vec<String>, Vec<String>) = env::args().skip(1).partition(|arg| arg.starts_with("--"));
+    let source = match paths.first() {
+        Some(path) => fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error)),
+        None => {
+            let mut source = String::new();
+            io::stdin().read_to_string(&mut source).map(|_| source).map_err(|error| error.to_string())
+        }
+    };
+    let source = match source {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!("{}", error);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let interpreter = thread::Builder::new().stack_size(STACK_SIZE).spawn(move || {
+        let stress = flags.iter().any(|flag| flag == "--gc-stress");
+        let result = if flags.iter().any(|flag| flag == "--dump") {
+            vm::compile(&source).map(|iseq| print!("{}", iseq.disassemble())).map_err(|error| error.to_string())
+        } else if flags.iter().any(|flag| flag == "--constants") {
+            constants(&source).map(|outline| print!("{}", outline))
+        } else if flags.iter().any(|flag| flag == "--vm") {
+            let mut vm = Vm::new();
+            vm.runtime().heap.stress = stress;
+            let result = vm.eval(&source).map(drop).map_err(|error| error.to_string());
+            if flags.iter().any(|flag| flag == "--stats") {
+                let stats = vm.cache_stats();
+                eprintln!("inline cache: {} hits, {} misses ({:.1}%)", stats.hits, stats.misses, stats.hit_rate() * 100.0);
+            }
+            result
+        } else {
+            let mut interpreter = Interpreter::new();
+            interpreter.runtime().heap.stress = stress;
+            interpreter.eval(&source).map(drop).map_err(|error| error.to_string())
+        };
+        match result {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("{}", error);
+                ExitCode::FAILURE
+            }
+        }
+    });
+    interpreter.and_then(|handle| handle.join().map_err(|_| io::Error::other("interpreter panicked"))).unwrap_or(ExitCode::FAILURE)
+}
+
+fn constants(source: &str) -> Result<String, String> {
+    let program = Parser::new(source).parse_program().map_err(|errors| EvalError::Syntax(errors).to_string())?;
+    let mut tree = ConstantTree::with_runtime(&Runtime::new());
+    tree.add_program(&program).map_err(|error| error.message)?;
+    Ok(tree.outline())
+}
@@ -0,0 +1,2327 @@
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+
+use crate::ast::{
+    Begin, Block, BlockParams, Call, Case, CaseIn, Class, Def, For, HashElement, HashPatternRest, If, InClause, Line,
+    Module, Node, OpAssign, Param, Params, Pattern, RangeKind, Rescue, Super, Target, When, While,
+};
+use crate::lexer::{Lexeme, Lexer};
+use crate::token::{StringPart, Token};
+use crate::unparser::{unparse, unparse_param};
+
+const KEYWORDS: [&str; 33] = [
+    "def", "end", "do", "class", "module", "if", "elsif", "else", "unless", "then", "and", "or", "not", "return",
+    "while", "until", "for", "in", "break", "next", "redo", "begin", "rescue", "ensure", "retry", "case", "when",
+    "nil", "true", "false", "self", "yield", "super",
+];
+
+// Keywords that can start a command argument, as in `puts nil` or `private def helper`
+const VALUE_KEYWORDS: [&str; 9] = ["nil", "true", "false", "self", "def", "case", "begin", "yield", "super"];
+
+#[derive(Debug, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    pub line: usize,
+}
+
+// An element of an array or find pattern
+enum PatternElement {
+    Pattern(Pattern),
+    Splat(Option<String>),
+}
+
+struct Scope {
+    locals: HashSet<String>,
+    // Blocks can see the locals of the enclosing scope, method and class bodies can't
+    transparent: bool,
+}
+
+// A keyword waiting for its `end`
+struct Opener {
+    keyword: String,
+    line: usize,
+    // Indentation of the line the keyword is on
+    indent: usize,
+}
+
+// Implicit parameters (`_1`, `it`) referenced by a block that has no `|params|`
+struct BlockContext {
+    explicit: bool,
+    numbered: u8,
+    it: bool,
+}
+
+pub struct Parser {
+    tokens: Vec<Lexeme>,
+    position: usize,
+    scopes: Vec<Scope>,
+    blocks: Vec<BlockContext>,
+    // Set while parsing a loop condition, where `do` starts the loop body instead of a block
+    no_do_block: bool,
+    // Set while parsing block parameters, where `|` closes them instead of being an operator
+    in_block_params: bool,
+    // Set inside `rescue` clauses, the only place `retry` is allowed
+    in_rescue: bool,
+    openers: Vec<Opener>,
+    diagnostics: Vec<ParseError>,
+    // Set when a second pass uses indentation to find which `end` is missing
+    guess_missing_ends: bool,
+    missing_end: bool,
+}
+
+impl Parser {
+    pub fn new(input: &str) -> Self {
+        Parser {
+            tokens: Lexer::new(input).tokenize(),
+            position: 0,
+            scopes: vec![Scope { locals: HashSet::new(), transparent: false }],
+            blocks: Vec::new(),
+            no_do_block: false,
+            in_block_params: false,
+            in_rescue: false,
+            openers: Vec::new(),
+            diagnostics: Vec::new(),
+            guess_missing_ends: false,
+            missing_end: false,
+        }
+    }
+
+    pub fn parse_program(&mut self) -> Result<Vec<Node>, Vec<ParseError>> {
+        let (program, diagnostics) = self.parse_with_recovery();
+        if diagnostics.is_empty() {
+            Ok(program)
+        } else {
+            Err(diagnostics)
+        }
+    }
+
+    // Parses the whole input even when it has syntax errors, returning every diagnostic.
+    // Statements that fail to parse are replaced by `Node::Error`.
+    pub fn parse_with_recovery(&mut self) -> (Vec<Node>, Vec<ParseError>) {
+        let program = self.parse_all();
+
+        // Without an `end` every following one closes the wrong keyword, so all that's
+        // known is that something is unclosed by the end of the file. Parsing again while
+        // comparing indentation finds the keyword that most likely lacks its `end`.
+        if !self.missing_end {
+            return (program, std::mem::take(&mut self.diagnostics));
+        }
+        self.reset();
+        self.guess_missing_ends = true;
+        let program = self.parse_all();
+        (program, std::mem::take(&mut self.diagnostics))
+    }
+
+    fn parse_all(&mut self) -> Vec<Node> {
+        let mut program = Vec::new();
+        loop {
+            program.extend(self.parse_statements(&[]));
+            if self.at(&Token::Eof) {
+                break;
+            }
+            // A stray `end`, `}` or `)`
+            let error = self.error_at_current(format!("unexpected {}", describe(self.peek())));
+            self.report(error);
+            self.advance();
+        }
+        program
+    }
+
+    fn reset(&mut self) {
+        self.position = 0;
+        self.scopes = vec![Scope { locals: HashSet::new(), transparent: false }];
+        self.blocks.clear();
+        self.no_do_block = false;
+        self.in_block_params = false;
+        self.in_rescue = false;
+        self.openers.clear();
+        self.diagnostics.clear();
+        self.missing_end = false;
+    }
+
+    // Parses statements until EOF, a closing `}` or one of the `terminators` keywords.
+    // The caller is responsible for consuming whatever closed the sequence.
+    // A statement with a syntax error is reported and skipped up to the next line.
+    fn parse_statements(&mut self, terminators: &[&str]) -> Vec<Node> {
+        let mut body = Vec::new();
+
+        loop {
+            self.skip_terminators();
+            if self.at_closer(terminators) || self.at_missing_end() {
+                break;
+            }
+
+            let start = self.position;
+            let (scopes, blocks, openers) = (self.scopes.len(), self.blocks.len(), self.openers.len());
+            let (no_do_block, in_rescue) = (self.no_do_block, self.in_rescue);
+
+            match self.parse_statement() {
+                Ok(node) => body.push(node),
+                Err(error) => {
+                    self.report(error);
+                    self.scopes.truncate(scopes);
+                    self.blocks.truncate(blocks);
+                    self.openers.truncate(openers);
+                    self.no_do_block = no_do_block;
+                    self.in_rescue = in_rescue;
+                    body.push(Node::Error);
+                    self.synchronize(start, terminators);
+                    continue;
+                }
+            }
+
+            if !self.at(&Token::BreakLine)
+                && !self.at(&Token::Semicolon)
+                && !self.at_closer(terminators)
+                && !self.at_missing_end()
+            {
+                let error = self.error_at_current(format!("unexpected {}", describe(self.peek())));
+                self.report(error);
+                self.synchronize(start, terminators);
+            }
+        }
+
+        body
+    }
+
+    // Skips to where the next statement can start: a new line, a `;`, something that
+    // closes the enclosing body or a definition
+    fn synchronize(&mut self, start: usize, terminators: &[&str]) {
+        if self.position == start && !self.at_closer(terminators) {
+            self.advance();
+        }
+        while !self.at(&Token::BreakLine)
+            && !self.at(&Token::Semicolon)
+            && !self.at_closer(terminators)
+            && !["def", "class", "module"].iter().any(|keyword| self.at_keyword(keyword))
+        {
+            self.advance();
+        }
+    }
+
+    // Only the first error on a line is kept, the others are usually caused by it
+    fn report(&mut self, error: ParseError) {
+        if self.diagnostics.last().is_some_and(|last| last.line == error.line) {
+            return;
+        }
+        self.diagnostics.push(error);
+    }
+
+    // A statement is an expression optionally followed by `if`/`unless`/`while`/`until`/`rescue` modifiers
+    fn parse_statement(&mut self) -> Result<Node, ParseError> {
+        let mut node = match self.parse_multiple_assign()? {
+            Some(node) => node,
+            None => self.parse_expression_statement()?,
+        };
+
+        loop {
+            if self.at_keyword("if") {
+                self.advance();
+                let condition = self.parse_expression_statement()?;
+                node = Node::If(If { condition: Box::new(condition), then_body: vec![node], else_body: vec![] });
+            } else if self.at_keyword("unless") {
+                self.advance();
+                let condition = self.parse_expression_statement()?;
+                node = Node::If(If { condition: Box::new(condition), then_body: vec![], else_body: vec![node] });
+            } else if self.at_keyword("while") || self.at_keyword("until") {
+                let until = self.at_keyword("until");
+                self.advance();
+                let mut condition = self.parse_expression_statement()?;
+                if until {
+                    condition = Node::Not(Box::new(condition));
+                }
+                // Only `begin ... end while cond` checks the condition after the first iteration
+                let (body, do_while) = match node {
+                    Node::Begin(begin) if !begin.has_clauses() => (begin.body, true),
+                    node @ Node::Begin(_) => (vec![node], true),
+                    node => (vec![node], false),
+                };
+                node = Node::While(While { condition: Box::new(condition), body, do_while });
+            } else if self.at_keyword("rescue") {
+                self.advance();
+                node = self.rescue_modifier(node)?;
+            } else {
+                break;
+            }
+        }
+
+        Ok(node)
+    }
+
+    // `and`/`or` bind looser than anything but the modifiers, and have the same precedence
+    fn parse_expression_statement(&mut self) -> Result<Node, ParseError> {
+        let mut left = self.parse_not()?;
+
+        loop {
+            if self.at_keyword("and") {
+                self.advance();
+                self.skip_newlines();
+                left = Node::And(Box::new(left), Box::new(self.parse_not()?));
+            } else if self.at_keyword("or") {
+                self.advance();
+                self.skip_newlines();
+                left = Node::Or(Box::new(left), Box::new(self.parse_not()?));
+            } else {
+                break;
+            }
+        }
+
+        Ok(left)
+    }
+
+    fn parse_not(&mut self) -> Result<Node, ParseError> {
+        if self.at_keyword("not") {
+            self.advance();
+            return Ok(Node::Not(Box::new(self.parse_not()?)));
+        }
+
+        self.parse_expression()
+    }
+
+    fn parse_expression(&mut self) -> Result<Node, ParseError> {
+        if let Token::Identifier(name) = self.peek() {
+            if is_local_name(name) && !is_keyword(name) && self.peek_at(1) == &Token::Equal {
+                let name = name.clone();
+                self.advance();
+                self.advance();
+                self.skip_newlines();
+                self.declare(&name);
+                let value = self.parse_assigned_value()?;
+                return Ok(Node::LocalAssign(name, Box::new(value)));
+            }
+        }
+
+        let node = self.parse_ternary()?;
+
+        if self.at(&Token::Equal) && matches!(node, Node::Constant(_) | Node::ScopedConstant(..)) {
+            self.advance();
+            self.skip_newlines();
+            let value = self.parse_assigned_value()?;
+            return Ok(Node::ConstantAssign(Box::new(node), Box::new(value)));
+        }
+
+        if self.at(&Token::Equal) {
+            let target = self.target(node)?;
+            self.advance();
+            let line = self.line();
+            self.skip_newlines();
+            let value = self.parse_assigned_value()?;
+            return Ok(assignment(target, value, line));
+        }
+
+        if let Token::OperatorAssign(operator) = self.peek() {
+            let operator = operator.clone();
+            let target = self.target(node)?;
+            self.advance();
+            let line = Line(self.line());
+            self.skip_newlines();
+            let value = self.parse_assigned_value()?;
+            return Ok(Node::OpAssign(OpAssign { target, operator, value: Box::new(value), line }));
+        }
+
+        Ok(node)
+    }
+
+    // `a = risky rescue fallback` rescues the value, not the whole assignment
+    fn parse_assigned_value(&mut self) -> Result<Node, ParseError> {
+        let value = self.parse_expression()?;
+        if self.at_keyword("rescue") {
+            self.advance();
+            return self.rescue_modifier(value);
+        }
+        Ok(value)
+    }
+
+    // Turns the expression on the left of `=` into an assignment target,
+    // declaring the local variable it names
+    fn target(&mut self, node: Node) -> Result<Target, ParseError> {
+        let target = match node {
+            Node::LocalVariable(name) => Target::Local(name),
+            Node::InstanceVariable(name) => Target::InstanceVariable(name),
+            Node::Call(Call { receiver: None, method, args, block: None, .. })
+                if args.is_empty() && is_local_name(&method) && is_assignable_name(&method) =>
+            {
+                Target::Local(method)
+            }
+            Node::Call(Call { receiver: Some(receiver), method, args, block: None, .. }) if method == "[]" => {
+                Target::Index(receiver, args)
+            }
+            Node::Call(Call { receiver: Some(receiver), method, args, block: None, .. })
+                if args.is_empty() && is_assignable_name(&method) =>
+            {
+                Target::Attribute(receiver, method)
+            }
+            node => return self.error(format!("cannot assign to `{}`", unparse(&[node]))),
+        };
+
+        if let Target::Local(name) = &target {
+            self.declare(name);
+        }
+        Ok(target)
+    }
+
+    // `a, b = b, a`. Statements are only scanned for this form when a comma or
+    // splat comes before a `=` on the same line, and if the left side doesn't turn
+    // out to be a list of targets the statement is parsed again as an expression.
+    fn parse_multiple_assign(&mut self) -> Result<Option<Node>, ParseError> {
+        if !self.at_multiple_assignment() {
+            return Ok(None);
+        }
+
+        let start = self.position;
+        let scopes = self.scopes.last().map(|scope| scope.locals.clone());
+        let targets = match self.parse_targets(&Token::Equal) {
+            Ok(targets) if self.at(&Token::Equal) => targets,
+            _ => {
+                self.position = start;
+                if let (Some(scope), Some(locals)) = (self.scopes.last_mut(), scopes) {
+                    scope.locals = locals;
+                }
+                return Ok(None);
+            }
+        };
+        self.advance();
+        self.skip_newlines();
+
+        let mut values = Vec::new();
+        loop {
+            if self.at(&Token::Asterisk) {
+                self.advance();
+                values.push(Node::Splat(Box::new(self.parse_ternary()?)));
+            } else {
+                values.push(self.parse_expression()?);
+            }
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+            self.skip_newlines();
+        }
+
+        let value = match values.pop() {
+            Some(value) if values.is_empty() && !matches!(value, Node::Splat(_)) => value,
+            Some(value) => {
+                values.push(value);
+                Node::Array(values)
+            }
+            None => unreachable!("at least one value was parsed"),
+        };
+        Ok(Some(Node::MultipleAssign(targets, Box::new(value))))
+    }
+
+    fn at_multiple_assignment(&self) -> bool {
+        let mut depth = 0usize;
+        let mut list = self.at(&Token::Asterisk);
+
+        for lexeme in &self.tokens[self.position..] {
+            match &lexeme.token {
+                Token::LeftParenthesis | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                Token::RightParenthesis | Token::RightBracket | Token::RightBrace if depth == 0 => return false,
+                Token::RightParenthesis | Token::RightBracket | Token::RightBrace => depth -= 1,
+                Token::Comma if depth == 0 => list = true,
+                Token::Equal if depth == 0 => return list,
+                Token::BreakLine | Token::Semicolon | Token::Pipe if depth == 0 => return false,
+                Token::Identifier(name) if is_keyword(name) && name != "self" => return false,
+                Token::Eof => return false,
+                _ => {}
+            }
+        }
+        false
+    }
+
+    // Comma separated targets up to `closer`, which is left for the caller to consume.
+    // A trailing comma, as in `first, = list`, ignores the remaining values.
+    fn parse_targets(&mut self, closer: &Token) -> Result<Vec<Target>, ParseError> {
+        let mut targets: Vec<Target> = Vec::new();
+
+        loop {
+            let target = self.parse_target()?;
+            if matches!(target, Target::Splat(_)) && targets.iter().any(|target| matches!(target, Target::Splat(_))) {
+                return self.error("multiple splats in assignment".to_string());
+            }
+            targets.push(target);
+
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+            if self.at(closer) {
+                targets.push(Target::Splat(None));
+                break;
+            }
+        }
+
+        Ok(targets)
+    }
+
+    fn parse_target(&mut self) -> Result<Target, ParseError> {
+        if self.at(&Token::Asterisk) {
+            self.advance();
+            if matches!(self.peek(), Token::Comma | Token::Equal | Token::RightParenthesis) {
+                return Ok(Target::Splat(None));
+            }
+            return Ok(Target::Splat(Some(Box::new(self.parse_target()?))));
+        }
+
+        if self.at(&Token::LeftParenthesis) {
+            self.advance();
+            let targets = self.parse_targets(&Token::RightParenthesis)?;
+            self.expect(Token::RightParenthesis)?;
+            return Ok(Target::Nested(targets));
+        }
+
+        // A bare name is a new local, even if a method of the same name takes arguments
+        if let Token::Identifier(name) = self.peek() {
+            if is_local_name(name) && !is_keyword(name) && is_assignable_name(name)
+                && matches!(self.peek_at(1), Token::Comma | Token::Equal | Token::RightParenthesis)
+            {
+                let name = name.clone();
+                self.advance();
+                self.declare(&name);
+                return Ok(Target::Local(name));
+            }
+        }
+
+        let node = self.parse_postfix()?;
+        self.target(node)
+    }
+
+    fn parse_ternary(&mut self) -> Result<Node, ParseError> {
+        let condition = self.parse_range()?;
+        if !self.at(&Token::Question) {
+            return Ok(condition);
+        }
+
+        self.advance();
+        self.skip_newlines();
+        let then_branch = self.parse_ternary()?;
+        self.skip_newlines();
+        self.expect(Token::Colon)?;
+        self.skip_newlines();
+        let else_branch = self.parse_ternary()?;
+
+        Ok(Node::If(If {
+            condition: Box::new(condition),
+            then_body: vec![then_branch],
+            else_body: vec![else_branch],
+        }))
+    }
+
+    // `a..b`, `a...b`, the endless `a..` and the beginless `..b`
+    fn parse_range(&mut self) -> Result<Node, ParseError> {
+        if let Some(kind) = range_kind(self.peek()) {
+            self.advance();
+            let end = self.parse_binary(0)?;
+            return Ok(Node::Range(None, Some(Box::new(end)), kind));
+        }
+
+        let start = self.parse_binary(0)?;
+        let Some(kind) = range_kind(self.peek()) else {
+            return Ok(start);
+        };
+        self.advance();
+
+        let end = if self.at_range_end() { None } else { Some(Box::new(self.parse_binary(0)?)) };
+        Ok(Node::Range(Some(Box::new(start)), end, kind))
+    }
+
+    fn at_range_end(&self) -> bool {
+        self.at_value_end()
+            || matches!(self.peek(), Token::RightBracket | Token::Comma | Token::Question | Token::Colon)
+            || self.at_keyword("then")
+            || self.at_keyword("do")
+    }
+
+    // Precedence climbing over the binary operators, which all become method calls
+    // except for `&&` and `||`
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Node, ParseError> {
+        let mut left = self.parse_unary()?;
+
+        while let Some((precedence, operator)) = binary_operator(self.peek()) {
+            if precedence < min_precedence || (operator == "|" && self.in_block_params) {
+                break;
+            }
+            self.advance();
+            let line = self.line();
+            self.skip_newlines();
+            let right_associative = operator == "**";
+            let right = self.parse_binary(if right_associative { precedence } else { precedence + 1 })?;
+            left = match operator {
+                "&&" => Node::And(Box::new(left), Box::new(right)),
+                "||" => Node::Or(Box::new(left), Box::new(right)),
+                _ => Node::Call(Call::new(Some(left), operator, vec![right], line)),
+            };
+        }
+
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Node, ParseError> {
+        if self.at(&Token::Not) {
+            self.advance();
+            return Ok(Node::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.at(&Token::Minus) {
+            self.advance();
+            let line = self.line();
+            // A minus glued to a number makes a negative literal, so `-2.abs` is `(-2).abs`, but `**` binds
+            // tighter than any minus, so `-2 ** 2` is `-(2 ** 2)`
+            let literal = match self.peek() {
+                _ if self.current().space_before => None,
+                Token::Number(value) => Some(Node::Integer(*value)),
+                Token::Float(value) => Some(Node::Float(*value)),
+                Token::Bignum(digits) => Some(Node::Bignum(digits.clone())),
+                _ => None,
+            };
+            if let Some(literal) = literal {
+                self.advance();
+                if !self.at(&Token::AsteriskAsterisk) {
+                    return self.parse_postfix_from(negate(literal, line));
+                }
+                return Ok(negate(self.parse_power_from(literal)?, line));
+            }
+            let operand = self.parse_unary()?;
+            return Ok(negate(self.parse_power_from(operand)?, line));
+        }
+
+        self.parse_postfix()
+    }
+
+    // The `**` that follows a unary minus's operand, which it applies to first
+    fn parse_power_from(&mut self, base: Node) -> Result<Node, ParseError> {
+        if !self.at(&Token::AsteriskAsterisk) {
+            return Ok(base);
+        }
+        self.advance();
+        let line = self.line();
+        self.skip_newlines();
+        let (precedence, _) = binary_operator(&Token::AsteriskAsterisk).expect("`**` is a binary operator");
+        let exponent = self.parse_binary(precedence)?;
+        Ok(Node::Call(Call::new(Some(base), "**", vec![exponent], line)))
+    }
+
+    fn parse_postfix(&mut self) -> Result<Node, ParseError> {
+        let node = self.parse_primary()?;
+        self.parse_postfix_from(node)
+    }
+
+    fn parse_postfix_from(&mut self, mut node: Node) -> Result<Node, ParseError> {
+
+        loop {
+            if self.at(&Token::Dot) {
+                self.advance();
+                self.skip_newlines();
+                // `callable.(args)` is shorthand for `callable.call(args)`
+                let method = match self.peek() {
+                    Token::Identifier(name) => name.clone(),
+                    Token::LeftParenthesis => "call".to_string(),
+                    _ => return self.unexpected(),
+                };
+                if method != "call" || !self.at(&Token::LeftParenthesis) {
+                    self.advance();
+                }
+                let line = self.line();
+                let mut call = Call::new(Some(node), &method, self.parse_call_arguments()?, line);
+                self.parse_block_if_present(&mut call)?;
+                node = Node::Call(call);
+            } else if self.at(&Token::ColonColon) {
+                self.advance();
+                let name = match self.peek() {
+                    Token::Identifier(name) => name.clone(),
+                    _ => return self.unexpected(),
+                };
+                self.advance();
+                // `Foo::Bar` is a constant, `Foo::bar` and `Foo::Bar()` are method calls
+                if name.starts_with(|c: char| c.is_uppercase()) && !self.at_call_arguments() {
+                    node = Node::ScopedConstant(Some(Box::new(node)), name);
+                } else {
+                    let line = self.line();
+                    let mut call = Call::new(Some(node), &name, self.parse_call_arguments()?, line);
+                    self.parse_block_if_present(&mut call)?;
+                    node = Node::Call(call);
+                }
+            } else if self.at(&Token::LeftBracket) && (!self.current().space_before || !could_be_command(&node)) {
+                self.advance();
+                let line = self.line();
+                let args = self.parse_list(Token::RightBracket)?;
+                node = Node::Call(Call::new(Some(node), "[]", args, line));
+            } else {
+                break;
+            }
+        }
+
+        Ok(node)
+    }
+
+    // Comma separated expressions up to `closer`, allowing splats, newlines and a trailing comma
+    fn parse_list(&mut self, closer: Token) -> Result<Vec<Node>, ParseError> {
+        let mut elements = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.at(&closer) {
+                break;
+            }
+            if self.at(&Token::Asterisk) {
+                self.advance();
+                elements.push(Node::Splat(Box::new(self.parse_ternary()?)));
+            } else {
+                elements.push(self.parse_expression()?);
+            }
+            self.skip_newlines();
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+
+        self.expect(closer)?;
+        Ok(elements)
+    }
+
+    fn parse_primary(&mut self) -> Result<Node, ParseError> {
+        let token = self.peek().clone();
+
+        match token {
+            Token::Number(value) => {
+                self.advance();
+                Ok(Node::Integer(value))
+            }
+            Token::Bignum(digits) => {
+                self.advance();
+                Ok(Node::Bignum(digits))
+            }
+            Token::Float(value) => {
+                self.advance();
+                Ok(Node::Float(value))
+            }
+            Token::Text(text) => {
+                self.advance();
+                Ok(Node::Str(text))
+            }
+            Token::Bytes(bytes) => {
+                self.advance();
+                Ok(Node::Bytes(bytes))
+            }
+            Token::Regexp(source, options) => {
+                self.advance();
+                Ok(Node::Regexp(source, options))
+            }
+            Token::Symbol(name) => {
+                self.advance();
+                Ok(Node::Symbol(name))
+            }
+            // `(a; b)` holds statements, modifiers included, and is the value of the last one
+            Token::Interpolation(..) => {
+                let parts = self.current().parts.clone();
+                self.advance();
+                let mut nodes = Vec::new();
+                for part in parts {
+                    match part {
+                        StringPart::Text(bytes) if bytes.is_empty() => {}
+                        StringPart::Text(bytes) => nodes.push(match String::from_utf8(bytes) {
+                            Ok(text) => Node::Str(text),
+                            Err(error) => Node::Bytes(error.into_bytes()),
+                        }),
+                        // The code is parsed in place of the string's lexemes, so it sees the locals
+                        StringPart::Code(lexemes) => {
+                            let tokens = std::mem::replace(&mut self.tokens, lexemes);
+                            let position = std::mem::replace(&mut self.position, 0);
+                            let code = self.parse_sequence(Token::Eof);
+                            self.tokens = tokens;
+                            self.position = position;
+                            nodes.push(code?);
+                        }
+                    }
+                }
+                Ok(Node::InterpolatedString(nodes))
+            }
+            Token::LeftParenthesis => {
+                self.advance();
+                let node = self.parse_sequence(Token::RightParenthesis)?;
+                self.advance();
+                Ok(node)
+            }
+            // In operand position a brace can only open a hash, blocks are attached by the caller
+            Token::LeftBrace => self.parse_hash(),
+            Token::LeftBracket => {
+                self.advance();
+                Ok(Node::Array(self.parse_list(Token::RightBracket)?))
+            }
+            Token::ColonColon => {
+                self.advance();
+                let name = self.expect_constant_name()?;
+                Ok(Node::ScopedConstant(None, name))
+            }
+            Token::InstanceVariable(name) => {
+                self.advance();
+                Ok(Node::InstanceVariable(name))
+            }
+            Token::Lambda => self.parse_lambda(),
+            Token::Identifier(name) => self.parse_identifier(name),
+            _ => self.unexpected(),
+        }
+    }
+
+    // Statements separated by newlines or `;` up to `closer`, which is left for the caller:
+    // `nil` when there are none, a `begin` when there are several
+    fn parse_sequence(&mut self, closer: Token) -> Result<Node, ParseError> {
+        self.skip_terminators();
+        let mut body = Vec::new();
+        while !self.at(&closer) {
+            body.push(self.parse_statement()?);
+            if !self.at(&closer) && !self.at(&Token::BreakLine) && !self.at(&Token::Semicolon) {
+                return self.unexpected();
+            }
+            self.skip_terminators();
+        }
+        Ok(match body.len() {
+            0 => Node::Nil,
+            1 => body.pop().expect("one statement"),
+            _ => Node::Begin(Begin { body, rescues: Vec::new(), else_body: None, ensure_body: None }),
+        })
+    }
+
+    fn parse_identifier(&mut self, name: String) -> Result<Node, ParseError> {
+        match name.as_str() {
+            "nil" => return self.keyword_node(Node::Nil),
+            "true" => return self.keyword_node(Node::True),
+            "false" => return self.keyword_node(Node::False),
+            "self" => return self.keyword_node(Node::SelfNode),
+            "def" => return self.parse_def(),
+            "class" => return self.parse_class(),
+            "module" => return self.parse_module(),
+            "if" => return self.parse_if(),
+            "unless" => return self.parse_unless(),
+            "return" => return self.parse_return(),
+            "while" | "until" => return self.parse_while(),
+            "for" => return self.parse_for(),
+            "break" | "next" | "redo" => return self.parse_jump(),
+            "begin" => return self.parse_begin(),
+            "retry" => return self.parse_retry(),
+            "case" => return self.parse_case(),
+            "yield" => return self.parse_yield(),
+            "super" => return self.parse_super(),
+            _ if is_keyword(&name) => return self.unexpected(),
+            _ => {}
+        }
+
+        self.advance();
+
+        // A constant followed by arguments is a method named like it, as in `Integer("12")`
+        if name.starts_with(|c: char| c.is_uppercase()) && !self.at_call_arguments() && !self.at_constant_command_argument() {
+            return Ok(Node::Constant(name));
+        }
+        // `foo()` calls the method even when a local shadows it
+        if self.is_local(&name) && !self.at_call_arguments() {
+            return Ok(Node::LocalVariable(name));
+        }
+        if let Some(node) = self.implicit_block_param(&name)? {
+            return Ok(node);
+        }
+
+        let line = self.line();
+        let mut call = Call::new(None, &name, self.parse_call_arguments()?, line);
+        self.parse_block_if_present(&mut call)?;
+        Ok(Node::Call(call))
+    }
+
+    // `if cond [then] ... elsif cond [then] ... else ... end`
+    fn parse_if(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let node = self.parse_if_branches()?;
+        self.close()?;
+        Ok(node)
+    }
+
+    // Everything after `if`/`elsif` up to, but not including, the closing `end`
+    fn parse_if_branches(&mut self) -> Result<Node, ParseError> {
+        let condition = self.parse_condition()?;
+        let then_body = self.parse_statements(&["elsif", "else", "end"]);
+
+        let else_body = if self.at_keyword("elsif") {
+            self.advance();
+            vec![self.parse_if_branches()?]
+        } else if self.at_keyword("else") {
+            self.advance();
+            self.parse_statements(&["end"])
+        } else {
+            vec![]
+        };
+
+        Ok(Node::If(If { condition: Box::new(condition), then_body, else_body }))
+    }
+
+    fn parse_unless(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let condition = self.parse_condition()?;
+        let body = self.parse_statements(&["else", "end"]);
+
+        let else_body = if self.at_keyword("else") {
+            self.advance();
+            self.parse_statements(&["end"])
+        } else {
+            vec![]
+        };
+        self.close()?;
+
+        Ok(Node::If(If { condition: Box::new(condition), then_body: else_body, else_body: body }))
+    }
+
+    // The condition of a compound statement, terminated by a newline, `;` or `then`
+    fn parse_condition(&mut self) -> Result<Node, ParseError> {
+        let condition = self.parse_expression_statement()?;
+        self.skip_separator("then")?;
+        Ok(condition)
+    }
+
+    // The newline or `;` after a condition, which can also be or be followed by `separator`
+    fn skip_separator(&mut self, separator: &str) -> Result<(), ParseError> {
+        if self.at_keyword(separator) {
+            self.advance();
+        } else if self.at(&Token::BreakLine) || self.at(&Token::Semicolon) {
+            self.skip_terminators();
+            if self.at_keyword(separator) {
+                self.advance();
+            }
+        } else {
+            return self.unexpected();
+        }
+        Ok(())
+    }
+
+    // Loop conditions can be followed by `do`, which must not be taken as a block of the last call
+    fn parse_loop_condition(&mut self) -> Result<Node, ParseError> {
+        let no_do_block = std::mem::replace(&mut self.no_do_block, true);
+        let condition = self.parse_expression_statement();
+        self.no_do_block = no_do_block;
+
+        let condition = condition?;
+        self.skip_separator("do")?;
+        Ok(condition)
+    }
+
+    // `while cond [do] ... end` and `until cond [do] ... end`
+    fn parse_while(&mut self) -> Result<Node, ParseError> {
+        let until = self.at_keyword("until");
+        self.open();
+
+        let mut condition = self.parse_loop_condition()?;
+        if until {
+            condition = Node::Not(Box::new(condition));
+        }
+        let body = self.parse_body()?;
+
+        Ok(Node::While(While { condition: Box::new(condition), body, do_while: false }))
+    }
+
+    // `for a, b in collection [do] ... end`
+    fn parse_for(&mut self) -> Result<Node, ParseError> {
+        self.open();
+
+        let mut variables = Vec::new();
+        loop {
+            let name = self.expect_local_name()?;
+            self.declare(&name);
+            variables.push(name);
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect_keyword("in")?;
+
+        let iterable = self.parse_loop_condition()?;
+        let body = self.parse_body()?;
+
+        Ok(Node::For(For { variables, iterable: Box::new(iterable), body }))
+    }
+
+    // `break`, `next` and `redo`, the first two with an optional value
+    fn parse_jump(&mut self) -> Result<Node, ParseError> {
+        let keyword = match self.peek() {
+            Token::Identifier(keyword) => keyword.clone(),
+            _ => return self.unexpected(),
+        };
+        self.advance();
+
+        if keyword == "redo" {
+            return Ok(Node::Redo);
+        }
+        let value = if self.at_value_end() { None } else { Some(Box::new(self.parse_jump_value()?)) };
+
+        Ok(if keyword == "break" { Node::Break(value) } else { Node::Next(value) })
+    }
+
+    // `case [subject] when ... end` or `case subject in ... end`
+    fn parse_case(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let subject = if self.at(&Token::BreakLine) || self.at(&Token::Semicolon) {
+            None
+        } else {
+            Some(Box::new(self.parse_expression_statement()?))
+        };
+        self.skip_terminators();
+
+        if self.at_keyword("in") {
+            return match subject {
+                Some(subject) => self.parse_case_in(subject),
+                None => self.error("case/in requires a subject".to_string()),
+            };
+        }
+
+        let mut whens = Vec::new();
+        while self.at_keyword("when") {
+            self.advance();
+            let mut conditions = Vec::new();
+            loop {
+                self.skip_newlines();
+                conditions.push(self.parse_expression()?);
+                if !self.at(&Token::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+            self.skip_separator("then")?;
+            let body = self.parse_statements(&["when", "else", "end"]);
+            whens.push(When { conditions, body });
+        }
+        if whens.is_empty() {
+            return self.unexpected();
+        }
+
+        let else_body = self.parse_case_else()?;
+        self.close()?;
+        Ok(Node::Case(Case { subject, whens, else_body }))
+    }
+
+    fn parse_case_in(&mut self, subject: Box<Node>) -> Result<Node, ParseError> {
+        let mut clauses = Vec::new();
+
+        while self.at_keyword("in") {
+            self.advance();
+            let pattern = self.parse_top_pattern()?;
+            let guard = if self.at_keyword("if") {
+                self.advance();
+                Some(Box::new(self.parse_expression_statement()?))
+            } else if self.at_keyword("unless") {
+                self.advance();
+                Some(Box::new(Node::Not(Box::new(self.parse_expression_statement()?))))
+            } else {
+                None
+            };
+            self.skip_separator("then")?;
+            let body = self.parse_statements(&["in", "else", "end"]);
+            clauses.push(InClause { pattern, guard, body });
+        }
+
+        let else_body = self.parse_case_else()?;
+        self.close()?;
+        Ok(Node::CaseIn(CaseIn { subject, clauses, else_body }))
+    }
+
+    fn parse_case_else(&mut self) -> Result<Option<Vec<Node>>, ParseError> {
+        if !self.at_keyword("else") {
+            return Ok(None);
+        }
+        self.advance();
+        Ok(Some(self.parse_statements(&["end"])))
+    }
+
+    // At the top of an `in` clause array and hash patterns can leave out their brackets,
+    // as in `in first, *rest` or `in name:, age:`
+    fn parse_top_pattern(&mut self) -> Result<Pattern, ParseError> {
+        if self.at_label() || self.at(&Token::AsteriskAsterisk) {
+            return self.parse_hash_pattern(None, None);
+        }
+
+        let first = self.parse_pattern_element()?;
+        if !self.at(&Token::Comma) {
+            return match first {
+                PatternElement::Pattern(pattern) => Ok(pattern),
+                splat => self.sequence_pattern(None, vec![splat]),
+            };
+        }
+
+        let mut elements = vec![first];
+        while self.at(&Token::Comma) {
+            self.advance();
+            elements.push(self.parse_pattern_element()?);
+        }
+        self.sequence_pattern(None, elements)
+    }
+
+    // `pattern | pattern => name`
+    fn parse_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let mut alternatives = vec![self.parse_primary_pattern()?];
+        while self.at(&Token::Pipe) {
+            self.advance();
+            self.skip_newlines();
+            alternatives.push(self.parse_primary_pattern()?);
+        }
+
+        let mut pattern = if alternatives.len() == 1 {
+            alternatives.remove(0)
+        } else {
+            Pattern::Alternative(alternatives)
+        };
+
+        while self.at(&Token::Arrow) {
+            self.advance();
+            let name = self.expect_local_name()?;
+            self.declare(&name);
+            pattern = Pattern::Capture(Box::new(pattern), name);
+        }
+
+        Ok(pattern)
+    }
+
+    fn parse_primary_pattern(&mut self) -> Result<Pattern, ParseError> {
+        match self.peek().clone() {
+            Token::LeftBracket => {
+                self.advance();
+                let elements = self.parse_pattern_elements(Token::RightBracket)?;
+                self.sequence_pattern(None, elements)
+            }
+            Token::LeftBrace => {
+                self.advance();
+                self.parse_hash_pattern(None, Some(Token::RightBrace))
+            }
+            Token::Caret => {
+                self.advance();
+                if self.at(&Token::LeftParenthesis) {
+                    self.advance();
+                    self.skip_newlines();
+                    let expression = self.parse_expression_statement()?;
+                    self.skip_newlines();
+                    self.expect(Token::RightParenthesis)?;
+                    return Ok(Pattern::Pin(expression));
+                }
+                let name = self.expect_local_name()?;
+                if !self.is_local(&name) {
+                    return self.error(format!("{}: no such local variable", name));
+                }
+                Ok(Pattern::Pin(Node::LocalVariable(name)))
+            }
+            Token::Identifier(name) if is_local_name(&name) && !is_keyword(&name) => {
+                self.advance();
+                if name != "_" {
+                    self.declare(&name);
+                }
+                Ok(Pattern::Bind(name))
+            }
+            Token::Identifier(name) if name.starts_with(|c: char| c.is_uppercase()) => self.parse_constant_pattern(),
+            Token::ColonColon => self.parse_constant_pattern(),
+            _ => Ok(Pattern::Value(self.parse_value_pattern()?)),
+        }
+    }
+
+    // Literals and literal ranges, as in `in 1..5` or `in ..0`
+    fn parse_value_pattern(&mut self) -> Result<Node, ParseError> {
+        if let Some(kind) = range_kind(self.peek()) {
+            self.advance();
+            let end = self.parse_unary()?;
+            return Ok(Node::Range(None, Some(Box::new(end)), kind));
+        }
+
+        let start = self.parse_unary()?;
+        let Some(kind) = range_kind(self.peek()) else {
+            return Ok(start);
+        };
+        self.advance();
+
+        let end = if self.at_pattern_end() || self.at(&Token::Pipe) || self.at(&Token::Arrow) || self.at(&Token::RightBracket) {
+            None
+        } else {
+            Some(Box::new(self.parse_unary()?))
+        };
+        Ok(Node::Range(Some(Box::new(start)), end, kind))
+    }
+
+    // `Const`, `Const(...)` or `Const[...]`
+    fn parse_constant_pattern(&mut self) -> Result<Pattern, ParseError> {
+        let constant = self.parse_constant_path()?;
+        let closer = match self.peek() {
+            _ if self.current().space_before => return Ok(Pattern::Value(constant)),
+            Token::LeftParenthesis => Token::RightParenthesis,
+            Token::LeftBracket => Token::RightBracket,
+            _ => return Ok(Pattern::Value(constant)),
+        };
+        self.advance();
+        self.skip_newlines();
+
+        if self.at_label() || self.at(&Token::AsteriskAsterisk) {
+            return self.parse_hash_pattern(Some(constant), Some(closer));
+        }
+        let elements = self.parse_pattern_elements(closer)?;
+        self.sequence_pattern(Some(constant), elements)
+    }
+
+    fn parse_pattern_elements(&mut self, closer: Token) -> Result<Vec<PatternElement>, ParseError> {
+        let mut elements = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at(&closer) {
+                break;
+            }
+            elements.push(self.parse_pattern_element()?);
+            self.skip_newlines();
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        self.expect(closer)?;
+        Ok(elements)
+    }
+
+    fn parse_pattern_element(&mut self) -> Result<PatternElement, ParseError> {
+        if self.at(&Token::Asterisk) {
+            self.advance();
+            return Ok(PatternElement::Splat(self.parse_optional_param_name()));
+        }
+        Ok(PatternElement::Pattern(self.parse_pattern()?))
+    }
+
+    // An array pattern allows a single splat, a find pattern starts and ends with one
+    fn sequence_pattern(&self, constant: Option<Node>, elements: Vec<PatternElement>) -> Result<Pattern, ParseError> {
+        let splats = elements.iter().filter(|element| matches!(element, PatternElement::Splat(_))).count();
+        let mut pre = Vec::new();
+        let mut rest = None;
+        let mut post = Vec::new();
+
+        match splats {
+            0 | 1 => {
+                for element in elements {
+                    match element {
+                        PatternElement::Splat(name) => rest = Some(name),
+                        PatternElement::Pattern(pattern) if rest.is_none() => pre.push(pattern),
+                        PatternElement::Pattern(pattern) => post.push(pattern),
+                    }
+                }
+                Ok(Pattern::Array { constant, pre, rest, post })
+            }
+            2 if elements.len() > 2 => {
+                let mut elements = elements.into_iter();
+                let (Some(PatternElement::Splat(pre)), Some(PatternElement::Splat(post))) =
+                    (elements.next(), elements.next_back())
+                else {
+                    return self.error("find pattern must start and end with a splat".to_string());
+                };
+                let mut middle = Vec::new();
+                for element in elements {
+                    match element {
+                        PatternElement::Pattern(pattern) => middle.push(pattern),
+                        PatternElement::Splat(_) => unreachable!("only two splats"),
+                    }
+                }
+                Ok(Pattern::Find { constant, pre, middle, post })
+            }
+            _ => self.error("too many splats in array pattern".to_string()),
+        }
+    }
+
+    // `key:, key: pattern, **rest`. Without a closer, as at the top of an `in` clause,
+    // the pattern ends with the line.
+    fn parse_hash_pattern(&mut self, constant: Option<Node>, closer: Option<Token>) -> Result<Pattern, ParseError> {
+        let mut pairs = Vec::new();
+        let mut rest = None;
+
+        loop {
+            if closer.is_some() {
+                self.skip_newlines();
+            }
+            if closer.as_ref().is_some_and(|closer| self.at(closer)) {
+                break;
+            }
+
+            if self.at(&Token::AsteriskAsterisk) {
+                self.advance();
+                if self.at_keyword("nil") {
+                    self.advance();
+                    rest = Some(HashPatternRest::Nil);
+                } else {
+                    let name = self.expect_local_name()?;
+                    self.declare(&name);
+                    rest = Some(HashPatternRest::Named(name));
+                }
+            } else {
+                let key = match self.peek() {
+                    Token::Identifier(key) if self.at_label() => key.clone(),
+                    _ => return self.unexpected(),
+                };
+                self.advance();
+                self.advance();
+
+                if self.at_pattern_end() {
+                    self.declare(&key);
+                    pairs.push((key, None));
+                } else {
+                    pairs.push((key, Some(self.parse_pattern()?)));
+                }
+            }
+
+            if closer.is_some() {
+                self.skip_newlines();
+            }
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+
+        if let Some(closer) = closer {
+            self.expect(closer)?;
+        }
+        Ok(Pattern::Hash { constant, pairs, rest })
+    }
+
+    // `name:` or `"name":` directly followed by its colon
+    fn at_label(&self) -> bool {
+        matches!(self.peek(), Token::Identifier(_) | Token::Text(_))
+            && self.peek_at(1) == &Token::Colon
+            && !self.tokens[(self.position + 1).min(self.tokens.len() - 1)].space_before
+    }
+
+    fn at_pattern_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Comma | Token::RightBrace | Token::RightParenthesis | Token::BreakLine | Token::Semicolon | Token::Eof
+        ) || ["then", "if", "unless"].iter().any(|keyword| self.at_keyword(keyword))
+    }
+
+    fn parse_begin(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let body = self.parse_statements(&["rescue", "else", "ensure", "end"]);
+        let begin = self.parse_rescue_clauses(body)?;
+        self.close()?;
+        Ok(Node::Begin(begin))
+    }
+
+    // Statements up to `end`, with the optional clauses method and `do` block bodies can have.
+    // The closing `end` is left for the caller.
+    fn parse_statements_with_rescue(&mut self) -> Result<Vec<Node>, ParseError> {
+        let body = self.parse_statements(&["rescue", "else", "ensure", "end"]);
+        let begin = self.parse_rescue_clauses(body)?;
+        if begin.has_clauses() {
+            Ok(vec![Node::Begin(begin)])
+        } else {
+            Ok(begin.body)
+        }
+    }
+
+    // `rescue A, B => e ... else ... ensure ...` following the statements in `body`
+    fn parse_rescue_clauses(&mut self, body: Vec<Node>) -> Result<Begin, ParseError> {
+        let mut rescues = Vec::new();
+
+        while self.at_keyword("rescue") {
+            self.advance();
+
+            let mut classes = Vec::new();
+            if !self.at(&Token::Arrow) && !self.at(&Token::BreakLine) && !self.at(&Token::Semicolon) && !self.at_keyword("then") {
+                loop {
+                    classes.push(self.parse_ternary()?);
+                    if !self.at(&Token::Comma) {
+                        break;
+                    }
+                    self.advance();
+                    self.skip_newlines();
+                }
+            }
+
+            let variable = if self.at(&Token::Arrow) {
+                self.advance();
+                let name = self.expect_local_name()?;
+                self.declare(&name);
+                Some(name)
+            } else {
+                None
+            };
+            self.skip_separator("then")?;
+
+            let in_rescue = std::mem::replace(&mut self.in_rescue, true);
+            let body = self.parse_statements(&["rescue", "else", "ensure", "end"]);
+            self.in_rescue = in_rescue;
+            rescues.push(Rescue { classes, variable, body });
+        }
+
+        let else_body = if self.at_keyword("else") {
+            if rescues.is_empty() {
+                return self.error("else without rescue is useless".to_string());
+            }
+            self.advance();
+            Some(self.parse_statements(&["ensure", "end"]))
+        } else {
+            None
+        };
+
+        let ensure_body = if self.at_keyword("ensure") {
+            self.advance();
+            Some(self.parse_statements(&["end"]))
+        } else {
+            None
+        };
+
+        Ok(Begin { body, rescues, else_body, ensure_body })
+    }
+
+    // `expr rescue fallback`, which rescues `StandardError` only
+    fn rescue_modifier(&mut self, node: Node) -> Result<Node, ParseError> {
+        let fallback = self.parse_expression()?;
+        Ok(Node::Begin(Begin {
+            body: vec![node],
+            rescues: vec![Rescue { classes: vec![], variable: None, body: vec![fallback] }],
+            else_body: None,
+            ensure_body: None,
+        }))
+    }
+
+    fn parse_retry(&mut self) -> Result<Node, ParseError> {
+        if !self.in_rescue {
+            return self.error("Invalid retry".to_string());
+        }
+        self.advance();
+        Ok(Node::Retry)
+    }
+
+    // `yield`, `yield a, b` or `yield(a)`, taking arguments like a method call
+    fn parse_yield(&mut self) -> Result<Node, ParseError> {
+        self.advance();
+        Ok(Node::Yield(self.parse_call_arguments()?))
+    }
+
+    // `super` passes the current arguments along, `super(a)` and `super a` pass their own
+    fn parse_super(&mut self) -> Result<Node, ParseError> {
+        self.advance();
+        let explicit = self.at_call_arguments() || self.at_command_argument();
+        let mut call = Call::new(None, "super", self.parse_call_arguments()?, self.line());
+        self.parse_block_if_present(&mut call)?;
+        Ok(Node::Super(Super { args: explicit.then_some(call.args), block: call.block, line: call.line }))
+    }
+
+    fn parse_return(&mut self) -> Result<Node, ParseError> {
+        self.advance();
+        if self.at_value_end() {
+            return Ok(Node::Return(None));
+        }
+        Ok(Node::Return(Some(Box::new(self.parse_jump_value()?))))
+    }
+
+    // `return a, b` returns an array
+    fn parse_jump_value(&mut self) -> Result<Node, ParseError> {
+        let value = self.parse_expression()?;
+        if !self.at(&Token::Comma) {
+            return Ok(value);
+        }
+
+        let mut values = vec![value];
+        while self.at(&Token::Comma) {
+            self.advance();
+            self.skip_newlines();
+            values.push(self.parse_expression()?);
+        }
+        Ok(Node::Array(values))
+    }
+
+    // Whether an optional value, like the one of `return`, is absent
+    fn at_value_end(&self) -> bool {
+        matches!(self.peek(), Token::BreakLine | Token::Semicolon | Token::Eof | Token::RightBrace | Token::RightParenthesis)
+            || ["end", "if", "unless", "while", "until", "rescue"].iter().any(|keyword| self.at_keyword(keyword))
+    }
+
+    fn keyword_node(&mut self, node: Node) -> Result<Node, ParseError> {
+        self.advance();
+        Ok(node)
+    }
+
+    // `_1`..`_9` and `it` refer to the arguments of the innermost block when it declares no parameters
+    fn implicit_block_param(&mut self, name: &str) -> Result<Option<Node>, ParseError> {
+        let number = match name.strip_prefix('_') {
+            Some(digit) if digit.len() == 1 => digit.parse::<u8>().ok().filter(|n| *n > 0),
+            _ => None,
+        };
+        let calls_it = name == "it" && !self.at_call_arguments() && !self.at_command_argument();
+
+        if number.is_none() && !calls_it {
+            return Ok(None);
+        }
+
+        let context = match self.blocks.last_mut() {
+            Some(context) => context,
+            None if number.is_some() => return self.error(format!("numbered parameter {} outside block", name)),
+            None => return Ok(None),
+        };
+
+        if context.explicit {
+            if number.is_some() {
+                return self.error("numbered parameter used in block with ordinary parameters".to_string());
+            }
+            return Ok(None);
+        }
+
+        match number {
+            Some(number) => context.numbered = context.numbered.max(number),
+            None => context.it = true,
+        }
+        Ok(Some(Node::LocalVariable(name.to_string())))
+    }
+
+    // Arguments between parentheses that immediately follow the method name,
+    // or the arguments of a command call like `puts a, b`
+    fn parse_call_arguments(&mut self) -> Result<Vec<Node>, ParseError> {
+        if self.at_call_arguments() {
+            self.advance();
+            let no_do_block = std::mem::take(&mut self.no_do_block);
+            let args = self.parse_arguments(Some(Token::RightParenthesis));
+            self.no_do_block = no_do_block;
+            return args;
+        }
+
+        if self.at_command_argument() {
+            // A `do` block after the arguments belongs to the command, not to its last argument
+            let no_do_block = std::mem::replace(&mut self.no_do_block, true);
+            let args = self.parse_arguments(None);
+            self.no_do_block = no_do_block;
+            return args;
+        }
+
+        Ok(Vec::new())
+    }
+
+    // Without a closer the arguments end with the line. Labels and `key => value` pairs
+    // are collected into a hash passed as the last argument.
+    fn parse_arguments(&mut self, closer: Option<Token>) -> Result<Vec<Node>, ParseError> {
+        let mut args = Vec::new();
+        let mut hash = Vec::new();
+        let mut block_pass = None;
+
+        loop {
+            if let Some(closer) = &closer {
+                self.skip_newlines();
+                if self.at(closer) {
+                    break;
+                }
+            }
+
+            if self.at(&Token::Ampersand) {
+                self.advance();
+                block_pass = Some(Node::BlockPass(Box::new(self.parse_ternary()?)));
+                if closer.is_some() {
+                    self.skip_newlines();
+                }
+                break;
+            } else if self.at(&Token::Asterisk) {
+                self.advance();
+                args.push(Node::Splat(Box::new(self.parse_ternary()?)));
+            } else if self.at(&Token::AsteriskAsterisk) || self.at_label() {
+                hash.push(self.parse_hash_element()?);
+            } else {
+                let arg = self.parse_expression()?;
+                if self.at(&Token::Arrow) {
+                    self.advance();
+                    self.skip_newlines();
+                    hash.push(HashElement::Pair(arg, self.parse_expression()?));
+                } else if !hash.is_empty() {
+                    return self.error("positional argument after keyword arguments".to_string());
+                } else {
+                    args.push(arg);
+                }
+            }
+
+            if closer.is_some() {
+                self.skip_newlines();
+            }
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+            self.skip_newlines();
+        }
+
+        if let Some(closer) = closer {
+            self.expect(closer)?;
+        }
+        if !hash.is_empty() {
+            args.push(Node::KeywordHash(hash));
+        }
+        args.extend(block_pass);
+        Ok(args)
+    }
+
+    fn at_call_arguments(&self) -> bool {
+        self.at(&Token::LeftParenthesis) && !self.current().space_before
+    }
+
+    // Whether the current token starts the first argument of a call without parentheses.
+    // It has to be separated from the method name, and operators that could also be
+    // binary (`foo -1`, `foo *args`) must be glued to their operand.
+    fn at_command_argument(&self) -> bool {
+        let glued_operand = !self.tokens[(self.position + 1).min(self.tokens.len() - 1)].space_before;
+
+        self.current().space_before
+            && match self.peek() {
+                Token::Number(_)
+                | Token::Float(_)
+                | Token::Bignum(_)
+                | Token::Text(_)
+                | Token::Bytes(_)
+                | Token::Interpolation(..)
+                | Token::Regexp(..)
+                | Token::Symbol(_)
+                | Token::InstanceVariable(_)
+                | Token::Lambda
+                | Token::LeftBracket
+                | Token::LeftParenthesis
+                | Token::ColonColon
+                | Token::Not => true,
+                Token::Minus | Token::Asterisk | Token::AsteriskAsterisk | Token::Ampersand => glued_operand,
+                Token::Identifier(name) => !is_keyword(name) || VALUE_KEYWORDS.contains(&name.as_str()),
+                _ => false,
+            }
+    }
+
+    // `Foo ::Bar` stays a scoped constant rather than a call with a top-level constant
+    fn at_constant_command_argument(&self) -> bool {
+        self.at_command_argument() && !self.at(&Token::ColonColon)
+    }
+
+    fn parse_block_if_present(&mut self, call: &mut Call) -> Result<(), ParseError> {
+        let block = if self.at(&Token::LeftBrace) {
+            self.advance();
+            let block = self.parse_block_body(false)?;
+            self.expect(Token::RightBrace)?;
+            block
+        } else if self.at_keyword("do") && !self.no_do_block {
+            self.open();
+            let block = self.parse_block_body(true)?;
+            self.close()?;
+            block
+        } else {
+            return Ok(());
+        };
+
+        if matches!(call.args.last(), Some(Node::BlockPass(_))) {
+            return self.error("both block arg and actual block given".to_string());
+        }
+        call.block = Some(Rc::new(block));
+        Ok(())
+    }
+
+    fn parse_block_body(&mut self, do_block: bool) -> Result<Block, ParseError> {
+        self.scopes.push(Scope { locals: HashSet::new(), transparent: true });
+
+        let params = if self.at(&Token::Pipe) {
+            self.advance();
+            let in_block_params = std::mem::replace(&mut self.in_block_params, true);
+            let params = self.parse_block_params(&Token::Pipe);
+            self.in_block_params = in_block_params;
+            let params = params?;
+            self.expect(Token::Pipe)?;
+            Some(params)
+        } else if self.at(&Token::PipePipe) {
+            self.advance();
+            Some(Params::default())
+        } else {
+            None
+        };
+
+        self.parse_block_statements(params, do_block)
+    }
+
+    // Brace blocks end at `}`, `do` blocks at `end` and can have rescue clauses.
+    // The block's scope has already been pushed for the parameters.
+    fn parse_block_statements(&mut self, params: Option<Params>, do_block: bool) -> Result<Block, ParseError> {
+        self.blocks.push(BlockContext { explicit: params.is_some(), numbered: 0, it: false });
+        let no_do_block = std::mem::take(&mut self.no_do_block);
+        let body = if do_block { self.parse_statements_with_rescue() } else { Ok(self.parse_statements(&[])) };
+        self.no_do_block = no_do_block;
+        let context = self.blocks.pop().expect("block context pushed above");
+        self.scopes.pop();
+
+        let params = match params {
+            Some(params) => BlockParams::Explicit(params),
+            None if context.numbered > 0 => BlockParams::Numbered(context.numbered),
+            None if context.it => BlockParams::It,
+            None => BlockParams::Explicit(Params::default()),
+        };
+
+        Ok(Block { params, body: body? })
+    }
+
+    // `|a, (b, c); tmp|` or `->(a, (b, c); tmp)`, up to the closer which is left for the caller
+    fn parse_block_params(&mut self, closer: &Token) -> Result<Params, ParseError> {
+        let mut params = Params::default();
+
+        if !self.at(closer) && !self.at(&Token::Semicolon) {
+            params.params = self.parse_param_list()?;
+        }
+
+        if self.at(&Token::Semicolon) {
+            self.advance();
+            loop {
+                let name = self.expect_local_name()?;
+                self.declare(&name);
+                params.locals.push(name);
+                if !self.at(&Token::Comma) {
+                    break;
+                }
+                self.advance();
+            }
+        }
+
+        self.validate_params(&params)?;
+        Ok(params)
+    }
+
+    // `->(x) { }`, `-> x { }` or `-> do ... end`
+    fn parse_lambda(&mut self) -> Result<Node, ParseError> {
+        self.advance();
+        self.scopes.push(Scope { locals: HashSet::new(), transparent: true });
+
+        let params = if self.at(&Token::LeftParenthesis) {
+            self.advance();
+            let params = self.parse_block_params(&Token::RightParenthesis)?;
+            self.skip_newlines();
+            self.expect(Token::RightParenthesis)?;
+            Some(params)
+        } else if self.at(&Token::LeftBrace) || self.at_keyword("do") {
+            None
+        } else {
+            let params = self.parse_block_params(&Token::LeftBrace)?;
+            Some(params)
+        };
+
+        let block = if self.at(&Token::LeftBrace) {
+            self.advance();
+            let block = self.parse_block_statements(params, false)?;
+            self.expect(Token::RightBrace)?;
+            block
+        } else if self.at_keyword("do") {
+            self.open();
+            let block = self.parse_block_statements(params, true)?;
+            self.close()?;
+            block
+        } else {
+            return self.unexpected();
+        };
+
+        Ok(Node::Lambda(Rc::new(block)))
+    }
+
+    fn parse_param_list(&mut self) -> Result<Vec<Param>, ParseError> {
+        let mut params = Vec::new();
+        loop {
+            self.skip_newlines();
+            params.push(self.parse_param()?);
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+        Ok(params)
+    }
+
+    fn parse_param(&mut self) -> Result<Param, ParseError> {
+        match self.peek() {
+            Token::LeftParenthesis => {
+                self.advance();
+                let params = self.parse_param_list()?;
+                self.skip_newlines();
+                self.expect(Token::RightParenthesis)?;
+                Ok(Param::Destructure(params))
+            }
+            Token::Asterisk => {
+                self.advance();
+                Ok(Param::Rest(self.parse_optional_param_name()))
+            }
+            Token::AsteriskAsterisk => {
+                self.advance();
+                Ok(Param::KeywordRest(self.parse_optional_param_name()))
+            }
+            Token::Ampersand => {
+                self.advance();
+                Ok(Param::Block(self.parse_optional_param_name()))
+            }
+            _ => {
+                let name = self.expect_local_name()?;
+                self.declare(&name);
+
+                if self.at(&Token::Colon) && !self.current().space_before {
+                    self.advance();
+                    if self.at_param_end() {
+                        return Ok(Param::RequiredKeyword(name));
+                    }
+                    return Ok(Param::OptionalKeyword(name, self.parse_expression()?));
+                }
+                if self.at(&Token::Equal) {
+                    self.advance();
+                    self.skip_newlines();
+                    return Ok(Param::Optional(name, self.parse_expression()?));
+                }
+                Ok(Param::Required(name))
+            }
+        }
+    }
+
+    // The name after `*`, `**` and `&` can be left out to accept and discard the arguments
+    fn parse_optional_param_name(&mut self) -> Option<String> {
+        match self.peek() {
+            Token::Identifier(name) if is_local_name(name) && !is_keyword(name) => {
+                let name = name.clone();
+                self.advance();
+                self.declare(&name);
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    fn at_param_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            Token::Comma | Token::RightParenthesis | Token::Pipe | Token::Semicolon | Token::BreakLine | Token::Eof
+        )
+    }
+
+    // Parameters must come in the order required, optional, rest, post-required,
+    // keywords, keyword rest and block, with the last three appearing at most once.
+    fn validate_params(&self, params: &Params) -> Result<(), ParseError> {
+        let mut last_rank = 0;
+        for param in &params.params {
+            let rank = match param {
+                Param::Required(_) | Param::Destructure(_) if last_rank == 0 => 0,
+                Param::Required(_) | Param::Destructure(_) => 3,
+                Param::Optional(..) => 1,
+                Param::Rest(_) => 2,
+                Param::RequiredKeyword(_) | Param::OptionalKeyword(..) => 4,
+                Param::KeywordRest(_) => 5,
+                Param::Block(_) => 6,
+            };
+            let repeated = rank == last_rank && matches!(param, Param::Rest(_) | Param::KeywordRest(_) | Param::Block(_));
+            if rank < last_rank || repeated {
+                return self.error(format!("unexpected parameter `{}`", unparse_param(param)));
+            }
+            last_rank = rank;
+        }
+
+        let names = params.names();
+        for (index, name) in names.iter().enumerate() {
+            if !name.starts_with('_') && names[..index].contains(name) {
+                return self.error(format!("duplicated argument name `{}`", name));
+            }
+        }
+
+        Ok(())
+    }
+
+    // `def name(params) ... end`, `def self.name ... end` and the endless `def name(params) = expr`
+    fn parse_def(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let singleton = self.parse_def_singleton();
+        let name = self.parse_def_name()?;
+
+        let def = self.in_isolated_scope(|parser| parser.parse_def_rest(singleton, name))?;
+        Ok(Node::Def(def))
+    }
+
+    // Method, class and module bodies start a fresh set of locals and can't refer
+    // to the implicit parameters of an enclosing block
+    fn in_isolated_scope<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ParseError>) -> Result<T, ParseError> {
+        self.scopes.push(Scope { locals: HashSet::new(), transparent: false });
+        let blocks = std::mem::take(&mut self.blocks);
+        let in_rescue = std::mem::take(&mut self.in_rescue);
+        let result = parse(self);
+        self.blocks = blocks;
+        self.in_rescue = in_rescue;
+        self.scopes.pop();
+        result
+    }
+
+    fn parse_body(&mut self) -> Result<Vec<Node>, ParseError> {
+        let body = self.parse_statements(&["end"]);
+        self.close()?;
+        Ok(body)
+    }
+
+    // Method, class and module bodies, which can have rescue clauses without a `begin`
+    fn parse_body_with_rescue(&mut self) -> Result<Vec<Node>, ParseError> {
+        let body = self.parse_statements_with_rescue()?;
+        self.close()?;
+        Ok(body)
+    }
+
+    // `class Name < Superclass ... end` or `class << target ... end`
+    fn parse_class(&mut self) -> Result<Node, ParseError> {
+        self.open();
+
+        if self.at(&Token::ShiftLeft) {
+            self.advance();
+            let target = self.parse_expression()?;
+            let body = self.in_isolated_scope(Self::parse_body_with_rescue)?;
+            return Ok(Node::SingletonClass(Box::new(target), body));
+        }
+
+        let path = self.parse_constant_path()?;
+        let superclass = if self.at(&Token::LessThan) {
+            self.advance();
+            Some(Box::new(self.parse_expression()?))
+        } else {
+            None
+        };
+        let body = self.in_isolated_scope(Self::parse_body_with_rescue)?;
+
+        Ok(Node::Class(Class { path: Box::new(path), superclass, body }))
+    }
+
+    fn parse_module(&mut self) -> Result<Node, ParseError> {
+        self.open();
+        let path = self.parse_constant_path()?;
+        let body = self.in_isolated_scope(Self::parse_body_with_rescue)?;
+
+        Ok(Node::Module(Module { path: Box::new(path), body }))
+    }
+
+    // `Name`, `Outer::Name` or `::Name`
+    fn parse_constant_path(&mut self) -> Result<Node, ParseError> {
+        let mut path = if self.at(&Token::ColonColon) {
+            self.advance();
+            Node::ScopedConstant(None, self.expect_constant_name()?)
+        } else {
+            Node::Constant(self.expect_constant_name()?)
+        };
+
+        while self.at(&Token::ColonColon) {
+            self.advance();
+            path = Node::ScopedConstant(Some(Box::new(path)), self.expect_constant_name()?);
+        }
+
+        Ok(path)
+    }
+
+    fn expect_constant_name(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Token::Identifier(name) if name.starts_with(|c: char| c.is_uppercase()) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => self.error(format!("expected a constant name, found {}", describe(self.peek()))),
+        }
+    }
+
+    fn parse_def_singleton(&mut self) -> Option<Box<Node>> {
+        if self.peek_at(1) != &Token::Dot {
+            return None;
+        }
+
+        let receiver = match self.peek() {
+            Token::Identifier(name) if name == "self" => Node::SelfNode,
+            Token::Identifier(name) if name.starts_with(|c: char| c.is_uppercase()) => Node::Constant(name.clone()),
+            Token::Identifier(name) if self.is_local(name) => Node::LocalVariable(name.clone()),
+            _ => return None,
+        };
+        self.advance();
+        self.advance();
+        Some(Box::new(receiver))
+    }
+
+    fn parse_def_name(&mut self) -> Result<String, ParseError> {
+        // `def -@` and `def +@`, the unary operators
+        let unary = matches!(self.peek(), Token::Minus | Token::Plus)
+            && matches!(self.peek_at(1), Token::Illegal(sigil) if sigil == "@")
+            && !self.tokens[self.position + 1].space_before;
+        if unary {
+            let operator = if self.at(&Token::Minus) { "-@" } else { "+@" };
+            self.advance();
+            self.advance();
+            return Ok(operator.to_string());
+        }
+        if let Some(operator) = operator_method_name(self.peek()) {
+            self.advance();
+            return Ok(operator.to_string());
+        }
+        // `def [](index)` and `def []=(index, value)`
+        if self.at(&Token::LeftBracket) && self.peek_at(1) == &Token::RightBracket {
+            self.advance();
+            self.advance();
+            let setter = self.at(&Token::Equal) && !self.current().space_before;
+            if setter {
+                self.advance();
+                return Ok("[]=".to_string());
+            }
+            return Ok("[]".to_string());
+        }
+
+        let name = match self.peek() {
+            Token::Identifier(name) => name.clone(),
+            _ => return self.unexpected(),
+        };
+        self.advance();
+
+        // `def name=(value)` defines a setter, as opposed to `def name = value`
+        let setter = self.at(&Token::Equal)
+            && !self.current().space_before
+            && self.peek_at(1) == &Token::LeftParenthesis;
+        if setter {
+            self.advance();
+            return Ok(format!("{}=", name));
+        }
+
+        Ok(name)
+    }
+
+    fn parse_def_rest(&mut self, singleton: Option<Box<Node>>, name: String) -> Result<Def, ParseError> {
+        let mut params = Params::default();
+        if self.at(&Token::LeftParenthesis) {
+            self.advance();
+            self.skip_newlines();
+            if !self.at(&Token::RightParenthesis) {
+                params.params = self.parse_param_list()?;
+                self.skip_newlines();
+            }
+            self.expect(Token::RightParenthesis)?;
+        } else if !self.at(&Token::BreakLine) && !self.at(&Token::Semicolon) && !self.at(&Token::Equal) {
+            params.params = self.parse_param_list()?;
+        }
+        self.validate_params(&params)?;
+
+        if self.at(&Token::Equal) {
+            if is_setter_name(&name) {
+                return self.error(format!("setter method `{}` cannot be defined in an endless method definition", name));
+            }
+            self.advance();
+            self.skip_newlines();
+            // An endless method has no `end` to wait for
+            self.openers.pop();
+            let body = vec![self.parse_statement()?];
+            return Ok(Def { singleton, name, params, body });
+        }
+
+        let body = self.parse_body_with_rescue()?;
+        Ok(Def { singleton, name, params, body })
+    }
+
+    // `{ :key => value, label: value, "quoted label": value, **other }`
+    fn parse_hash(&mut self) -> Result<Node, ParseError> {
+        self.expect(Token::LeftBrace)?;
+        let mut elements = Vec::new();
+
+        loop {
+            self.skip_newlines();
+            if self.at(&Token::RightBrace) {
+                break;
+            }
+            elements.push(self.parse_hash_element()?);
+            self.skip_newlines();
+            if !self.at(&Token::Comma) {
+                break;
+            }
+            self.advance();
+        }
+
+        self.expect(Token::RightBrace)?;
+        Ok(Node::Hash(elements))
+    }
+
+    fn parse_hash_element(&mut self) -> Result<HashElement, ParseError> {
+        if self.at(&Token::AsteriskAsterisk) {
+            self.advance();
+            return Ok(HashElement::DoubleSplat(self.parse_ternary()?));
+        }
+
+        let key = match self.peek() {
+            Token::Identifier(name) | Token::Text(name) if self.at_label() => {
+                let key = Node::Symbol(name.clone());
+                self.advance();
+                self.advance();
+                key
+            }
+            _ => {
+                let key = self.parse_expression()?;
+                self.skip_newlines();
+                self.expect(Token::Arrow)?;
+                key
+            }
+        };
+        self.skip_newlines();
+
+        Ok(HashElement::Pair(key, self.parse_expression()?))
+    }
+
+    fn expect_local_name(&mut self) -> Result<String, ParseError> {
+        match self.peek() {
+            Token::Identifier(name) if is_local_name(name) && !is_keyword(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => self.unexpected(),
+        }
+    }
+
+    fn declare(&mut self, name: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.locals.insert(name.to_string());
+        }
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        for scope in self.scopes.iter().rev() {
+            if scope.locals.contains(name) {
+                return true;
+            }
+            if !scope.transparent {
+                break;
+            }
+        }
+        false
+    }
+
+    fn current(&self) -> &Lexeme {
+        &self.tokens[self.position]
+    }
+
+    fn peek(&self) -> &Token {
+        &self.current().token
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let index = (self.position + offset).min(self.tokens.len() - 1);
+        &self.tokens[index].token
+    }
+
+    // The line of the token read last
+    fn line(&self) -> usize {
+        self.tokens[self.position.saturating_sub(1)].span.line
+    }
+
+    fn advance(&mut self) {
+        if self.position < self.tokens.len() - 1 {
+            self.position += 1;
+        }
+    }
+
+    fn at(&self, token: &Token) -> bool {
+        self.peek() == token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Token::Identifier(name) if name == keyword)
+    }
+
+    fn at_closer(&self, terminators: &[&str]) -> bool {
+        matches!(self.peek(), Token::Eof | Token::RightBrace)
+            || terminators.iter().any(|keyword| self.at_keyword(keyword))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), ParseError> {
+        if self.at(&token) {
+            self.advance();
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`, found {}", token, describe(self.peek())))
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<(), ParseError> {
+        if self.at_keyword(keyword) {
+            self.advance();
+            Ok(())
+        } else {
+            self.error(format!("expected `{}`, found {}", keyword, describe(self.peek())))
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.at(&Token::BreakLine) {
+            self.advance();
+        }
+    }
+
+    fn skip_terminators(&mut self) {
+        while self.at(&Token::BreakLine) || self.at(&Token::Semicolon) {
+            self.advance();
+        }
+    }
+
+    fn unexpected<T>(&self) -> Result<T, ParseError> {
+        self.error(format!("unexpected {}", describe(self.peek())))
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        Err(self.error_at_current(message))
+    }
+
+    fn error_at_current(&self, message: String) -> ParseError {
+        ParseError { message, line: self.current().span.line }
+    }
+
+    // Consumes the keyword that opens a body closed by `end`
+    fn open(&mut self) {
+        let keyword = self.peek().to_string();
+        let line = self.current().span.line;
+        let first_on_line = self.tokens[..self.position]
+            .iter()
+            .rposition(|lexeme| lexeme.token == Token::BreakLine)
+            .map_or(0, |index| index + 1);
+        let indent = self.tokens[first_on_line].span.column;
+
+        self.openers.push(Opener { keyword, line, indent });
+        self.advance();
+    }
+
+    // Consumes the `end` of the innermost opener. When the body was cut short by the end
+    // of the file, a closing brace or an `end` or definition that belongs to an outer
+    // keyword, the `end` is reported missing and parsing carries on as if it was there.
+    fn close(&mut self) -> Result<(), ParseError> {
+        let missing_end = self.at_missing_end();
+        let opener = self.openers.pop().expect("every close follows an open");
+
+        if self.at_keyword("end") && !missing_end {
+            self.advance();
+            return Ok(());
+        }
+        if !missing_end && !matches!(self.peek(), Token::Eof | Token::RightBrace) {
+            return self.error(format!("expected `end`, found {}", describe(self.peek())));
+        }
+
+        // Every unclosed opener is reported, even when several of them end on the same line
+        self.missing_end = true;
+        let error = self.error_at_current(format!("missing `end` for `{}` opened at line {}", opener.keyword, opener.line));
+        self.diagnostics.push(error);
+        Ok(())
+    }
+
+    // With `guess_missing_ends`, an `end` indented less than the innermost opener, or
+    // a definition indented no more than it, is taken as proof the opener was never closed
+    fn at_missing_end(&self) -> bool {
+        let Some(opener) = self.openers.last() else {
+            return false;
+        };
+        let first_on_line = self.position == 0 || self.tokens[self.position - 1].token == Token::BreakLine;
+        if !self.guess_missing_ends || !first_on_line {
+            return false;
+        }
+
+        let indent = self.current().span.column;
+        if self.at_keyword("end") {
+            indent < opener.indent
+        } else {
+            ["def", "class", "module"].iter().any(|keyword| self.at_keyword(keyword)) && indent <= opener.indent
+        }
+    }
+}
+
+// A token as diagnostics quote it, in backticks unless it's the end of a line or the input
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Eof | Token::BreakLine => token.to_string(),
+        token => format!("`{}`", token),
+    }
+}
+
+// Folds a minus into a numeric literal, moving between `Integer` and `Bignum` as the value needs
+fn negate(node: Node, line: usize) -> Node {
+    match node {
+        Node::Integer(value) => match value.checked_neg() {
+            Some(value) => Node::Integer(value),
+            None => Node::Bignum((-BigInt::from(value)).to_string()),
+        },
+        Node::Float(value) => Node::Float(-value),
+        Node::Bignum(digits) => {
+            let value = -digits.parse::<BigInt>().expect("bignum literals are digits");
+            match i64::try_from(&value) {
+                Ok(value) => Node::Integer(value),
+                Err(_) => Node::Bignum(value.to_string()),
+            }
+        }
+        operand => Node::Call(Call::new(Some(operand), "-@", vec![], line)),
+    }
+}
+
+fn binary_operator(token: &Token) -> Option<(u8, &'static str)> {
+    match token {
+        Token::PipePipe => Some((1, "||")),
+        Token::AmpersandAmpersand => Some((2, "&&")),
+        Token::EqualEqual => Some((3, "==")),
+        Token::EqualEqualEqual => Some((3, "===")),
+        Token::NotEqual => Some((3, "!=")),
+        Token::Spaceship => Some((3, "<=>")),
+        Token::Match => Some((3, "=~")),
+        Token::NotMatch => Some((3, "!~")),
+        Token::LessThan => Some((4, "<")),
+        Token::LessThanOrEqual => Some((4, "<=")),
+        Token::GreaterThan => Some((4, ">")),
+        Token::GreaterThanOrEqual => Some((4, ">=")),
+        Token::Pipe => Some((5, "|")),
+        Token::Caret => Some((5, "^")),
+        Token::Ampersand => Some((6, "&")),
+        Token::ShiftLeft => Some((7, "<<")),
+        Token::ShiftRight => Some((7, ">>")),
+        Token::Plus => Some((8, "+")),
+        Token::Minus => Some((8, "-")),
+        Token::Asterisk => Some((9, "*")),
+        Token::Slash => Some((9, "/")),
+        Token::Percent => Some((9, "%")),
+        Token::AsteriskAsterisk => Some((10, "**")),
+        _ => None,
+    }
+}
+
+// Operators that can be redefined with `def`
+fn operator_method_name(token: &Token) -> Option<&'static str> {
+    match token {
+        Token::PipePipe | Token::AmpersandAmpersand => None,
+        Token::Not => Some("!"),
+        _ => binary_operator(token).map(|(_, name)| name),
+    }
+}
+
+fn range_kind(token: &Token) -> Option<RangeKind> {
+    match token {
+        Token::DotDot => Some(RangeKind::Inclusive),
+        Token::DotDotDot => Some(RangeKind::Exclusive),
+        _ => None,
+    }
+}
+
+// A bare method name like `foo`, which followed by a space and `[` takes an array argument
+// instead of being indexed
+fn could_be_command(node: &Node) -> bool {
+    matches!(node, Node::Call(Call { args, block: None, .. }) if args.is_empty())
+}
+
+fn assignment(target: Target, value: Node, line: usize) -> Node {
+    match target {
+        Target::Local(name) => Node::LocalAssign(name, Box::new(value)),
+        Target::InstanceVariable(name) => Node::InstanceVariableAssign(name, Box::new(value)),
+        Target::Index(receiver, mut args) => {
+            args.push(value);
+            Node::Call(Call::new(Some(*receiver), "[]=", args, line))
+        }
+        Target::Attribute(receiver, name) => {
+            Node::Call(Call::new(Some(*receiver), &format!("{}=", name), vec![value], line))
+        }
+        target => Node::MultipleAssign(vec![target], Box::new(value)),
+    }
+}
+
+// `name=`, but not operators like `==` or `<=`
+fn is_setter_name(name: &str) -> bool {
+    name.strip_suffix('=').is_some_and(|base| base.ends_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+// Predicate and bang methods have no setter, `empty? = 1` is an error
+fn is_assignable_name(name: &str) -> bool {
+    !name.ends_with(['?', '!'])
+}
+
+pub(crate) fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+fn is_local_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_lowercase() || c == '_')
+}
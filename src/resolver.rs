@@ -0,0 +1,293 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::ast::{Class, Def, Module, Node};
+use crate::runtime::{ObjectId, Runtime};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ConstantKind {
+    Class,
+    Module,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ConstantEntry {
+    pub kind: ConstantKind,
+    // Fully qualified name of the superclass. `None` for modules, classes that
+    // inherit from Object and superclasses that aren't constants.
+    pub superclass: Option<String>,
+    pub constants: BTreeMap<String, ConstantEntry>,
+    pub instance_methods: Vec<String>,
+    pub singleton_methods: Vec<String>,
+    // Defined by the runtime rather than declared by a program
+    pub builtin: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResolveError {
+    pub message: String,
+}
+
+// The tree of classes and modules declared by one or more programs.
+// Adding a program that declares an existing class or module reopens it, so a
+// class can be spread across several files as long as the declarations agree.
+#[derive(Debug, Default)]
+pub struct ConstantTree {
+    root: BTreeMap<String, ConstantEntry>,
+}
+
+impl ConstantEntry {
+    fn new(kind: ConstantKind, superclass: Option<String>) -> Self {
+        ConstantEntry {
+            kind,
+            superclass,
+            constants: BTreeMap::new(),
+            instance_methods: Vec::new(),
+            singleton_methods: Vec::new(),
+            builtin: false,
+        }
+    }
+}
+
+impl ConstantTree {
+    pub fn new() -> Self {
+        ConstantTree::default()
+    }
+
+    // A tree that starts with the classes and modules the runtime defines, so programs can
+    // subclass and reopen them
+    pub fn with_runtime(runtime: &Runtime) -> Self {
+        let object = runtime.classes.object;
+        let mut paths = HashMap::from([(object, "Object".to_string())]);
+        runtime_paths(runtime, object, None, &mut paths);
+        // Parents before the constants nested in them
+        let mut classes: Vec<_> = paths.iter().collect();
+        classes.sort_by_key(|(_, path)| (path.matches("::").count(), path.as_str()));
+
+        let mut tree = ConstantTree::new();
+        for (id, path) in classes {
+            let class = runtime.class_value(*id).expect("only classes and modules have paths");
+            let (kind, superclass) = match class.is_module {
+                true => (ConstantKind::Module, None),
+                false => {
+                    let superclass = class.superclass.filter(|&superclass| superclass != object);
+                    (ConstantKind::Class, superclass.and_then(|superclass| paths.get(&superclass)).cloned())
+                }
+            };
+            let mut entry = ConstantEntry::new(kind, superclass);
+            entry.builtin = true;
+            let (parent, name) = match path.rsplit_once("::") {
+                Some((parent, name)) => (Some(parent), name),
+                None => (None, path.as_str()),
+            };
+            let constants = match parent {
+                Some(parent) => &mut tree.entry_mut(parent).constants,
+                None => &mut tree.root,
+            };
+            constants.insert(name.to_string(), entry);
+        }
+        tree
+    }
+
+    pub fn add_program(&mut self, program: &[Node]) -> Result<(), ResolveError> {
+        self.add_body(&[], program, false)
+    }
+
+    // Looks a constant up by its fully qualified name, as in `Outer::Inner`
+    pub fn lookup(&self, path: &str) -> Option<&ConstantEntry> {
+        let mut segments = path.trim_start_matches("::").split("::");
+        let mut entry = self.root.get(segments.next()?)?;
+        for segment in segments {
+            entry = entry.constants.get(segment)?;
+        }
+        Some(entry)
+    }
+
+    // The classes and modules the programs declared or reopened, one per line and indented
+    // by nesting, each followed by its methods
+    pub fn outline(&self) -> String {
+        let mut lines = Vec::new();
+        outline_constants(&self.root, None, 0, &mut lines);
+        lines.into_iter().map(|line| line + "\n").collect()
+    }
+
+    // `nesting` holds the fully qualified name of every enclosing class or module,
+    // innermost last, which is the order constants are searched in.
+    fn add_body(&mut self, nesting: &[String], body: &[Node], singleton: bool) -> Result<(), ResolveError> {
+        for node in body {
+            match node {
+                Node::Class(class) => self.add_class(nesting, class)?,
+                Node::Module(module) => self.add_module(nesting, module)?,
+                Node::Def(def) => self.add_method(nesting, def, singleton),
+                Node::SingletonClass(target, body) if **target == Node::SelfNode => {
+                    self.add_body(nesting, body, true)?
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn add_class(&mut self, nesting: &[String], class: &Class) -> Result<(), ResolveError> {
+        // `class Foo < Object` says no more than `class Foo`
+        let superclass = match class.superclass.as_deref().and_then(Node::constant_path) {
+            Some(path) => Some(self.resolve(nesting, &path)?).filter(|superclass| superclass != "Object"),
+            None => None,
+        };
+        let explicit_superclass = class.superclass.is_some();
+        let name = self.declare(nesting, &class.path, ConstantKind::Class, superclass.clone())?;
+
+        let entry = self.entry_mut(&name);
+        if explicit_superclass && entry.superclass != superclass {
+            return Err(ResolveError { message: format!("superclass mismatch for class {}", name) });
+        }
+
+        self.add_body(&nested(nesting, name), &class.body, false)
+    }
+
+    fn add_module(&mut self, nesting: &[String], module: &Module) -> Result<(), ResolveError> {
+        let name = self.declare(nesting, &module.path, ConstantKind::Module, None)?;
+        self.add_body(&nested(nesting, name), &module.body, false)
+    }
+
+    fn add_method(&mut self, nesting: &[String], def: &Def, singleton: bool) {
+        let Some(owner) = nesting.last() else {
+            return;
+        };
+        let singleton = singleton || def.singleton.as_deref() == Some(&Node::SelfNode);
+        let entry = self.entry_mut(owner);
+        let methods = if singleton { &mut entry.singleton_methods } else { &mut entry.instance_methods };
+        if !methods.contains(&def.name) {
+            methods.push(def.name.clone());
+        }
+    }
+
+    // Creates the class or module named by `path`, or reopens it if it already exists.
+    // Returns its fully qualified name.
+    fn declare(
+        &mut self,
+        nesting: &[String],
+        path: &Node,
+        kind: ConstantKind,
+        superclass: Option<String>,
+    ) -> Result<String, ResolveError> {
+        let (parent, name) = match path {
+            Node::Constant(name) => (nesting.last().cloned(), name),
+            Node::ScopedConstant(None, name) => (None, name),
+            Node::ScopedConstant(Some(scope), name) => {
+                let scope = scope.constant_path().ok_or_else(|| ResolveError {
+                    message: "class or module path must be made of constants".to_string(),
+                })?;
+                (Some(self.resolve(nesting, &scope)?), name)
+            }
+            _ => return Err(ResolveError { message: format!("{:?} is not a constant", path) }),
+        };
+
+        let constants = match &parent {
+            Some(parent) => &mut self.entry_mut(parent).constants,
+            None => &mut self.root,
+        };
+        let entry = constants.entry(name.clone()).or_insert_with(|| ConstantEntry::new(kind, superclass));
+        let full_name = match parent {
+            Some(parent) => format!("{}::{}", parent, name),
+            None => name.clone(),
+        };
+
+        if entry.kind != kind {
+            let expected = match kind {
+                ConstantKind::Class => "class",
+                ConstantKind::Module => "module",
+            };
+            return Err(ResolveError { message: format!("{} is not a {}", full_name, expected) });
+        }
+
+        Ok(full_name)
+    }
+
+    // Ruby's lexical lookup: the first segment is searched from the innermost
+    // enclosing scope outwards, the remaining ones inside the constant found.
+    fn resolve(&self, nesting: &[String], path: &str) -> Result<String, ResolveError> {
+        let uninitialized = || ResolveError { message: format!("uninitialized constant {}", path) };
+
+        if let Some(absolute) = path.strip_prefix("::") {
+            return self.lookup(absolute).map(|_| absolute.to_string()).ok_or_else(uninitialized);
+        }
+
+        let first = path.split("::").next().unwrap_or(path);
+        let rest = &path[first.len()..];
+        let candidates = nesting.iter().rev().map(|scope| format!("{}::{}", scope, first));
+
+        for candidate in candidates.chain(std::iter::once(first.to_string())) {
+            if self.lookup(&candidate).is_some() {
+                let full_name = format!("{}{}", candidate, rest);
+                return self.lookup(&full_name).map(|_| full_name.clone()).ok_or_else(uninitialized);
+            }
+        }
+
+        Err(uninitialized())
+    }
+
+    fn entry_mut(&mut self, path: &str) -> &mut ConstantEntry {
+        let mut segments = path.split("::");
+        let first = segments.next().unwrap_or(path);
+        let mut entry = self.root.get_mut(first).expect("constant was declared before use");
+        for segment in segments {
+            entry = entry.constants.get_mut(segment).expect("constant was declared before use");
+        }
+        entry
+    }
+}
+
+fn nested(nesting: &[String], name: String) -> Vec<String> {
+    let mut nesting = nesting.to_vec();
+    nesting.push(name);
+    nesting
+}
+
+// The fully qualified name of every class and module reachable from `module`'s constants
+fn runtime_paths(runtime: &Runtime, module: ObjectId, prefix: Option<&str>, paths: &mut HashMap<ObjectId, String>) {
+    let Some(class) = runtime.class_value(module) else {
+        return;
+    };
+    for (name, value) in &class.constants {
+        let Some(id) = value.object_id().filter(|&id| runtime.class_value(id).is_some()) else {
+            continue;
+        };
+        // `Object::Object` and the like
+        if paths.contains_key(&id) {
+            continue;
+        }
+        let path = match prefix {
+            Some(prefix) => format!("{}::{}", prefix, name),
+            None => name.clone(),
+        };
+        paths.insert(id, path.clone());
+        runtime_paths(runtime, id, Some(&path), paths);
+    }
+}
+
+// Builtin classes only show up when a program added methods to them or to what they contain
+fn outline_constants(constants: &BTreeMap<String, ConstantEntry>, parent: Option<&str>, depth: usize, lines: &mut Vec<String>) {
+    for (name, entry) in constants {
+        let path = match parent {
+            Some(parent) => format!("{}::{}", parent, name),
+            None => name.clone(),
+        };
+        let indent = "  ".repeat(depth);
+        let keyword = match entry.kind {
+            ConstantKind::Class => "class",
+            ConstantKind::Module => "module",
+        };
+        let heading = match &entry.superclass {
+            Some(superclass) if !entry.builtin => format!("{}{} {} < {}", indent, keyword, path, superclass),
+            _ => format!("{}{} {}", indent, keyword, path),
+        };
+        let start = lines.len();
+        lines.push(heading);
+        let methods = entry.singleton_methods.iter().map(|name| format!("self.{}", name)).chain(entry.instance_methods.iter().cloned());
+        lines.extend(methods.map(|name| format!("{}  def {}", indent, name)));
+        outline_constants(&entry.constants, Some(&path), depth + 1, lines);
+        if entry.builtin && lines.len() == start + 1 {
+            lines.truncate(start);
+        }
+    }
+}
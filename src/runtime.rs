@@ -0,0 +1,1391 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use regex::bytes::Regex;
+
+use crate::builtins;
+use crate::gc::{Heap, Tracer};
+use crate::interp::{AstMethod, Closure};
+use crate::vm::{IseqClosure, IseqMethod};
+pub use crate::word::{Unpacked, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjectId(u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+pub struct Object {
+    // The object's singleton class once it has one
+    pub class: ObjectId,
+    pub ivars: Vec<(String, Value)>,
+    pub frozen: bool,
+    pub kind: ObjectKind,
+}
+
+pub enum ObjectKind {
+    Plain,
+    String(RString),
+    Regexp(RRegexp),
+    Encoding(Encoding),
+    Array(Vec<Value>),
+    Hash(RHash),
+    Range(Value, Value, bool),
+    Proc(Proc),
+    Class(RClass),
+    Exception(ExceptionData),
+    // Integers outside the fixnum range, which are never equal to a fixnum
+    Bignum(BigInt),
+    // Floats that can't be flonums. Like bignums, they're frozen.
+    Float(f64),
+}
+
+// A string's bytes, read as characters of its encoding. UTF-8 strings can still hold
+// invalid bytes (`force_encoding` doesn't check them), each of which is a character.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RString {
+    pub bytes: Vec<u8>,
+    pub encoding: Encoding,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    // `ASCII-8BIT`, where every byte is a character
+    Binary,
+}
+
+// A compiled pattern, with the source and options it was written with
+#[derive(Debug, Clone)]
+pub struct RRegexp {
+    pub source: String,
+    pub options: String,
+    pub regex: Regex,
+}
+
+// Classes and modules, including singleton classes
+pub struct RClass {
+    pub name: Option<String>,
+    pub superclass: Option<ObjectId>,
+    pub is_module: bool,
+    // The object a singleton class belongs to
+    pub attached: Option<Value>,
+    pub methods: HashMap<String, Rc<Method>>,
+    pub constants: HashMap<String, Value>,
+    // Modules mixed in with `include` and `prepend`, in the order they were added
+    pub includes: Vec<ObjectId>,
+    pub prepends: Vec<ObjectId>,
+    // How `new` allocates instances, inherited by subclasses
+    pub allocator: Allocator,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Allocator {
+    Object,
+    String,
+    Array,
+    Hash,
+    Exception,
+    // Integers, symbols, procs... have no `new`, or a dedicated one
+    None,
+}
+
+#[derive(Clone)]
+pub struct Method {
+    pub name: String,
+    pub owner: ObjectId,
+    pub body: MethodBody,
+    pub visibility: Visibility,
+}
+
+#[derive(Clone)]
+pub enum MethodBody {
+    // Arity uses the same convention as `Params::arity`, and is checked before the call
+    Native(NativeFn, i32),
+    Ast(Rc<AstMethod>),
+    Iseq(Rc<IseqMethod>),
+    // `define_method`, running the proc as a lambda with the receiver as `self`
+    Proc(Value),
+    // `attr_reader` and `attr_writer`, reading and writing the instance variable without its `@`
+    AttrReader(String),
+    AttrWriter(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Public,
+    // Only callable without a receiver, or with `self` as the receiver
+    Private,
+}
+
+// Why a call didn't find a method it could call
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Missing {
+    Undefined,
+    // Undefined, and the call was a bare identifier, which reads like a variable in errors
+    VariableLike,
+    Private,
+}
+
+// Native methods receive `self`, the arguments and the block, and call back into
+// whichever backend is running through the `Executor`.
+pub type NativeFn = fn(&mut dyn Executor, Value, &[Value], Option<Value>) -> Result<Value, Unwind>;
+// Native blocks receive the value they were made with and what they're yielded
+pub type BlockFn = fn(&mut dyn Executor, Value, &[Value]) -> Result<Value, Unwind>;
+
+pub struct Proc {
+    pub body: ProcBody,
+    pub lambda: bool,
+}
+
+#[derive(Clone)]
+pub enum ProcBody {
+    Ast(Rc<Closure>),
+    Iseq(Rc<IseqClosure>),
+    // `&:name`, calling `name` on the first argument
+    Symbol(String),
+    // A block written in Rust, called with the value it was made with and what it's
+    // yielded. It's how `Enumerable` visits what `each` yields as it's yielded.
+    Native(BlockFn, Value),
+}
+
+pub struct ExceptionData {
+    // `nil` uses the class name as the message
+    pub message: Value,
+    // Where it was first raised, `None` until then
+    pub backtrace: Option<Vec<String>>,
+}
+
+// Hash keys are compared by value for strings and arrays, and by identity for other objects
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HashKey {
+    Nil,
+    True,
+    False,
+    Integer(i64),
+    Bignum(BigInt),
+    // The bits of the float, with `-0.0` as `0.0`
+    Float(u64),
+    Symbol(Symbol),
+    String(Vec<u8>),
+    Array(Vec<HashKey>),
+    // An array inside itself
+    Recursive,
+    Object(ObjectId),
+}
+
+// Keeps its entries in insertion order
+#[derive(Default, Clone)]
+pub struct RHash {
+    pub entries: Vec<(Value, Value)>,
+    index: HashMap<HashKey, usize>,
+    pub default: Option<Value>,
+    // The block given to `Hash.new`, called with the hash and the key on a miss
+    pub default_proc: Option<Value>,
+    // Built by a call site from its keyword arguments, and only until a method binds them
+    pub keywords: bool,
+}
+
+// Everything that leaves an expression other than its value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unwind {
+    Raise(Value),
+    // Leaves the method (or lambda) whose frame has this id
+    Return(Value, usize),
+    // `None` while inside the loop, the proc breaking out once it leaves a block
+    Break(Value, Option<ObjectId>),
+    Next(Value),
+    Redo,
+    Retry,
+}
+
+impl Unwind {
+    // The value it carries, which has to stay alive while it's on its way
+    pub fn value(self) -> Option<Value> {
+        match self {
+            Unwind::Raise(value) | Unwind::Return(value, _) | Unwind::Break(value, _) | Unwind::Next(value) => Some(value),
+            Unwind::Redo | Unwind::Retry => None,
+        }
+    }
+}
+
+// How native methods reach back into the backend that called them
+pub trait Executor {
+    fn runtime(&mut self) -> &mut Runtime;
+
+    fn send(&mut self, receiver: Value, method: &str, args: &[Value], block: Option<Value>) -> Result<Value, Unwind>;
+
+    fn call_proc(&mut self, proc: Value, args: &[Value]) -> Result<Value, Unwind>;
+
+    // The block given to the method running the native method's caller
+    fn current_block(&mut self) -> Option<Value>;
+
+    // Calls the block with another `self`, and `definee` as where `def` adds methods,
+    // for `instance_eval` and `class_eval`
+    fn call_proc_as(&mut self, proc: Value, self_value: Value, definee: ObjectId, args: &[Value]) -> Result<Value, Unwind>;
+
+    // What `private` and `public` without arguments do: set the visibility of the
+    // methods the native method's caller defines next
+    fn set_default_visibility(&mut self, visibility: Visibility);
+
+    // Collects garbage with the backend's values as roots, then calls the finalizers
+    // of what was collected
+    fn collect_garbage(&mut self);
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EvalError {
+    Syntax(Vec<crate::parser::ParseError>),
+    // An exception nobody rescued
+    Exception { class: String, message: String, backtrace: Vec<String> },
+}
+
+// The class of every built-in value, looked up often enough to be worth keeping at hand
+pub struct CoreClasses {
+    pub basic_object: ObjectId,
+    pub object: ObjectId,
+    pub kernel: ObjectId,
+    pub module: ObjectId,
+    pub class: ObjectId,
+    pub nil: ObjectId,
+    pub true_class: ObjectId,
+    pub false_class: ObjectId,
+    pub integer: ObjectId,
+    pub float: ObjectId,
+    pub string: ObjectId,
+    pub regexp: ObjectId,
+    pub encoding: ObjectId,
+    pub symbol: ObjectId,
+    pub array: ObjectId,
+    pub hash: ObjectId,
+    pub range: ObjectId,
+    pub proc: ObjectId,
+    pub exception: ObjectId,
+    pub standard_error: ObjectId,
+}
+
+pub struct Runtime {
+    pub heap: Heap,
+    symbols: Vec<String>,
+    symbol_ids: HashMap<String, Symbol>,
+    pub classes: CoreClasses,
+    // `self` at the top level
+    pub main: Value,
+    // Method names of the active frames with the lines they're on, innermost last, for
+    // backtraces
+    pub frames: Vec<(String, usize)>,
+    // The exception the innermost running `rescue` clause handles, `$!`, which a bare
+    // `raise` raises again
+    pub errinfo: Value,
+    // The methods running on each receiver and argument that stop at a collection containing
+    // itself, like MRI's `rb_exec_recursive`
+    pub recursion: HashSet<(&'static str, ObjectId, ObjectId)>,
+    // Moves on whenever what a method lookup finds may have changed, which
+    // invalidates every inline cache at once
+    method_serial: u64,
+    // Why the call a user-defined `method_missing` is handling failed, for when it passes
+    // the call on to `BasicObject#method_missing` with `super`
+    pub missing_reason: Missing,
+    captured_output: Option<String>,
+}
+
+impl ObjectId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        ObjectId(index as u32)
+    }
+}
+
+impl Symbol {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    pub(crate) fn from_index(index: usize) -> Self {
+        Symbol(index as u32)
+    }
+}
+
+impl CoreClasses {
+    // They're all reachable from `Object` unless a constant is reassigned, but the
+    // runtime refers to them either way
+    fn trace(&self, tracer: &mut Tracer) {
+        let classes = [
+            self.basic_object,
+            self.object,
+            self.kernel,
+            self.module,
+            self.class,
+            self.nil,
+            self.true_class,
+            self.false_class,
+            self.integer,
+            self.float,
+            self.string,
+            self.regexp,
+            self.encoding,
+            self.symbol,
+            self.array,
+            self.hash,
+            self.range,
+            self.proc,
+            self.exception,
+            self.standard_error,
+        ];
+        for class in classes {
+            tracer.mark_id(class);
+        }
+    }
+}
+
+impl RClass {
+    fn new(name: Option<String>, superclass: Option<ObjectId>, is_module: bool, allocator: Allocator) -> Self {
+        RClass {
+            name,
+            superclass,
+            is_module,
+            attached: None,
+            methods: HashMap::new(),
+            constants: HashMap::new(),
+            includes: Vec::new(),
+            prepends: Vec::new(),
+            allocator,
+        }
+    }
+}
+
+impl RHash {
+    pub fn get(&self, key: &HashKey) -> Option<Value> {
+        self.index.get(key).map(|&position| self.entries[position].1)
+    }
+
+    pub fn insert(&mut self, key: HashKey, key_value: Value, value: Value) {
+        match self.index.get(&key) {
+            Some(&position) => self.entries[position].1 = value,
+            None => {
+                self.index.insert(key, self.entries.len());
+                self.entries.push((key_value, value));
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &HashKey) -> Option<Value> {
+        let position = self.index.remove(key)?;
+        let (_, value) = self.entries.remove(position);
+        for index in self.index.values_mut() {
+            if *index > position {
+                *index -= 1;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl RString {
+    pub fn new(text: &str) -> Self {
+        RString { bytes: text.as_bytes().to_vec(), encoding: Encoding::Utf8 }
+    }
+
+    // UTF-8 bytes, which literals with `\xNN` escapes can leave invalid
+    pub fn utf8(bytes: Vec<u8>) -> Self {
+        RString { bytes, encoding: Encoding::Utf8 }
+    }
+
+    pub fn binary(bytes: Vec<u8>) -> Self {
+        RString { bytes, encoding: Encoding::Binary }
+    }
+
+    // The text, if the bytes are valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.bytes).ok()
+    }
+
+    // The text with invalid bytes replaced, for showing it
+    pub fn text(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.bytes)
+    }
+
+    // The bytes of each character
+    pub fn chars(&self) -> Vec<&[u8]> {
+        match self.encoding {
+            Encoding::Binary => self.bytes.chunks(1).collect(),
+            Encoding::Utf8 => {
+                let mut chars = Vec::new();
+                for chunk in self.bytes.utf8_chunks() {
+                    let valid = chunk.valid();
+                    chars.extend(valid.char_indices().map(|(start, c)| &valid.as_bytes()[start..start + c.len_utf8()]));
+                    chars.extend(chunk.invalid().chunks(1));
+                }
+                chars
+            }
+        }
+    }
+
+    // How many characters come before a byte offset
+    pub fn char_index(&self, offset: usize) -> usize {
+        let mut position = 0;
+        self.chars()
+            .into_iter()
+            .take_while(|c| {
+                position += c.len();
+                position <= offset
+            })
+            .count()
+    }
+
+    pub fn is_ascii(&self) -> bool {
+        self.bytes.is_ascii()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.encoding == Encoding::Binary || self.as_str().is_some()
+    }
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Binary => "ASCII-8BIT",
+        }
+    }
+
+    // Also accepts `BINARY`, and names in any case
+    pub fn find(name: &str) -> Option<Encoding> {
+        match name.to_ascii_uppercase().as_str() {
+            "UTF-8" => Some(Encoding::Utf8),
+            "ASCII-8BIT" | "BINARY" => Some(Encoding::Binary),
+            _ => None,
+        }
+    }
+
+    // The constant under `Encoding` holding it
+    pub fn constant(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF_8",
+            Encoding::Binary => "ASCII_8BIT",
+        }
+    }
+}
+
+impl RRegexp {
+    // Ruby's `^` and `$` always match at line boundaries, and its `m` is what the
+    // `regex` crate calls `s`. `\h` and `\Z` have no equivalent, so they're spelled out.
+    pub fn new(source: &str, options: &str) -> Result<Self, String> {
+        let mut flags = String::from("m");
+        for (option, flag) in [('i', 'i'), ('m', 's'), ('x', 'x')] {
+            if options.contains(option) {
+                flags.push(flag);
+            }
+        }
+        let mut pattern = format!("(?{})", flags);
+        let mut chars = source.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                pattern.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('h') => pattern.push_str("[0-9a-fA-F]"),
+                Some('Z') => pattern.push_str("(?:\\n?\\z)"),
+                Some(escaped) => {
+                    pattern.push('\\');
+                    pattern.push(escaped);
+                }
+                None => pattern.push('\\'),
+            }
+        }
+        let regex = Regex::new(&pattern).map_err(|error| error.to_string().lines().last().unwrap_or_default().trim_start_matches("error: ").to_string())?;
+        let options = ['m', 'i', 'x'].iter().filter(|&&option| options.contains(option)).collect();
+        Ok(RRegexp { source: source.to_string(), options, regex })
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvalError::Syntax(errors) => {
+                let lines: Vec<String> =
+                    errors.iter().map(|error| format!("line {}: {}", error.line, error.message)).collect();
+                write!(f, "syntax error\n{}", lines.join("\n"))
+            }
+            // Laid out like Ruby does, the innermost frame first
+            EvalError::Exception { class, message, backtrace } => {
+                let mut lines = backtrace.iter();
+                if let Some(first) = lines.next() {
+                    write!(f, "{}: ", first)?;
+                }
+                write!(f, "{} ({})", message, class)?;
+                for line in lines {
+                    write!(f, "\n\tfrom {}", line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime::new()
+    }
+}
+
+impl Runtime {
+    pub fn new() -> Self {
+        // Placeholder ids, fixed up right after `Object`, `Module` and `Class` exist
+        let placeholder = ObjectId(0);
+        let mut runtime = Runtime {
+            heap: Heap::default(),
+            symbols: Vec::new(),
+            symbol_ids: HashMap::new(),
+            classes: CoreClasses {
+                basic_object: placeholder,
+                object: placeholder,
+                kernel: placeholder,
+                module: placeholder,
+                class: placeholder,
+                nil: placeholder,
+                true_class: placeholder,
+                false_class: placeholder,
+                integer: placeholder,
+                float: placeholder,
+                string: placeholder,
+                regexp: placeholder,
+                encoding: placeholder,
+                symbol: placeholder,
+                array: placeholder,
+                hash: placeholder,
+                range: placeholder,
+                proc: placeholder,
+                exception: placeholder,
+                standard_error: placeholder,
+            },
+            main: Value::NIL,
+            frames: Vec::new(),
+            errinfo: Value::NIL,
+            recursion: HashSet::new(),
+            method_serial: 0,
+            missing_reason: Missing::Undefined,
+            captured_output: None,
+        };
+
+        // `BasicObject`, `Object`, `Module` and `Class` are each other's class and superclass,
+        // so they're created with a dangling class and patched afterwards
+        let basic_object = runtime.new_class_object(Some("BasicObject"), None, false, Allocator::Object);
+        let object = runtime.new_class_object(Some("Object"), Some(basic_object), false, Allocator::Object);
+        let module = runtime.new_class_object(Some("Module"), Some(object), false, Allocator::None);
+        let class = runtime.new_class_object(Some("Class"), Some(module), false, Allocator::None);
+        for id in [basic_object, object, module, class] {
+            runtime.object_mut(id).class = class;
+            runtime.set_constant(object, runtime.class_name(id).as_str(), Value::object(id));
+        }
+        runtime.classes.basic_object = basic_object;
+        runtime.classes.object = object;
+        runtime.classes.module = module;
+        runtime.classes.class = class;
+        runtime.singleton_class(Value::object(class)).expect("classes have singleton classes");
+
+        let kernel = runtime.define_module("Kernel", None);
+        runtime.include_module(object, kernel).expect("Kernel is a module");
+        runtime.classes.kernel = kernel;
+
+        let core = |runtime: &mut Runtime, name: &str, allocator| runtime.define_class(name, object, None, allocator);
+        runtime.classes.nil = core(&mut runtime, "NilClass", Allocator::None);
+        runtime.classes.true_class = core(&mut runtime, "TrueClass", Allocator::None);
+        runtime.classes.false_class = core(&mut runtime, "FalseClass", Allocator::None);
+        runtime.classes.integer = core(&mut runtime, "Integer", Allocator::None);
+        runtime.classes.float = core(&mut runtime, "Float", Allocator::None);
+        runtime.classes.string = core(&mut runtime, "String", Allocator::String);
+        runtime.classes.regexp = core(&mut runtime, "Regexp", Allocator::None);
+        runtime.classes.encoding = core(&mut runtime, "Encoding", Allocator::None);
+        runtime.classes.symbol = core(&mut runtime, "Symbol", Allocator::None);
+        runtime.classes.array = core(&mut runtime, "Array", Allocator::Array);
+        runtime.classes.hash = core(&mut runtime, "Hash", Allocator::Hash);
+        runtime.classes.range = core(&mut runtime, "Range", Allocator::None);
+        runtime.classes.proc = core(&mut runtime, "Proc", Allocator::None);
+        runtime.classes.exception = core(&mut runtime, "Exception", Allocator::Exception);
+
+        let main = runtime.alloc(object, ObjectKind::Plain);
+        runtime.main = main;
+
+        builtins::define(&mut runtime);
+        runtime.classes.standard_error = runtime.constant(object, "StandardError").and_then(Value::object_id).expect("StandardError is defined");
+        // Everything allocated so far is reachable from `Object`
+        runtime.heap.unroot_to(0);
+        runtime
+    }
+
+    // Heap
+
+    pub fn alloc(&mut self, class: ObjectId, kind: ObjectKind) -> Value {
+        let id = self.heap.alloc(Object { class, ivars: Vec::new(), frozen: false, kind });
+        Value::object(id)
+    }
+
+    pub fn object(&self, id: ObjectId) -> &Object {
+        self.heap.get(id)
+    }
+
+    pub fn object_mut(&mut self, id: ObjectId) -> &mut Object {
+        self.heap.get_mut(id)
+    }
+
+    // Collects with the backend's roots in `tracer`, returning the finalizers of the
+    // collected objects for the backend to call
+    pub fn collect_garbage(&mut self, mut tracer: Tracer) -> Vec<(Value, ObjectId)> {
+        tracer.mark(self.main);
+        tracer.mark(self.errinfo);
+        self.classes.trace(&mut tracer);
+        let sweep = self.heap.collect(tracer);
+        if sweep.freed_classes {
+            self.invalidate_method_caches();
+        }
+        sweep.finalizers
+    }
+
+    pub fn kind(&self, value: Value) -> Option<&ObjectKind> {
+        value.object_id().map(|id| &self.object(id).kind)
+    }
+
+    // What the value stands for, with floats and 64-bit integers read out of the heap, so
+    // only bignums past 64 bits are left as objects
+    pub fn unpack(&self, value: Value) -> Unpacked {
+        match value.unpack().expect("values are never `UNDEF`") {
+            Unpacked::Object(id) => match &self.object(id).kind {
+                ObjectKind::Float(value) => Unpacked::Float(*value),
+                ObjectKind::Bignum(value) => i64::try_from(value).map_or(Unpacked::Object(id), Unpacked::Integer),
+                _ => Unpacked::Object(id),
+            },
+            unpacked => unpacked,
+        }
+    }
+
+    // An integer result, as a bignum only if it doesn't fit in a fixnum
+    pub fn integer(&mut self, value: BigInt) -> Value {
+        match i64::try_from(&value).ok().and_then(Value::fixnum) {
+            Some(fixnum) => fixnum,
+            None => self.frozen(self.classes.integer, ObjectKind::Bignum(value)),
+        }
+    }
+
+    pub fn int(&mut self, value: i64) -> Value {
+        match Value::fixnum(value) {
+            Some(fixnum) => fixnum,
+            None => self.frozen(self.classes.integer, ObjectKind::Bignum(BigInt::from(value))),
+        }
+    }
+
+    pub fn float(&mut self, value: f64) -> Value {
+        match Value::flonum(value) {
+            Some(flonum) => flonum,
+            None => self.frozen(self.classes.float, ObjectKind::Float(value)),
+        }
+    }
+
+    // Numbers are frozen, whether or not they're in the heap
+    fn frozen(&mut self, class: ObjectId, kind: ObjectKind) -> Value {
+        let number = self.alloc(class, kind);
+        self.object_mut(number.object_id().expect("just allocated")).frozen = true;
+        number
+    }
+
+    pub fn string(&mut self, text: &str) -> Value {
+        self.alloc(self.classes.string, ObjectKind::String(RString::new(text)))
+    }
+
+    pub fn string_from(&mut self, string: RString) -> Value {
+        self.alloc(self.classes.string, ObjectKind::String(string))
+    }
+
+    pub fn regexp(&mut self, regexp: RRegexp) -> Value {
+        self.alloc(self.classes.regexp, ObjectKind::Regexp(regexp))
+    }
+
+    // Invalid patterns raise `RegexpError`
+    pub fn new_regexp(&mut self, source: &str, options: &str) -> Result<Value, Unwind> {
+        match RRegexp::new(source, options) {
+            Ok(regexp) => Ok(self.regexp(regexp)),
+            Err(message) => Err(self.error("RegexpError", &format!("{}: /{}/", message, source))),
+        }
+    }
+
+    // `Encoding::UTF_8` and friends
+    pub fn encoding(&self, encoding: Encoding) -> Value {
+        self.constant(self.classes.encoding, encoding.constant()).expect("encodings are defined")
+    }
+
+    pub fn array(&mut self, elements: Vec<Value>) -> Value {
+        self.alloc(self.classes.array, ObjectKind::Array(elements))
+    }
+
+    pub fn hash(&mut self, hash: RHash) -> Value {
+        self.alloc(self.classes.hash, ObjectKind::Hash(hash))
+    }
+
+    pub fn range(&mut self, start: Value, end: Value, exclusive: bool) -> Value {
+        self.alloc(self.classes.range, ObjectKind::Range(start, end, exclusive))
+    }
+
+    pub fn proc(&mut self, body: ProcBody, lambda: bool) -> Value {
+        self.alloc(self.classes.proc, ObjectKind::Proc(Proc { body, lambda }))
+    }
+
+    // `None` for strings that aren't valid UTF-8 too
+    pub fn string_value(&self, value: Value) -> Option<&str> {
+        self.rstring_value(value).and_then(RString::as_str)
+    }
+
+    pub fn rstring_value(&self, value: Value) -> Option<&RString> {
+        match self.kind(value) {
+            Some(ObjectKind::String(string)) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn regexp_value(&self, value: Value) -> Option<&RRegexp> {
+        match self.kind(value) {
+            Some(ObjectKind::Regexp(regexp)) => Some(regexp),
+            _ => None,
+        }
+    }
+
+    pub fn array_value(&self, value: Value) -> Option<&Vec<Value>> {
+        match self.kind(value) {
+            Some(ObjectKind::Array(elements)) => Some(elements),
+            _ => None,
+        }
+    }
+
+    pub fn hash_value(&self, value: Value) -> Option<&RHash> {
+        match self.kind(value) {
+            Some(ObjectKind::Hash(hash)) => Some(hash),
+            _ => None,
+        }
+    }
+
+    pub fn bignum_value(&self, value: Value) -> Option<&BigInt> {
+        match self.kind(value) {
+            Some(ObjectKind::Bignum(value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn proc_value(&self, value: Value) -> Option<&Proc> {
+        match self.kind(value) {
+            Some(ObjectKind::Proc(proc)) => Some(proc),
+            _ => None,
+        }
+    }
+
+    pub fn class_value(&self, id: ObjectId) -> Option<&RClass> {
+        match &self.object(id).kind {
+            ObjectKind::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub fn class_value_mut(&mut self, id: ObjectId) -> Option<&mut RClass> {
+        match &mut self.object_mut(id).kind {
+            ObjectKind::Class(class) => Some(class),
+            _ => None,
+        }
+    }
+
+    pub fn hash_key(&self, value: Value) -> HashKey {
+        self.hash_key_within(value, &mut Vec::new())
+    }
+
+    // `within` holds the arrays the key is inside of, so an array containing itself ends
+    fn hash_key_within(&self, value: Value, within: &mut Vec<ObjectId>) -> HashKey {
+        match self.unpack(value) {
+            Unpacked::Nil => HashKey::Nil,
+            Unpacked::True => HashKey::True,
+            Unpacked::False => HashKey::False,
+            Unpacked::Integer(value) => HashKey::Integer(value),
+            Unpacked::Float(value) => HashKey::Float((value + 0.0).to_bits()),
+            Unpacked::Symbol(symbol) => HashKey::Symbol(symbol),
+            Unpacked::Object(id) => match &self.object(id).kind {
+                ObjectKind::String(string) => HashKey::String(string.bytes.clone()),
+                ObjectKind::Array(_) if within.contains(&id) => HashKey::Recursive,
+                ObjectKind::Array(elements) => {
+                    within.push(id);
+                    let key = HashKey::Array(elements.iter().map(|&element| self.hash_key_within(element, within)).collect());
+                    within.pop();
+                    key
+                }
+                ObjectKind::Bignum(value) => HashKey::Bignum(value.clone()),
+                _ => HashKey::Object(id),
+            },
+        }
+    }
+
+    // Keyword arguments
+
+    // Marks the hash a call site built from its keyword arguments, the last argument, which
+    // `**{}` leaves out altogether
+    pub fn pass_keywords(&mut self, args: &mut Vec<Value>) {
+        let Some(id) = args.last().and_then(|last| last.object_id()) else {
+            return;
+        };
+        if let ObjectKind::Hash(hash) = &mut self.object_mut(id).kind {
+            hash.keywords = true;
+            if hash.entries.is_empty() {
+                args.pop();
+            }
+        }
+    }
+
+    // The keyword arguments a call site passed, taken off the end of the arguments for a
+    // method with keyword parameters and left as a positional hash otherwise
+    pub fn take_keywords(&mut self, args: &mut Vec<Value>, takes_keywords: bool) -> Vec<(Value, Value)> {
+        let Some(id) = args.last().and_then(|last| last.object_id()) else {
+            return Vec::new();
+        };
+        let ObjectKind::Hash(hash) = &mut self.object_mut(id).kind else {
+            return Vec::new();
+        };
+        if !std::mem::take(&mut hash.keywords) || !takes_keywords {
+            return Vec::new();
+        }
+        let keywords = hash.entries.clone();
+        args.pop();
+        keywords
+    }
+
+    // Symbols
+
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbol_ids.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.symbols.len() as u32);
+        self.symbols.push(name.to_string());
+        self.symbol_ids.insert(name.to_string(), symbol);
+        symbol
+    }
+
+    pub fn symbol_name(&self, symbol: Symbol) -> &str {
+        &self.symbols[symbol.0 as usize]
+    }
+
+    pub fn symbol(&mut self, name: &str) -> Value {
+        Value::symbol(self.intern(name))
+    }
+
+    // Classes and modules
+
+    fn new_class_object(&mut self, name: Option<&str>, superclass: Option<ObjectId>, is_module: bool, allocator: Allocator) -> ObjectId {
+        let class = RClass::new(name.map(str::to_string), superclass, is_module, allocator);
+        let metaclass = if is_module { self.classes.module } else { self.classes.class };
+        self.alloc(metaclass, ObjectKind::Class(class)).object_id().expect("classes are objects")
+    }
+
+    // Creates a class, as a constant of `namespace` (`Object` when `None`) if it has a name
+    pub fn define_class(&mut self, name: &str, superclass: ObjectId, namespace: Option<ObjectId>, allocator: Allocator) -> ObjectId {
+        let full_name = self.qualified_name(name, namespace);
+        let class = self.new_class_object(Some(&full_name), Some(superclass), false, allocator);
+        self.set_constant(namespace.unwrap_or(self.classes.object), name, Value::object(class));
+        // Created upfront so class methods defined on a superclass later are inherited
+        self.singleton_class(Value::object(class)).expect("classes have singleton classes");
+        class
+    }
+
+    pub fn define_module(&mut self, name: &str, namespace: Option<ObjectId>) -> ObjectId {
+        let full_name = self.qualified_name(name, namespace);
+        let module = self.new_class_object(Some(&full_name), None, true, Allocator::None);
+        self.set_constant(namespace.unwrap_or(self.classes.object), name, Value::object(module));
+        module
+    }
+
+    fn qualified_name(&self, name: &str, namespace: Option<ObjectId>) -> String {
+        match namespace {
+            Some(namespace) if namespace != self.classes.object => format!("{}::{}", self.class_name(namespace), name),
+            _ => name.to_string(),
+        }
+    }
+
+    pub fn define_native(&mut self, class: ObjectId, name: &str, arity: i32, function: NativeFn) {
+        self.add_method(class, name, MethodBody::Native(function, arity));
+    }
+
+    pub fn define_singleton_native(&mut self, class: ObjectId, name: &str, arity: i32, function: NativeFn) {
+        let singleton = self.singleton_class(Value::object(class)).expect("classes have singleton classes");
+        self.define_native(singleton, name, arity, function);
+    }
+
+    // Methods are public, apart from the ones Ruby always makes private
+    pub fn add_method(&mut self, class: ObjectId, name: &str, body: MethodBody) {
+        let visibility = match name {
+            "initialize" | "initialize_copy" | "respond_to_missing?" => Visibility::Private,
+            _ => Visibility::Public,
+        };
+        self.insert_method(class, Method { name: name.to_string(), owner: class, body, visibility });
+    }
+
+    fn insert_method(&mut self, class: ObjectId, method: Method) {
+        if let Some(class) = self.class_value_mut(class) {
+            class.methods.insert(method.name.clone(), Rc::new(method));
+        }
+        self.invalidate_method_caches();
+    }
+
+    // `private :name` and `public :name`. An inherited method gets a copy in the module,
+    // which still belongs to the original owner as far as `super` is concerned.
+    pub fn set_visibility(&mut self, module: ObjectId, name: &str, visibility: Visibility) -> Result<(), Unwind> {
+        let Some(method) = self.find_method(module, name) else {
+            let kind = if self.class_value(module).is_some_and(|class| class.is_module) { "module" } else { "class" };
+            let message = format!("undefined method '{}' for {} '{}'", name, kind, self.class_name(module));
+            return Err(self.error("NameError", &message));
+        };
+        if method.visibility != visibility {
+            self.insert_method(module, Method { visibility, ..(*method).clone() });
+        }
+        Ok(())
+    }
+
+    pub fn method_serial(&self) -> u64 {
+        self.method_serial
+    }
+
+    // For anything that changes which method a lookup finds, like defining one
+    pub fn invalidate_method_caches(&mut self) {
+        self.method_serial += 1;
+    }
+
+    // The class that holds the value's methods, which is its singleton class if it has one
+    pub fn class_of(&self, value: Value) -> ObjectId {
+        match value.unpack().expect("values are never `UNDEF`") {
+            Unpacked::Nil => self.classes.nil,
+            Unpacked::True => self.classes.true_class,
+            Unpacked::False => self.classes.false_class,
+            Unpacked::Integer(_) => self.classes.integer,
+            Unpacked::Float(_) => self.classes.float,
+            Unpacked::Symbol(_) => self.classes.symbol,
+            Unpacked::Object(id) => self.object(id).class,
+        }
+    }
+
+    // What `value.class` returns, skipping singleton classes
+    pub fn real_class_of(&self, value: Value) -> ObjectId {
+        let mut class = self.class_of(value);
+        while let Some(RClass { attached: Some(_), superclass: Some(superclass), .. }) = self.class_value(class) {
+            class = *superclass;
+        }
+        class
+    }
+
+    pub fn singleton_class(&mut self, value: Value) -> Result<ObjectId, Unwind> {
+        let Unpacked::Object(id) = self.unpack(value) else {
+            return Err(self.error("TypeError", "can't define singleton"));
+        };
+        let class = self.object(id).class;
+        if self.class_value(class).is_some_and(|class| class.attached == Some(value)) {
+            return Ok(class);
+        }
+
+        // The singleton class of a class inherits from its superclass's, so class methods are inherited
+        let superclass = match self.class_value(id) {
+            Some(RClass { superclass: Some(superclass), is_module: false, .. }) => {
+                let superclass = *superclass;
+                self.singleton_class(Value::object(superclass))?
+            }
+            _ => class,
+        };
+        let mut singleton = RClass::new(None, Some(superclass), false, Allocator::None);
+        singleton.attached = Some(value);
+        let singleton = self.alloc(self.classes.class, ObjectKind::Class(singleton)).object_id().expect("classes are objects");
+        self.object_mut(id).class = singleton;
+        Ok(singleton)
+    }
+
+    // Where methods are looked up: each class of the superclass chain, after the modules
+    // prepended to it and before the ones it includes, latest first
+    pub fn ancestors(&self, class: ObjectId) -> Vec<ObjectId> {
+        let mut ancestors = Vec::new();
+        let mut current = Some(class);
+        while let Some(class) = current {
+            self.push_with_mixins(class, &mut ancestors);
+            current = self.class_value(class).and_then(|class| class.superclass);
+        }
+        ancestors
+    }
+
+    // Modules mixed in more than once, say by a module and by the class including it, only
+    // count where they first appear
+    fn push_with_mixins(&self, module: ObjectId, ancestors: &mut Vec<ObjectId>) {
+        if ancestors.contains(&module) {
+            return;
+        }
+        let Some(class) = self.class_value(module) else {
+            return;
+        };
+        for &prepended in class.prepends.iter().rev() {
+            self.push_with_mixins(prepended, ancestors);
+        }
+        ancestors.push(module);
+        for &included in class.includes.iter().rev() {
+            self.push_with_mixins(included, ancestors);
+        }
+    }
+
+    pub fn find_method(&self, class: ObjectId, name: &str) -> Option<Rc<Method>> {
+        self.ancestors(class).into_iter().find_map(|class| self.own_method(class, name))
+    }
+
+    // What `super` calls from `method`: the next method of that name after its owner in
+    // the ancestors of `self`'s class
+    pub fn find_super_method(&self, class: ObjectId, method: &Method) -> Option<Rc<Method>> {
+        let ancestors = self.ancestors(class);
+        let position = ancestors.iter().position(|&ancestor| ancestor == method.owner)?;
+        ancestors[position + 1..].iter().find_map(|&ancestor| self.own_method(ancestor, &method.name))
+    }
+
+    fn own_method(&self, module: ObjectId, name: &str) -> Option<Rc<Method>> {
+        self.class_value(module).and_then(|class| class.methods.get(name).cloned())
+    }
+
+    // `include`, which adds the module's methods after the target's own
+    pub fn include_module(&mut self, target: ObjectId, module: ObjectId) -> Result<(), Unwind> {
+        self.check_mixin(target, module, "include")?;
+        // Already there through a superclass or another module
+        if self.ancestors(target).contains(&module) {
+            return Ok(());
+        }
+        if let Some(class) = self.class_value_mut(target) {
+            class.includes.push(module);
+        }
+        self.invalidate_method_caches();
+        Ok(())
+    }
+
+    // `prepend`, which adds the module's methods before the target's own
+    pub fn prepend_module(&mut self, target: ObjectId, module: ObjectId) -> Result<(), Unwind> {
+        self.check_mixin(target, module, "prepend")?;
+        if self.class_value(target).is_some_and(|class| class.prepends.contains(&module)) {
+            return Ok(());
+        }
+        if let Some(class) = self.class_value_mut(target) {
+            class.prepends.push(module);
+        }
+        self.invalidate_method_caches();
+        Ok(())
+    }
+
+    fn check_mixin(&mut self, target: ObjectId, module: ObjectId, how: &str) -> Result<(), Unwind> {
+        if !self.class_value(module).is_some_and(|class| class.is_module) {
+            let class = self.class_name(self.real_class_of(Value::object(module)));
+            return Err(self.error("TypeError", &format!("wrong argument type {} (expected Module)", class)));
+        }
+        if self.ancestors(module).contains(&target) {
+            return Err(self.error("ArgumentError", &format!("cyclic {} detected", how)));
+        }
+        Ok(())
+    }
+
+    pub fn is_a(&self, value: Value, class: ObjectId) -> bool {
+        self.ancestors(self.class_of(value)).contains(&class)
+    }
+
+    pub fn class_name(&self, class: ObjectId) -> String {
+        match self.class_value(class) {
+            Some(RClass { name: Some(name), .. }) => name.clone(),
+            Some(RClass { attached: Some(attached), .. }) => format!("#<Class:{}>", self.describe(*attached)),
+            _ => format!("#<Class:{:?}>", class),
+        }
+    }
+
+    // A short description of a value for error messages, without calling back into Ruby code
+    pub fn describe(&self, value: Value) -> String {
+        match self.unpack(value) {
+            Unpacked::Nil => "nil".to_string(),
+            Unpacked::True => "true".to_string(),
+            Unpacked::False => "false".to_string(),
+            Unpacked::Integer(value) => value.to_string(),
+            Unpacked::Float(value) => builtins::format_float(value),
+            Unpacked::Symbol(symbol) => format!(":{}", self.symbol_name(symbol)),
+            Unpacked::Object(id) => match &self.object(id).kind {
+                ObjectKind::Class(_) => self.class_name(id),
+                ObjectKind::Bignum(value) => value.to_string(),
+                _ if value == self.main => "main".to_string(),
+                _ => format!("#<{}>", self.class_name(self.real_class_of(value))),
+            },
+        }
+    }
+
+    // Instance variables
+
+    // Unset instance variables, and those of immediates, are `nil`
+    pub fn ivar(&self, object: Value, name: &str) -> Value {
+        let ivars = object.object_id().map(|id| &self.object(id).ivars);
+        ivars.and_then(|ivars| ivars.iter().find(|(ivar, _)| ivar == name)).map_or(Value::NIL, |&(_, value)| value)
+    }
+
+    pub fn set_ivar(&mut self, object: ObjectId, name: &str, value: Value) {
+        let ivars = &mut self.object_mut(object).ivars;
+        match ivars.iter_mut().find(|(ivar, _)| ivar == name) {
+            Some(slot) => slot.1 = value,
+            None => ivars.push((name.to_string(), value)),
+        }
+    }
+
+    // `class Name < superclass` creates the class, or reopens it if it exists
+    pub fn open_class(&mut self, namespace: ObjectId, name: &str, superclass: Option<Value>) -> Result<ObjectId, Unwind> {
+        let is_class = |runtime: &Runtime, id: ObjectId| runtime.class_value(id).is_some_and(|class| !class.is_module);
+        let superclass = match superclass {
+            Some(value) => match value.object_id().filter(|&id| is_class(self, id)) {
+                Some(id) => Some(id),
+                None => {
+                    let message = format!("superclass must be an instance of Class (given {})", self.describe(value));
+                    return Err(self.error("TypeError", &message));
+                }
+            },
+            None => None,
+        };
+
+        match self.constant(namespace, name) {
+            Some(existing) => {
+                let Some(existing) = existing.object_id().filter(|&id| is_class(self, id)) else {
+                    return Err(self.error("TypeError", &format!("{} is not a class", name)));
+                };
+                if superclass.is_some() && superclass != self.class_value(existing).and_then(|class| class.superclass) {
+                    return Err(self.error("TypeError", &format!("superclass mismatch for class {}", name)));
+                }
+                Ok(existing)
+            }
+            None => {
+                let superclass = superclass.unwrap_or(self.classes.object);
+                let allocator = self.class_value(superclass).map(|class| class.allocator).expect("superclass is a class");
+                Ok(self.define_class(name, superclass, Some(namespace), allocator))
+            }
+        }
+    }
+
+    pub fn open_module(&mut self, namespace: ObjectId, name: &str) -> Result<ObjectId, Unwind> {
+        match self.constant(namespace, name) {
+            Some(existing) => match existing.object_id().filter(|&id| self.class_value(id).is_some_and(|module| module.is_module)) {
+                Some(existing) => Ok(existing),
+                None => Err(self.error("TypeError", &format!("{} is not a module", name))),
+            },
+            None => Ok(self.define_module(name, Some(namespace))),
+        }
+    }
+
+    // Constants
+
+    pub fn constant(&self, module: ObjectId, name: &str) -> Option<Value> {
+        self.class_value(module).and_then(|module| module.constants.get(name).copied())
+    }
+
+    // The lexically enclosing modules first (innermost last in `nesting`), then the ancestors of the innermost one
+    pub fn lookup_constant(&mut self, nesting: &[ObjectId], name: &str) -> Result<Value, Unwind> {
+        let lexical = nesting.iter().rev().find_map(|&module| self.constant(module, name));
+        let innermost = nesting.last().copied().unwrap_or(self.classes.object);
+        match lexical.or_else(|| self.inherited_constant(innermost, name)) {
+            Some(value) => Ok(value),
+            None => Err(self.error("NameError", &format!("uninitialized constant {}", name))),
+        }
+    }
+
+    // `Scope::Name` looks in the scope and its ancestors, but not in `Object` unless that's the scope
+    pub fn scoped_constant(&mut self, module: ObjectId, name: &str) -> Result<Value, Unwind> {
+        let object = self.classes.object;
+        let found = self
+            .ancestors(module)
+            .into_iter()
+            .filter(|&ancestor| ancestor != object || module == object)
+            .find_map(|ancestor| self.constant(ancestor, name));
+        match found {
+            Some(value) => Ok(value),
+            None => {
+                let message = if module == object {
+                    format!("uninitialized constant {}", name)
+                } else {
+                    format!("uninitialized constant {}::{}", self.class_name(module), name)
+                };
+                Err(self.error("NameError", &message))
+            }
+        }
+    }
+
+    pub fn set_constant(&mut self, module: ObjectId, name: &str, value: Value) {
+        if let Some(module) = self.class_value_mut(module) {
+            module.constants.insert(name.to_string(), value);
+        }
+    }
+
+    // Looks a constant up in the module and its ancestors, then in `Object`
+    pub fn inherited_constant(&self, module: ObjectId, name: &str) -> Option<Value> {
+        self.ancestors(module)
+            .into_iter()
+            .chain([self.classes.object])
+            .find_map(|module| self.constant(module, name))
+    }
+
+    // Exceptions
+
+    // Builds an exception of the named class, which must exist, ready to be raised
+    pub fn error(&mut self, class_name: &str, message: &str) -> Unwind {
+        let class = self
+            .constant(self.classes.object, class_name)
+            .and_then(Value::object_id)
+            .unwrap_or(self.classes.standard_error);
+        let message = self.string(message);
+        Unwind::Raise(self.exception(class, message))
+    }
+
+    pub fn argument_count_error(&mut self, given: usize, expected: &str) -> Unwind {
+        let message = format!("wrong number of arguments (given {}, expected {})", given, expected);
+        self.error("ArgumentError", &message)
+    }
+
+    // Checks an argument count against an arity in the `Params::arity` convention
+    pub fn check_arity(&mut self, arity: i32, given: usize) -> Result<(), Unwind> {
+        let required = if arity < 0 { (-arity - 1) as usize } else { arity as usize };
+        if given == required || (arity < 0 && given > required) {
+            return Ok(());
+        }
+        let expected = if arity < 0 { format!("{}+", required) } else { required.to_string() };
+        Err(self.argument_count_error(given, &expected))
+    }
+
+    // A method lookup that failed, or found a private method the call can't reach
+    pub fn undefined_method(&mut self, receiver: Value, name: &str, missing: Missing) -> Unwind {
+        let description = self.receiver_description(receiver);
+        match missing {
+            Missing::Undefined => {
+                let message = format!("undefined method '{}' for {}", name, description);
+                self.error("NoMethodError", &message)
+            }
+            Missing::VariableLike => {
+                let message = format!("undefined local variable or method '{}' for {}", name, description);
+                self.error("NameError", &message)
+            }
+            Missing::Private => {
+                let message = format!("private method '{}' called for {}", name, description);
+                self.error("NoMethodError", &message)
+            }
+        }
+    }
+
+    // `super` from a method that no ancestor after its owner defines, or outside of any method
+    pub fn no_super_method(&mut self, receiver: Value, method: Option<&Method>) -> Unwind {
+        let Some(method) = method else {
+            return self.error("RuntimeError", "super called outside of method");
+        };
+        let message = format!("super: no superclass method '{}' for {}", method.name, self.receiver_description(receiver));
+        self.error("NoMethodError", &message)
+    }
+
+    // A bare `super` in a method defined from a block, which has no parameters of its own to pass
+    pub fn implicit_super_in_define_method(&mut self) -> Unwind {
+        let message = "implicit argument passing of super from method defined by define_method() is not supported. \
+            Specify all arguments explicitly.";
+        self.error("RuntimeError", message)
+    }
+
+    // How errors about method calls refer to the receiver
+    fn receiver_description(&self, receiver: Value) -> String {
+        match receiver.object_id() {
+            None if matches!(receiver, Value::NIL | Value::TRUE | Value::FALSE) => self.describe(receiver),
+            _ if receiver == self.main => "main:Object".to_string(),
+            Some(id) if self.class_value(id).is_some() => {
+                let kind = if self.class_value(id).is_some_and(|class| class.is_module) { "module" } else { "class" };
+                format!("{} {}", kind, self.class_name(id))
+            }
+            _ => format!("an instance of {}", self.class_name(self.real_class_of(receiver))),
+        }
+    }
+
+    // A `break`, `next`, `redo`, `retry` or `return` that escaped everything that could handle it
+    pub fn jump_error(&mut self, unwind: Unwind) -> Unwind {
+        let message = match unwind {
+            Unwind::Raise(_) => return unwind,
+            Unwind::Return(..) => "unexpected return",
+            Unwind::Break(..) => "break from proc-closure",
+            Unwind::Next(_) => "unexpected next",
+            Unwind::Redo => "unexpected redo",
+            Unwind::Retry => "Invalid retry",
+        };
+        self.error("LocalJumpError", message)
+    }
+
+    pub fn exception(&mut self, class: ObjectId, message: Value) -> Value {
+        let backtrace = Some(self.backtrace());
+        self.alloc(class, ObjectKind::Exception(ExceptionData { message, backtrace }))
+    }
+
+    // A frame starts on the line of the call that made it
+    pub fn push_frame(&mut self, name: &str) {
+        let line = self.frames.last().map_or(1, |&(_, line)| line);
+        self.frames.push((name.to_string(), line));
+    }
+
+    // Moves the innermost frame to the line of the call it's making
+    pub fn set_line(&mut self, line: usize) {
+        if let Some(frame) = self.frames.last_mut() {
+            frame.1 = line;
+        }
+    }
+
+    pub fn backtrace(&self) -> Vec<String> {
+        self.frames.iter().rev().map(|(name, line)| format!("{}:in '{}'", line, name)).collect()
+    }
+
+    pub fn exception_message(&self, exception: Value) -> String {
+        match self.kind(exception) {
+            Some(ObjectKind::Exception(data)) => match self.rstring_value(data.message) {
+                Some(message) => message.text().into_owned(),
+                None if data.message == Value::NIL => self.class_name(self.real_class_of(exception)),
+                None => self.describe(data.message),
+            },
+            _ => self.describe(exception),
+        }
+    }
+
+    // Turns an exception that reached the top level into an error for the caller
+    pub fn uncaught(&self, exception: Value) -> EvalError {
+        let backtrace = match self.kind(exception) {
+            Some(ObjectKind::Exception(data)) => data.backtrace.clone().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        EvalError::Exception {
+            class: self.class_name(self.real_class_of(exception)),
+            message: self.exception_message(exception),
+            backtrace,
+        }
+    }
+
+    // Output
+
+    // Keeps what the program prints instead of writing it to stdout
+    pub fn capture_output(&mut self) {
+        self.captured_output = Some(String::new());
+    }
+
+    pub fn take_output(&mut self) -> String {
+        self.captured_output.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    pub fn write_output(&mut self, text: &str) {
+        match &mut self.captured_output {
+            Some(output) => output.push_str(text),
+            None => print!("{}", text),
+        }
+    }
+}
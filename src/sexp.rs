@@ -0,0 +1,301 @@
+use crate::ast::{
+    Begin, Block, BlockParams, Call, HashElement, HashPatternRest, Node, OpAssign, Param, Params, Pattern, RangeKind,
+    Target,
+};
+use crate::builtins;
+use crate::runtime::RString;
+
+// A compact, one line per statement dump of the tree in the spirit of `ruby --dump=parsetree`.
+// Node names follow the ones of the `parser` gem where there is an equivalent.
+pub fn to_sexp(program: &[Node]) -> String {
+    program.iter().map(node).collect::<Vec<_>>().join("\n")
+}
+
+fn list(name: &str, children: impl IntoIterator<Item = String>) -> String {
+    let mut sexp = format!("({}", name);
+    for child in children {
+        sexp.push(' ');
+        sexp.push_str(&child);
+    }
+    sexp.push(')');
+    sexp
+}
+
+fn optional(node: &Option<Box<Node>>) -> String {
+    node.as_deref().map_or_else(|| "nil".to_string(), self::node)
+}
+
+// Bodies of more than one statement are grouped with `begin`, empty ones are `nil`
+fn body(nodes: &[Node]) -> String {
+    match nodes {
+        [] => "nil".to_string(),
+        [single] => node(single),
+        nodes => list("begin", nodes.iter().map(node)),
+    }
+}
+
+fn optional_body(nodes: &Option<Vec<Node>>) -> String {
+    nodes.as_deref().map_or_else(|| "nil".to_string(), body)
+}
+
+fn node(node: &Node) -> String {
+    match node {
+        Node::Integer(value) => list("int", [value.to_string()]),
+        Node::Float(value) => list("float", [format!("{:?}", value)]),
+        Node::Bignum(digits) => list("int", [digits.clone()]),
+        Node::Str(text) => list("str", [format!("{:?}", text)]),
+        Node::Bytes(bytes) => list("str", [builtins::quote_string(&RString::utf8(bytes.clone()))]),
+        Node::Regexp(source, options) => {
+            let options = list("regopt", options.chars().map(|option| format!(":{}", option)));
+            list("regexp", [list("str", [format!("{:?}", source)]), options])
+        }
+        Node::Symbol(name) => list("sym", [format!(":{}", name)]),
+        Node::Nil => "(nil)".to_string(),
+        Node::True => "(true)".to_string(),
+        Node::False => "(false)".to_string(),
+        Node::SelfNode => "(self)".to_string(),
+        Node::LocalVariable(name) => list("lvar", [name.clone()]),
+        Node::Constant(name) => list("const", ["nil".to_string(), name.clone()]),
+        Node::ScopedConstant(Some(scope), name) => list("const", [self::node(scope), name.clone()]),
+        Node::ScopedConstant(None, name) => list("const", ["(cbase)".to_string(), name.clone()]),
+        Node::ConstantAssign(path, value) => match &**path {
+            Node::ScopedConstant(Some(scope), name) => list("casgn", [self::node(scope), name.clone(), self::node(value)]),
+            Node::ScopedConstant(None, name) => list("casgn", ["(cbase)".to_string(), name.clone(), self::node(value)]),
+            path => list("casgn", ["nil".to_string(), path.constant_path().unwrap_or_default(), self::node(value)]),
+        },
+        Node::LocalAssign(name, value) => list("lvasgn", [name.clone(), self::node(value)]),
+        Node::InstanceVariable(name) => list("ivar", [format!("@{}", name)]),
+        Node::InstanceVariableAssign(name, value) => list("ivasgn", [format!("@{}", name), self::node(value)]),
+        Node::MultipleAssign(targets, value) => {
+            list("masgn", [list("mlhs", targets.iter().map(target)), self::node(value)])
+        }
+        Node::OpAssign(op_assign) => self::op_assign(op_assign),
+        Node::Call(call) => self::call(call),
+        Node::Array(elements) => list("array", elements.iter().map(self::node)),
+        Node::InterpolatedString(parts) => list(
+            "dstr",
+            parts.iter().map(|part| match part {
+                Node::Str(_) | Node::Bytes(_) => self::node(part),
+                code => list("begin", [self::node(code)]),
+            }),
+        ),
+        Node::Hash(elements) => list("hash", elements.iter().map(hash_element)),
+        Node::KeywordHash(elements) => list("kwargs", elements.iter().map(hash_element)),
+        Node::Range(start, end, kind) => {
+            let name = match kind {
+                RangeKind::Inclusive => "irange",
+                RangeKind::Exclusive => "erange",
+            };
+            list(name, [optional(start), optional(end)])
+        }
+        Node::Splat(value) => list("splat", [self::node(value)]),
+        Node::BlockPass(value) => list("block_pass", [self::node(value)]),
+        Node::Lambda(block) => self::block("(lambda)".to_string(), block),
+        Node::Def(def) => {
+            let mut children = Vec::new();
+            let name = match &def.singleton {
+                Some(singleton) => {
+                    children.push(self::node(singleton));
+                    "defs"
+                }
+                None => "def",
+            };
+            children.push(def.name.clone());
+            children.push(params(&def.params));
+            children.push(body(&def.body));
+            list(name, children)
+        }
+        Node::Class(class) => list(
+            "class",
+            [self::node(&class.path), optional(&class.superclass), body(&class.body)],
+        ),
+        Node::Module(module) => list("module", [self::node(&module.path), body(&module.body)]),
+        Node::SingletonClass(target, nodes) => list("sclass", [self::node(target), body(nodes)]),
+        Node::If(node) => list(
+            "if",
+            [self::node(&node.condition), body(&node.then_body), body(&node.else_body)],
+        ),
+        Node::And(left, right) => list("and", [self::node(left), self::node(right)]),
+        Node::Or(left, right) => list("or", [self::node(left), self::node(right)]),
+        Node::Not(value) => list("not", [self::node(value)]),
+        Node::Return(value) => list("return", value.as_deref().map(self::node)),
+        Node::Break(value) => list("break", value.as_deref().map(self::node)),
+        Node::Next(value) => list("next", value.as_deref().map(self::node)),
+        Node::Redo => "(redo)".to_string(),
+        Node::Retry => "(retry)".to_string(),
+        Node::While(node) => {
+            let name = if node.do_while { "while_post" } else { "while" };
+            list(name, [self::node(&node.condition), body(&node.body)])
+        }
+        Node::For(node) => {
+            let variables = match &node.variables[..] {
+                [variable] => list("lvasgn", [variable.clone()]),
+                variables => list("mlhs", variables.iter().map(|variable| list("lvasgn", [variable.clone()]))),
+            };
+            list("for", [variables, self::node(&node.iterable), body(&node.body)])
+        }
+        Node::Begin(begin) => list("kwbegin", [self::begin(begin)]),
+        Node::Case(case) => {
+            let mut children = vec![optional(&case.subject)];
+            for when in &case.whens {
+                children.push(list("when", when.conditions.iter().map(self::node).chain([body(&when.body)])));
+            }
+            children.push(optional_body(&case.else_body));
+            list("case", children)
+        }
+        Node::CaseIn(case) => {
+            let mut children = vec![self::node(&case.subject)];
+            for clause in &case.clauses {
+                let guard = clause.guard.as_deref().map_or_else(|| "nil".to_string(), |guard| {
+                    list("if_guard", [self::node(guard)])
+                });
+                children.push(list("in_pattern", [pattern(&clause.pattern), guard, body(&clause.body)]));
+            }
+            children.push(optional_body(&case.else_body));
+            list("case_match", children)
+        }
+        Node::Yield(args) => list("yield", args.iter().map(self::node)),
+        Node::Super(node) => {
+            let send = match &node.args {
+                Some(args) => list("super", args.iter().map(self::node)),
+                None => "(zsuper)".to_string(),
+            };
+            match &node.block {
+                Some(block) => self::block(send, block),
+                None => send,
+            }
+        }
+        Node::Error => "(error)".to_string(),
+    }
+}
+
+fn call(call: &Call) -> String {
+    let mut children = vec![optional(&call.receiver), call.method.clone()];
+    children.extend(call.args.iter().map(node));
+    let send = list("send", children);
+
+    match &call.block {
+        Some(block) => self::block(send, block),
+        None => send,
+    }
+}
+
+fn block(call: String, block: &Block) -> String {
+    let params = match &block.params {
+        BlockParams::Explicit(explicit) => params(explicit),
+        BlockParams::Numbered(count) => list("numargs", [count.to_string()]),
+        BlockParams::It => "(itarg)".to_string(),
+    };
+    list("block", [call, params, body(&block.body)])
+}
+
+fn params(params: &Params) -> String {
+    let locals = params.locals.iter().map(|name| list("shadowarg", [name.clone()]));
+    list("args", params.params.iter().map(param).chain(locals))
+}
+
+fn param(param: &Param) -> String {
+    let named = |name: &str, value: &Option<String>| list(name, value.clone());
+
+    match param {
+        Param::Required(name) => list("arg", [name.clone()]),
+        Param::Destructure(params) => list("mlhs", params.iter().map(self::param)),
+        Param::Optional(name, default) => list("optarg", [name.clone(), node(default)]),
+        Param::Rest(name) => named("restarg", name),
+        Param::RequiredKeyword(name) => list("kwarg", [name.clone()]),
+        Param::OptionalKeyword(name, default) => list("kwoptarg", [name.clone(), node(default)]),
+        Param::KeywordRest(name) => named("kwrestarg", name),
+        Param::Block(name) => named("blockarg", name),
+    }
+}
+
+fn target(target: &Target) -> String {
+    match target {
+        Target::Local(name) => list("lvasgn", [name.clone()]),
+        Target::InstanceVariable(name) => list("ivasgn", [format!("@{}", name)]),
+        Target::Index(receiver, args) => list("indexasgn", [node(receiver)].into_iter().chain(args.iter().map(node))),
+        Target::Attribute(receiver, name) => list("send", [node(receiver), format!("{}=", name)]),
+        Target::Splat(target) => list("splat", target.as_deref().map(self::target)),
+        Target::Nested(targets) => list("mlhs", targets.iter().map(self::target)),
+    }
+}
+
+fn op_assign(op_assign: &OpAssign) -> String {
+    let target = self::target(&op_assign.target);
+    let value = node(&op_assign.value);
+    match op_assign.operator.as_str() {
+        "||" => list("or_asgn", [target, value]),
+        "&&" => list("and_asgn", [target, value]),
+        operator => list("op_asgn", [target, operator.to_string(), value]),
+    }
+}
+
+fn hash_element(element: &HashElement) -> String {
+    match element {
+        HashElement::Pair(key, value) => list("pair", [node(key), node(value)]),
+        HashElement::DoubleSplat(value) => list("kwsplat", [node(value)]),
+    }
+}
+
+fn begin(begin: &Begin) -> String {
+    let mut sexp = body(&begin.body);
+
+    if !begin.rescues.is_empty() || begin.else_body.is_some() {
+        let mut children = vec![sexp];
+        for rescue in &begin.rescues {
+            let classes = match &rescue.classes[..] {
+                [] => "nil".to_string(),
+                classes => list("array", classes.iter().map(node)),
+            };
+            let variable = rescue.variable.as_ref().map_or_else(|| "nil".to_string(), |name| list("lvasgn", [name.clone()]));
+            children.push(list("resbody", [classes, variable, body(&rescue.body)]));
+        }
+        children.push(optional_body(&begin.else_body));
+        sexp = list("rescue", children);
+    }
+
+    if let Some(ensure_body) = &begin.ensure_body {
+        sexp = list("ensure", [sexp, body(ensure_body)]);
+    }
+    sexp
+}
+
+fn pattern(pattern: &Pattern) -> String {
+    let rest = |name: &Option<String>| list("match_rest", name.iter().map(|name| list("match_var", [name.clone()])));
+    let with_constant = |constant: &Option<Node>, sexp: String| match constant {
+        Some(constant) => list("const_pattern", [node(constant), sexp]),
+        None => sexp,
+    };
+
+    match pattern {
+        Pattern::Value(value) => node(value),
+        Pattern::Pin(value) => list("pin", [node(value)]),
+        Pattern::Bind(name) => list("match_var", [name.clone()]),
+        Pattern::Alternative(patterns) => list("match_alt", patterns.iter().map(self::pattern)),
+        Pattern::Capture(pattern, name) => list("match_as", [self::pattern(pattern), list("match_var", [name.clone()])]),
+        Pattern::Array { constant, pre, rest: splat, post } => {
+            let mut children: Vec<String> = pre.iter().map(self::pattern).collect();
+            children.extend(splat.as_ref().map(rest));
+            children.extend(post.iter().map(self::pattern));
+            with_constant(constant, list("array_pattern", children))
+        }
+        Pattern::Find { constant, pre, middle, post } => {
+            let children = [rest(pre)].into_iter().chain(middle.iter().map(self::pattern)).chain([rest(post)]);
+            with_constant(constant, list("find_pattern", children))
+        }
+        Pattern::Hash { constant, pairs, rest: hash_rest } => {
+            let mut children: Vec<String> = pairs
+                .iter()
+                .map(|(key, pattern)| match pattern {
+                    Some(pattern) => list("pair", [format!("(sym :{})", key), self::pattern(pattern)]),
+                    None => list("match_var", [key.clone()]),
+                })
+                .collect();
+            children.extend(hash_rest.as_ref().map(|hash_rest| match hash_rest {
+                HashPatternRest::Named(name) => rest(&Some(name.clone())),
+                HashPatternRest::Nil => "(match_nil_pattern)".to_string(),
+            }));
+            with_constant(constant, list("hash_pattern", children))
+        }
+    }
+}
@@ -1,13 +1,25 @@
-#[derive(Debug, PartialEq)]
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+
+use crate::builtins;
+use crate::lexer::Lexeme;
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
 pub enum Token {
     Identifier(String),
-    Number(i32),
+    Number(i64),
+    // Integer literals too large for 64 bits, as their digits
+    Bignum(String),
+    Float(f64),
     Plus,
     Minus,
     Eof,
     WhiteSpace,
     BreakLine,
     Text(String),
+    // String literals whose escapes make them invalid UTF-8
+    Bytes(#[serde(serialize_with = "serialize_bytes")] Vec<u8>),
     LeftParenthesis,
     RightParenthesis,
     Comma,  
@@ -16,18 +28,128 @@ pub enum Token {
     EqualEqualEqual,
     Not,
     NotEqual,
+    // `=~` and `!~`
+    Match,
+    NotMatch,
     LessThan,
     GreaterThan,
     LessThanOrEqual,
     GreaterThanOrEqual,
+    // `<=>`
+    Spaceship,
     Arrow, // =>
+    Lambda, // ->
     Illegal(String),
     Asterisk,
     Slash,
     Percent,
+    // `"text #{code} ..."`, with the text before the first `#{}` and the source of its code.
+    // The lexeme holds every part.
     Interpolation(String, String),
+    // `/source/options`
+    Regexp(String, String),
     Symbol(String),
     LeftBrace,
     RightBrace,
     Colon,
-}
\ No newline at end of file
+    Pipe,
+    Dot,
+    Semicolon,
+    AsteriskAsterisk,
+    Ampersand,
+    ColonColon,
+    ShiftLeft,
+    ShiftRight,
+    Question,
+    PipePipe,
+    AmpersandAmpersand,
+    LeftBracket,
+    RightBracket,
+    Caret,
+    DotDot,
+    DotDotDot,
+    // `@name`, without the sigil
+    InstanceVariable(String),
+    // `+=`, `||=` and friends, holding the operator without the `=`
+    OperatorAssign(String),
+}
+// The token as it's written, for diagnostics
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Token::Identifier(name) | Token::Bignum(name) | Token::Illegal(name) => name,
+            Token::Number(value) => return write!(f, "{}", value),
+            Token::Float(value) => return write!(f, "{:?}", value),
+            Token::Text(text) => return write!(f, "{}", builtins::quote(text)),
+            Token::Bytes(bytes) => return write!(f, "{}", builtins::quote(&String::from_utf8_lossy(bytes))),
+            Token::Interpolation(text, code) => return write!(f, "\"{}#{{{}}}\"", text, code),
+            Token::Regexp(source, options) => return write!(f, "/{}/{}", source, options),
+            Token::Symbol(name) => return write!(f, "{}", builtins::inspect_symbol(name)),
+            Token::InstanceVariable(name) => return write!(f, "@{}", name),
+            Token::OperatorAssign(operator) => return write!(f, "{}=", operator),
+            Token::Eof => "end of input",
+            Token::WhiteSpace => " ",
+            Token::BreakLine => "end of line",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::LeftParenthesis => "(",
+            Token::RightParenthesis => ")",
+            Token::Comma => ",",
+            Token::Equal => "=",
+            Token::EqualEqual => "==",
+            Token::EqualEqualEqual => "===",
+            Token::Not => "!",
+            Token::NotEqual => "!=",
+            Token::Match => "=~",
+            Token::NotMatch => "!~",
+            Token::LessThan => "<",
+            Token::GreaterThan => ">",
+            Token::LessThanOrEqual => "<=",
+            Token::GreaterThanOrEqual => ">=",
+            Token::Spaceship => "<=>",
+            Token::Arrow => "=>",
+            Token::Lambda => "->",
+            Token::Asterisk => "*",
+            Token::Slash => "/",
+            Token::Percent => "%",
+            Token::LeftBrace => "{",
+            Token::RightBrace => "}",
+            Token::Colon => ":",
+            Token::Pipe => "|",
+            Token::Dot => ".",
+            Token::Semicolon => ";",
+            Token::AsteriskAsterisk => "**",
+            Token::Ampersand => "&",
+            Token::ColonColon => "::",
+            Token::ShiftLeft => "<<",
+            Token::ShiftRight => ">>",
+            Token::Question => "?",
+            Token::PipePipe => "||",
+            Token::AmpersandAmpersand => "&&",
+            Token::LeftBracket => "[",
+            Token::RightBracket => "]",
+            Token::Caret => "^",
+            Token::DotDot => "..",
+            Token::DotDotDot => "...",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+// A piece of an interpolated string: literal bytes, or the lexemes of the code in `#{}`
+// ending with `Eof`
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub enum StringPart {
+    Text(#[serde(serialize_with = "serialize_bytes")] Vec<u8>),
+    Code(Vec<Lexeme>),
+}
+
+// Bytes are serialized as text, with `\xNN` for the ones that aren't valid UTF-8
+fn serialize_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let mut text = String::new();
+    for chunk in bytes.utf8_chunks() {
+        text.push_str(chunk.valid());
+        chunk.invalid().iter().for_each(|byte| text.push_str(&format!("\\x{:02X}", byte)));
+    }
+    serializer.serialize_str(&text)
+}
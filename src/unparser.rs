@@ -0,0 +1,588 @@
+use std::collections::HashSet;
+
+use crate::ast::{
+    Begin, Block, BlockParams, Call, Case, CaseIn, Def, HashElement, HashPatternRest, If, Node, OpAssign, Param,
+    Params, Pattern, RangeKind, Rescue, Target,
+};
+use crate::builtins;
+use crate::runtime::RString;
+use crate::visitor::{walk_node, walk_params, walk_pattern, walk_rescue, walk_target, Visitor};
+
+// How tightly an expression binds, from assignments to literals. An operand that binds
+// looser than its position requires is wrapped in parentheses.
+const ASSIGNMENT: u8 = 0;
+const RANGE: u8 = 1;
+const UNARY: u8 = 12;
+const PRIMARY: u8 = 13;
+
+// Renders a program back into source text, in a canonical layout: two space indentation,
+// parenthesized call arguments, braces for blocks and keyword forms for every conditional.
+// Parsing the output gives back a tree equal to `program`. Trees recovered from syntax
+// errors are the exception, their `Node::Error`s are printed as `nil`.
+pub fn unparse(program: &[Node]) -> String {
+    let mut locals = LocalNames::default();
+    for node in program {
+        locals.visit_node(node);
+    }
+
+    Unparser { locals: locals.names }.statements(program, 0)
+}
+
+// A single parameter, as diagnostics show it
+pub fn unparse_param(param: &Param) -> String {
+    Unparser { locals: HashSet::new() }.param(param, 0)
+}
+
+// Every name used as a local variable. A method call without receiver nor arguments
+// that shares its name with one of them, or with `it`, needs `()` to not be read as a variable.
+#[derive(Default)]
+struct LocalNames {
+    names: HashSet<String>,
+}
+
+impl Visitor for LocalNames {
+    fn visit_node(&mut self, node: &Node) {
+        match node {
+            Node::LocalVariable(name) | Node::LocalAssign(name, _) => {
+                self.names.insert(name.clone());
+            }
+            Node::For(node) => self.names.extend(node.variables.iter().cloned()),
+            _ => {}
+        }
+        walk_node(self, node);
+    }
+
+    fn visit_params(&mut self, params: &Params) {
+        self.names.extend(params.names().into_iter().map(str::to_string));
+        walk_params(self, params);
+    }
+
+    fn visit_target(&mut self, target: &Target) {
+        self.names.extend(target.locals().into_iter().map(str::to_string));
+        walk_target(self, target);
+    }
+
+    fn visit_rescue(&mut self, rescue: &Rescue) {
+        self.names.extend(rescue.variable.clone());
+        walk_rescue(self, rescue);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Bind(name) | Pattern::Capture(_, name) => {
+                self.names.insert(name.clone());
+            }
+            Pattern::Array { rest: Some(Some(name)), .. } => {
+                self.names.insert(name.clone());
+            }
+            Pattern::Find { pre, post, .. } => self.names.extend(pre.iter().chain(post).cloned()),
+            Pattern::Hash { pairs, rest, .. } => {
+                self.names.extend(pairs.iter().filter(|(_, pattern)| pattern.is_none()).map(|(key, _)| key.clone()));
+                if let Some(HashPatternRest::Named(name)) = rest {
+                    self.names.insert(name.clone());
+                }
+            }
+            _ => {}
+        }
+        walk_pattern(self, pattern);
+    }
+}
+
+struct Unparser {
+    locals: HashSet<String>,
+}
+
+impl Unparser {
+    // One statement per line, each indented by `indent` levels
+    fn statements(&self, body: &[Node], indent: usize) -> String {
+        body.iter()
+            .map(|node| format!("{}{}", "  ".repeat(indent), self.expression(node, ASSIGNMENT, indent)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The statements of a compound body on the lines after its opening line
+    fn body(&self, body: &[Node], indent: usize) -> String {
+        if body.is_empty() {
+            String::new()
+        } else {
+            format!("\n{}", self.statements(body, indent))
+        }
+    }
+
+    fn expression(&self, node: &Node, min_precedence: u8, indent: usize) -> String {
+        let (text, precedence) = self.node(node, indent);
+        if precedence < min_precedence {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    fn list(&self, nodes: &[Node], indent: usize) -> String {
+        nodes.iter().map(|node| self.expression(node, ASSIGNMENT, indent)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn node(&self, node: &Node, indent: usize) -> (String, u8) {
+        let end = format!("\n{}end", "  ".repeat(indent));
+
+        match node {
+            Node::Integer(value) => (value.to_string(), if *value < 0 { UNARY } else { PRIMARY }),
+            Node::Float(value) => (format!("{:?}", value), if *value < 0.0 { UNARY } else { PRIMARY }),
+            Node::Bignum(digits) => (digits.clone(), if digits.starts_with('-') { UNARY } else { PRIMARY }),
+            Node::Str(text) if text.contains('\\') || text.chars().any(char::is_control) => (builtins::quote(text), PRIMARY),
+            Node::Str(text) if text.contains('\'') && !text.contains('"') => (format!("\"{}\"", text), PRIMARY),
+            Node::Str(text) => (format!("'{}'", text), PRIMARY),
+            Node::Bytes(bytes) => (builtins::quote_string(&RString::utf8(bytes.clone())), PRIMARY),
+            Node::Regexp(source, options) => (format!("/{}/{}", source.replace('/', "\\/"), options), PRIMARY),
+            Node::Symbol(name) => (builtins::inspect_symbol(name), PRIMARY),
+            Node::Nil | Node::Error => ("nil".to_string(), PRIMARY),
+            Node::True => ("true".to_string(), PRIMARY),
+            Node::False => ("false".to_string(), PRIMARY),
+            Node::SelfNode => ("self".to_string(), PRIMARY),
+            Node::LocalVariable(name) | Node::Constant(name) => (name.clone(), PRIMARY),
+            Node::ScopedConstant(Some(scope), name) => {
+                (format!("{}::{}", self.expression(scope, PRIMARY, indent), name), PRIMARY)
+            }
+            Node::ScopedConstant(None, name) => (format!("::{}", name), PRIMARY),
+            Node::ConstantAssign(path, value) => (
+                format!("{} = {}", self.expression(path, PRIMARY, indent), self.expression(value, ASSIGNMENT, indent)),
+                ASSIGNMENT,
+            ),
+            Node::LocalAssign(name, value) => {
+                (format!("{} = {}", name, self.expression(value, ASSIGNMENT, indent)), ASSIGNMENT)
+            }
+            Node::InstanceVariable(name) => (format!("@{}", name), PRIMARY),
+            Node::InstanceVariableAssign(name, value) => {
+                (format!("@{} = {}", name, self.expression(value, ASSIGNMENT, indent)), ASSIGNMENT)
+            }
+            Node::MultipleAssign(targets, value) => {
+                let targets = targets.iter().map(|target| self.target(target, indent)).collect::<Vec<_>>();
+                (format!("{} = {}", targets.join(", "), self.expression(value, ASSIGNMENT, indent)), ASSIGNMENT)
+            }
+            Node::OpAssign(op_assign) => (self.op_assign(op_assign, indent), ASSIGNMENT),
+            Node::Call(call) => self.call(call, indent),
+            Node::Array(elements) => (format!("[{}]", self.list(elements, indent)), PRIMARY),
+            Node::InterpolatedString(parts) => {
+                let mut text = String::new();
+                for part in parts {
+                    let quoted = match part {
+                        Node::Str(literal) => builtins::quote(literal),
+                        Node::Bytes(bytes) => builtins::quote_string(&RString::utf8(bytes.clone())),
+                        code => format!("\"#{{{}}}\"", self.node(code, indent).0),
+                    };
+                    text.push_str(&quoted[1..quoted.len() - 1]);
+                }
+                (format!("\"{}\"", text), PRIMARY)
+            }
+            Node::Hash(elements) if elements.is_empty() => ("{}".to_string(), PRIMARY),
+            Node::Hash(elements) => {
+                let elements = elements.iter().map(|element| self.hash_element(element, indent)).collect::<Vec<_>>();
+                (format!("{{ {} }}", elements.join(", ")), PRIMARY)
+            }
+            Node::KeywordHash(elements) => {
+                let elements = elements.iter().map(|element| self.hash_element(element, indent)).collect::<Vec<_>>();
+                (elements.join(", "), ASSIGNMENT)
+            }
+            Node::Range(start, end, kind) => {
+                let operand = |node: &Option<Box<Node>>| match node {
+                    Some(node) => self.expression(node, RANGE + 1, indent),
+                    None => String::new(),
+                };
+                (format!("{}{}{}", operand(start), range_operator(*kind), operand(end)), RANGE)
+            }
+            Node::Splat(value) => (format!("*{}", self.expression(value, RANGE, indent)), PRIMARY),
+            Node::BlockPass(value) => (format!("&{}", self.expression(value, RANGE, indent)), PRIMARY),
+            Node::Lambda(block) => {
+                let params = match &block.params {
+                    BlockParams::Explicit(params) if !params.is_empty() => format!("({})", self.params(params, indent)),
+                    _ => String::new(),
+                };
+                (format!("->{} {}", params, self.braces("", &block.body, indent)), PRIMARY)
+            }
+            Node::Def(def) => (self.def(def, indent), PRIMARY),
+            Node::Class(class) => {
+                let mut text = format!("class {}", self.expression(&class.path, PRIMARY, indent));
+                if let Some(superclass) = &class.superclass {
+                    text.push_str(&format!(" < {}", self.expression(superclass, RANGE + 1, indent)));
+                }
+                (format!("{}{}{}", text, self.body(&class.body, indent + 1), end), PRIMARY)
+            }
+            Node::Module(module) => {
+                let path = self.expression(&module.path, PRIMARY, indent);
+                (format!("module {}{}{}", path, self.body(&module.body, indent + 1), end), PRIMARY)
+            }
+            Node::SingletonClass(target, body) => {
+                let target = self.expression(target, ASSIGNMENT, indent);
+                (format!("class << {}{}{}", target, self.body(body, indent + 1), end), PRIMARY)
+            }
+            Node::If(node) => (format!("if {}{}", self.if_branches(node, indent), end), PRIMARY),
+            Node::And(left, right) => self.binary(left, "&&", right, indent),
+            Node::Or(left, right) => self.binary(left, "||", right, indent),
+            Node::Not(value) => (format!("!{}", self.expression(value, UNARY, indent)), UNARY),
+            Node::Return(value) => (self.jump("return", value, indent), ASSIGNMENT),
+            Node::Break(value) => (self.jump("break", value, indent), ASSIGNMENT),
+            Node::Next(value) => (self.jump("next", value, indent), ASSIGNMENT),
+            Node::Redo => ("redo".to_string(), PRIMARY),
+            Node::Retry => ("retry".to_string(), PRIMARY),
+            Node::While(node) if node.do_while => {
+                let condition = self.expression(&node.condition, ASSIGNMENT, indent);
+                (format!("begin{}{} while {}", self.body(&node.body, indent + 1), end, condition), ASSIGNMENT)
+            }
+            Node::While(node) => {
+                let condition = self.expression(&node.condition, ASSIGNMENT, indent);
+                (format!("while {}{}{}", condition, self.body(&node.body, indent + 1), end), PRIMARY)
+            }
+            Node::For(node) => {
+                let iterable = self.expression(&node.iterable, ASSIGNMENT, indent);
+                let body = self.body(&node.body, indent + 1);
+                (format!("for {} in {}{}{}", node.variables.join(", "), iterable, body, end), PRIMARY)
+            }
+            Node::Begin(begin) => (format!("begin{}{}", self.begin_clauses(begin, indent), end), PRIMARY),
+            Node::Case(case) => (self.case(case, indent), PRIMARY),
+            Node::CaseIn(case) => (self.case_in(case, indent), PRIMARY),
+            Node::Yield(args) if args.is_empty() => ("yield".to_string(), PRIMARY),
+            Node::Yield(args) => (format!("yield({})", self.arguments(args, indent)), PRIMARY),
+            Node::Super(node) => {
+                let mut text = match &node.args {
+                    Some(args) => format!("super({})", self.arguments(args, indent)),
+                    None => "super".to_string(),
+                };
+                if let Some(block) = &node.block {
+                    text.push(' ');
+                    text.push_str(&self.block(block, indent));
+                }
+                (text, PRIMARY)
+            }
+        }
+    }
+
+    fn call(&self, call: &Call, indent: usize) -> (String, u8) {
+        if let (Some(receiver), None) = (&call.receiver, &call.block) {
+            match (call.method.as_str(), &call.args[..]) {
+                ("[]", args) => {
+                    let receiver = self.expression(receiver, PRIMARY, indent);
+                    return (format!("{}[{}]", receiver, self.list(args, indent)), PRIMARY);
+                }
+                ("[]=", [args @ .., value]) => {
+                    let receiver = self.expression(receiver, PRIMARY, indent);
+                    let value = self.expression(value, ASSIGNMENT, indent);
+                    return (format!("{}[{}] = {}", receiver, self.list(args, indent), value), ASSIGNMENT);
+                }
+                ("-@", []) => return (format!("-{}", self.expression(receiver, UNARY, indent)), UNARY),
+                (method, [right]) if binary_precedence(method).is_some() => {
+                    return self.binary(receiver, method, right, indent);
+                }
+                (method, [value]) if is_setter(method) => {
+                    let receiver = self.expression(receiver, PRIMARY, indent);
+                    let value = self.expression(value, ASSIGNMENT, indent);
+                    return (format!("{}.{} = {}", receiver, &method[..method.len() - 1], value), ASSIGNMENT);
+                }
+                _ => {}
+            }
+        }
+
+        let mut text = match &call.receiver {
+            Some(receiver) => format!("{}.{}", self.expression(receiver, PRIMARY, indent), call.method),
+            None => call.method.clone(),
+        };
+        // Without arguments, calls named like a local or a constant need `()` to stay calls
+        let ambiguous = call.receiver.is_none()
+            && (self.locals.contains(&call.method) || call.method == "it" || call.method.starts_with(|c: char| c.is_uppercase()));
+        if !call.args.is_empty() || ambiguous {
+            text.push_str(&format!("({})", self.arguments(&call.args, indent)));
+        }
+        if let Some(block) = &call.block {
+            text.push(' ');
+            text.push_str(&self.block(block, indent));
+        }
+        (text, PRIMARY)
+    }
+
+    fn arguments(&self, args: &[Node], indent: usize) -> String {
+        args.iter().map(|arg| self.expression(arg, ASSIGNMENT, indent)).collect::<Vec<_>>().join(", ")
+    }
+
+    fn binary(&self, left: &Node, operator: &str, right: &Node, indent: usize) -> (String, u8) {
+        let precedence = binary_precedence(operator).expect("binary operator");
+        let (left_precedence, right_precedence) =
+            if operator == "**" { (precedence + 1, precedence) } else { (precedence, precedence + 1) };
+        let left = self.expression(left, left_precedence, indent);
+        let right = self.expression(right, right_precedence, indent);
+        (format!("{} {} {}", left, operator, right), precedence)
+    }
+
+    fn block(&self, block: &Block, indent: usize) -> String {
+        let params = match &block.params {
+            BlockParams::Explicit(params) if !params.is_empty() => format!(" |{}|", self.params(params, indent)),
+            _ => String::new(),
+        };
+        self.braces(&params, &block.body, indent)
+    }
+
+    // `{ |params| body }`, on several lines unless the body is a single line
+    fn braces(&self, params: &str, body: &[Node], indent: usize) -> String {
+        match body {
+            [] => format!("{{{} }}", params),
+            [node] => {
+                let statement = self.expression(node, ASSIGNMENT, indent);
+                if statement.contains('\n') {
+                    format!("{{{}{}\n{}}}", params, self.body(body, indent + 1), "  ".repeat(indent))
+                } else {
+                    format!("{{{} {} }}", params, statement)
+                }
+            }
+            body => format!("{{{}{}\n{}}}", params, self.body(body, indent + 1), "  ".repeat(indent)),
+        }
+    }
+
+    fn params(&self, params: &Params, indent: usize) -> String {
+        let mut text = params.params.iter().map(|param| self.param(param, indent)).collect::<Vec<_>>().join(", ");
+        if !params.locals.is_empty() {
+            text.push_str(&format!("; {}", params.locals.join(", ")));
+        }
+        text
+    }
+
+    fn param(&self, param: &Param, indent: usize) -> String {
+        let name = |name: &Option<String>| name.clone().unwrap_or_default();
+
+        match param {
+            Param::Required(name) => name.clone(),
+            Param::Destructure(params) => {
+                format!("({})", params.iter().map(|param| self.param(param, indent)).collect::<Vec<_>>().join(", "))
+            }
+            Param::Optional(name, default) => format!("{} = {}", name, self.expression(default, ASSIGNMENT, indent)),
+            Param::Rest(rest) => format!("*{}", name(rest)),
+            Param::RequiredKeyword(name) => format!("{}:", name),
+            Param::OptionalKeyword(name, default) => format!("{}: {}", name, self.expression(default, ASSIGNMENT, indent)),
+            Param::KeywordRest(rest) => format!("**{}", name(rest)),
+            Param::Block(block) => format!("&{}", name(block)),
+        }
+    }
+
+    fn def(&self, def: &Def, indent: usize) -> String {
+        let mut text = String::from("def ");
+        if let Some(singleton) = &def.singleton {
+            text.push_str(&format!("{}.", self.expression(singleton, PRIMARY, indent)));
+        }
+        text.push_str(&def.name);
+        if !def.params.params.is_empty() {
+            text.push_str(&format!("({})", self.params(&def.params, indent)));
+        }
+        format!("{}{}\n{}end", text, self.body(&def.body, indent + 1), "  ".repeat(indent))
+    }
+
+    fn target(&self, target: &Target, indent: usize) -> String {
+        match target {
+            Target::Local(name) => name.clone(),
+            Target::InstanceVariable(name) => format!("@{}", name),
+            Target::Index(receiver, args) => {
+                format!("{}[{}]", self.expression(receiver, PRIMARY, indent), self.list(args, indent))
+            }
+            Target::Attribute(receiver, name) => format!("{}.{}", self.expression(receiver, PRIMARY, indent), name),
+            Target::Splat(None) => "*".to_string(),
+            Target::Splat(Some(target)) => format!("*{}", self.target(target, indent)),
+            Target::Nested(targets) => {
+                format!("({})", targets.iter().map(|target| self.target(target, indent)).collect::<Vec<_>>().join(", "))
+            }
+        }
+    }
+
+    fn op_assign(&self, op_assign: &OpAssign, indent: usize) -> String {
+        let target = self.target(&op_assign.target, indent);
+        let value = self.expression(&op_assign.value, ASSIGNMENT, indent);
+        format!("{} {}= {}", target, op_assign.operator, value)
+    }
+
+    fn hash_element(&self, element: &HashElement, indent: usize) -> String {
+        match element {
+            HashElement::Pair(Node::Symbol(key), value) if is_label(key) => {
+                format!("{}: {}", key, self.expression(value, ASSIGNMENT, indent))
+            }
+            HashElement::Pair(key, value) => {
+                let key = self.expression(key, ASSIGNMENT, indent);
+                format!("{} => {}", key, self.expression(value, ASSIGNMENT, indent))
+            }
+            HashElement::DoubleSplat(value) => format!("**{}", self.expression(value, RANGE, indent)),
+        }
+    }
+
+    // `return`, `break` and `next`, whose value takes the rest of the line
+    fn jump(&self, keyword: &str, value: &Option<Box<Node>>, indent: usize) -> String {
+        match value {
+            Some(value) => format!("{} {}", keyword, self.expression(value, ASSIGNMENT, indent)),
+            None => keyword.to_string(),
+        }
+    }
+
+    // Everything after `if` up to the `end`, with a lone `If` in the else branch as `elsif`
+    fn if_branches(&self, node: &If, indent: usize) -> String {
+        let margin = "  ".repeat(indent);
+        let mut text = format!(
+            "{}{}",
+            self.expression(&node.condition, ASSIGNMENT, indent),
+            self.body(&node.then_body, indent + 1)
+        );
+
+        match &node.else_body[..] {
+            [] => {}
+            [Node::If(elsif)] => text.push_str(&format!("\n{}elsif {}", margin, self.if_branches(elsif, indent))),
+            else_body => text.push_str(&format!("\n{}else{}", margin, self.body(else_body, indent + 1))),
+        }
+        text
+    }
+
+    fn begin_clauses(&self, begin: &Begin, indent: usize) -> String {
+        let margin = "  ".repeat(indent);
+        let mut text = self.body(&begin.body, indent + 1);
+
+        for rescue in &begin.rescues {
+            text.push_str(&format!("\n{}rescue", margin));
+            if !rescue.classes.is_empty() {
+                text.push_str(&format!(" {}", self.list(&rescue.classes, indent)));
+            }
+            if let Some(variable) = &rescue.variable {
+                text.push_str(&format!(" => {}", variable));
+            }
+            text.push_str(&self.body(&rescue.body, indent + 1));
+        }
+        if let Some(else_body) = &begin.else_body {
+            text.push_str(&format!("\n{}else{}", margin, self.body(else_body, indent + 1)));
+        }
+        if let Some(ensure_body) = &begin.ensure_body {
+            text.push_str(&format!("\n{}ensure{}", margin, self.body(ensure_body, indent + 1)));
+        }
+        text
+    }
+
+    fn case(&self, case: &Case, indent: usize) -> String {
+        let margin = "  ".repeat(indent);
+        let mut text = String::from("case");
+        if let Some(subject) = &case.subject {
+            text.push_str(&format!(" {}", self.expression(subject, ASSIGNMENT, indent)));
+        }
+
+        for when in &case.whens {
+            let conditions = self.list(&when.conditions, indent);
+            text.push_str(&format!("\n{}when {}{}", margin, conditions, self.body(&when.body, indent + 1)));
+        }
+        if let Some(else_body) = &case.else_body {
+            text.push_str(&format!("\n{}else{}", margin, self.body(else_body, indent + 1)));
+        }
+        format!("{}\n{}end", text, margin)
+    }
+
+    fn case_in(&self, case: &CaseIn, indent: usize) -> String {
+        let margin = "  ".repeat(indent);
+        let mut text = format!("case {}", self.expression(&case.subject, ASSIGNMENT, indent));
+
+        for clause in &case.clauses {
+            text.push_str(&format!("\n{}in {}", margin, self.pattern(&clause.pattern, indent)));
+            if let Some(guard) = &clause.guard {
+                text.push_str(&format!(" if {}", self.expression(guard, ASSIGNMENT, indent)));
+            }
+            text.push_str(&self.body(&clause.body, indent + 1));
+        }
+        if let Some(else_body) = &case.else_body {
+            text.push_str(&format!("\n{}else{}", margin, self.body(else_body, indent + 1)));
+        }
+        format!("{}\n{}end", text, margin)
+    }
+
+    fn pattern(&self, pattern: &Pattern, indent: usize) -> String {
+        let rest = |name: &Option<String>| format!("*{}", name.clone().unwrap_or_default());
+        let join = |parts: Vec<String>| parts.join(", ");
+        let constant = |constant: &Option<Node>| match constant {
+            Some(constant) => self.expression(constant, PRIMARY, indent),
+            None => String::new(),
+        };
+
+        match pattern {
+            Pattern::Value(Node::Range(start, end, kind)) => {
+                let operand = |node: &Option<Box<Node>>| match node {
+                    Some(node) => self.expression(node, UNARY, indent),
+                    None => String::new(),
+                };
+                format!("{}{}{}", operand(start), range_operator(*kind), operand(end))
+            }
+            Pattern::Value(value) => self.expression(value, UNARY, indent),
+            Pattern::Pin(Node::LocalVariable(name)) => format!("^{}", name),
+            Pattern::Pin(value) => format!("^({})", self.expression(value, ASSIGNMENT, indent)),
+            Pattern::Bind(name) => name.clone(),
+            Pattern::Alternative(patterns) => {
+                patterns.iter().map(|pattern| self.pattern(pattern, indent)).collect::<Vec<_>>().join(" | ")
+            }
+            Pattern::Capture(pattern, name) => format!("{} => {}", self.pattern(pattern, indent), name),
+            Pattern::Array { constant: array_constant, pre, rest: splat, post } => {
+                let mut parts: Vec<String> = pre.iter().map(|pattern| self.pattern(pattern, indent)).collect();
+                parts.extend(splat.as_ref().map(rest));
+                parts.extend(post.iter().map(|pattern| self.pattern(pattern, indent)));
+                format!("{}[{}]", constant(array_constant), join(parts))
+            }
+            Pattern::Find { constant: find_constant, pre, middle, post } => {
+                let middle = middle.iter().map(|pattern| self.pattern(pattern, indent));
+                let parts = [rest(pre)].into_iter().chain(middle).chain([rest(post)]).collect();
+                format!("{}[{}]", constant(find_constant), join(parts))
+            }
+            Pattern::Hash { constant: hash_constant, pairs, rest: hash_rest } => {
+                let mut parts: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, pattern)| match pattern {
+                        Some(pattern) => format!("{}: {}", key, self.pattern(pattern, indent)),
+                        None => format!("{}:", key),
+                    })
+                    .collect();
+                parts.extend(hash_rest.as_ref().map(|hash_rest| match hash_rest {
+                    HashPatternRest::Named(name) => format!("**{}", name),
+                    HashPatternRest::Nil => "**nil".to_string(),
+                }));
+                match hash_constant {
+                    Some(_) => format!("{}({})", constant(hash_constant), join(parts)),
+                    None if parts.is_empty() => "{}".to_string(),
+                    None => format!("{{ {} }}", join(parts)),
+                }
+            }
+        }
+    }
+}
+
+// Mirrors the parser's table, shifted up by one to make room for ranges
+fn binary_precedence(operator: &str) -> Option<u8> {
+    let precedence = match operator {
+        "||" => 1,
+        "&&" => 2,
+        "==" | "===" | "!=" | "<=>" | "=~" | "!~" => 3,
+        "<" | "<=" | ">" | ">=" => 4,
+        "|" | "^" => 5,
+        "&" => 6,
+        "<<" | ">>" => 7,
+        "+" | "-" => 8,
+        "*" | "/" | "%" => 9,
+        "**" => 10,
+        _ => return None,
+    };
+    Some(precedence + RANGE)
+}
+
+fn range_operator(kind: RangeKind) -> &'static str {
+    match kind {
+        RangeKind::Inclusive => "..",
+        RangeKind::Exclusive => "...",
+    }
+}
+
+// Symbol keys that can be written as `key:`, which takes an identifier and at most a `?` or `!`
+fn is_label(name: &str) -> bool {
+    let identifier = name.strip_suffix(['?', '!']).unwrap_or(name);
+    !identifier.is_empty()
+        && !identifier.starts_with(|c: char| c.is_ascii_digit())
+        && identifier.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+// `name=` methods are called with assignment syntax
+fn is_setter(method: &str) -> bool {
+    method.len() > 1
+        && method.ends_with('=')
+        && method[..method.len() - 1].chars().all(|c| c.is_alphanumeric() || c == '_')
+}
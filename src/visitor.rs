@@ -0,0 +1,777 @@
+use std::rc::Rc;
+
+use crate::ast::{
+    Begin, Block, BlockParams, Call, Case, CaseIn, Class, Def, For, HashElement, If, InClause, Module, Node, OpAssign,
+    Param, Params, Pattern, Rescue, Target, When, While,
+};
+
+// Passes over the tree implement `Visitor` (or `VisitorMut` to change the tree in place)
+// and override the methods for the nodes they care about. Every method defaults to the
+// matching `walk_*` function, which visits the children, so an override that still
+// wants to reach nested nodes calls it itself.
+
+pub trait Visitor {
+    fn visit_node(&mut self, node: &Node) {
+        walk_node(self, node);
+    }
+
+    fn visit_call(&mut self, call: &Call) {
+        walk_call(self, call);
+    }
+
+    fn visit_block(&mut self, block: &Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_params(&mut self, params: &Params) {
+        walk_params(self, params);
+    }
+
+    fn visit_param(&mut self, param: &Param) {
+        walk_param(self, param);
+    }
+
+    fn visit_target(&mut self, target: &Target) {
+        walk_target(self, target);
+    }
+
+    fn visit_op_assign(&mut self, op_assign: &OpAssign) {
+        walk_op_assign(self, op_assign);
+    }
+
+    fn visit_hash_element(&mut self, element: &HashElement) {
+        walk_hash_element(self, element);
+    }
+
+    fn visit_def(&mut self, def: &Def) {
+        walk_def(self, def);
+    }
+
+    fn visit_class(&mut self, class: &Class) {
+        walk_class(self, class);
+    }
+
+    fn visit_module(&mut self, module: &Module) {
+        walk_module(self, module);
+    }
+
+    fn visit_if(&mut self, node: &If) {
+        walk_if(self, node);
+    }
+
+    fn visit_while(&mut self, node: &While) {
+        walk_while(self, node);
+    }
+
+    fn visit_for(&mut self, node: &For) {
+        walk_for(self, node);
+    }
+
+    fn visit_begin(&mut self, begin: &Begin) {
+        walk_begin(self, begin);
+    }
+
+    fn visit_rescue(&mut self, rescue: &Rescue) {
+        walk_rescue(self, rescue);
+    }
+
+    fn visit_case(&mut self, case: &Case) {
+        walk_case(self, case);
+    }
+
+    fn visit_when(&mut self, when: &When) {
+        walk_when(self, when);
+    }
+
+    fn visit_case_in(&mut self, case: &CaseIn) {
+        walk_case_in(self, case);
+    }
+
+    fn visit_in_clause(&mut self, clause: &InClause) {
+        walk_in_clause(self, clause);
+    }
+
+    fn visit_pattern(&mut self, pattern: &Pattern) {
+        walk_pattern(self, pattern);
+    }
+}
+
+pub fn walk_body<V: Visitor + ?Sized>(visitor: &mut V, body: &[Node]) {
+    for node in body {
+        visitor.visit_node(node);
+    }
+}
+
+pub fn walk_node<V: Visitor + ?Sized>(visitor: &mut V, node: &Node) {
+    match node {
+        Node::Integer(_)
+        | Node::Float(_)
+        | Node::Bignum(_)
+        | Node::Str(_)
+        | Node::Bytes(_)
+        | Node::Regexp(..)
+        | Node::Symbol(_)
+        | Node::Nil
+        | Node::True
+        | Node::False
+        | Node::SelfNode
+        | Node::LocalVariable(_)
+        | Node::Constant(_)
+        | Node::InstanceVariable(_)
+        | Node::Redo
+        | Node::Retry
+        | Node::Error => {}
+        Node::ScopedConstant(scope, _) => {
+            if let Some(scope) = scope {
+                visitor.visit_node(scope);
+            }
+        }
+        Node::LocalAssign(_, value)
+        | Node::InstanceVariableAssign(_, value)
+        | Node::Splat(value)
+        | Node::BlockPass(value)
+        | Node::Not(value) => visitor.visit_node(value),
+        Node::ConstantAssign(path, value) => {
+            visitor.visit_node(path);
+            visitor.visit_node(value);
+        }
+        Node::MultipleAssign(targets, value) => {
+            for target in targets {
+                visitor.visit_target(target);
+            }
+            visitor.visit_node(value);
+        }
+        Node::OpAssign(op_assign) => visitor.visit_op_assign(op_assign),
+        Node::Call(call) => visitor.visit_call(call),
+        Node::Array(elements) | Node::InterpolatedString(elements) => walk_body(visitor, elements),
+        Node::Hash(elements) | Node::KeywordHash(elements) => {
+            for element in elements {
+                visitor.visit_hash_element(element);
+            }
+        }
+        Node::Range(start, end, _) => {
+            if let Some(start) = start {
+                visitor.visit_node(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_node(end);
+            }
+        }
+        Node::Lambda(block) => visitor.visit_block(block),
+        Node::Def(def) => visitor.visit_def(def),
+        Node::Class(class) => visitor.visit_class(class),
+        Node::Module(module) => visitor.visit_module(module),
+        Node::SingletonClass(target, body) => {
+            visitor.visit_node(target);
+            walk_body(visitor, body);
+        }
+        Node::If(node) => visitor.visit_if(node),
+        Node::And(left, right) | Node::Or(left, right) => {
+            visitor.visit_node(left);
+            visitor.visit_node(right);
+        }
+        Node::Return(value) | Node::Break(value) | Node::Next(value) => {
+            if let Some(value) = value {
+                visitor.visit_node(value);
+            }
+        }
+        Node::While(node) => visitor.visit_while(node),
+        Node::For(node) => visitor.visit_for(node),
+        Node::Begin(begin) => visitor.visit_begin(begin),
+        Node::Case(case) => visitor.visit_case(case),
+        Node::CaseIn(case) => visitor.visit_case_in(case),
+        Node::Yield(args) => walk_body(visitor, args),
+        Node::Super(node) => {
+            if let Some(args) = &node.args {
+                walk_body(visitor, args);
+            }
+            if let Some(block) = &node.block {
+                visitor.visit_block(block);
+            }
+        }
+    }
+}
+
+pub fn walk_call<V: Visitor + ?Sized>(visitor: &mut V, call: &Call) {
+    if let Some(receiver) = &call.receiver {
+        visitor.visit_node(receiver);
+    }
+    walk_body(visitor, &call.args);
+    if let Some(block) = &call.block {
+        visitor.visit_block(block);
+    }
+}
+
+pub fn walk_block<V: Visitor + ?Sized>(visitor: &mut V, block: &Block) {
+    if let BlockParams::Explicit(params) = &block.params {
+        visitor.visit_params(params);
+    }
+    walk_body(visitor, &block.body);
+}
+
+pub fn walk_params<V: Visitor + ?Sized>(visitor: &mut V, params: &Params) {
+    for param in &params.params {
+        visitor.visit_param(param);
+    }
+}
+
+pub fn walk_param<V: Visitor + ?Sized>(visitor: &mut V, param: &Param) {
+    match param {
+        Param::Optional(_, default) | Param::OptionalKeyword(_, default) => visitor.visit_node(default),
+        Param::Destructure(params) => {
+            for param in params {
+                visitor.visit_param(param);
+            }
+        }
+        Param::Required(_)
+        | Param::Rest(_)
+        | Param::RequiredKeyword(_)
+        | Param::KeywordRest(_)
+        | Param::Block(_) => {}
+    }
+}
+
+pub fn walk_target<V: Visitor + ?Sized>(visitor: &mut V, target: &Target) {
+    match target {
+        Target::Local(_) | Target::InstanceVariable(_) | Target::Splat(None) => {}
+        Target::Index(receiver, args) => {
+            visitor.visit_node(receiver);
+            walk_body(visitor, args);
+        }
+        Target::Attribute(receiver, _) => visitor.visit_node(receiver),
+        Target::Splat(Some(target)) => visitor.visit_target(target),
+        Target::Nested(targets) => {
+            for target in targets {
+                visitor.visit_target(target);
+            }
+        }
+    }
+}
+
+pub fn walk_op_assign<V: Visitor + ?Sized>(visitor: &mut V, op_assign: &OpAssign) {
+    visitor.visit_target(&op_assign.target);
+    visitor.visit_node(&op_assign.value);
+}
+
+pub fn walk_hash_element<V: Visitor + ?Sized>(visitor: &mut V, element: &HashElement) {
+    match element {
+        HashElement::Pair(key, value) => {
+            visitor.visit_node(key);
+            visitor.visit_node(value);
+        }
+        HashElement::DoubleSplat(value) => visitor.visit_node(value),
+    }
+}
+
+pub fn walk_def<V: Visitor + ?Sized>(visitor: &mut V, def: &Def) {
+    if let Some(singleton) = &def.singleton {
+        visitor.visit_node(singleton);
+    }
+    visitor.visit_params(&def.params);
+    walk_body(visitor, &def.body);
+}
+
+pub fn walk_class<V: Visitor + ?Sized>(visitor: &mut V, class: &Class) {
+    visitor.visit_node(&class.path);
+    if let Some(superclass) = &class.superclass {
+        visitor.visit_node(superclass);
+    }
+    walk_body(visitor, &class.body);
+}
+
+pub fn walk_module<V: Visitor + ?Sized>(visitor: &mut V, module: &Module) {
+    visitor.visit_node(&module.path);
+    walk_body(visitor, &module.body);
+}
+
+pub fn walk_if<V: Visitor + ?Sized>(visitor: &mut V, node: &If) {
+    visitor.visit_node(&node.condition);
+    walk_body(visitor, &node.then_body);
+    walk_body(visitor, &node.else_body);
+}
+
+pub fn walk_while<V: Visitor + ?Sized>(visitor: &mut V, node: &While) {
+    visitor.visit_node(&node.condition);
+    walk_body(visitor, &node.body);
+}
+
+pub fn walk_for<V: Visitor + ?Sized>(visitor: &mut V, node: &For) {
+    visitor.visit_node(&node.iterable);
+    walk_body(visitor, &node.body);
+}
+
+pub fn walk_begin<V: Visitor + ?Sized>(visitor: &mut V, begin: &Begin) {
+    walk_body(visitor, &begin.body);
+    for rescue in &begin.rescues {
+        visitor.visit_rescue(rescue);
+    }
+    if let Some(else_body) = &begin.else_body {
+        walk_body(visitor, else_body);
+    }
+    if let Some(ensure_body) = &begin.ensure_body {
+        walk_body(visitor, ensure_body);
+    }
+}
+
+pub fn walk_rescue<V: Visitor + ?Sized>(visitor: &mut V, rescue: &Rescue) {
+    walk_body(visitor, &rescue.classes);
+    walk_body(visitor, &rescue.body);
+}
+
+pub fn walk_case<V: Visitor + ?Sized>(visitor: &mut V, case: &Case) {
+    if let Some(subject) = &case.subject {
+        visitor.visit_node(subject);
+    }
+    for when in &case.whens {
+        visitor.visit_when(when);
+    }
+    if let Some(else_body) = &case.else_body {
+        walk_body(visitor, else_body);
+    }
+}
+
+pub fn walk_when<V: Visitor + ?Sized>(visitor: &mut V, when: &When) {
+    walk_body(visitor, &when.conditions);
+    walk_body(visitor, &when.body);
+}
+
+pub fn walk_case_in<V: Visitor + ?Sized>(visitor: &mut V, case: &CaseIn) {
+    visitor.visit_node(&case.subject);
+    for clause in &case.clauses {
+        visitor.visit_in_clause(clause);
+    }
+    if let Some(else_body) = &case.else_body {
+        walk_body(visitor, else_body);
+    }
+}
+
+pub fn walk_in_clause<V: Visitor + ?Sized>(visitor: &mut V, clause: &InClause) {
+    visitor.visit_pattern(&clause.pattern);
+    if let Some(guard) = &clause.guard {
+        visitor.visit_node(guard);
+    }
+    walk_body(visitor, &clause.body);
+}
+
+pub fn walk_pattern<V: Visitor + ?Sized>(visitor: &mut V, pattern: &Pattern) {
+    match pattern {
+        Pattern::Value(node) | Pattern::Pin(node) => visitor.visit_node(node),
+        Pattern::Bind(_) => {}
+        Pattern::Alternative(patterns) => {
+            for pattern in patterns {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Capture(pattern, _) => visitor.visit_pattern(pattern),
+        Pattern::Array { constant, pre, post, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for pattern in pre.iter().chain(post) {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Find { constant, middle, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for pattern in middle {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Hash { constant, pairs, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for (_, pattern) in pairs {
+                if let Some(pattern) = pattern {
+                    visitor.visit_pattern(pattern);
+                }
+            }
+        }
+    }
+}
+
+// Same as `Visitor`, for passes that rewrite the tree. Replacing `*node` in `visit_node`
+// swaps a whole subtree.
+pub trait VisitorMut {
+    fn visit_node(&mut self, node: &mut Node) {
+        walk_node_mut(self, node);
+    }
+
+    fn visit_call(&mut self, call: &mut Call) {
+        walk_call_mut(self, call);
+    }
+
+    fn visit_block(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_params(&mut self, params: &mut Params) {
+        walk_params_mut(self, params);
+    }
+
+    fn visit_param(&mut self, param: &mut Param) {
+        walk_param_mut(self, param);
+    }
+
+    fn visit_target(&mut self, target: &mut Target) {
+        walk_target_mut(self, target);
+    }
+
+    fn visit_op_assign(&mut self, op_assign: &mut OpAssign) {
+        walk_op_assign_mut(self, op_assign);
+    }
+
+    fn visit_hash_element(&mut self, element: &mut HashElement) {
+        walk_hash_element_mut(self, element);
+    }
+
+    fn visit_def(&mut self, def: &mut Def) {
+        walk_def_mut(self, def);
+    }
+
+    fn visit_class(&mut self, class: &mut Class) {
+        walk_class_mut(self, class);
+    }
+
+    fn visit_module(&mut self, module: &mut Module) {
+        walk_module_mut(self, module);
+    }
+
+    fn visit_if(&mut self, node: &mut If) {
+        walk_if_mut(self, node);
+    }
+
+    fn visit_while(&mut self, node: &mut While) {
+        walk_while_mut(self, node);
+    }
+
+    fn visit_for(&mut self, node: &mut For) {
+        walk_for_mut(self, node);
+    }
+
+    fn visit_begin(&mut self, begin: &mut Begin) {
+        walk_begin_mut(self, begin);
+    }
+
+    fn visit_rescue(&mut self, rescue: &mut Rescue) {
+        walk_rescue_mut(self, rescue);
+    }
+
+    fn visit_case(&mut self, case: &mut Case) {
+        walk_case_mut(self, case);
+    }
+
+    fn visit_when(&mut self, when: &mut When) {
+        walk_when_mut(self, when);
+    }
+
+    fn visit_case_in(&mut self, case: &mut CaseIn) {
+        walk_case_in_mut(self, case);
+    }
+
+    fn visit_in_clause(&mut self, clause: &mut InClause) {
+        walk_in_clause_mut(self, clause);
+    }
+
+    fn visit_pattern(&mut self, pattern: &mut Pattern) {
+        walk_pattern_mut(self, pattern);
+    }
+}
+
+pub fn walk_body_mut<V: VisitorMut + ?Sized>(visitor: &mut V, body: &mut [Node]) {
+    for node in body {
+        visitor.visit_node(node);
+    }
+}
+
+pub fn walk_node_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut Node) {
+    match node {
+        Node::Integer(_)
+        | Node::Float(_)
+        | Node::Bignum(_)
+        | Node::Str(_)
+        | Node::Bytes(_)
+        | Node::Regexp(..)
+        | Node::Symbol(_)
+        | Node::Nil
+        | Node::True
+        | Node::False
+        | Node::SelfNode
+        | Node::LocalVariable(_)
+        | Node::Constant(_)
+        | Node::InstanceVariable(_)
+        | Node::Redo
+        | Node::Retry
+        | Node::Error => {}
+        Node::ScopedConstant(scope, _) => {
+            if let Some(scope) = scope {
+                visitor.visit_node(scope);
+            }
+        }
+        Node::LocalAssign(_, value)
+        | Node::InstanceVariableAssign(_, value)
+        | Node::Splat(value)
+        | Node::BlockPass(value)
+        | Node::Not(value) => visitor.visit_node(value),
+        Node::ConstantAssign(path, value) => {
+            visitor.visit_node(path);
+            visitor.visit_node(value);
+        }
+        Node::MultipleAssign(targets, value) => {
+            for target in targets {
+                visitor.visit_target(target);
+            }
+            visitor.visit_node(value);
+        }
+        Node::OpAssign(op_assign) => visitor.visit_op_assign(op_assign),
+        Node::Call(call) => visitor.visit_call(call),
+        Node::Array(elements) | Node::InterpolatedString(elements) => walk_body_mut(visitor, elements),
+        Node::Hash(elements) | Node::KeywordHash(elements) => {
+            for element in elements {
+                visitor.visit_hash_element(element);
+            }
+        }
+        Node::Range(start, end, _) => {
+            if let Some(start) = start {
+                visitor.visit_node(start);
+            }
+            if let Some(end) = end {
+                visitor.visit_node(end);
+            }
+        }
+        Node::Lambda(block) => visitor.visit_block(Rc::make_mut(block)),
+        Node::Def(def) => visitor.visit_def(def),
+        Node::Class(class) => visitor.visit_class(class),
+        Node::Module(module) => visitor.visit_module(module),
+        Node::SingletonClass(target, body) => {
+            visitor.visit_node(target);
+            walk_body_mut(visitor, body);
+        }
+        Node::If(node) => visitor.visit_if(node),
+        Node::And(left, right) | Node::Or(left, right) => {
+            visitor.visit_node(left);
+            visitor.visit_node(right);
+        }
+        Node::Return(value) | Node::Break(value) | Node::Next(value) => {
+            if let Some(value) = value {
+                visitor.visit_node(value);
+            }
+        }
+        Node::While(node) => visitor.visit_while(node),
+        Node::For(node) => visitor.visit_for(node),
+        Node::Begin(begin) => visitor.visit_begin(begin),
+        Node::Case(case) => visitor.visit_case(case),
+        Node::CaseIn(case) => visitor.visit_case_in(case),
+        Node::Yield(args) => walk_body_mut(visitor, args),
+        Node::Super(node) => {
+            if let Some(args) = &mut node.args {
+                walk_body_mut(visitor, args);
+            }
+            if let Some(block) = &mut node.block {
+                visitor.visit_block(Rc::make_mut(block));
+            }
+        }
+    }
+}
+
+pub fn walk_call_mut<V: VisitorMut + ?Sized>(visitor: &mut V, call: &mut Call) {
+    if let Some(receiver) = &mut call.receiver {
+        visitor.visit_node(receiver);
+    }
+    walk_body_mut(visitor, &mut call.args);
+    if let Some(block) = &mut call.block {
+        visitor.visit_block(Rc::make_mut(block));
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    if let BlockParams::Explicit(params) = &mut block.params {
+        visitor.visit_params(params);
+    }
+    walk_body_mut(visitor, &mut block.body);
+}
+
+pub fn walk_params_mut<V: VisitorMut + ?Sized>(visitor: &mut V, params: &mut Params) {
+    for param in &mut params.params {
+        visitor.visit_param(param);
+    }
+}
+
+pub fn walk_param_mut<V: VisitorMut + ?Sized>(visitor: &mut V, param: &mut Param) {
+    match param {
+        Param::Optional(_, default) | Param::OptionalKeyword(_, default) => visitor.visit_node(default),
+        Param::Destructure(params) => {
+            for param in params {
+                visitor.visit_param(param);
+            }
+        }
+        Param::Required(_)
+        | Param::Rest(_)
+        | Param::RequiredKeyword(_)
+        | Param::KeywordRest(_)
+        | Param::Block(_) => {}
+    }
+}
+
+pub fn walk_target_mut<V: VisitorMut + ?Sized>(visitor: &mut V, target: &mut Target) {
+    match target {
+        Target::Local(_) | Target::InstanceVariable(_) | Target::Splat(None) => {}
+        Target::Index(receiver, args) => {
+            visitor.visit_node(receiver);
+            walk_body_mut(visitor, args);
+        }
+        Target::Attribute(receiver, _) => visitor.visit_node(receiver),
+        Target::Splat(Some(target)) => visitor.visit_target(target),
+        Target::Nested(targets) => {
+            for target in targets {
+                visitor.visit_target(target);
+            }
+        }
+    }
+}
+
+pub fn walk_op_assign_mut<V: VisitorMut + ?Sized>(visitor: &mut V, op_assign: &mut OpAssign) {
+    visitor.visit_target(&mut op_assign.target);
+    visitor.visit_node(&mut op_assign.value);
+}
+
+pub fn walk_hash_element_mut<V: VisitorMut + ?Sized>(visitor: &mut V, element: &mut HashElement) {
+    match element {
+        HashElement::Pair(key, value) => {
+            visitor.visit_node(key);
+            visitor.visit_node(value);
+        }
+        HashElement::DoubleSplat(value) => visitor.visit_node(value),
+    }
+}
+
+pub fn walk_def_mut<V: VisitorMut + ?Sized>(visitor: &mut V, def: &mut Def) {
+    if let Some(singleton) = &mut def.singleton {
+        visitor.visit_node(singleton);
+    }
+    visitor.visit_params(&mut def.params);
+    walk_body_mut(visitor, &mut def.body);
+}
+
+pub fn walk_class_mut<V: VisitorMut + ?Sized>(visitor: &mut V, class: &mut Class) {
+    visitor.visit_node(&mut class.path);
+    if let Some(superclass) = &mut class.superclass {
+        visitor.visit_node(superclass);
+    }
+    walk_body_mut(visitor, &mut class.body);
+}
+
+pub fn walk_module_mut<V: VisitorMut + ?Sized>(visitor: &mut V, module: &mut Module) {
+    visitor.visit_node(&mut module.path);
+    walk_body_mut(visitor, &mut module.body);
+}
+
+pub fn walk_if_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut If) {
+    visitor.visit_node(&mut node.condition);
+    walk_body_mut(visitor, &mut node.then_body);
+    walk_body_mut(visitor, &mut node.else_body);
+}
+
+pub fn walk_while_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut While) {
+    visitor.visit_node(&mut node.condition);
+    walk_body_mut(visitor, &mut node.body);
+}
+
+pub fn walk_for_mut<V: VisitorMut + ?Sized>(visitor: &mut V, node: &mut For) {
+    visitor.visit_node(&mut node.iterable);
+    walk_body_mut(visitor, &mut node.body);
+}
+
+pub fn walk_begin_mut<V: VisitorMut + ?Sized>(visitor: &mut V, begin: &mut Begin) {
+    walk_body_mut(visitor, &mut begin.body);
+    for rescue in &mut begin.rescues {
+        visitor.visit_rescue(rescue);
+    }
+    if let Some(else_body) = &mut begin.else_body {
+        walk_body_mut(visitor, else_body);
+    }
+    if let Some(ensure_body) = &mut begin.ensure_body {
+        walk_body_mut(visitor, ensure_body);
+    }
+}
+
+pub fn walk_rescue_mut<V: VisitorMut + ?Sized>(visitor: &mut V, rescue: &mut Rescue) {
+    walk_body_mut(visitor, &mut rescue.classes);
+    walk_body_mut(visitor, &mut rescue.body);
+}
+
+pub fn walk_case_mut<V: VisitorMut + ?Sized>(visitor: &mut V, case: &mut Case) {
+    if let Some(subject) = &mut case.subject {
+        visitor.visit_node(subject);
+    }
+    for when in &mut case.whens {
+        visitor.visit_when(when);
+    }
+    if let Some(else_body) = &mut case.else_body {
+        walk_body_mut(visitor, else_body);
+    }
+}
+
+pub fn walk_when_mut<V: VisitorMut + ?Sized>(visitor: &mut V, when: &mut When) {
+    walk_body_mut(visitor, &mut when.conditions);
+    walk_body_mut(visitor, &mut when.body);
+}
+
+pub fn walk_case_in_mut<V: VisitorMut + ?Sized>(visitor: &mut V, case: &mut CaseIn) {
+    visitor.visit_node(&mut case.subject);
+    for clause in &mut case.clauses {
+        visitor.visit_in_clause(clause);
+    }
+    if let Some(else_body) = &mut case.else_body {
+        walk_body_mut(visitor, else_body);
+    }
+}
+
+pub fn walk_in_clause_mut<V: VisitorMut + ?Sized>(visitor: &mut V, clause: &mut InClause) {
+    visitor.visit_pattern(&mut clause.pattern);
+    if let Some(guard) = &mut clause.guard {
+        visitor.visit_node(guard);
+    }
+    walk_body_mut(visitor, &mut clause.body);
+}
+
+pub fn walk_pattern_mut<V: VisitorMut + ?Sized>(visitor: &mut V, pattern: &mut Pattern) {
+    match pattern {
+        Pattern::Value(node) | Pattern::Pin(node) => visitor.visit_node(node),
+        Pattern::Bind(_) => {}
+        Pattern::Alternative(patterns) => {
+            for pattern in patterns {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Capture(pattern, _) => visitor.visit_pattern(pattern),
+        Pattern::Array { constant, pre, post, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for pattern in pre.iter_mut().chain(post) {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Find { constant, middle, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for pattern in middle {
+                visitor.visit_pattern(pattern);
+            }
+        }
+        Pattern::Hash { constant, pairs, .. } => {
+            if let Some(constant) = constant {
+                visitor.visit_node(constant);
+            }
+            for (_, pattern) in pairs {
+                if let Some(pattern) = pattern {
+                    visitor.visit_pattern(pattern);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1161 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::gc::{self, Tracer};
+use crate::bytecode::{CallInfo, CatchKind, ClassKind, ClassScope, Instruction, Iseq, MatchKind, ParamLayout, ParamSlot, ParamTarget, ThrowKind};
+use crate::compiler;
+use crate::parser::Parser;
+use crate::runtime::{
+    EvalError, Executor, Method, MethodBody, Missing, ObjectId, ObjectKind, ProcBody, RHash, RString, Runtime, Unwind,
+    Value, Visibility,
+};
+
+// Deeper recursion raises `SystemStackError` instead of overflowing the native stack
+const MAX_DEPTH: usize = 10_000;
+
+// A method defined with `def`, compiled
+pub struct IseqMethod {
+    pub iseq: Rc<Iseq>,
+    // The modules the `def` is nested in, for constant lookups
+    nesting: Rc<Vec<ObjectId>>,
+}
+
+// A compiled block together with the variables and `self` of the place it was written
+pub struct IseqClosure {
+    pub iseq: Rc<Iseq>,
+    env: Rc<Env>,
+    context: Rc<Context>,
+}
+
+// What method bodies, class bodies and the top level run with. Blocks share the
+// context they were created in, except lambdas which get their own `home`.
+#[derive(Clone)]
+struct Context {
+    self_value: Value,
+    // Where `def` adds methods
+    definee: ObjectId,
+    // The lexically enclosing modules, innermost last
+    nesting: Rc<Vec<ObjectId>>,
+    block: Option<Value>,
+    // The running method, which `super` continues the lookup from
+    method: Option<Rc<Method>>,
+    // Identifies the activation that `return` leaves
+    home: usize,
+    // What `def` makes methods, changed by `private` and `public` without arguments
+    visibility: Cell<Visibility>,
+}
+
+// The local variables of a running sequence, in the slots the compiler assigned.
+// Blocks reach the variables of the sequences they're nested in through `parent`.
+struct Env {
+    slots: RefCell<Vec<Value>>,
+    parent: Option<Rc<Env>>,
+}
+
+struct Frame {
+    iseq: Rc<Iseq>,
+    env: Rc<Env>,
+    context: Rc<Context>,
+    // Shared with the VM's list of running frames, which collections trace
+    values: Rc<FrameValues>,
+    pc: usize,
+    // Which keyword parameters were left out, in the order they're declared
+    missing_keywords: Vec<bool>,
+}
+
+// The values a frame holds besides its variables
+#[derive(Default)]
+struct FrameValues {
+    stack: RefCell<Vec<Value>>,
+    // The unwinds interrupted by the ensure bodies running, with the address of each body
+    pending: RefCell<Vec<(usize, Unwind)>>,
+}
+
+// Runs programs compiled to bytecode, a frame per method, block or class body
+pub struct Vm {
+    runtime: Runtime,
+    // Innermost last, so native methods can find the block of their caller
+    contexts: Vec<Rc<Context>>,
+    // The variables and values of the running frames, innermost last
+    frames: Vec<(Rc<Env>, Rc<FrameValues>)>,
+    // Homes of the running methods and lambdas, which are the only ones `return` can leave
+    homes: Vec<usize>,
+    next_home: usize,
+    cache_stats: CacheStats,
+}
+
+// How often sends found their method in the call site's inline cache
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl IseqClosure {
+    pub fn arity(&self) -> i32 {
+        self.iseq.params.arity
+    }
+
+    pub fn trace(&self, tracer: &mut Tracer) {
+        self.env.trace(tracer);
+        self.context.trace(tracer);
+    }
+}
+
+impl Context {
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark(self.self_value);
+        tracer.mark_id(self.definee);
+        tracer.mark_all(self.nesting.iter().map(|&module| Value::object(module)));
+        tracer.mark_all(self.block);
+        if let Some(method) = &self.method {
+            gc::trace_method_body(&method.body, tracer);
+        }
+    }
+}
+
+impl Env {
+    fn new(iseq: &Iseq, parent: Option<Rc<Env>>) -> Rc<Env> {
+        Rc::new(Env { slots: RefCell::new(vec![Value::NIL; iseq.locals.len()]), parent })
+    }
+
+    fn set(&self, index: usize, value: Value) {
+        self.slots.borrow_mut()[index] = value;
+    }
+
+    fn trace(&self, tracer: &mut Tracer) {
+        tracer.mark_all(self.slots.borrow().iter().copied());
+        if let Some(parent) = &self.parent {
+            parent.trace(tracer);
+        }
+    }
+}
+
+impl Frame {
+    fn new(iseq: Rc<Iseq>, env: Rc<Env>, context: Rc<Context>, pc: usize) -> Self {
+        let values = FrameValues { stack: RefCell::new(Vec::with_capacity(iseq.max_stack)), pending: RefCell::default() };
+        Frame { iseq, env, context, values: Rc::new(values), pc, missing_keywords: Vec::new() }
+    }
+
+    fn push(&mut self, value: Value) {
+        self.values.stack.borrow_mut().push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.values.stack.borrow_mut().pop().expect("the compiler keeps the stack balanced")
+    }
+
+    fn peek(&self) -> Value {
+        *self.values.stack.borrow().last().expect("the compiler keeps the stack balanced")
+    }
+
+    // The value `n` below the top
+    fn peek_at(&self, n: usize) -> Value {
+        let stack = self.values.stack.borrow();
+        stack[stack.len() - 1 - n]
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        let mut stack = self.values.stack.borrow_mut();
+        let start = stack.len() - count;
+        stack.split_off(start)
+    }
+
+    fn truncate(&mut self, depth: usize) {
+        self.values.stack.borrow_mut().truncate(depth);
+    }
+
+    fn depth(&self) -> usize {
+        self.values.stack.borrow().len()
+    }
+
+    fn env_at(&self, level: usize) -> &Rc<Env> {
+        let mut env = &self.env;
+        for _ in 0..level {
+            env = env.parent.as_ref().expect("the compiler only refers to enclosing scopes");
+        }
+        env
+    }
+
+    // Looks for a catch entry around the instruction that failed and jumps to its handler
+    fn catch(&mut self, unwind: Unwind) -> bool {
+        let address = self.pc - 1;
+        let iseq = self.iseq.clone();
+        for entry in &iseq.catch_table {
+            if address < entry.start || address >= entry.end {
+                continue;
+            }
+            let value = match (entry.kind, unwind) {
+                (CatchKind::Rescue, Unwind::Raise(exception)) => Some(exception),
+                (CatchKind::Ensure, unwind) => {
+                    self.values.pending.borrow_mut().push((entry.target, unwind));
+                    None
+                }
+                (CatchKind::Break, Unwind::Break(value, None)) => Some(value),
+                (CatchKind::Next, Unwind::Next(_)) | (CatchKind::Redo, Unwind::Redo) | (CatchKind::Retry, Unwind::Retry) => None,
+                _ => continue,
+            };
+            self.truncate(entry.depth);
+            if let Some(value) = value {
+                self.push(value);
+            }
+            self.pc = entry.target;
+            return true;
+        }
+        false
+    }
+}
+
+impl CacheStats {
+    // The share of sends that were hits, from 0 to 1
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            sends => self.hits as f64 / sends as f64,
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Vm {
+            runtime: Runtime::new(),
+            contexts: Vec::new(),
+            frames: Vec::new(),
+            homes: Vec::new(),
+            next_home: 0,
+            cache_stats: CacheStats::default(),
+        }
+    }
+
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache_stats
+    }
+
+    // Parses, compiles and runs a program, returning the value of its last statement
+    pub fn eval(&mut self, source: &str) -> Result<Value, EvalError> {
+        let iseq = compile(source)?;
+        self.run(&iseq)
+    }
+
+    pub fn run(&mut self, iseq: &Rc<Iseq>) -> Result<Value, EvalError> {
+        let context = Rc::new(Context {
+            self_value: self.runtime.main,
+            definee: self.runtime.classes.object,
+            nesting: Rc::default(),
+            block: None,
+            method: None,
+            home: self.new_home(),
+            visibility: Cell::new(Visibility::Public),
+        });
+        let frame = Frame::new(iseq.clone(), Env::new(iseq, None), context.clone(), 0);
+        let result = self.in_context(&context, Some("<main>"), true, |vm| vm.execute(frame));
+
+        match result {
+            Ok(value) => Ok(value),
+            Err(Unwind::Return(value, home)) if home == context.home => Ok(value),
+            Err(unwind) => {
+                let exception = match self.runtime.jump_error(unwind) {
+                    Unwind::Raise(exception) => exception,
+                    _ => unreachable!("jump errors are exceptions"),
+                };
+                Err(self.runtime.uncaught(exception))
+            }
+        }
+    }
+
+    fn new_home(&mut self) -> usize {
+        self.next_home += 1;
+        self.next_home
+    }
+
+    // Runs with `context` as the innermost context. A `frame` shows in backtraces,
+    // and an `activation` (methods, lambdas) is something `return` can leave.
+    fn in_context(
+        &mut self,
+        context: &Rc<Context>,
+        frame: Option<&str>,
+        activation: bool,
+        run: impl FnOnce(&mut Self) -> Result<Value, Unwind>,
+    ) -> Result<Value, Unwind> {
+        if self.contexts.len() >= MAX_DEPTH {
+            return Err(self.runtime.error("SystemStackError", "stack level too deep"));
+        }
+
+        self.contexts.push(context.clone());
+        if let Some(frame) = frame {
+            self.runtime.push_frame(frame);
+        }
+        if activation {
+            self.homes.push(context.home);
+        }
+        let mark = self.runtime.heap.root_mark();
+        let result = run(self);
+        self.runtime.heap.unroot_to(mark);
+        if activation {
+            self.homes.pop();
+        }
+        if frame.is_some() {
+            self.runtime.frames.pop();
+        }
+        self.contexts.pop();
+        result
+    }
+
+    // Runs the frame until it leaves, handling the unwinds its catch table covers
+    fn execute(&mut self, mut frame: Frame) -> Result<Value, Unwind> {
+        self.frames.push((frame.env.clone(), frame.values.clone()));
+        let result = loop {
+            match self.dispatch(&mut frame) {
+                Ok(value) => break Ok(value),
+                Err(unwind) if frame.catch(unwind) => {}
+                Err(unwind) => break Err(unwind),
+            }
+        };
+        self.frames.pop();
+        result
+    }
+
+    // What instructions allocate is rooted until the next one, and collections happen in between
+    fn dispatch(&mut self, frame: &mut Frame) -> Result<Value, Unwind> {
+        let iseq = frame.iseq.clone();
+        let mark = self.runtime.heap.root_mark();
+        loop {
+            self.runtime.heap.unroot_to(mark);
+            if self.runtime.heap.pending() {
+                self.collect_garbage();
+            }
+            let instruction = iseq.instructions[frame.pc];
+            frame.pc += 1;
+            match instruction {
+                Instruction::PutNil => frame.push(Value::NIL),
+                Instruction::PutTrue => frame.push(Value::TRUE),
+                Instruction::PutFalse => frame.push(Value::FALSE),
+                Instruction::PutSelf => frame.push(frame.context.self_value),
+                Instruction::PutInteger(value) => {
+                    let value = self.runtime.int(value);
+                    frame.push(value)
+                }
+                Instruction::PutFloat(value) => {
+                    let value = self.runtime.float(value);
+                    frame.push(value)
+                }
+                Instruction::PutBignum(index) => {
+                    let integer = self.runtime.integer(iseq.names[index].parse().expect("bignum literals are digits"));
+                    frame.push(integer);
+                }
+                Instruction::PutString(index) => {
+                    let string = self.runtime.string_from(RString::utf8(iseq.strings[index].clone()));
+                    frame.push(string);
+                }
+                Instruction::PutRegexp(source, options) => {
+                    let regexp = self.runtime.new_regexp(&iseq.names[source], &iseq.names[options])?;
+                    frame.push(regexp);
+                }
+                Instruction::PutSymbol(index) => {
+                    let symbol = self.runtime.symbol(&iseq.names[index]);
+                    frame.push(symbol);
+                }
+                Instruction::Pop => {
+                    frame.pop();
+                }
+                Instruction::Dup => frame.push(frame.peek()),
+                Instruction::TopN(n) => frame.push(frame.peek_at(n)),
+                Instruction::Adjust(n) => {
+                    let top = frame.pop();
+                    frame.truncate(frame.depth() - n);
+                    frame.push(top);
+                }
+                Instruction::GetLocal { index, level } => {
+                    let value = frame.env_at(level).slots.borrow()[index];
+                    frame.push(value);
+                }
+                Instruction::SetLocal { index, level } => {
+                    let value = frame.pop();
+                    frame.env_at(level).set(index, value);
+                }
+                Instruction::GetInstanceVariable(index) => {
+                    let value = self.runtime.ivar(frame.context.self_value, &iseq.names[index]);
+                    frame.push(value);
+                }
+                Instruction::SetInstanceVariable(index) => {
+                    let value = frame.pop();
+                    builtins::set_ivar(self, frame.context.self_value, &iseq.names[index], value)?;
+                }
+                Instruction::GetConstant(index) => {
+                    let value = self.runtime.lookup_constant(&frame.context.nesting, &iseq.names[index])?;
+                    frame.push(value);
+                }
+                Instruction::GetScopedConstant(index) => {
+                    let scope = frame.pop();
+                    let module = builtins::expect_module(self, scope)?;
+                    let value = self.runtime.scoped_constant(module, &iseq.names[index])?;
+                    frame.push(value);
+                }
+                Instruction::GetTopConstant(index) => {
+                    let value = self.runtime.scoped_constant(self.runtime.classes.object, &iseq.names[index])?;
+                    frame.push(value);
+                }
+                Instruction::SetConstant { name, scope } => {
+                    let value = frame.pop();
+                    let module = self.definition_scope(frame, scope)?;
+                    self.runtime.set_constant(module, &iseq.names[name], value);
+                    frame.push(value);
+                }
+                Instruction::ObjToString => {
+                    let value = frame.pop();
+                    let string = builtins::object_to_string(self, value)?;
+                    frame.push(string);
+                }
+                Instruction::ConcatStrings(count) => {
+                    let parts = frame.pop_many(count);
+                    let string = builtins::concat_strings(self, &parts)?;
+                    frame.push(string);
+                }
+                Instruction::NewArray(count) => {
+                    let elements = frame.pop_many(count);
+                    let array = self.runtime.array(elements);
+                    frame.push(array);
+                }
+                Instruction::SplatArray => {
+                    let value = frame.pop();
+                    let elements = builtins::splat(self, value)?;
+                    let array = self.runtime.array(elements);
+                    frame.push(array);
+                }
+                Instruction::ConcatArray => {
+                    let tail = frame.pop();
+                    let tail = self.runtime.array_value(tail).cloned().unwrap_or_default();
+                    let id = frame.peek().object_id().expect("arrays are objects");
+                    if let ObjectKind::Array(elements) = &mut self.runtime.object_mut(id).kind {
+                        elements.extend(tail);
+                    }
+                }
+                Instruction::ToArray => {
+                    let value = frame.pop();
+                    let array = match self.runtime.array_value(value) {
+                        Some(_) => value,
+                        None => self.runtime.array(vec![value]),
+                    };
+                    frame.push(array);
+                }
+                Instruction::ExpandArray { before, after, splat } => {
+                    let value = frame.pop();
+                    self.expand_array(frame, value, before, after, splat);
+                }
+                Instruction::NewHash(pairs) => {
+                    let values = frame.pop_many(pairs * 2);
+                    let mut hash = RHash::default();
+                    for pair in values.chunks(2) {
+                        let (key, value) = (pair[0], pair[1]);
+                        // String keys are frozen, like `Hash#[]=` does
+                        if let (Some(id), Some(_)) = (key.object_id(), self.runtime.rstring_value(key)) {
+                            self.runtime.object_mut(id).frozen = true;
+                        }
+                        hash.insert(self.runtime.hash_key(key), key, value);
+                    }
+                    let hash = self.runtime.hash(hash);
+                    frame.push(hash);
+                }
+                Instruction::MergeHash => {
+                    let other = frame.pop();
+                    let Some(entries) = self.runtime.hash_value(other).map(|hash| hash.entries.clone()) else {
+                        let class = self.runtime.class_name(self.runtime.real_class_of(other));
+                        return Err(self.runtime.error("TypeError", &format!("no implicit conversion of {} into Hash", class)));
+                    };
+                    let keys: Vec<_> = entries.iter().map(|&(key, _)| self.runtime.hash_key(key)).collect();
+                    let id = frame.peek().object_id().expect("hashes are objects");
+                    if let ObjectKind::Hash(hash) = &mut self.runtime.object_mut(id).kind {
+                        for (hash_key, (key, value)) in keys.into_iter().zip(entries) {
+                            hash.insert(hash_key, key, value);
+                        }
+                    }
+                }
+                Instruction::NewRange { exclusive } => {
+                    let end = frame.pop();
+                    let start = frame.pop();
+                    let range = self.runtime.range(start, end, exclusive);
+                    frame.push(range);
+                }
+                Instruction::Not => {
+                    let value = frame.pop();
+                    frame.push(Value::from_bool(!value.truthy()));
+                }
+                Instruction::Send(index) | Instruction::InvokeSuper(index) => {
+                    let info = &iseq.call_infos[index];
+                    self.runtime.set_line(info.line);
+                    let block_arg = match info.block_arg {
+                        true => {
+                            let value = frame.pop();
+                            builtins::block_argument(self, value)?
+                        }
+                        false => None,
+                    };
+                    let mut args = match info.splat {
+                        true => {
+                            let array = frame.pop();
+                            self.runtime.array_value(array).cloned().unwrap_or_default()
+                        }
+                        false => frame.pop_many(info.argc),
+                    };
+                    if info.keywords {
+                        self.runtime.pass_keywords(&mut args);
+                    }
+                    let receiver = frame.pop();
+                    let literal_block = info.block.map(|child| {
+                        self.closure(&iseq.children[child], &frame.env, &frame.context, false)
+                    });
+                    // Off the stack, and not bound to parameters yet
+                    self.runtime.heap.root(receiver);
+                    for &arg in &args {
+                        self.runtime.heap.root(arg);
+                    }
+                    self.runtime.heap.root(block_arg.unwrap_or(Value::NIL));
+
+                    let block = literal_block.or(block_arg);
+                    let result = match instruction {
+                        Instruction::Send(_) => self.call_site(receiver, info, &args, block),
+                        _ => self.call_super(receiver, info, &frame.context, &args, block),
+                    };
+                    let value = match (result, literal_block) {
+                        // `break` in the block leaves the call it was given to
+                        (Err(Unwind::Break(value, Some(tag))), Some(block)) if block.object_id() == Some(tag) => value,
+                        (Ok(_), _) if builtins::is_assignment_method(&info.method) => {
+                            args.last().copied().unwrap_or(Value::NIL)
+                        }
+                        (result, _) => result?,
+                    };
+                    frame.push(value);
+                }
+                Instruction::InvokeBlock { argc, splat, keywords } => {
+                    let mut args = match splat {
+                        true => {
+                            let array = frame.pop();
+                            self.runtime.array_value(array).cloned().unwrap_or_default()
+                        }
+                        false => frame.pop_many(argc),
+                    };
+                    if keywords {
+                        self.runtime.pass_keywords(&mut args);
+                    }
+                    let Some(block) = frame.context.block else {
+                        return Err(self.runtime.error("LocalJumpError", "no block given (yield)"));
+                    };
+                    for &arg in &args {
+                        self.runtime.heap.root(arg);
+                    }
+                    let value = self.call_block(block, &args, None)?;
+                    frame.push(value);
+                }
+                Instruction::Lambda(index) => {
+                    let lambda = self.closure(&iseq.children[index], &frame.env, &frame.context, true);
+                    frame.push(lambda);
+                }
+                Instruction::DefineMethod { name, body } => {
+                    let owner = frame.context.definee;
+                    let value = self.define_method(owner, &iseq, name, body, &frame.context);
+                    if frame.context.visibility.get() == Visibility::Private {
+                        self.runtime.set_visibility(owner, &iseq.names[name], Visibility::Private)?;
+                    }
+                    frame.push(value);
+                }
+                Instruction::DefineSingletonMethod { name, body } => {
+                    let target = frame.pop();
+                    let owner = self.runtime.singleton_class(target)?;
+                    let value = self.define_method(owner, &iseq, name, body, &frame.context);
+                    frame.push(value);
+                }
+                Instruction::DefineClass { kind, name, body, scope } => {
+                    let superclass = match kind {
+                        ClassKind::Class { superclass: true } => Some(frame.pop()),
+                        _ => None,
+                    };
+                    let namespace = self.definition_scope(frame, scope)?;
+                    let name = &iseq.names[name];
+                    let (module, frame_name) = match kind {
+                        ClassKind::Class { .. } => (self.runtime.open_class(namespace, name, superclass)?, format!("<class:{}>", name)),
+                        ClassKind::Module => (self.runtime.open_module(namespace, name)?, format!("<module:{}>", name)),
+                    };
+                    let value = self.run_module_body(module, &iseq.children[body], &frame_name, &frame.context)?;
+                    frame.push(value);
+                }
+                Instruction::DefineSingletonClass(body) => {
+                    let target = frame.pop();
+                    let singleton = self.runtime.singleton_class(target)?;
+                    let value = self.run_module_body(singleton, &iseq.children[body], "singleton class", &frame.context)?;
+                    frame.push(value);
+                }
+                Instruction::Jump(target) => frame.pc = target,
+                Instruction::BranchIf(target) => {
+                    if frame.pop().truthy() {
+                        frame.pc = target;
+                    }
+                }
+                Instruction::BranchUnless(target) => {
+                    if !frame.pop().truthy() {
+                        frame.pc = target;
+                    }
+                }
+                Instruction::Leave => return Ok(frame.pop()),
+                Instruction::Throw(kind) => {
+                    let value = frame.pop();
+                    return Err(match kind {
+                        ThrowKind::Return => {
+                            // A proc whose method already returned has nowhere to return to
+                            if !self.homes.contains(&frame.context.home) {
+                                return Err(self.runtime.error("LocalJumpError", "unexpected return"));
+                            }
+                            Unwind::Return(value, frame.context.home)
+                        }
+                        ThrowKind::Break => Unwind::Break(value, None),
+                        ThrowKind::Next => Unwind::Next(value),
+                        ThrowKind::Redo => Unwind::Redo,
+                        ThrowKind::Retry => Unwind::Retry,
+                        ThrowKind::Raise => Unwind::Raise(value),
+                    });
+                }
+                Instruction::EndEnsure(handler) => {
+                    let mut pending = frame.values.pending.borrow_mut();
+                    let index = pending
+                        .iter()
+                        .rposition(|&(body, _)| body == handler)
+                        .expect("ensure handlers are only entered through the catch table");
+                    let (_, unwind) = pending[index];
+                    // Anything after it was left behind by ensure bodies that didn't finish
+                    pending.truncate(index);
+                    return Err(unwind);
+                }
+                Instruction::GetErrinfo => frame.push(self.runtime.errinfo),
+                Instruction::SetErrinfo => self.runtime.errinfo = frame.pop(),
+                Instruction::CheckMatch(kind) => {
+                    let pattern = frame.pop();
+                    let matched = match kind {
+                        MatchKind::Case => {
+                            let target = frame.pop();
+                            self.send(pattern, "===", &[target], None)?.truthy()
+                        }
+                        MatchKind::Splat => {
+                            let target = frame.pop();
+                            let mut matched = false;
+                            for candidate in builtins::splat(self, pattern)? {
+                                if self.send(candidate, "===", &[target], None)?.truthy() {
+                                    matched = true;
+                                    break;
+                                }
+                            }
+                            matched
+                        }
+                        MatchKind::SplatTruthy => builtins::splat(self, pattern)?.iter().any(|value| value.truthy()),
+                    };
+                    frame.push(Value::from_bool(matched));
+                }
+                Instruction::CheckKeyword(index) => {
+                    let given = !frame.missing_keywords.get(index).copied().unwrap_or(true);
+                    frame.push(Value::from_bool(given));
+                }
+                Instruction::CheckLength { length, rest } => {
+                    let value = frame.pop();
+                    let fits = self.runtime.array_value(value).is_some_and(|elements| match rest {
+                        true => elements.len() >= length,
+                        false => elements.len() == length,
+                    });
+                    frame.push(Value::from_bool(fits));
+                }
+                Instruction::Deconstruct => {
+                    let value = frame.pop();
+                    let elements = match builtins::deconstruct(self, value)? {
+                        Some(elements) => self.runtime.array(elements),
+                        None => Value::NIL,
+                    };
+                    frame.push(elements);
+                }
+                Instruction::DeconstructKeys => {
+                    let value = frame.pop();
+                    let copy = match self.runtime.hash_value(value).cloned() {
+                        Some(hash) => self.runtime.hash(hash),
+                        None => Value::NIL,
+                    };
+                    frame.push(copy);
+                }
+                Instruction::DeleteKey(index) => {
+                    let key = self.runtime.symbol(&iseq.names[index]);
+                    let key = self.runtime.hash_key(key);
+                    let id = frame.peek().object_id().expect("hashes are objects");
+                    let removed = match &mut self.runtime.object_mut(id).kind {
+                        ObjectKind::Hash(hash) => hash.remove(&key),
+                        _ => None,
+                    };
+                    frame.push(removed.unwrap_or(Value::NIL));
+                    frame.push(Value::from_bool(removed.is_some()));
+                }
+            }
+        }
+    }
+
+    // Pushes the targets' values with the first target's on top. The splat gets what's
+    // left between the values before it and the ones after it.
+    fn expand_array(&mut self, frame: &mut Frame, value: Value, before: usize, after: usize, splat: bool) {
+        let elements = match self.runtime.array_value(value) {
+            Some(elements) => elements.clone(),
+            None => vec![value],
+        };
+        let value_at = |index: usize| elements.get(index).copied().unwrap_or(Value::NIL);
+        if splat {
+            let middle_end = elements.len().saturating_sub(after).max(before);
+            for index in (0..after).rev() {
+                frame.push(value_at(middle_end + index));
+            }
+            let middle = elements.get(before..middle_end).unwrap_or_default().to_vec();
+            let middle = self.runtime.array(middle);
+            frame.push(middle);
+        }
+        for index in (0..before).rev() {
+            frame.push(value_at(index));
+        }
+    }
+
+    // The module a `class`, `module` or constant assignment defines its constant in, popping it for `ClassScope::Explicit`
+    fn definition_scope(&mut self, frame: &mut Frame, scope: ClassScope) -> Result<ObjectId, Unwind> {
+        match scope {
+            ClassScope::Lexical => Ok(frame.context.nesting.last().copied().unwrap_or(self.runtime.classes.object)),
+            ClassScope::Explicit => {
+                let scope = frame.pop();
+                builtins::expect_module(self, scope)
+            }
+            ClassScope::Top => Ok(self.runtime.classes.object),
+        }
+    }
+
+    fn define_method(&mut self, owner: ObjectId, iseq: &Iseq, name: usize, body: usize, context: &Context) -> Value {
+        let method = IseqMethod { iseq: iseq.children[body].clone(), nesting: context.nesting.clone() };
+        let name = &iseq.names[name];
+        self.runtime.add_method(owner, name, MethodBody::Iseq(Rc::new(method)));
+        self.runtime.symbol(name)
+    }
+
+    // Class, module and singleton class bodies run with the module as `self`, in a new scope
+    fn run_module_body(&mut self, module: ObjectId, iseq: &Rc<Iseq>, frame: &str, context: &Context) -> Result<Value, Unwind> {
+        let nesting = context.nesting.iter().copied().chain([module]).collect();
+        let context = Rc::new(Context {
+            self_value: Value::object(module),
+            definee: module,
+            nesting: Rc::new(nesting),
+            block: None,
+            method: None,
+            home: self.new_home(),
+            visibility: Cell::new(Visibility::Public),
+        });
+        let body = Frame::new(iseq.clone(), Env::new(iseq, None), context.clone(), 0);
+        self.in_context(&context, Some(frame), false, |vm| vm.execute(body))
+    }
+
+    // Calls
+
+    // Sends from a call site, looking the method up only when the receiver's class
+    // isn't in the site's cache or methods changed since it was filled
+    fn call_site(&mut self, receiver: Value, info: &CallInfo, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        let class = self.runtime.class_of(receiver);
+        let serial = self.runtime.method_serial();
+        let method = match info.cache.lookup(class, serial) {
+            Some(method) => {
+                self.cache_stats.hits += 1;
+                method
+            }
+            None => {
+                self.cache_stats.misses += 1;
+                let Some(method) = self.runtime.find_method(class, &info.method) else {
+                    let missing = if info.variable_like { Missing::VariableLike } else { Missing::Undefined };
+                    return builtins::method_missing(self, receiver, &info.method, args, block, missing);
+                };
+                info.cache.fill(class, serial, method.clone());
+                method
+            }
+        };
+        // Private methods are only called without a receiver, or with `self`
+        if method.visibility == Visibility::Private && !info.fcall {
+            return builtins::method_missing(self, receiver, &info.method, args, block, Missing::Private);
+        }
+        self.invoke(receiver, &method, args, block)
+    }
+
+    // `super` from the context's method, passing the method's block unless it was given one
+    fn call_super(&mut self, receiver: Value, info: &CallInfo, context: &Context, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        let Some(method) = &context.method else {
+            return Err(self.runtime.no_super_method(receiver, None));
+        };
+        if info.zsuper && matches!(method.body, MethodBody::Proc(_)) {
+            return Err(self.runtime.implicit_super_in_define_method());
+        }
+        match self.runtime.find_super_method(self.runtime.class_of(receiver), method) {
+            Some(target) => self.invoke(receiver, &target, args, block.or(context.block)),
+            None => Err(self.runtime.no_super_method(receiver, Some(method))),
+        }
+    }
+
+    // Sends from the VM itself, which can call private methods
+    fn call_method(&mut self, receiver: Value, name: &str, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        match self.runtime.find_method(self.runtime.class_of(receiver), name) {
+            Some(method) => self.invoke(receiver, &method, args, block),
+            None => builtins::method_missing(self, receiver, name, args, block, Missing::Undefined),
+        }
+    }
+
+    fn invoke(&mut self, receiver: Value, method: &Rc<Method>, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        match &method.body {
+            MethodBody::Native(function, arity) => {
+                self.runtime.check_arity(*arity, args.len())?;
+                builtins::call_native(self, *function, receiver, args, block)
+            }
+            MethodBody::Iseq(compiled) => {
+                let context = Rc::new(Context {
+                    self_value: receiver,
+                    definee: method.owner,
+                    nesting: compiled.nesting.clone(),
+                    block,
+                    method: Some(method.clone()),
+                    home: self.new_home(),
+                    visibility: Cell::new(Visibility::Public),
+                });
+                let iseq = &compiled.iseq;
+                let result = self.in_context(&context, Some(&method.name), true, |vm| {
+                    let frame = vm.bind_frame(iseq, None, &context, args, block, true)?;
+                    vm.execute(frame)
+                });
+                match result {
+                    Err(Unwind::Return(value, home)) if home == context.home => Ok(value),
+                    result => result,
+                }
+            }
+            // Runs like a lambda, with the receiver as `self`
+            MethodBody::Proc(proc) => {
+                let Some(closure) = self.iseq_closure(*proc) else {
+                    return self.call_block(*proc, args, block);
+                };
+                let context = Rc::new(Context {
+                    self_value: receiver,
+                    definee: method.owner,
+                    block,
+                    method: Some(method.clone()),
+                    home: self.new_home(),
+                    visibility: Cell::new(Visibility::Public),
+                    ..(*closure.context).clone()
+                });
+                self.run_closure(*proc, &closure, context, true, Some(&method.name), args, block)
+            }
+            MethodBody::AttrReader(name) => {
+                self.runtime.check_arity(0, args.len())?;
+                Ok(self.runtime.ivar(receiver, name))
+            }
+            MethodBody::AttrWriter(name) => {
+                self.runtime.check_arity(1, args.len())?;
+                builtins::set_ivar(self, receiver, name, args[0])?;
+                Ok(args[0])
+            }
+            MethodBody::Ast(_) => unreachable!("methods defined from the AST only exist in the interpreter's runtime"),
+        }
+    }
+
+    fn closure(&mut self, iseq: &Rc<Iseq>, env: &Rc<Env>, context: &Rc<Context>, lambda: bool) -> Value {
+        let closure = IseqClosure { iseq: iseq.clone(), env: env.clone(), context: context.clone() };
+        self.runtime.proc(ProcBody::Iseq(Rc::new(closure)), lambda)
+    }
+
+    fn call_block(&mut self, proc: Value, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        let (body, lambda) = match self.runtime.proc_value(proc) {
+            Some(value) => (value.body.clone(), value.lambda),
+            None => return self.call_method(proc, "call", args, block),
+        };
+        let closure = match body {
+            ProcBody::Iseq(closure) => closure,
+            // `&:name` calls `name` on the first argument with the rest
+            ProcBody::Symbol(name) => {
+                let Some((&receiver, rest)) = args.split_first() else {
+                    return Err(self.runtime.error("ArgumentError", "no receiver given"));
+                };
+                return self.call_method(receiver, &name, rest, block);
+            }
+            ProcBody::Native(function, state) => return function(self, state, args),
+            ProcBody::Ast(_) => unreachable!("blocks from the AST only exist in the interpreter's runtime"),
+        };
+
+        let context = if lambda {
+            Rc::new(Context { home: self.new_home(), ..(*closure.context).clone() })
+        } else {
+            closure.context.clone()
+        };
+        self.run_closure(proc, &closure, context, lambda, None, args, block)
+    }
+
+    // The closure of a block written in the program, not made from a symbol
+    fn iseq_closure(&self, proc: Value) -> Option<Rc<IseqClosure>> {
+        match &self.runtime.proc_value(proc)?.body {
+            ProcBody::Iseq(closure) => Some(closure.clone()),
+            _ => None,
+        }
+    }
+
+    // Runs a block's body in `context`, which is its own or one with another `self`.
+    // Lambdas are activations `return` leaves, and so are methods defined from blocks.
+    #[allow(clippy::too_many_arguments)]
+    fn run_closure(
+        &mut self,
+        proc: Value,
+        closure: &IseqClosure,
+        context: Rc<Context>,
+        lambda: bool,
+        frame: Option<&str>,
+        args: &[Value],
+        block: Option<Value>,
+    ) -> Result<Value, Unwind> {
+        let result = self.in_context(&context, frame, lambda, |vm| {
+            let mut frame = vm.bind_frame(&closure.iseq, Some(closure.env.clone()), &context, args, block, lambda)?;
+            loop {
+                let env = frame.env.clone();
+                match vm.execute(frame) {
+                    // Runs the body again, without binding the arguments again
+                    Err(Unwind::Redo) => frame = Frame::new(closure.iseq.clone(), env, context.clone(), closure.iseq.body_start),
+                    Err(Unwind::Next(value)) => return Ok(value),
+                    result => return result,
+                }
+            }
+        });
+
+        match result {
+            Err(Unwind::Return(value, home)) if lambda && home == context.home => Ok(value),
+            Err(Unwind::Break(value, None)) if lambda => Ok(value),
+            // Tagged with the proc, so the call the block was given to can stop
+            Err(Unwind::Break(value, None)) => Err(Unwind::Break(value, proc.object_id())),
+            result => result,
+        }
+    }
+
+    // A frame for a method or block with the arguments bound to its parameters, starting
+    // after the defaults of the optional parameters that were passed
+    fn bind_frame(
+        &mut self,
+        iseq: &Rc<Iseq>,
+        parent: Option<Rc<Env>>,
+        context: &Rc<Context>,
+        args: &[Value],
+        block: Option<Value>,
+        strict: bool,
+    ) -> Result<Frame, Unwind> {
+        let env = Env::new(iseq, parent);
+        let (optional_given, missing_keywords) = self.bind_params(&iseq.params, args, block, strict, &env)?;
+        let mut frame = Frame::new(iseq.clone(), env, context.clone(), iseq.params.optional_entries[optional_given]);
+        frame.missing_keywords = missing_keywords;
+        Ok(frame)
+    }
+
+    // Binds arguments to parameters. Methods and lambdas are `strict` about the number of
+    // arguments, procs ignore extra ones, fill missing ones with `nil` and spread a single array.
+    // Returns how many optional parameters were passed, and which keywords weren't.
+    fn bind_params(
+        &mut self,
+        layout: &ParamLayout,
+        args: &[Value],
+        block: Option<Value>,
+        strict: bool,
+        env: &Env,
+    ) -> Result<(usize, Vec<bool>), Unwind> {
+        let mut args = args.to_vec();
+        let required = layout.params.iter().filter(|param| matches!(param, ParamSlot::Required(_) | ParamSlot::Destructure(_))).count();
+        let optional = layout.optional_count();
+        let rest = layout.params.iter().any(|param| matches!(param, ParamSlot::Rest(_)));
+        let takes_keywords = layout.params.iter().any(|param| matches!(param, ParamSlot::Keyword { .. } | ParamSlot::KeywordRest(_)));
+
+        let mut keywords = self.runtime.take_keywords(&mut args, takes_keywords);
+
+        if strict {
+            if args.len() < required || (!rest && args.len() > required + optional) {
+                let expected = if rest {
+                    format!("{}+", required)
+                } else if optional > 0 {
+                    format!("{}..{}", required, required + optional)
+                } else {
+                    required.to_string()
+                };
+                return Err(builtins::argument_count_error(self, args.len(), &expected));
+            }
+        } else {
+            let positional = required + optional;
+            if args.len() == 1 && (positional > 1 || (rest && positional > 0)) {
+                if let Some(elements) = self.runtime.array_value(args[0]) {
+                    args = elements.clone();
+                }
+            }
+            if args.len() < required {
+                args.resize(required, Value::NIL);
+            }
+            if !rest {
+                args.truncate(required + optional);
+            }
+        }
+
+        let optional_given = optional.min(args.len() - required);
+        let rest_length = args.len() - required - optional_given;
+        let (mut position, mut optional_seen) = (0, 0);
+        let mut missing_keywords = Vec::new();
+        for param in &layout.params {
+            match param {
+                ParamSlot::Required(slot) => {
+                    env.set(*slot, args[position]);
+                    position += 1;
+                }
+                ParamSlot::Destructure(targets) => {
+                    self.destructure_param(targets, args[position], env);
+                    position += 1;
+                }
+                ParamSlot::Optional(slot) => {
+                    if optional_seen < optional_given {
+                        env.set(*slot, args[position]);
+                        position += 1;
+                    }
+                    optional_seen += 1;
+                }
+                ParamSlot::Rest(slot) => {
+                    let elements = args[position..position + rest_length].to_vec();
+                    position += rest_length;
+                    if let Some(slot) = slot {
+                        let array = self.runtime.array(elements);
+                        env.set(*slot, array);
+                    }
+                }
+                ParamSlot::Keyword { name, slot, required } => {
+                    let key = self.runtime.symbol(name);
+                    match keywords.iter().position(|&(candidate, _)| candidate == key) {
+                        Some(index) => {
+                            env.set(*slot, keywords.remove(index).1);
+                            missing_keywords.push(false);
+                        }
+                        None if *required => {
+                            return Err(self.runtime.error("ArgumentError", &format!("missing keyword: :{}", name)));
+                        }
+                        None => missing_keywords.push(true),
+                    }
+                }
+                ParamSlot::KeywordRest(slot) => {
+                    let mut hash = RHash::default();
+                    for (key, value) in keywords.drain(..) {
+                        hash.insert(self.runtime.hash_key(key), key, value);
+                    }
+                    if let Some(slot) = slot {
+                        let hash = self.runtime.hash(hash);
+                        env.set(*slot, hash);
+                    }
+                }
+                ParamSlot::Block(slot) => {
+                    if let Some(slot) = slot {
+                        env.set(*slot, block.unwrap_or(Value::NIL));
+                    }
+                }
+            }
+        }
+
+        if let Some(&(key, _)) = keywords.first() {
+            let message = format!("unknown keyword: {}", self.runtime.describe(key));
+            return Err(self.runtime.error("ArgumentError", &message));
+        }
+        Ok((optional_given, missing_keywords))
+    }
+
+    // `|(a, *b)|` destructures an argument the way multiple assignment does
+    fn destructure_param(&mut self, targets: &[ParamTarget], value: Value, env: &Env) {
+        let elements = match self.runtime.array_value(value) {
+            Some(elements) => elements.clone(),
+            None => vec![value],
+        };
+        let value_at = |index: usize| elements.get(index).copied().unwrap_or(Value::NIL);
+        let splat = targets.iter().position(|target| matches!(target, ParamTarget::Splat(_)));
+        let after = splat.map_or(0, |splat| targets.len() - splat - 1);
+        let middle_end = elements.len().saturating_sub(after).max(splat.unwrap_or(0));
+
+        for (index, target) in targets.iter().enumerate() {
+            let value = match splat {
+                Some(splat) if index == splat => {
+                    let middle = elements.get(splat..middle_end).unwrap_or_default().to_vec();
+                    self.runtime.array(middle)
+                }
+                Some(splat) if index > splat => value_at(middle_end + index - splat - 1),
+                _ => value_at(index),
+            };
+            match target {
+                ParamTarget::Local(slot) | ParamTarget::Splat(Some(slot)) => env.set(*slot, value),
+                ParamTarget::Splat(None) => {}
+                ParamTarget::Nested(inner) => self.destructure_param(inner, value, env),
+            }
+        }
+    }
+}
+
+// Parses and compiles a program without running it, for `--dump`
+pub fn compile(source: &str) -> Result<Rc<Iseq>, EvalError> {
+    let program = Parser::new(source).parse_program().map_err(EvalError::Syntax)?;
+    Ok(compiler::compile(&program))
+}
+
+impl Executor for Vm {
+    fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    fn send(&mut self, receiver: Value, method: &str, args: &[Value], block: Option<Value>) -> Result<Value, Unwind> {
+        self.call_method(receiver, method, args, block)
+    }
+
+    fn call_proc(&mut self, proc: Value, args: &[Value]) -> Result<Value, Unwind> {
+        self.call_block(proc, args, None)
+    }
+
+    fn current_block(&mut self) -> Option<Value> {
+        self.contexts.last().and_then(|context| context.block)
+    }
+
+    // Blocks keep their variables and where `return` goes, lambdas keep returning from themselves
+    fn call_proc_as(&mut self, proc: Value, self_value: Value, definee: ObjectId, args: &[Value]) -> Result<Value, Unwind> {
+        let Some(closure) = self.iseq_closure(proc) else {
+            return self.call_block(proc, args, None);
+        };
+        let lambda = self.runtime.proc_value(proc).is_some_and(|value| value.lambda);
+        let home = if lambda { self.new_home() } else { closure.context.home };
+        let context = Rc::new(Context {
+            self_value,
+            definee,
+            home,
+            visibility: Cell::new(Visibility::Public),
+            ..(*closure.context).clone()
+        });
+        self.run_closure(proc, &closure, context, lambda, None, args, None)
+    }
+
+    fn set_default_visibility(&mut self, visibility: Visibility) {
+        if let Some(context) = self.contexts.last() {
+            context.visibility.set(visibility);
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        let mut tracer = Tracer::default();
+        for context in &self.contexts {
+            context.trace(&mut tracer);
+        }
+        for (env, values) in &self.frames {
+            env.trace(&mut tracer);
+            tracer.mark_all(values.stack.borrow().iter().copied());
+            tracer.mark_all(values.pending.borrow().iter().filter_map(|&(_, unwind)| unwind.value()));
+        }
+        let finalizers = self.runtime.collect_garbage(tracer);
+        builtins::run_finalizers(self, finalizers);
+    }
+}
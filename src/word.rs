@@ -0,0 +1,163 @@
+use std::fmt;
+
+use crate::runtime::{ObjectId, Symbol};
+
+// A value as a single 64-bit word, which is what the interpreter, the VM and the functions
+// `llvm` emits all pass around. The tagging is CRuby's, so immediates need no allocation,
+// and `object_id` uses it to give them the ids CRuby does:
+//
+//   xxxx...xxx1  fixnum, a 63-bit integer shifted left by one
+//   xxxx...xx10  flonum, a float with its exponent rotated into the low bits
+//   xxxx...0x0c  symbol, its index shifted left by 8
+//   0x00, 0x08, 0x14  false, nil and true
+//   xxxx...x000  heap reference, `(index + 2) << 3` so it's never `false` or `nil`
+//
+// Heap references hold the object's slot rather than its address, since the heap's
+// storage moves as it grows. Integers outside the fixnum range are `Bignum`s and floats
+// that can't be flonums are `Float`s in the heap, which `Runtime::unpack` reads back.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(transparent)]
+pub struct Value(u64);
+
+// What a value stands for, to match on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unpacked {
+    Nil,
+    True,
+    False,
+    Integer(i64),
+    Float(f64),
+    Symbol(Symbol),
+    Object(ObjectId),
+}
+
+pub const FIXNUM_FLAG: u64 = 0x01;
+pub const FLONUM_MASK: u64 = 0x03;
+pub const FLONUM_FLAG: u64 = 0x02;
+pub const SYMBOL_MASK: u64 = 0xff;
+pub const SYMBOL_FLAG: u64 = 0x0c;
+pub const SYMBOL_SHIFT: u32 = 8;
+pub const HEAP_MASK: u64 = 0x07;
+pub const FIXNUM_MAX: i64 = (1 << 62) - 1;
+pub const FIXNUM_MIN: i64 = -(1 << 62);
+
+// The encoding of `0.0`, whose bits would otherwise rotate into `false`
+const FLONUM_ZERO: u64 = 0x8000_0000_0000_0002;
+
+impl Value {
+    pub const FALSE: Value = Value(0x00);
+    pub const NIL: Value = Value(0x08);
+    pub const TRUE: Value = Value(0x14);
+    // Not a value: what native code returns when it can't handle its operands
+    pub const UNDEF: Value = Value(0x34);
+
+    pub fn from_bits(bits: u64) -> Value {
+        Value(bits)
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_bool(value: bool) -> Value {
+        if value {
+            Value::TRUE
+        } else {
+            Value::FALSE
+        }
+    }
+
+    pub fn truthy(self) -> bool {
+        self != Value::NIL && self != Value::FALSE
+    }
+
+    pub fn fixnum(value: i64) -> Option<Value> {
+        (FIXNUM_MIN..=FIXNUM_MAX).contains(&value).then_some(Value(((value << 1) as u64) | FIXNUM_FLAG))
+    }
+
+    // Floats whose exponent is close enough to zero, which covers about everything
+    // but very large and very small magnitudes, and infinities and NaN
+    pub fn flonum(value: f64) -> Option<Value> {
+        let bits = value.to_bits();
+        let exponent = (bits >> 60) & 0x7;
+        if bits != 0x3000_0000_0000_0000 && (exponent == 3 || exponent == 4) {
+            Some(Value((bits.rotate_left(3) & !0x01) | FLONUM_FLAG))
+        } else if bits == 0 {
+            Some(Value(FLONUM_ZERO))
+        } else {
+            None
+        }
+    }
+
+    pub fn symbol(symbol: Symbol) -> Value {
+        Value(((symbol.index() as u64) << SYMBOL_SHIFT) | SYMBOL_FLAG)
+    }
+
+    pub fn object(id: ObjectId) -> Value {
+        Value((id.index() as u64 + 2) << 3)
+    }
+
+    pub fn is_fixnum(self) -> bool {
+        self.0 & FIXNUM_FLAG != 0
+    }
+
+    pub fn is_flonum(self) -> bool {
+        self.0 & FLONUM_MASK == FLONUM_FLAG
+    }
+
+    pub fn as_fixnum(self) -> Option<i64> {
+        self.is_fixnum().then_some(self.0 as i64 >> 1)
+    }
+
+    pub fn as_flonum(self) -> Option<f64> {
+        self.is_flonum().then(|| decode_flonum(self.0))
+    }
+
+    pub fn as_symbol(self) -> Option<Symbol> {
+        (self.0 & SYMBOL_MASK == SYMBOL_FLAG).then(|| Symbol::from_index((self.0 >> SYMBOL_SHIFT) as usize))
+    }
+
+    pub fn object_id(self) -> Option<ObjectId> {
+        (self.0 & HEAP_MASK == 0 && self.0 >= 0x10).then(|| ObjectId::from_index((self.0 >> 3) as usize - 2))
+    }
+
+    // What the word stands for, with the floats the heap holds still as their objects.
+    // `None` for `UNDEF` and bit patterns no value encodes to.
+    pub fn unpack(self) -> Option<Unpacked> {
+        if let Some(value) = self.as_fixnum() {
+            return Some(Unpacked::Integer(value));
+        }
+        if let Some(value) = self.as_flonum() {
+            return Some(Unpacked::Float(value));
+        }
+        match self {
+            Value::FALSE => Some(Unpacked::False),
+            Value::NIL => Some(Unpacked::Nil),
+            Value::TRUE => Some(Unpacked::True),
+            _ => match (self.as_symbol(), self.object_id()) {
+                (Some(symbol), _) => Some(Unpacked::Symbol(symbol)),
+                (_, Some(id)) => Some(Unpacked::Object(id)),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.unpack() {
+            Some(unpacked) => unpacked.fmt(f),
+            None if *self == Value::UNDEF => write!(f, "Undef"),
+            None => write!(f, "Value({:#x})", self.0),
+        }
+    }
+}
+
+fn decode_flonum(bits: u64) -> f64 {
+    if bits == FLONUM_ZERO {
+        return 0.0;
+    }
+    // The exponent started with `011` or `100`, so its dropped top two bits follow from the third
+    let top = 2 - (bits >> 63);
+    f64::from_bits((top | (bits & !FLONUM_MASK)).rotate_right(3))
+}
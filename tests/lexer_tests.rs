@@ -243,6 +243,15 @@ mod lexer_tests {
         assert_eq!(lexer.next_token(), Token::Eof);
     }
 
+    #[test]
+    fn test_escapes_that_are_not_utf8() {
+        let mut lexer = Lexer::new("\"\\xff\\u00e9\" '\\xff'");
+        assert_eq!(lexer.next_token(), Token::Bytes(vec![0xff, 0xc3, 0xa9]));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Text("\\xff".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
     #[test]
     fn test_symbol_token() {
         let mut lexer = Lexer::new(":symbol");
@@ -250,6 +259,28 @@ mod lexer_tests {
         assert_eq!(lexer.next_token(), Token::Eof);
     }
 
+    #[test]
+    fn test_method_name_symbols() {
+        let symbols = |input: &str| -> Vec<Token> {
+            let mut lexer = Lexer::new(input);
+            let mut tokens = Vec::new();
+            loop {
+                match lexer.next_token() {
+                    Token::Eof => return tokens,
+                    Token::WhiteSpace => {}
+                    token => tokens.push(token),
+                }
+            }
+        };
+        let symbol = |name: &str| Token::Symbol(name.to_string());
+
+        assert_eq!(symbols(":odd? :save! :name= :@a"), vec![symbol("odd?"), symbol("save!"), symbol("name="), symbol("@a")]);
+        assert_eq!(symbols(":+ :[]= :<=> :** :-@"), vec![symbol("+"), symbol("[]="), symbol("<=>"), symbol("**"), symbol("-@")]);
+        assert_eq!(symbols(":\"two words\""), vec![symbol("two words")]);
+        assert_eq!(symbols("{:a=>1}"), vec![Token::LeftBrace, symbol("a"), Token::Arrow, Token::Number(1), Token::RightBrace]);
+        assert_eq!(symbols(":a==b"), vec![symbol("a"), Token::EqualEqual, Token::Identifier("b".to_string())]);
+    }
+
     #[test]
     fn test_block_delimiters() {
         let mut lexer = Lexer::new("{ }");
@@ -315,4 +346,238 @@ mod lexer_tests {
         assert_eq!(lexer.next_token(), Token::RightBrace);
         assert_eq!(lexer.next_token(), Token::Eof);
     }
+
+    #[test]
+    fn test_block_params_delimiters() {
+        let mut lexer = Lexer::new("list.each { |x; tmp| }");
+
+        assert_eq!(lexer.next_token(), Token::Identifier("list".to_string()));
+        assert_eq!(lexer.next_token(), Token::Dot);
+        assert_eq!(lexer.next_token(), Token::Identifier("each".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::LeftBrace);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Pipe);
+        assert_eq!(lexer.next_token(), Token::Identifier("x".to_string()));
+        assert_eq!(lexer.next_token(), Token::Semicolon);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("tmp".to_string()));
+        assert_eq!(lexer.next_token(), Token::Pipe);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::RightBrace);
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_identifier_with_underscore_and_digits() {
+        let mut lexer = Lexer::new("_1 each_with_index");
+
+        assert_eq!(lexer.next_token(), Token::Identifier("_1".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("each_with_index".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_splat_and_block_params() {
+        let mut lexer = Lexer::new("*a **b &c");
+
+        assert_eq!(lexer.next_token(), Token::Asterisk);
+        assert_eq!(lexer.next_token(), Token::Identifier("a".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::AsteriskAsterisk);
+        assert_eq!(lexer.next_token(), Token::Identifier("b".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Ampersand);
+        assert_eq!(lexer.next_token(), Token::Identifier("c".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_scope_and_shift_operators() {
+        let mut lexer = Lexer::new("A::B << self");
+
+        assert_eq!(lexer.next_token(), Token::Identifier("A".to_string()));
+        assert_eq!(lexer.next_token(), Token::ColonColon);
+        assert_eq!(lexer.next_token(), Token::Identifier("B".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::ShiftLeft);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("self".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_conditional_operators() {
+        let mut lexer = Lexer::new("empty? ? !a : b != c || d && e");
+
+        assert_eq!(lexer.next_token(), Token::Identifier("empty?".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Question);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Not);
+        assert_eq!(lexer.next_token(), Token::Identifier("a".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Colon);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("b".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::NotEqual);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("c".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::PipePipe);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("d".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::AmpersandAmpersand);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("e".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_array_and_range_tokens() {
+        let mut lexer = Lexer::new("[1..2, 3...4]");
+
+        assert_eq!(lexer.next_token(), Token::LeftBracket);
+        assert_eq!(lexer.next_token(), Token::Number(1));
+        assert_eq!(lexer.next_token(), Token::DotDot);
+        assert_eq!(lexer.next_token(), Token::Number(2));
+        assert_eq!(lexer.next_token(), Token::Comma);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Number(3));
+        assert_eq!(lexer.next_token(), Token::DotDotDot);
+        assert_eq!(lexer.next_token(), Token::Number(4));
+        assert_eq!(lexer.next_token(), Token::RightBracket);
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_lexemes_record_preceding_whitespace() {
+        let mut lexer = Lexer::new("foo -1 - 2");
+
+        let spaces: Vec<(Token, bool)> = std::iter::from_fn(|| Some(lexer.next_lexeme()))
+            .map(|lexeme| (lexeme.token, lexeme.space_before))
+            .take(6)
+            .collect();
+        assert_eq!(
+            spaces,
+            vec![
+                (Token::Identifier("foo".to_string()), false),
+                (Token::Minus, true),
+                (Token::Number(1), false),
+                (Token::Minus, true),
+                (Token::Number(2), true),
+                (Token::Eof, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_instance_variables_and_operator_assignment() {
+        let mut lexer = Lexer::new("@count+=1 ||= &&= <<=");
+
+        assert_eq!(lexer.next_token(), Token::InstanceVariable("count".to_string()));
+        assert_eq!(lexer.next_token(), Token::OperatorAssign("+".to_string()));
+        assert_eq!(lexer.next_token(), Token::Number(1));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::OperatorAssign("||".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::OperatorAssign("&&".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::OperatorAssign("<<".to_string()));
+        assert_eq!(lexer.next_token(), Token::Eof);
+    }
+
+    #[test]
+    fn test_lambda_arrow() {
+        let mut lexer = Lexer::new("->(x) -1");
+
+        assert_eq!(lexer.next_token(), Token::Lambda);
+        assert_eq!(lexer.next_token(), Token::LeftParenthesis);
+        assert_eq!(lexer.next_token(), Token::Identifier("x".to_string()));
+        assert_eq!(lexer.next_token(), Token::RightParenthesis);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Minus);
+        assert_eq!(lexer.next_token(), Token::Number(1));
+    }
+
+    #[test]
+    fn test_float() {
+        let mut lexer = Lexer::new("1.5 2e3 1.times");
+
+        assert_eq!(lexer.next_token(), Token::Float(1.5));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Float(2000.0));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Number(1));
+        assert_eq!(lexer.next_token(), Token::Dot);
+    }
+
+    #[test]
+    fn test_large_numbers_and_shifts() {
+        let mut lexer = Lexer::new("1_000 99999999999999999999 a >> 1 <=> b >>= 2");
+
+        assert_eq!(lexer.next_token(), Token::Number(1000));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Bignum("99999999999999999999".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("a".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::ShiftRight);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Number(1));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Spaceship);
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Identifier("b".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::OperatorAssign(">>".to_string()));
+    }
+
+    #[test]
+    fn test_string_escapes() {
+        let mut lexer = Lexer::new(r#""a\tb\n\"c\" \\ \e \x41é\u{1F600} \#{x}" 'it\'s \n'"#);
+
+        assert_eq!(lexer.next_token(), Token::Text("a\tb\n\"c\" \\ \u{1b} Aé😀 #{x}".to_string()));
+        assert_eq!(lexer.next_token(), Token::WhiteSpace);
+        assert_eq!(lexer.next_token(), Token::Text("it's \\n".to_string()));
+    }
+
+    #[test]
+    fn test_regexp_or_division() {
+        let tokens = |input: &str| {
+            let mut lexer = Lexer::new(input);
+            std::iter::from_fn(|| Some(lexer.next_lexeme().token)).take_while(|token| *token != Token::Eof).collect::<Vec<_>>()
+        };
+        let regexp = |source: &str, options: &str| Token::Regexp(source.to_string(), options.to_string());
+        let identifier = |name: &str| Token::Identifier(name.to_string());
+
+        assert_eq!(tokens(r"x = /a\/b\d/mi"), vec![identifier("x"), Token::Equal, regexp(r"a/b\d", "mi")]);
+        assert_eq!(tokens("puts /x/"), vec![identifier("puts"), regexp("x", "")]);
+        assert_eq!(tokens("a / b / c"), vec![identifier("a"), Token::Slash, identifier("b"), Token::Slash, identifier("c")]);
+        assert_eq!(tokens("(4)/2/1"), vec![
+            Token::LeftParenthesis,
+            Token::Number(4),
+            Token::RightParenthesis,
+            Token::Slash,
+            Token::Number(2),
+            Token::Slash,
+            Token::Number(1),
+        ]);
+        assert_eq!(tokens("s =~ /x/"), vec![identifier("s"), Token::Match, regexp("x", "")]);
+        assert_eq!(tokens("s !~ /x/"), vec![identifier("s"), Token::NotMatch, regexp("x", "")]);
+    }
+
+    #[test]
+    fn test_lexemes_record_line_and_column() {
+        let mut lexer = Lexer::new("def foo\n  bar\nend");
+
+        let positions: Vec<(usize, usize)> = std::iter::from_fn(|| Some(lexer.next_lexeme()))
+            .take(6)
+            .map(|lexeme| (lexeme.span.line, lexeme.span.column))
+            .collect();
+        assert_eq!(positions, vec![(1, 1), (1, 5), (1, 8), (2, 3), (2, 6), (3, 1)]);
+    }
 }
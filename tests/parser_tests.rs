@@ -0,0 +1,1535 @@
+#[cfg(test)]
+mod parser_tests {
+    use chimiaguin::ast::{
+        Begin, Block, BlockParams, Call, Case, CaseIn, Class, Def, For, HashElement, HashPatternRest, If, InClause,
+        Line, Module, Node, OpAssign, Param, Params, Pattern, RangeKind, Rescue, Super, Target, When, While,
+    };
+    use chimiaguin::parser::Parser;
+
+    fn parse(input: &str) -> Vec<Node> {
+        Parser::new(input).parse_program().expect("program should parse")
+    }
+
+    fn call(receiver: Option<Node>, method: &str, args: Vec<Node>) -> Node {
+        Node::Call(Call::new(receiver, method, args, 0))
+    }
+
+    fn local(name: &str) -> Node {
+        Node::LocalVariable(name.to_string())
+    }
+
+    fn block_of(node: &Node) -> &Block {
+        match node {
+            Node::Call(Call { block: Some(block), .. }) => block,
+            other => panic!("expected a call with a block, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_do_block_with_params() {
+        let program = parse("list.each do |x, y|\n  puts(x)\nend");
+
+        let block = block_of(&program[0]);
+        assert_eq!(
+            block.params,
+            BlockParams::Explicit(Params {
+                params: vec![Param::Required("x".to_string()), Param::Required("y".to_string())],
+                locals: vec![],
+            })
+        );
+        assert_eq!(block.body, vec![call(None, "puts", vec![local("x")])]);
+    }
+
+    #[test]
+    fn test_brace_block_with_params() {
+        let program = parse("map { |x| x * 2 }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(block.body, vec![call(Some(local("x")), "*", vec![Node::Integer(2)])]);
+    }
+
+    #[test]
+    fn test_block_local_variables() {
+        let program = parse("each { |x; tmp| tmp = x }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(
+            block.params,
+            BlockParams::Explicit(Params {
+                params: vec![Param::Required("x".to_string())],
+                locals: vec!["tmp".to_string()],
+            })
+        );
+        assert_eq!(block.body, vec![Node::LocalAssign("tmp".to_string(), Box::new(local("x")))]);
+    }
+
+    #[test]
+    fn test_destructuring_block_params() {
+        let program = parse("each_with_index { |(a, b), c| a }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(
+            block.params,
+            BlockParams::Explicit(Params {
+                params: vec![
+                    Param::Destructure(vec![Param::Required("a".to_string()), Param::Required("b".to_string())]),
+                    Param::Required("c".to_string()),
+                ],
+                locals: vec![],
+            })
+        );
+        assert_eq!(block.body, vec![local("a")]);
+    }
+
+    #[test]
+    fn test_numbered_block_params() {
+        let program = parse("pairs.map { _1 + _2 }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(block.params, BlockParams::Numbered(2));
+        assert_eq!(block.body, vec![call(Some(local("_1")), "+", vec![local("_2")])]);
+    }
+
+    #[test]
+    fn test_it_block_param() {
+        let program = parse("map { it * 2 }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(block.params, BlockParams::It);
+        assert_eq!(block.body, vec![call(Some(local("it")), "*", vec![Node::Integer(2)])]);
+    }
+
+    #[test]
+    fn test_it_is_a_method_call_in_block_with_params() {
+        let program = parse("map { |x| it }");
+
+        let block = block_of(&program[0]);
+        assert_eq!(block.body, vec![call(None, "it", vec![])]);
+    }
+
+    #[test]
+    fn test_numbered_param_with_ordinary_params_is_an_error() {
+        let result = Parser::new("map { |x| _1 }").parse_program();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_brace_is_a_hash_in_operand_position() {
+        let program = parse("a = { :key => 'value', b: 1 }");
+
+        assert_eq!(
+            program,
+            vec![Node::LocalAssign(
+                "a".to_string(),
+                Box::new(Node::Hash(vec![
+                    HashElement::Pair(Node::Symbol("key".to_string()), Node::Str("value".to_string())),
+                    HashElement::Pair(Node::Symbol("b".to_string()), Node::Integer(1)),
+                ]))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_brace_is_a_block_after_call_arguments() {
+        let program = parse("list.inject(0) { |sum, x| sum + x }");
+
+        match &program[0] {
+            Node::Call(call) => {
+                assert_eq!(call.method, "inject");
+                assert_eq!(call.args, vec![Node::Integer(0)]);
+                assert!(call.block.is_some());
+            }
+            other => panic!("expected a call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_block_sees_outer_locals() {
+        let program = parse("total = 0\neach { |x| total }");
+
+        let block = block_of(&program[1]);
+        assert_eq!(block.body, vec![local("total")]);
+    }
+
+    #[test]
+    fn test_parentheses_call_method_shadowed_by_local() {
+        let program = parse("total = 0
+total()
+total");
+
+        assert_eq!(program[1], call(None, "total", vec![]));
+        assert_eq!(program[2], local("total"));
+    }
+
+    #[test]
+    fn test_interpolated_string_sees_locals() {
+        let program = parse("name = 1\n\"a#{name}b#{x; 2}\"");
+
+        assert_eq!(
+            program[1],
+            Node::InterpolatedString(vec![
+                Node::Str("a".to_string()),
+                local("name"),
+                Node::Str("b".to_string()),
+                Node::Begin(Begin {
+                    body: vec![call(None, "x", vec![]), Node::Integer(2)],
+                    rescues: vec![],
+                    else_body: None,
+                    ensure_body: None
+                }),
+            ])
+        );
+        assert!(Parser::new("\"#{1 +}\"").parse_program().is_err());
+    }
+
+    fn def_of(node: &Node) -> &Def {
+        match node {
+            Node::Def(def) => def,
+            other => panic!("expected a method definition, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_def_without_params() {
+        let program = parse("def add\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.name, "add");
+        assert!(def.params.is_empty());
+        assert!(def.body.is_empty());
+    }
+
+    #[test]
+    fn test_def_with_required_params() {
+        let program = parse("def add(a, b)\n  a + b\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.params.params, vec![Param::Required("a".to_string()), Param::Required("b".to_string())]);
+        assert_eq!(def.body, vec![call(Some(local("a")), "+", vec![local("b")])]);
+        assert_eq!(def.params.arity(), 2);
+    }
+
+    #[test]
+    fn test_def_with_every_param_kind() {
+        let program = parse("def full(a, b = 1, *rest, c, key:, opt: 2, **opts, &block)\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(
+            def.params.params,
+            vec![
+                Param::Required("a".to_string()),
+                Param::Optional("b".to_string(), Node::Integer(1)),
+                Param::Rest(Some("rest".to_string())),
+                Param::Required("c".to_string()),
+                Param::RequiredKeyword("key".to_string()),
+                Param::OptionalKeyword("opt".to_string(), Node::Integer(2)),
+                Param::KeywordRest(Some("opts".to_string())),
+                Param::Block(Some("block".to_string())),
+            ]
+        );
+        assert_eq!(def.params.arity(), -4);
+    }
+
+    #[test]
+    fn test_def_with_anonymous_splats() {
+        let program = parse("def forward(*, **, &)\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.params.params, vec![Param::Rest(None), Param::KeywordRest(None), Param::Block(None)]);
+        assert_eq!(def.params.arity(), -1);
+    }
+
+    #[test]
+    fn test_params_are_locals_in_body() {
+        let program = parse("def greet(name, greeting: 'hi')\n  greeting\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.body, vec![local("greeting")]);
+    }
+
+    #[test]
+    fn test_def_without_parentheses() {
+        let program = parse("def add a, b\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.params.params, vec![Param::Required("a".to_string()), Param::Required("b".to_string())]);
+    }
+
+    #[test]
+    fn test_endless_def() {
+        let program = parse("def sq(x) = x * x");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.name, "sq");
+        assert_eq!(def.body, vec![call(Some(local("x")), "*", vec![local("x")])]);
+
+        let program = parse("def ==(other) = true\ndef <=(other) = true\ndef >=(other) = true\ndef ===(other) = true\ndef !=(other) = true");
+        let names: Vec<_> = program.iter().map(|node| def_of(node).name.as_str()).collect();
+        assert_eq!(names, vec!["==", "<=", ">=", "===", "!="]);
+        assert!(Parser::new("def name=(value) = value").parse_program().is_err());
+    }
+
+    #[test]
+    fn test_singleton_def() {
+        let program = parse("def self.create\nend");
+
+        let def = def_of(&program[0]);
+        assert_eq!(def.singleton, Some(Box::new(Node::SelfNode)));
+        assert_eq!(def.name, "create");
+    }
+
+    #[test]
+    fn test_setter_and_operator_defs() {
+        let program = parse("def name=(value)\nend\ndef ==(other)\nend\ndef [](index)\nend\ndef []=(index, value)\nend\ndef -@\nend");
+
+        assert_eq!(def_of(&program[0]).name, "name=");
+        assert_eq!(def_of(&program[1]).name, "==");
+        assert_eq!(def_of(&program[2]).name, "[]");
+        assert_eq!(def_of(&program[3]).name, "[]=");
+        assert_eq!(def_of(&program[3]).params.arity(), 2);
+        assert_eq!(def_of(&program[4]).name, "-@");
+    }
+
+    #[test]
+    fn test_invalid_param_lists() {
+        assert!(Parser::new("def m(*a, *b)\nend").parse_program().is_err());
+        assert!(Parser::new("def m(&b, a)\nend").parse_program().is_err());
+        assert!(Parser::new("def m(a, a)\nend").parse_program().is_err());
+        assert!(Parser::new("def m(a = 1, b, c = 2)\nend").parse_program().is_err());
+    }
+
+    #[test]
+    fn test_method_body_does_not_see_outer_locals() {
+        let program = parse("x = 1\ndef m\n  x\nend");
+
+        assert_eq!(def_of(&program[1]).body, vec![call(None, "x", vec![])]);
+    }
+
+    fn constant(name: &str) -> Node {
+        Node::Constant(name.to_string())
+    }
+
+    #[test]
+    fn test_class_definition() {
+        let program = parse("class Dog\nend");
+
+        assert_eq!(
+            program,
+            vec![Node::Class(Class { path: Box::new(constant("Dog")), superclass: None, body: vec![] })]
+        );
+    }
+
+    #[test]
+    fn test_class_with_superclass() {
+        let program = parse("class Dog < Animal\n  def bark\n  end\nend");
+
+        match &program[0] {
+            Node::Class(class) => {
+                assert_eq!(class.superclass, Some(Box::new(constant("Animal"))));
+                assert_eq!(def_of(&class.body[0]).name, "bark");
+            }
+            other => panic!("expected a class, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_nested_module_and_scoped_class() {
+        let program = parse("module Zoo\n  class Animals::Dog < ::Base\n  end\nend");
+
+        let Node::Module(Module { path, body }) = &program[0] else {
+            panic!("expected a module, got {:?}", program[0]);
+        };
+        assert_eq!(**path, constant("Zoo"));
+        assert_eq!(
+            body[0],
+            Node::Class(Class {
+                path: Box::new(Node::ScopedConstant(Some(Box::new(constant("Animals"))), "Dog".to_string())),
+                superclass: Some(Box::new(Node::ScopedConstant(None, "Base".to_string()))),
+                body: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_singleton_class_block() {
+        let program = parse("class Dog\n  class << self\n    def create\n    end\n  end\nend");
+
+        let Node::Class(class) = &program[0] else {
+            panic!("expected a class, got {:?}", program[0]);
+        };
+        let Node::SingletonClass(target, body) = &class.body[0] else {
+            panic!("expected a singleton class, got {:?}", class.body[0]);
+        };
+        assert_eq!(**target, Node::SelfNode);
+        assert_eq!(def_of(&body[0]).name, "create");
+    }
+
+    #[test]
+    fn test_scoped_constant_and_method_call() {
+        let program = parse("A::B::C\nA::build");
+
+        assert_eq!(
+            program[0],
+            Node::ScopedConstant(
+                Some(Box::new(Node::ScopedConstant(Some(Box::new(constant("A"))), "B".to_string()))),
+                "C".to_string()
+            )
+        );
+        assert_eq!(program[1], call(Some(constant("A")), "build", vec![]));
+    }
+
+    #[test]
+    fn test_constant_with_arguments_is_a_method_call() {
+        let program = parse("Integer(\"12\")\nFoo 3\nFoo\nFoo ::Bar");
+
+        assert_eq!(program[0], call(None, "Integer", vec![Node::Str("12".to_string())]));
+        assert_eq!(program[1], call(None, "Foo", vec![Node::Integer(3)]));
+        assert_eq!(program[2], constant("Foo"));
+        assert!(matches!(program[3], Node::ScopedConstant(..)));
+    }
+
+    #[test]
+    fn test_class_name_must_be_a_constant() {
+        assert!(Parser::new("class dog\nend").parse_program().is_err());
+    }
+
+    fn if_node(condition: Node, then_body: Vec<Node>, else_body: Vec<Node>) -> Node {
+        Node::If(If { condition: Box::new(condition), then_body, else_body })
+    }
+
+    fn vcall(name: &str) -> Node {
+        call(None, name, vec![])
+    }
+
+    #[test]
+    fn test_if_elsif_else() {
+        let program = parse("if a\n  1\nelsif b then 2\nelse\n  3\nend");
+
+        assert_eq!(
+            program,
+            vec![if_node(
+                vcall("a"),
+                vec![Node::Integer(1)],
+                vec![if_node(vcall("b"), vec![Node::Integer(2)], vec![Node::Integer(3)])]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_single_line_if_with_then() {
+        let program = parse("if a then 1 else 2 end");
+
+        assert_eq!(program, vec![if_node(vcall("a"), vec![Node::Integer(1)], vec![Node::Integer(2)])]);
+    }
+
+    #[test]
+    fn test_unless_swaps_branches() {
+        let program = parse("unless a\n  1\nelse\n  2\nend");
+
+        assert_eq!(program, vec![if_node(vcall("a"), vec![Node::Integer(2)], vec![Node::Integer(1)])]);
+    }
+
+    #[test]
+    fn test_statement_modifiers() {
+        let program = parse("return x if y\nfoo unless bar");
+
+        assert_eq!(
+            program,
+            vec![
+                if_node(vcall("y"), vec![Node::Return(Some(Box::new(vcall("x"))))], vec![]),
+                if_node(vcall("bar"), vec![], vec![vcall("foo")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_statements_in_parentheses() {
+        let program = parse("y = (foo unless bar)\n(a; b)\n()");
+
+        let unless = if_node(vcall("bar"), vec![], vec![vcall("foo")]);
+        assert_eq!(program[0], Node::LocalAssign("y".to_string(), Box::new(unless)));
+        let Node::Begin(sequence) = &program[1] else {
+            panic!("expected a sequence, got {:?}", program[1]);
+        };
+        assert_eq!(sequence.body, vec![vcall("a"), vcall("b")]);
+        assert_eq!(program[2], Node::Nil);
+        assert!(Parser::new("(raise 'z' rescue 5)").parse_program().is_ok());
+    }
+
+    #[test]
+    fn test_ternary() {
+        let program = parse("a ? b : c ? 1 : 2");
+
+        assert_eq!(
+            program,
+            vec![if_node(
+                vcall("a"),
+                vec![vcall("b")],
+                vec![if_node(vcall("c"), vec![Node::Integer(1)], vec![Node::Integer(2)])]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_conditional_is_an_expression() {
+        let program = parse("x = if a then 1 else 2 end");
+
+        assert_eq!(
+            program,
+            vec![Node::LocalAssign(
+                "x".to_string(),
+                Box::new(if_node(vcall("a"), vec![Node::Integer(1)], vec![Node::Integer(2)]))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let program = parse("a | b ^ c & d << 1\nx < y | z\nlist.map { |v, w = 1| v | w }");
+        let binary = |left: Node, operator: &str, right: Node| call(Some(left), operator, vec![right]);
+
+        assert_eq!(
+            program[0],
+            binary(
+                binary(vcall("a"), "|", vcall("b")),
+                "^",
+                binary(vcall("c"), "&", binary(vcall("d"), "<<", Node::Integer(1)))
+            )
+        );
+        assert_eq!(program[1], binary(vcall("x"), "<", binary(vcall("y"), "|", vcall("z"))));
+        assert!(matches!(&program[2], Node::Call(Call { block: Some(_), .. })));
+    }
+
+    #[test]
+    fn test_boolean_operators() {
+        let program = parse("a || b && !c\nnot a and b or c");
+
+        assert_eq!(
+            program[0],
+            Node::Or(
+                Box::new(vcall("a")),
+                Box::new(Node::And(Box::new(vcall("b")), Box::new(Node::Not(Box::new(vcall("c"))))))
+            )
+        );
+        assert_eq!(
+            program[1],
+            Node::Or(
+                Box::new(Node::And(Box::new(Node::Not(Box::new(vcall("a")))), Box::new(vcall("b")))),
+                Box::new(vcall("c"))
+            )
+        );
+    }
+
+    #[test]
+    fn test_predicate_method_in_condition() {
+        let program = parse("if list.empty? && a != b\nend");
+
+        assert_eq!(
+            program,
+            vec![if_node(
+                Node::And(
+                    Box::new(call(Some(vcall("list")), "empty?", vec![])),
+                    Box::new(call(Some(vcall("a")), "!=", vec![vcall("b")]))
+                ),
+                vec![],
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_missing_end_for_if() {
+        assert!(Parser::new("if a\n  1\n").parse_program().is_err());
+    }
+
+    fn while_node(condition: Node, body: Vec<Node>, do_while: bool) -> Node {
+        Node::While(While { condition: Box::new(condition), body, do_while })
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let program = parse("while running do\n  step\nend");
+
+        assert_eq!(program, vec![while_node(vcall("running"), vec![vcall("step")], false)]);
+    }
+
+    #[test]
+    fn test_until_loop_negates_condition() {
+        let program = parse("until done\n  step\nend");
+
+        assert_eq!(program, vec![while_node(Node::Not(Box::new(vcall("done"))), vec![vcall("step")], false)]);
+    }
+
+    #[test]
+    fn test_while_condition_does_not_take_do_block() {
+        let program = parse("while queue.any? do\n  queue.pop\nend");
+
+        assert_eq!(
+            program,
+            vec![while_node(
+                call(Some(vcall("queue")), "any?", vec![]),
+                vec![call(Some(vcall("queue")), "pop", vec![])],
+                false
+            )]
+        );
+    }
+
+    #[test]
+    fn test_loop_modifiers() {
+        let program = parse("step while running\nstep until done");
+
+        assert_eq!(
+            program,
+            vec![
+                while_node(vcall("running"), vec![vcall("step")], false),
+                while_node(Node::Not(Box::new(vcall("done"))), vec![vcall("step")], false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_begin_end_while_is_a_do_while() {
+        let program = parse("begin\n  step\n  break if done\nend while true");
+
+        assert_eq!(
+            program,
+            vec![while_node(
+                Node::True,
+                vec![vcall("step"), if_node(vcall("done"), vec![Node::Break(None)], vec![])],
+                true
+            )]
+        );
+    }
+
+    #[test]
+    fn test_for_in_loop() {
+        let program = parse("for key, value in pairs do\n  next key if value\nend\nkey");
+
+        assert_eq!(
+            program,
+            vec![
+                Node::For(For {
+                    variables: vec!["key".to_string(), "value".to_string()],
+                    iterable: Box::new(vcall("pairs")),
+                    body: vec![if_node(local("value"), vec![Node::Next(Some(Box::new(local("key"))))], vec![])],
+                }),
+                local("key"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_loop_with_break_value_and_redo() {
+        let program = parse("loop do\n  break 42\n  redo\nend");
+
+        let block = block_of(&program[0]);
+        assert_eq!(block.body, vec![Node::Break(Some(Box::new(Node::Integer(42)))), Node::Redo]);
+    }
+
+    fn patterns_of(node: &Node) -> Vec<&Pattern> {
+        match node {
+            Node::CaseIn(case) => case.clauses.iter().map(|clause| &clause.pattern).collect(),
+            other => panic!("expected case/in, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_case_when() {
+        let program = parse("case x\nwhen 1, 2 then 'low'\nwhen String\n  'text'\nelse\n  'other'\nend");
+
+        assert_eq!(
+            program,
+            vec![Node::Case(Case {
+                subject: Some(Box::new(vcall("x"))),
+                whens: vec![
+                    When { conditions: vec![Node::Integer(1), Node::Integer(2)], body: vec![Node::Str("low".to_string())] },
+                    When { conditions: vec![constant("String")], body: vec![Node::Str("text".to_string())] },
+                ],
+                else_body: Some(vec![Node::Str("other".to_string())]),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_case_without_subject() {
+        let program = parse("case\nwhen a then 1\nend");
+
+        assert_eq!(
+            program,
+            vec![Node::Case(Case {
+                subject: None,
+                whens: vec![When { conditions: vec![vcall("a")], body: vec![Node::Integer(1)] }],
+                else_body: None,
+            })]
+        );
+    }
+
+    #[test]
+    fn test_case_in_with_guard() {
+        let program = parse("case value\nin Integer | Float => n if n > 0\n  n\nelse\n  0\nend");
+
+        assert_eq!(
+            program,
+            vec![Node::CaseIn(CaseIn {
+                subject: Box::new(vcall("value")),
+                clauses: vec![InClause {
+                    pattern: Pattern::Capture(
+                        Box::new(Pattern::Alternative(vec![
+                            Pattern::Value(constant("Integer")),
+                            Pattern::Value(constant("Float")),
+                        ])),
+                        "n".to_string()
+                    ),
+                    guard: Some(Box::new(call(Some(local("n")), ">", vec![Node::Integer(0)]))),
+                    body: vec![local("n")],
+                }],
+                else_body: Some(vec![Node::Integer(0)]),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_array_patterns() {
+        let program = parse("case list\nin [] then 0\nin [first, *rest] then first\nin Point[x, _]\nin a, b\nend");
+
+        assert_eq!(
+            patterns_of(&program[0]),
+            vec![
+                &Pattern::Array { constant: None, pre: vec![], rest: None, post: vec![] },
+                &Pattern::Array {
+                    constant: None,
+                    pre: vec![Pattern::Bind("first".to_string())],
+                    rest: Some(Some("rest".to_string())),
+                    post: vec![],
+                },
+                &Pattern::Array {
+                    constant: Some(constant("Point")),
+                    pre: vec![Pattern::Bind("x".to_string()), Pattern::Bind("_".to_string())],
+                    rest: None,
+                    post: vec![],
+                },
+                &Pattern::Array {
+                    constant: None,
+                    pre: vec![Pattern::Bind("a".to_string()), Pattern::Bind("b".to_string())],
+                    rest: None,
+                    post: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_pattern() {
+        let program = parse("case list\nin [*, :error, message, *post]\n  message\nend");
+
+        assert_eq!(
+            patterns_of(&program[0]),
+            vec![&Pattern::Find {
+                constant: None,
+                pre: None,
+                middle: vec![Pattern::Value(Node::Symbol("error".to_string())), Pattern::Bind("message".to_string())],
+                post: Some("post".to_string()),
+            }]
+        );
+        let Node::CaseIn(case) = &program[0] else { unreachable!() };
+        assert_eq!(case.clauses[0].body, vec![local("message")]);
+    }
+
+    #[test]
+    fn test_hash_patterns() {
+        let program = parse("case user\nin {name: String => name, age:}\nin role: :admin, **nil\nin Point(x:, **rest)\nend");
+
+        assert_eq!(
+            patterns_of(&program[0]),
+            vec![
+                &Pattern::Hash {
+                    constant: None,
+                    pairs: vec![
+                        (
+                            "name".to_string(),
+                            Some(Pattern::Capture(Box::new(Pattern::Value(constant("String"))), "name".to_string()))
+                        ),
+                        ("age".to_string(), None),
+                    ],
+                    rest: None,
+                },
+                &Pattern::Hash {
+                    constant: None,
+                    pairs: vec![("role".to_string(), Some(Pattern::Value(Node::Symbol("admin".to_string()))))],
+                    rest: Some(HashPatternRest::Nil),
+                },
+                &Pattern::Hash {
+                    constant: Some(constant("Point")),
+                    pairs: vec![("x".to_string(), None)],
+                    rest: Some(HashPatternRest::Named("rest".to_string())),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pin_patterns() {
+        let program = parse("expected = 1\ncase value\nin ^expected then true\nin ^(expected + 1) then false\nend");
+
+        assert_eq!(
+            patterns_of(&program[1]),
+            vec![
+                &Pattern::Pin(local("expected")),
+                &Pattern::Pin(call(Some(local("expected")), "+", vec![Node::Integer(1)])),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pin_requires_a_local() {
+        assert!(Parser::new("case value\nin ^missing\nend").parse_program().is_err());
+    }
+
+    fn rescue(classes: Vec<Node>, variable: Option<&str>, body: Vec<Node>) -> Rescue {
+        Rescue { classes, variable: variable.map(str::to_string), body }
+    }
+
+    #[test]
+    fn test_begin_rescue_else_ensure() {
+        let program = parse(
+            "begin\n  work\nrescue ArgumentError, TypeError => e\n  e\nrescue\n  retry\nelse\n  done\nensure\n  cleanup\nend",
+        );
+
+        assert_eq!(
+            program,
+            vec![Node::Begin(Begin {
+                body: vec![vcall("work")],
+                rescues: vec![
+                    rescue(vec![constant("ArgumentError"), constant("TypeError")], Some("e"), vec![local("e")]),
+                    rescue(vec![], None, vec![Node::Retry]),
+                ],
+                else_body: Some(vec![vcall("done")]),
+                ensure_body: Some(vec![vcall("cleanup")]),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_method_level_rescue() {
+        let program = parse("def fetch\n  load\nrescue IOError => error\n  raise(error)\nensure\n  close\nend");
+
+        assert_eq!(
+            def_of(&program[0]).body,
+            vec![Node::Begin(Begin {
+                body: vec![vcall("load")],
+                rescues: vec![rescue(vec![constant("IOError")], Some("error"), vec![call(None, "raise", vec![local("error")])])],
+                else_body: None,
+                ensure_body: Some(vec![vcall("close")]),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_do_block_rescue() {
+        let program = parse("each do |x|\n  work(x)\nrescue\n  skip\nend");
+
+        let block = block_of(&program[0]);
+        assert!(matches!(&block.body[..], [Node::Begin(begin)] if begin.rescues.len() == 1));
+    }
+
+    #[test]
+    fn test_rescue_modifier() {
+        let program = parse("value = load(input) rescue 0\nwork rescue nil");
+
+        let rescued = |node: Node, fallback: Node| {
+            Node::Begin(Begin {
+                body: vec![node],
+                rescues: vec![rescue(vec![], None, vec![fallback])],
+                else_body: None,
+                ensure_body: None,
+            })
+        };
+        assert_eq!(
+            program,
+            vec![
+                Node::LocalAssign(
+                    "value".to_string(),
+                    Box::new(rescued(call(None, "load", vec![vcall("input")]), Node::Integer(0)))
+                ),
+                rescued(vcall("work"), Node::Nil),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_retry_outside_rescue_is_an_error() {
+        assert!(Parser::new("retry").parse_program().is_err());
+        assert!(Parser::new("begin\n  retry\nend").parse_program().is_err());
+    }
+
+    #[test]
+    fn test_else_without_rescue_is_an_error() {
+        assert!(Parser::new("begin\n  work\nelse\n  other\nend").parse_program().is_err());
+    }
+
+    fn range(start: Option<Node>, end: Option<Node>, kind: RangeKind) -> Node {
+        Node::Range(start.map(Box::new), end.map(Box::new), kind)
+    }
+
+    #[test]
+    fn test_array_literals() {
+        let program = parse("[]\n[1, *rest, 'two',\n  :three,\n]");
+
+        assert_eq!(
+            program,
+            vec![
+                Node::Array(vec![]),
+                Node::Array(vec![
+                    Node::Integer(1),
+                    Node::Splat(Box::new(vcall("rest"))),
+                    Node::Str("two".to_string()),
+                    Node::Symbol("three".to_string()),
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_hash_literal_element_kinds() {
+        let program = parse("{ 1 => :one, name: 'x', \"quoted key\": 2, **defaults, }");
+
+        assert_eq!(
+            program,
+            vec![Node::Hash(vec![
+                HashElement::Pair(Node::Integer(1), Node::Symbol("one".to_string())),
+                HashElement::Pair(Node::Symbol("name".to_string()), Node::Str("x".to_string())),
+                HashElement::Pair(Node::Symbol("quoted key".to_string()), Node::Integer(2)),
+                HashElement::DoubleSplat(vcall("defaults")),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_nested_literals_across_lines() {
+        let program = parse("{\n  list: [1, [2]],\n  empty: {}\n}");
+
+        assert_eq!(
+            program,
+            vec![Node::Hash(vec![
+                HashElement::Pair(
+                    Node::Symbol("list".to_string()),
+                    Node::Array(vec![Node::Integer(1), Node::Array(vec![Node::Integer(2)])])
+                ),
+                HashElement::Pair(Node::Symbol("empty".to_string()), Node::Hash(vec![])),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let program = parse("1..10\n0...n\n(1..)\n(..5)\nlist[1..]");
+
+        assert_eq!(
+            program,
+            vec![
+                range(Some(Node::Integer(1)), Some(Node::Integer(10)), RangeKind::Inclusive),
+                range(Some(Node::Integer(0)), Some(vcall("n")), RangeKind::Exclusive),
+                range(Some(Node::Integer(1)), None, RangeKind::Inclusive),
+                range(None, Some(Node::Integer(5)), RangeKind::Inclusive),
+                call(Some(vcall("list")), "[]", vec![range(Some(Node::Integer(1)), None, RangeKind::Inclusive)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_range_binds_looser_than_arithmetic() {
+        let program = parse("a + 1..b * 2");
+
+        assert_eq!(
+            program,
+            vec![range(
+                Some(call(Some(vcall("a")), "+", vec![Node::Integer(1)])),
+                Some(call(Some(vcall("b")), "*", vec![Node::Integer(2)])),
+                RangeKind::Inclusive
+            )]
+        );
+    }
+
+    #[test]
+    fn test_index_expressions() {
+        let program = parse("list = [1]\nlist[0]\nlist [0]\nmatrix[1][2]");
+
+        assert_eq!(program[1], call(Some(local("list")), "[]", vec![Node::Integer(0)]));
+        assert_eq!(program[2], call(Some(local("list")), "[]", vec![Node::Integer(0)]));
+        assert_eq!(
+            program[3],
+            call(Some(call(Some(vcall("matrix")), "[]", vec![Node::Integer(1)])), "[]", vec![Node::Integer(2)])
+        );
+    }
+
+    #[test]
+    fn test_range_patterns() {
+        let program = parse("case age\nin ..17 then :minor\nin 18...65 then :adult\nin 65.. then :senior\nend");
+
+        assert_eq!(
+            patterns_of(&program[0]),
+            vec![
+                &Pattern::Value(range(None, Some(Node::Integer(17)), RangeKind::Inclusive)),
+                &Pattern::Value(range(Some(Node::Integer(18)), Some(Node::Integer(65)), RangeKind::Exclusive)),
+                &Pattern::Value(range(Some(Node::Integer(65)), None, RangeKind::Inclusive)),
+            ]
+        );
+    }
+
+    fn symbol(name: &str) -> Node {
+        Node::Symbol(name.to_string())
+    }
+
+    #[test]
+    fn test_command_call() {
+        let program = parse("puts 'Hello, World!'");
+
+        assert_eq!(program, vec![call(None, "puts", vec![Node::Str("Hello, World!".to_string())])]);
+    }
+
+    #[test]
+    fn test_command_call_with_multiple_args() {
+        let program = parse("puts a, b,\n  c");
+
+        assert_eq!(program, vec![call(None, "puts", vec![vcall("a"), vcall("b"), vcall("c")])]);
+    }
+
+    #[test]
+    fn test_braced_hash_argument_is_not_keywords() {
+        let program = parse("f({a: 1})\nf(a: 1)");
+        let pair = || vec![HashElement::Pair(symbol("a"), Node::Integer(1))];
+
+        assert_eq!(program, vec![call(None, "f", vec![Node::Hash(pair())]), call(None, "f", vec![Node::KeywordHash(pair())])]);
+    }
+
+    #[test]
+    fn test_command_call_with_trailing_hash() {
+        let program = parse("validates :name, presence: true, 'length' => 3");
+
+        assert_eq!(
+            program,
+            vec![call(
+                None,
+                "validates",
+                vec![
+                    symbol("name"),
+                    Node::KeywordHash(vec![
+                        HashElement::Pair(symbol("presence"), Node::True),
+                        HashElement::Pair(Node::Str("length".to_string()), Node::Integer(3)),
+                    ]),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_paren_call_with_splats_and_keywords() {
+        let program = parse("run(*args, verbose: true, **opts)");
+
+        assert_eq!(
+            program,
+            vec![call(
+                None,
+                "run",
+                vec![
+                    Node::Splat(Box::new(vcall("args"))),
+                    Node::KeywordHash(vec![
+                        HashElement::Pair(symbol("verbose"), Node::True),
+                        HashElement::DoubleSplat(vcall("opts")),
+                    ]),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_do_block_binds_to_the_command() {
+        let program = parse("foo bar do |x|\n  x\nend");
+
+        let Node::Call(foo) = &program[0] else {
+            panic!("expected a call, got {:?}", program[0]);
+        };
+        assert_eq!(foo.method, "foo");
+        assert_eq!(foo.args, vec![vcall("bar")]);
+        assert!(foo.block.is_some());
+    }
+
+    #[test]
+    fn test_brace_block_binds_to_the_last_argument() {
+        let program = parse("foo bar { |x| x }");
+
+        let Node::Call(foo) = &program[0] else {
+            panic!("expected a call, got {:?}", program[0]);
+        };
+        assert!(foo.block.is_none());
+        assert!(matches!(&foo.args[0], Node::Call(bar) if bar.method == "bar" && bar.block.is_some()));
+    }
+
+    #[test]
+    fn test_negative_number_literals() {
+        let program = parse("-2.abs\n- 2.abs\n-9223372036854775808\n-1.5 >> 1\n-(-9223372036854775808)\n-2 ** 2\n- 2 ** 2");
+
+        assert_eq!(
+            program,
+            vec![
+                call(Some(Node::Integer(-2)), "abs", vec![]),
+                call(Some(call(Some(Node::Integer(2)), "abs", vec![])), "-@", vec![]),
+                Node::Integer(i64::MIN),
+                call(Some(Node::Float(-1.5)), ">>", vec![Node::Integer(1)]),
+                Node::Bignum("9223372036854775808".to_string()),
+                call(Some(call(Some(Node::Integer(2)), "**", vec![Node::Integer(2)])), "-@", vec![]),
+                call(Some(call(Some(Node::Integer(2)), "**", vec![Node::Integer(2)])), "-@", vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_regexp_literals() {
+        let program = parse(r#"text =~ /\d+/i
+text.split(/,\s*/)"#);
+
+        assert_eq!(
+            program,
+            vec![
+                call(Some(call(None, "text", vec![])), "=~", vec![Node::Regexp(r"\d+".to_string(), "i".to_string())]),
+                call(Some(call(None, "text", vec![])), "split", vec![Node::Regexp(r",\s*".to_string(), String::new())]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_decides_unary_minus() {
+        let program = parse("foo -1\nfoo - 1\nfoo-1\nx = 2\nx -1");
+
+        assert_eq!(
+            program,
+            vec![
+                call(None, "foo", vec![Node::Integer(-1)]),
+                call(Some(vcall("foo")), "-", vec![Node::Integer(1)]),
+                call(Some(vcall("foo")), "-", vec![Node::Integer(1)]),
+                Node::LocalAssign("x".to_string(), Box::new(Node::Integer(2))),
+                call(Some(local("x")), "-", vec![Node::Integer(1)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_whitespace_decides_index_or_array_argument() {
+        let program = parse("foo [1]\nfoo[1]");
+
+        assert_eq!(
+            program,
+            vec![
+                call(None, "foo", vec![Node::Array(vec![Node::Integer(1)])]),
+                call(Some(vcall("foo")), "[]", vec![Node::Integer(1)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nested_commands_and_receivers() {
+        let program = parse("list.push item, other if ready");
+
+        assert_eq!(
+            program,
+            vec![if_node(
+                vcall("ready"),
+                vec![call(Some(vcall("list")), "push", vec![vcall("item"), vcall("other")])],
+                vec![]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_return_multiple_values() {
+        let program = parse("def pair\n  return 1, 2\nend");
+
+        assert_eq!(
+            def_of(&program[0]).body,
+            vec![Node::Return(Some(Box::new(Node::Array(vec![Node::Integer(1), Node::Integer(2)]))))]
+        );
+    }
+
+    #[test]
+    fn test_yield() {
+        let program = parse("def each\n  yield\n  yield 1, 2\n  yield(3) + 1\nend");
+
+        assert_eq!(
+            def_of(&program[0]).body,
+            vec![
+                Node::Yield(vec![]),
+                Node::Yield(vec![Node::Integer(1), Node::Integer(2)]),
+                call(Some(Node::Yield(vec![Node::Integer(3)])), "+", vec![Node::Integer(1)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_super() {
+        let program = parse("def initialize(a, *rest, key:, &block)\n  super\n  super()\n  super a, 1\n  super { 2 }\nend");
+        let body = &def_of(&program[0]).body;
+
+        assert_eq!(body[0], Node::Super(Super { args: None, block: None, line: Line::default() }));
+        assert_eq!(body[1], Node::Super(Super { args: Some(vec![]), block: None, line: Line::default() }));
+        assert_eq!(body[2], Node::Super(Super { args: Some(vec![local("a"), Node::Integer(1)]), block: None, line: Line::default() }));
+        assert!(matches!(&body[3], Node::Super(Super { args: None, block: Some(_), .. })));
+
+        // A bare `super` passes the parameters along
+        assert_eq!(
+            def_of(&program[0]).params.forwarded(),
+            vec![
+                local("a"),
+                Node::Splat(Box::new(local("rest"))),
+                Node::KeywordHash(vec![HashElement::Pair(Node::Symbol("key".to_string()), local("key"))]),
+                Node::BlockPass(Box::new(local("block"))),
+            ]
+        );
+    }
+
+    fn target(name: &str) -> Target {
+        Target::Local(name.to_string())
+    }
+
+    #[test]
+    fn test_swap_with_multiple_assignment() {
+        let program = parse("a = 1\nb = 2\na, b = b, a");
+
+        assert_eq!(
+            program[2],
+            Node::MultipleAssign(vec![target("a"), target("b")], Box::new(Node::Array(vec![local("b"), local("a")])))
+        );
+    }
+
+    #[test]
+    fn test_multiple_assignment_with_splat_and_nesting() {
+        let program = parse("first, *rest = list\n(a, b), c = pairs\nhead, = list\nrest");
+
+        assert_eq!(
+            program,
+            vec![
+                Node::MultipleAssign(
+                    vec![target("first"), Target::Splat(Some(Box::new(target("rest"))))],
+                    Box::new(vcall("list"))
+                ),
+                Node::MultipleAssign(
+                    vec![Target::Nested(vec![target("a"), target("b")]), target("c")],
+                    Box::new(vcall("pairs"))
+                ),
+                Node::MultipleAssign(vec![target("head"), Target::Splat(None)], Box::new(vcall("list"))),
+                local("rest"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_multiple_assignment_to_attributes_and_indexes() {
+        let program = parse("self.x, pairs[0] = *values");
+
+        assert_eq!(
+            program,
+            vec![Node::MultipleAssign(
+                vec![
+                    Target::Attribute(Box::new(Node::SelfNode), "x".to_string()),
+                    Target::Index(Box::new(vcall("pairs")), vec![Node::Integer(0)]),
+                ],
+                Box::new(Node::Array(vec![Node::Splat(Box::new(vcall("values")))]))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_command_arguments_are_not_multiple_assignment() {
+        let program = parse("foo a, b = 1");
+
+        assert_eq!(
+            program,
+            vec![call(
+                None,
+                "foo",
+                vec![vcall("a"), Node::LocalAssign("b".to_string(), Box::new(Node::Integer(1)))]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_instance_variable_assignment() {
+        let program = parse("@count = @count + 1");
+
+        assert_eq!(
+            program,
+            vec![Node::InstanceVariableAssign(
+                "count".to_string(),
+                Box::new(call(Some(Node::InstanceVariable("count".to_string())), "+", vec![Node::Integer(1)]))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_constant_assignment() {
+        let program = parse("LIMIT = 10\nConfig::DEFAULT = LIMIT");
+
+        assert_eq!(
+            program,
+            vec![
+                Node::ConstantAssign(Box::new(Node::Constant("LIMIT".to_string())), Box::new(Node::Integer(10))),
+                Node::ConstantAssign(
+                    Box::new(Node::ScopedConstant(Some(Box::new(Node::Constant("Config".to_string()))), "DEFAULT".to_string())),
+                    Box::new(Node::Constant("LIMIT".to_string()))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_attribute_and_index_assignment_are_setter_calls() {
+        let program = parse("user.name = other\ncache[key] = value");
+
+        assert_eq!(
+            program,
+            vec![
+                call(Some(vcall("user")), "name=", vec![vcall("other")]),
+                call(Some(vcall("cache")), "[]=", vec![vcall("key"), vcall("value")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_call_lines() {
+        let program = parse("x = 1\nputs(x,\n  x + 2)\nh[:a] -= 1\ndef m\n\n  super\nend");
+        let Node::Call(call) = &program[1] else { panic!("expected a call, got {:?}", program[1]) };
+        assert_eq!(call.line.0, 2);
+        let Node::Call(sum) = &call.args[1] else { panic!("expected a call, got {:?}", call.args[1]) };
+        assert_eq!(sum.line.0, 3);
+        let Node::OpAssign(op_assign) = &program[2] else { panic!("expected an op-assign, got {:?}", program[2]) };
+        assert_eq!(op_assign.line.0, 4);
+        let Node::Super(node) = &def_of(&program[3]).body[0] else { panic!("expected super") };
+        assert_eq!(node.line.0, 7);
+    }
+
+    #[test]
+    fn test_operator_assignment() {
+        let program = parse("total += 1\n@items ||= []\nh[k] ||= []\nobj.count -= step");
+
+        let op_assign = |target, operator: &str, value| {
+            Node::OpAssign(OpAssign { target, operator: operator.to_string(), value: Box::new(value), line: Line::default() })
+        };
+        assert_eq!(
+            program,
+            vec![
+                op_assign(target("total"), "+", Node::Integer(1)),
+                op_assign(Target::InstanceVariable("items".to_string()), "||", Node::Array(vec![])),
+                op_assign(Target::Index(Box::new(vcall("h")), vec![vcall("k")]), "||", Node::Array(vec![])),
+                op_assign(Target::Attribute(Box::new(vcall("obj")), "count".to_string()), "-", vcall("step")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_operator_assignment_declares_local() {
+        let program = parse("memo ||= 0\nmemo");
+
+        assert_eq!(program[1], local("memo"));
+    }
+
+    #[test]
+    fn test_invalid_assignment_targets() {
+        assert!(Parser::new("foo(1) = 2").parse_program().is_err());
+        assert!(Parser::new("a + b += 1").parse_program().is_err());
+        assert!(Parser::new("*a, *b = list").parse_program().is_err());
+    }
+
+    fn lambda_of(node: &Node) -> &Block {
+        match node {
+            Node::Lambda(block) => block,
+            other => panic!("expected a lambda, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_stabby_lambda() {
+        let program = parse("->(x, y = 1) { x + y }");
+
+        let block = lambda_of(&program[0]);
+        assert_eq!(
+            block.params,
+            BlockParams::Explicit(Params {
+                params: vec![Param::Required("x".to_string()), Param::Optional("y".to_string(), Node::Integer(1))],
+                locals: vec![],
+            })
+        );
+        assert_eq!(block.body, vec![call(Some(local("x")), "+", vec![local("y")])]);
+    }
+
+    #[test]
+    fn test_stabby_lambda_forms() {
+        let program = parse("-> x { x }\n-> { it }\n-> do\n  1\nend");
+
+        assert_eq!(
+            lambda_of(&program[0]).params,
+            BlockParams::Explicit(Params { params: vec![Param::Required("x".to_string())], locals: vec![] })
+        );
+        assert_eq!(lambda_of(&program[0]).body, vec![local("x")]);
+        assert_eq!(lambda_of(&program[1]).params, BlockParams::It);
+        assert_eq!(lambda_of(&program[2]).body, vec![Node::Integer(1)]);
+    }
+
+    #[test]
+    fn test_lambda_as_command_argument() {
+        let program = parse("scope :active, -> { where(active: true) }");
+
+        let Node::Call(scope) = &program[0] else {
+            panic!("expected a call, got {:?}", program[0]);
+        };
+        assert_eq!(scope.args[0], symbol("active"));
+        assert!(matches!(scope.args[1], Node::Lambda(_)));
+    }
+
+    #[test]
+    fn test_lambda_and_proc_are_calls_with_blocks() {
+        let program = parse("lambda { |x| x }\nproc { 1 }\nProc.new { 1 }");
+
+        assert!(matches!(&program[0], Node::Call(Call { method, block: Some(_), .. }) if method == "lambda"));
+        assert!(matches!(&program[1], Node::Call(Call { method, block: Some(_), .. }) if method == "proc"));
+        assert!(matches!(&program[2], Node::Call(Call { method, block: Some(_), .. }) if method == "new"));
+    }
+
+    #[test]
+    fn test_block_pass() {
+        let program = parse("names.map(&:upcase)\neach &handler\nfind(1, key: 2, &blk)");
+
+        assert_eq!(
+            program,
+            vec![
+                call(Some(vcall("names")), "map", vec![Node::BlockPass(Box::new(symbol("upcase")))]),
+                call(None, "each", vec![Node::BlockPass(Box::new(vcall("handler")))]),
+                call(
+                    None,
+                    "find",
+                    vec![
+                        Node::Integer(1),
+                        Node::KeywordHash(vec![HashElement::Pair(symbol("key"), Node::Integer(2))]),
+                        Node::BlockPass(Box::new(vcall("blk"))),
+                    ]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_block_pass_with_literal_block_is_an_error() {
+        assert!(Parser::new("map(&:upcase) { |x| x }").parse_program().is_err());
+        assert!(Parser::new("map(&:upcase, 1)").parse_program().is_err());
+    }
+
+    #[test]
+    fn test_invoking_callables() {
+        let program = parse("add = ->(a, b) { a + b }\nadd.(1, 2)\nadd.call(1, 2)\nadd[1, 2]");
+
+        let args = vec![Node::Integer(1), Node::Integer(2)];
+        assert_eq!(
+            program[1..],
+            [
+                call(Some(local("add")), "call", args.clone()),
+                call(Some(local("add")), "call", args.clone()),
+                call(Some(local("add")), "[]", args),
+            ]
+        );
+    }
+
+    fn diagnostics(input: &str) -> Vec<(usize, String)> {
+        let (_, diagnostics) = Parser::new(input).parse_with_recovery();
+        diagnostics.into_iter().map(|error| (error.line, error.message)).collect()
+    }
+
+    #[test]
+    fn test_reports_every_syntax_error() {
+        let (program, diagnostics) = Parser::new("x = )\ny = 1\nfoo(]\nz").parse_with_recovery();
+
+        assert_eq!(diagnostics.iter().map(|error| error.line).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(
+            program,
+            vec![Node::Error, Node::LocalAssign("y".to_string(), Box::new(Node::Integer(1))), Node::Error, vcall("z")]
+        );
+    }
+
+    #[test]
+    fn test_recovers_inside_bodies() {
+        let (program, diagnostics) = Parser::new("def greet\n  x = )\n  hello\nend\ngreet").parse_with_recovery();
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(def_of(&program[0]).body, vec![Node::Error, vcall("hello")]);
+        assert_eq!(program[1], vcall("greet"));
+    }
+
+    #[test]
+    fn test_missing_end_at_end_of_file() {
+        assert_eq!(
+            diagnostics("x = 1\nwhile x\n  x = x - 1\n"),
+            vec![(4, "missing `end` for `while` opened at line 2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_missing_end_guessed_from_next_definition() {
+        let input = "class Greeter\n  def hello\n    puts 1\n\n  def bye\n    puts 2\n  end\nend\n";
+
+        assert_eq!(diagnostics(input), vec![(5, "missing `end` for `def` opened at line 2".to_string())]);
+        let (program, _) = Parser::new(input).parse_with_recovery();
+        let Node::Class(class) = &program[0] else {
+            panic!("expected a class, got {:?}", program[0]);
+        };
+        assert_eq!(class.body.len(), 2);
+    }
+
+    #[test]
+    fn test_missing_end_guessed_from_dedented_end() {
+        let input = "def check(x)\n  if x\n    puts x\nend\n";
+
+        assert_eq!(diagnostics(input), vec![(4, "missing `end` for `if` opened at line 2".to_string())]);
+    }
+
+    #[test]
+    fn test_missing_end_reported_for_every_opener() {
+        let input = "class C\n  def c\n    1\nclass D\nend\n";
+
+        assert_eq!(
+            diagnostics(input),
+            vec![
+                (4, "missing `end` for `def` opened at line 2".to_string()),
+                (4, "missing `end` for `class` opened at line 1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diagnostics_quote_source_text() {
+        assert_eq!(
+            diagnostics("end
+foo(1]
+1 = 2
+def f(*a, b = 1)
+end
+class foo
+end
+x = 1 +"),
+            vec![
+                (1, "unexpected `end`".to_string()),
+                (2, "expected `)`, found `]`".to_string()),
+                (3, "cannot assign to `1`".to_string()),
+                (4, "unexpected parameter `b = 1`".to_string()),
+                (5, "unexpected `end`".to_string()),
+                (6, "expected a constant name, found `foo`".to_string()),
+                (7, "unexpected `end`".to_string()),
+                (8, "unexpected end of input".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_missing_end_before_closing_brace() {
+        assert_eq!(
+            diagnostics("each { |x|\n  if x\n    puts x\n}"),
+            vec![(4, "missing `end` for `if` opened at line 2".to_string())]
+        );
+    }
+}
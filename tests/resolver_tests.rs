@@ -0,0 +1,87 @@
+#[cfg(test)]
+mod resolver_tests {
+    use chimiaguin::parser::Parser;
+    use chimiaguin::resolver::{ConstantKind, ConstantTree};
+    use chimiaguin::runtime::Runtime;
+
+    fn add(tree: &mut ConstantTree, input: &str) -> Result<(), String> {
+        let program = Parser::new(input).parse_program().expect("program should parse");
+        tree.add_program(&program).map_err(|error| error.message)
+    }
+
+    #[test]
+    fn test_builds_nested_constants() {
+        let mut tree = ConstantTree::new();
+        add(&mut tree, "module Zoo\n  class Animal\n  end\n  class Dog < Animal\n  end\nend").unwrap();
+
+        assert_eq!(tree.lookup("Zoo").unwrap().kind, ConstantKind::Module);
+        assert_eq!(tree.lookup("Zoo::Animal").unwrap().kind, ConstantKind::Class);
+        assert_eq!(tree.lookup("Zoo::Dog").unwrap().superclass, Some("Zoo::Animal".to_string()));
+        assert!(tree.lookup("Dog").is_none());
+    }
+
+    #[test]
+    fn test_reopens_classes_across_programs() {
+        let mut tree = ConstantTree::new();
+        add(&mut tree, "class Animal\nend\nclass Dog < Animal\n  def bark\n  end\nend").unwrap();
+        add(&mut tree, "class Dog\n  def self.create\n  end\n  def fetch\n  end\nend").unwrap();
+        add(&mut tree, "class Dog < Animal\n  class << self\n    def breeds\n    end\n  end\nend").unwrap();
+
+        let dog = tree.lookup("Dog").unwrap();
+        assert_eq!(dog.instance_methods, vec!["bark".to_string(), "fetch".to_string()]);
+        assert_eq!(dog.singleton_methods, vec!["create".to_string(), "breeds".to_string()]);
+    }
+
+    #[test]
+    fn test_scoped_class_path() {
+        let mut tree = ConstantTree::new();
+        add(&mut tree, "module A\nend\nclass A::B\nend\nmodule A\n  class B::C\n  end\nend").unwrap();
+
+        assert!(tree.lookup("A::B::C").is_some());
+    }
+
+    #[test]
+    fn test_superclass_mismatch() {
+        let mut tree = ConstantTree::new();
+        add(&mut tree, "class Animal\nend\nclass Plant\nend\nclass Dog < Animal\nend").unwrap();
+
+        let result = add(&mut tree, "class Dog < Plant\nend");
+        assert_eq!(result, Err("superclass mismatch for class Dog".to_string()));
+
+        let mut tree = ConstantTree::with_runtime(&Runtime::new());
+        add(&mut tree, "class Dog\nend\nclass Dog < Object\nend").unwrap();
+        assert_eq!(add(&mut tree, "class Dog < Exception\nend"), Err("superclass mismatch for class Dog".to_string()));
+    }
+
+    #[test]
+    fn test_reopening_with_a_different_kind() {
+        let mut tree = ConstantTree::new();
+        add(&mut tree, "class Dog\nend").unwrap();
+
+        assert_eq!(add(&mut tree, "module Dog\nend"), Err("Dog is not a module".to_string()));
+    }
+
+    #[test]
+    fn test_uninitialized_constant() {
+        let mut tree = ConstantTree::new();
+
+        assert_eq!(add(&mut tree, "class Dog < Animal\nend"), Err("uninitialized constant Animal".to_string()));
+        assert_eq!(add(&mut tree, "class Zoo::Dog\nend"), Err("uninitialized constant Zoo".to_string()));
+    }
+
+    #[test]
+    fn test_core_constants_from_the_runtime() {
+        let mut tree = ConstantTree::with_runtime(&Runtime::new());
+        add(&mut tree, "class MyError < StandardError\nend\nclass String\n  def shout\n  end\nend").unwrap();
+        add(&mut tree, "class Encoding::CompatibilityError\nend\nmodule Kernel\nend").unwrap();
+
+        assert_eq!(tree.lookup("MyError").unwrap().superclass, Some("StandardError".to_string()));
+        assert_eq!(tree.lookup("KeyError").unwrap().superclass, Some("IndexError".to_string()));
+        assert_eq!(tree.lookup("Enumerable").unwrap().kind, ConstantKind::Module);
+        assert_eq!(add(&mut tree, "module String\nend"), Err("String is not a module".to_string()));
+        add(&mut tree, "class Object\n  def helper\n  end\nend\nclass MyError\nend\nclass String < Object\nend").unwrap();
+        assert_eq!(tree.lookup("Object").unwrap().superclass, Some("BasicObject".to_string()));
+        assert_eq!(tree.lookup("String").unwrap().superclass, None);
+        assert_eq!(tree.outline(), "class MyError < StandardError\nclass Object\n  def helper\nclass String\n  def shout\n");
+    }
+}
@@ -0,0 +1,1121 @@
+#[cfg(test)]
+mod script_tests {
+    use chimiaguin::interp::Interpreter;
+    use chimiaguin::runtime::{EvalError, Runtime, Value};
+    use chimiaguin::vm::Vm;
+
+    // Every program here runs on both backends, which have to agree on what it does
+    trait Backend {
+        fn eval(&mut self, input: &str) -> Result<Value, EvalError>;
+        fn runtime(&mut self) -> &mut Runtime;
+    }
+
+    macro_rules! backend {
+        ($($backend:ty),*) => {
+            $(impl Backend for $backend {
+                fn eval(&mut self, input: &str) -> Result<Value, EvalError> {
+                    <$backend>::eval(self, input)
+                }
+
+                fn runtime(&mut self) -> &mut Runtime {
+                    <$backend>::runtime(self)
+                }
+            })*
+        };
+    }
+
+    backend!(Interpreter, Vm);
+
+    fn backends() -> [(&'static str, Box<dyn Backend>); 2] {
+        [("interpreter", Box::new(Interpreter::new())), ("VM", Box::new(Vm::new()))]
+    }
+
+    // What `run` gives on each backend, which has to be the same on both
+    fn agreed<T: PartialEq + std::fmt::Debug>(input: &str, mut run: impl FnMut(&str, &mut dyn Backend) -> T) -> T {
+        let [(first, mut interpreter), (second, mut vm)] = backends();
+        let (expected, actual) = (run(first, interpreter.as_mut()), run(second, vm.as_mut()));
+        assert_eq!(expected, actual, "the {} and the {} differ on:\n{}", first, second, input);
+        actual
+    }
+
+    // Runs the program and returns what it printed
+    fn run(input: &str) -> String {
+        agreed(input, |name, backend| {
+            backend.runtime().capture_output();
+            if let Err(error) = backend.eval(input) {
+                panic!("program failed in the {}: {}", name, error);
+            }
+            backend.runtime().take_output()
+        })
+    }
+
+    fn error(input: &str) -> (String, String) {
+        agreed(input, |name, backend| match backend.eval(input) {
+            Err(EvalError::Exception { class, message, .. }) => (class, message),
+            other => panic!("expected an exception in the {}, got {:?}", name, other),
+        })
+    }
+
+    // What the program printed before it raised, and the class of what it raised
+    fn run_until_error(input: &str) -> (String, String) {
+        agreed(input, |name, backend| {
+            backend.runtime().capture_output();
+            match backend.eval(input) {
+                Err(EvalError::Exception { class, .. }) => (backend.runtime().take_output(), class),
+                other => panic!("expected an exception in the {}, got {:?}", name, other),
+            }
+        })
+    }
+
+    #[test]
+    fn test_value_of_last_statement() {
+        for (_, mut backend) in backends() {
+            assert_eq!(backend.eval("x = 2\nx * 21"), Ok(Value::fixnum(42).unwrap()));
+            assert_eq!(backend.eval("nil"), Ok(Value::NIL));
+        }
+    }
+
+    #[test]
+    fn test_methods_and_locals() {
+        let input = "def add(a, b)\n  a + b\nend\n\nsum = add(1, 2)\nputs sum\nputs add(sum, 10)";
+        assert_eq!(run(input), "3\n13\n");
+    }
+
+    #[test]
+    fn test_classes_and_inheritance() {
+        let input = "class Animal
+  def initialize(name)
+    @name = name
+  end
+
+  def name
+    @name
+  end
+
+  def speak
+    name + ' makes a sound'
+  end
+end
+
+class Dog < Animal
+  def speak
+    name + ' says woof'
+  end
+end
+
+puts Animal.new('Cat').speak
+puts Dog.new('Rex').speak
+p Dog.new('Rex')
+p Dog.superclass, Dog.new('Rex').is_a?(Animal)";
+        assert_eq!(run(input), "Cat makes a sound\nRex says woof\n#<Dog @name=\"Rex\">\nAnimal\ntrue\n");
+    }
+
+    #[test]
+    fn test_blocks_and_yield() {
+        let input = "def twice
+  yield 1
+  yield 2
+end
+
+total = 0
+twice { |x| total += x }
+p total
+p [1, 2, 3].map { |x| x * x }
+p [1, 2, 3].map(&:to_s)
+p(block_given?)";
+        assert_eq!(run(input), "3\n[1, 4, 9]\n[\"1\", \"2\", \"3\"]\nfalse\n");
+    }
+
+    #[test]
+    fn test_break_next_and_return_from_blocks() {
+        let input = "p [1, 2, 3].each { |x| break x * 10 if x == 2 }
+p [1, 2, 3].map { |x| next 0 if x == 2; x }
+
+def first_even(list)
+  list.each { |x| return x if x.even? }
+  nil
+end
+p first_even([1, 4, 6])
+
+def make_proc
+  proc { return 1 }
+end
+make_proc.call";
+        assert_eq!(run_until_error(input), ("20\n[1, 0, 3]\n4\n".to_string(), "LocalJumpError".to_string()));
+    }
+
+    #[test]
+    fn test_lambdas_check_arity() {
+        assert_eq!(run("l = ->(x, y = 2) { return x + y }\np l.(1), l.call(1, 3)"), "3\n4\n");
+        assert_eq!(run("pr = proc { |a, b| [a, b] }\np pr.call(1), pr.call([2, 3], 4)"), "[1, nil]\n[[2, 3], 4]\n");
+        assert_eq!(
+            error("->(x) { x }.call(1, 2)"),
+            ("ArgumentError".to_string(), "wrong number of arguments (given 2, expected 1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_arguments() {
+        let input = "def f(a, b = 2, *rest, c, key: 1, **options, &block)
+  [a, b, rest, c, key, options]
+end
+p f(1, 9)
+p f(1, 2, 3, 4, 5, key: 6, other: 7)
+args = [1, 2]
+p f(*args)";
+        let output = "[1, 2, [], 9, 1, {}]\n[1, 2, [3, 4], 5, 6, {other: 7}]\n[1, 2, [], 2, 1, {}]\n";
+        assert_eq!(run(input), output);
+        assert_eq!(
+            error("def f(a, b = 1)\nend\nf"),
+            ("ArgumentError".to_string(), "wrong number of arguments (given 0, expected 1..2)".to_string())
+        );
+        assert_eq!(error("def f(key:)\nend\nf"), ("ArgumentError".to_string(), "missing keyword: :key".to_string()));
+    }
+
+    #[test]
+    fn test_only_keyword_arguments_bind_keywords() {
+        let input = "def kw(a, b: 2) = [a, b]
+p kw({x: 1}), kw({x: 1}, b: 3), kw(1, **{b: 4})
+def opts(h) = h
+def rest(*args, **kw) = [args, kw]
+p opts(a: 1), rest(1, {a: 2}), rest(1, a: 2), rest(**{})
+class Point
+  def initialize(x:, y: 0)
+    @x = x
+    @y = y
+  end
+
+  def to_s = \"(#{@x}, #{@y})\"
+end
+puts Point.new(x: 1, y: 2)
+def both(a, k: 1) = yield(a, k: k)
+p(both(3, k: 7) { |a, k: 0| [a, k] })";
+        let expected = "[{x: 1}, 2]
+[{x: 1}, 3]
+[1, 4]
+{a: 1}
+[[1, {a: 2}], {}]
+[[1], {a: 2}]
+[[], {}]
+(1, 2)
+[3, 7]
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(
+            error("def f(*args) = g(*args)\ndef g(a, k: 1) = a\nf(1, k: 2)"),
+            ("ArgumentError".to_string(), "wrong number of arguments (given 2, expected 1)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_assignments() {
+        let input = "a, (b, c), *d = 1, [2, 3], 4, 5
+p [a, b, c, d]
+h = {}
+h[:x] ||= 1
+h[:x] ||= 2
+h[:x] += 10
+p h
+class Box
+  def value
+    @value
+  end
+
+  def value=(value)
+    @value = value
+  end
+end
+box = Box.new
+p(box.value = 5)
+box.value *= 2
+p box.value
+LIMIT = 3
+module Config
+  SIZE = LIMIT * 2
+end
+Config::NAME = 'config'
+::TOP = :top
+p(LIMIT, Config::SIZE, Config::NAME, TOP, (FIVE = 5))";
+        assert_eq!(run(input), "[1, 2, 3, [4, 5]]\n{x: 11}\n5\n10\n3\n6\n\"config\"\n:top\n5\n");
+    }
+
+    #[test]
+    fn test_control_flow() {
+        let input = "i = 0
+i += 1 while i < 5
+p i
+for a, b in [[1, 2], [3, 4]]
+  p a + b
+end
+p a
+case 5
+when 1..3 then puts 'low'
+when 4..6 then puts 'mid'
+else puts 'high'
+end
+x = if i > 3 then 'big' else 'small' end
+puts x
+puts(nil || 'default')";
+        assert_eq!(run(input), "5\n3\n7\n3\nmid\nbig\ndefault\n");
+    }
+
+    #[test]
+    fn test_exceptions() {
+        let input = "attempts = 0
+begin
+  attempts += 1
+  raise ArgumentError, 'bad' if attempts < 3
+  puts 'done'
+rescue TypeError
+  puts 'wrong'
+rescue ArgumentError => e
+  p e
+  retry
+else
+  puts 'no error'
+ensure
+  puts 'ensure'
+end
+def risky
+  yield
+rescue ZeroDivisionError => e
+  e.message
+end
+p risky { 1 / 0 }";
+        let output = "#<ArgumentError: bad>\n#<ArgumentError: bad>\ndone\nno error\nensure\n\"divided by 0\"\n";
+        assert_eq!(run(input), output);
+    }
+
+    #[test]
+    fn test_raising_exception_objects() {
+        let input = "begin
+  raise TypeError.new('made')
+rescue => e
+  p e
+end
+error = ArgumentError.new('kept')
+begin
+  raise error, 'copied'
+rescue => e
+  p e, error, e.equal?(error)
+end
+begin
+  begin
+    raise 'first'
+  rescue => e
+    begin
+      raise 'second'
+    rescue
+    end
+    raise
+  end
+rescue => again
+  p again.equal?(e), again.backtrace
+end
+begin
+  begin
+    raise KeyError, 'inner'
+  rescue => e
+    raise e
+  end
+rescue KeyError => e
+  p e
+end
+begin
+  raise
+rescue => e
+  p e
+end
+p RuntimeError.new('never raised').backtrace";
+        let expected = "#<TypeError: made>
+#<ArgumentError: copied>
+#<ArgumentError: kept>
+false
+true
+[\"14:in '<main>'\"]
+#<KeyError: inner>
+#<RuntimeError: unhandled exception>
+nil
+";
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn test_uncaught_exceptions() {
+        assert_eq!(error("raise 'boom'"), ("RuntimeError".to_string(), "boom".to_string()));
+        assert_eq!(
+            error("foo"),
+            ("NameError".to_string(), "undefined local variable or method 'foo' for main:Object".to_string())
+        );
+        assert_eq!(error("nil.upcase"), ("NoMethodError".to_string(), "undefined method 'upcase' for nil".to_string()));
+        assert_eq!(error("Missing"), ("NameError".to_string(), "uninitialized constant Missing".to_string()));
+        assert_eq!(error("yield"), ("LocalJumpError".to_string(), "no block given (yield)".to_string()));
+
+        let input = "def inner\n  raise 'boom'\nend\ndef outer\n  inner\nend\nouter";
+        let backtrace = agreed(input, |name, backend| match backend.eval(input) {
+            Err(EvalError::Exception { backtrace, .. }) => backtrace,
+            other => panic!("expected an exception in the {}, got {:?}", name, other),
+        });
+        assert_eq!(backtrace, vec!["2:in 'inner'", "5:in 'outer'", "7:in '<main>'"]);
+    }
+
+    #[test]
+    fn test_pattern_matching() {
+        let input = "def describe(value)
+  case value
+  in [] then 'empty'
+  in [Integer => first, *rest] then 'starts with ' + first.to_s + ', ' + rest.length.to_s + ' more'
+  in {name: String => name, **rest} then name
+  in Integer | nil then 'scalar'
+  end
+end
+puts describe([])
+puts describe([1, 2, 3])
+puts describe({name: 'Ada', age: 36})
+puts describe(nil)";
+        assert_eq!(run(input), "empty\nstarts with 1, 2 more\nAda\nscalar\n");
+        assert_eq!(error("case 1\nin String then 2\nend"), ("NoMatchingPatternError".to_string(), "1".to_string()));
+    }
+
+    #[test]
+    fn test_modules_and_singletons() {
+        let input = "module Shapes
+  class Square
+    def self.unit
+      new(1)
+    end
+
+    def initialize(side)
+      @side = side
+    end
+
+    def area
+      @side * @side
+    end
+  end
+end
+p Shapes::Square.unit.area
+p Shapes::Square.new(3).area
+p Shapes::Square
+class << Shapes
+  def names
+    [:square]
+  end
+end
+p Shapes.names";
+        assert_eq!(run(input), "1\n9\nShapes::Square\n[:square]\n");
+    }
+
+    #[test]
+    fn test_syntax_errors_are_not_run() {
+        for (_, mut backend) in backends() {
+            backend.runtime().capture_output();
+            assert!(matches!(backend.eval("puts 1\ndef"), Err(EvalError::Syntax(_))));
+            assert_eq!(backend.runtime().take_output(), "");
+        }
+    }
+
+    #[test]
+    fn test_ensure_runs_when_leaving_loops() {
+        let input = "i = 0
+while i < 5
+  i += 1
+  next if i == 2
+  begin
+    break if i == 4
+  ensure
+    puts 'e'
+  end
+  p i
+end
+tries = 0
+begin
+  tries += 1
+  raise 'again' if tries < 3
+  p tries
+rescue
+  retry
+end";
+        assert_eq!(run(input), "e\n1\ne\n3\ne\n3\n");
+    }
+
+    #[test]
+    fn test_method_definitions_invalidate_inline_caches() {
+        let input = "class Greeter
+  def greet = 'hello'
+end
+g = Greeter.new
+results = []
+3.times do |i|
+  results << g.greet
+  if i == 0
+    class Greeter
+      def greet = 'hi'
+    end
+  end
+end
+p results
+def g.greet = 'hey'
+puts g.greet";
+        assert_eq!(run(input), "[\"hello\", \"hi\", \"hi\"]\nhey\n");
+    }
+
+    #[test]
+    fn test_mixins_and_super() {
+        let input = "module Greeting
+  def greet = 'hello, ' + super
+end
+module Loud
+  def greet = super.upcase
+end
+class Base
+  def initialize(name, tag: 'base')
+    @name = name
+    @tag = tag
+  end
+
+  def greet = @name
+end
+class Child < Base
+  include Greeting
+  prepend Loud
+
+  def initialize(name, tag: 'child')
+    super
+  end
+
+  def greet = '(' + super() + ')'
+end
+child = Child.new('c', tag: 'given')
+p child
+puts child.greet
+p Child.ancestors
+p Child.include?(Greeting), Child < Base, Base < Child, Base <= Greeting
+p BasicObject.superclass, Object.superclass
+module Shout
+  def shout = to_s + '!'
+end
+o = Object.new
+def o.to_s = 'o'
+o.extend(Shout)
+p o.shout, o.is_a?(Shout)
+class Anonymous < Base
+  def initialize(*, **, &)
+    super
+  end
+end
+p Anonymous.new('a', tag: 'kept')";
+        let expected = "#<Child @name=\"c\", @tag=\"given\">
+(HELLO, C)
+[Loud, Child, Greeting, Base, Object, Kernel, BasicObject]
+true
+true
+false
+nil
+nil
+BasicObject
+\"o!\"
+true
+#<Anonymous @name=\"a\", @tag=\"kept\">
+";
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn test_super_errors() {
+        assert_eq!(
+            error("class Broken\n  def missing = super\nend\nBroken.new.missing"),
+            ("NoMethodError".to_string(), "super: no superclass method 'missing' for an instance of Broken".to_string())
+        );
+        assert_eq!(error("super"), ("RuntimeError".to_string(), "super called outside of method".to_string()));
+        assert_eq!(
+            error("module A\nend\nmodule B\n  include A\nend\nA.include(B)"),
+            ("ArgumentError".to_string(), "cyclic include detected".to_string())
+        );
+        assert_eq!(
+            error("class A\nend\nclass B\n  include A\nend"),
+            ("TypeError".to_string(), "wrong argument type Class (expected Module)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_metaprogramming() {
+        let input = "class Ghost
+  def method_missing(name, *args)
+    name == :get ? args : super
+  end
+
+  def respond_to_missing?(name, include_all) = name == :get || super
+end
+g = Ghost.new
+p g.get(1, 2), g.respond_to?(:get), g.respond_to?(:other)
+class Point
+  attr_accessor :x, :y
+
+  def initialize(x, y)
+    @x = x
+    @y = y
+  end
+
+  private
+
+  def secret = 42
+
+  public
+
+  def reveal = secret + self.secret
+  define_method(:scaled) { |n = 2| x * n }
+end
+pt = Point.new(1, 2)
+pt.x = 10
+p pt.x, pt.reveal, pt.send(:secret), pt.public_send(:y)
+p pt.respond_to?(:secret), pt.respond_to?(:secret, true)
+pt.instance_variable_set(:@z, 3)
+p pt.instance_variable_get(:@z), pt.instance_variables
+p pt.scaled, pt.scaled(3)
+Point.class_eval do
+  def added = :added
+end
+pt.instance_eval do
+  def only_me = @x + @y
+end
+p pt.added, pt.only_me, Point.new(0, 0).respond_to?(:only_me)
+p 5.instance_exec(2) { |n| self * n }";
+        let expected = "[1, 2]
+true
+false
+10
+84
+42
+2
+false
+true
+3
+[:@x, :@y, :@z]
+20
+30
+:added
+12
+false
+10
+";
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn test_metaprogramming_errors() {
+        assert_eq!(
+            error("class Ghost\n  def method_missing(name) = super\nend\nGhost.new.other"),
+            ("NoMethodError".to_string(), "undefined method 'other' for an instance of Ghost".to_string())
+        );
+        assert_eq!(
+            error("class Ghost\n  def method_missing(name) = super\n  private def secret = 1\nend\nGhost.new.secret"),
+            ("NoMethodError".to_string(), "private method 'secret' called for an instance of Ghost".to_string())
+        );
+        assert_eq!(
+            error("class Safe\n  private def secret = 1\nend\nSafe.new.secret"),
+            ("NoMethodError".to_string(), "private method 'secret' called for an instance of Safe".to_string())
+        );
+        assert_eq!(
+            error("class Safe\n  private\n  def secret = 1\nend\nSafe.new.public_send(:secret)"),
+            ("NoMethodError".to_string(), "private method 'secret' called for an instance of Safe".to_string())
+        );
+        assert_eq!(
+            error("Object.new.instance_variable_get('x')"),
+            ("NameError".to_string(), "'x' is not allowed as an instance variable name".to_string())
+        );
+        assert_eq!(
+            error("class A\n  def m(x) = x\nend\nclass B < A\n  define_method(:m) { |x| super }\nend\nB.new.m(1)"),
+            (
+                "RuntimeError".to_string(),
+                "implicit argument passing of super from method defined by define_method() is not supported. \
+                 Specify all arguments explicitly."
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_floats() {
+        let input = "p 1.5 + 2, 3 / 2.0, 0.1 + 0.2, 7 % 2.5, -7.0 % 3
+p 1e20, 2.5e-5, 1.0 / 0, 10.to_f, 2.7.to_i, -2.5.round, 3.14159.round(2)
+p 1 == 1.0, 1.eql?(1.0), 1.5 < 2, 2 >= 2.0, [1, 2.0] == [1.0, 2]
+p({ 1.5 => :a }[1.5], 1.5.class, (0.0 / 0.0).nan?)";
+        let expected = "3.5
+1.5
+0.30000000000000004
+2.0
+2.0
+1.0e+20
+2.5e-05
+Infinity
+10.0
+2
+-3
+3.14
+true
+false
+true
+true
+true
+:a
+Float
+true
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(
+            error("(1.0 / 0).to_i"),
+            ("FloatDomainError".to_string(), "Infinity".to_string())
+        );
+        assert_eq!(
+            error("1.5 < 'a'"),
+            ("ArgumentError".to_string(), "comparison of Float with String failed".to_string())
+        );
+    }
+
+    #[test]
+    fn test_methods_named_like_constants() {
+        let input = "def Foo(x) = x * 2
+p Integer(\"12\"), Integer(\" 1_000 \"), Integer(-2.5), Float(\"1.5\"), Float(2), Foo(3)
+n = Integer \"5\"
+p n, [Integer, Float]";
+        let expected = "12\n1000\n-2\n1.5\n2.0\n6\n5\n[Integer, Float]\n";
+        assert_eq!(run(input), expected);
+        assert_eq!(error("Integer(\"abc\")"), ("ArgumentError".to_string(), "invalid value for Integer(): \"abc\"".to_string()));
+        assert_eq!(error("Float(\"1.5x\")"), ("ArgumentError".to_string(), "invalid value for Float(): \"1.5x\"".to_string()));
+        assert_eq!(error("Integer(nil)"), ("TypeError".to_string(), "can't convert nil into Integer".to_string()));
+    }
+
+    #[test]
+    fn test_bignums() {
+        let input = "p 9223372036854775807 + 1, 2 ** 100, -9223372036854775808.class
+big = 2 ** 100
+p big / 3, -big / 3, -big % 7, big.divmod(-3), (big - big + 5).class
+p 1 << 70, (1 << 70) >> 68, -5 >> 1, 5 << -1, 3.pow(200, 7), 3.pow(5, -7)
+p big.to_s(16), big == 2 ** 100, big.eql?(2 ** 100), big > 1.5, big <=> 2 ** 101, { big => 1 }[2 ** 100]
+p 1e20.to_i, 1_000_000 * 1_000_000 * 1_000_000 * 1_000_000
+(9223372036854775806..).each { |x| p x; break if x > 9223372036854775807 }
+p (2 ** 64...2 ** 64 + 2).to_a, (1..2 ** 64).each { |x| break x }
+p(-(-9223372036854775808), -(-99999999999999999999), -2 ** 2)
+p 5 & 3, 5 | 3, 5 ^ 3, -6 & 255, 1 | 2 & 3, (2 ** 64 + 3) & 1, -(2 ** 70) & 7, (2 ** 64 + 1) ^ 2 ** 64
+p [1, 2] | [2, 3], [1, 1, \"a\"] | [\"a\", 2.0]";
+        let expected = "9223372036854775808
+1267650600228229401496703205376
+Integer
+422550200076076467165567735125
+-422550200076076467165567735126
+5
+[-422550200076076467165567735126, -2]
+Integer
+1180591620717411303424
+4
+-3
+2
+2
+-2
+\"10000000000000000000000000\"
+true
+true
+true
+-1
+1
+100000000000000000000
+1000000000000000000000000
+9223372036854775806
+9223372036854775807
+9223372036854775808
+[18446744073709551616, 18446744073709551617]
+1
+9223372036854775808
+99999999999999999999
+-4
+1
+7
+6
+250
+3
+1
+0
+1
+[1, 2, 3]
+[1, \"a\", 2.0]
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(
+            error("(2 ** 64).times { }"),
+            ("RangeError".to_string(), "bignum too big to convert into 'long'".to_string())
+        );
+        assert_eq!(error("(2 ** 64) / 0"), ("ZeroDivisionError".to_string(), "divided by 0".to_string()));
+        assert_eq!(error("0 ** -1"), ("ZeroDivisionError".to_string(), "divided by 0".to_string()));
+    }
+
+    #[test]
+    fn test_strings() {
+        let input = "s = \"héllo\"
+p s.length, s.bytesize, s.encoding, s.b.length, 233.chr.encoding, s.b.valid_encoding?, s.b.force_encoding(\"UTF-8\") == s
+p s[1], s[-1], s[1, 3], s[1..], s[-3..-1], s[5], s[6], s[5, 2], s[\"ll\"]
+t = \"abc\"
+t << \"def\" << 33
+t[0] = \"X\"
+t[-2, 2] = \"?\"
+p t, \"const\".freeze.frozen?, \"const\".freeze.upcase.frozen?, \"ab\" + \"c\", \"ab\" * 3
+p \"%05.2f|%-4d|%x|%+d|%s|%p\" % [3.14159, 42, 255, 5, :sym, \"q\"], format(\"%e %g %g %%\", 12345.678, 0.0001, 1e20)
+p \"  hi  \".strip, \"Straße\".upcase, \"hello world\".capitalize, \"Hello\".swapcase, \"hello\\r\\n\".chomp, \"a\".center(5, \"*\")
+p \"a,b,,c,,\".split(\",\"), \" a  b c \".split, \"abc\".split(\"\"), \"a-b_c\".split(/([-_])/), \"a,b,c\".split(\",\", 2)
+chars = []
+\"héy\".each_char { |c| chars << c }
+p chars, \"42abc\".to_i, \"-0x1A\".to_i(16), \"1_000\".to_i, \"3.5e2xyz\".to_f, \"abc\".to_f, \"abc\".to_sym
+p \"tab\\t\\\"quoted\\\" \\\\ \\e \\u0001 \\#{x} #a\", 255.chr, \"café\".bytes
+p \"\\xff\".bytes, \"\\xff\".valid_encoding?, \"\\xe2\\x82\\xac\\xe2\".length, \"\\xe2\\x82\\xac\"
+keys = {}
+3.times { |i| keys[\"k#{i}\"] = i }
+p \"x#{1 + 1}y\", keys, \"#{nil}|#{:a}|#{[1, \"b\"]}|#{ { c: 2 }[:c] }|#{\"in #{s}\"}\"";
+        let expected = "5
+6
+#<Encoding:UTF-8>
+6
+#<Encoding:ASCII-8BIT>
+true
+true
+\"é\"
+\"o\"
+\"éll\"
+\"éllo\"
+\"llo\"
+nil
+nil
+\"\"
+\"ll\"
+\"Xbcde?\"
+true
+false
+\"abc\"
+\"ababab\"
+\"03.14|42  |ff|+5|sym|\\\"q\\\"\"
+\"1.234568e+04 0.0001 1e+20 %\"
+\"hi\"
+\"STRASSE\"
+\"Hello world\"
+\"hELLO\"
+\"hello\"
+\"**a**\"
+[\"a\", \"b\", \"\", \"c\"]
+[\"a\", \"b\", \"c\"]
+[\"a\", \"b\", \"c\"]
+[\"a\", \"-\", \"b\", \"_\", \"c\"]
+[\"a\", \"b,c\"]
+[\"h\", \"é\", \"y\"]
+42
+-26
+1000
+350.0
+0.0
+:abc
+\"tab\\t\\\"quoted\\\" \\\\ \\e \\u0001 \\#{x} #a\"
+\"\\xFF\"
+[99, 97, 102, 195, 169]
+[255]
+false
+2
+\"€\"
+\"x2y\"
+{\"k0\" => 0, \"k1\" => 1, \"k2\" => 2}
+\"|a|[1, \\\"b\\\"]|2|in héllo\"
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(
+            error("'a'.freeze << 'b'"),
+            ("FrozenError".to_string(), "can't modify frozen String: \"a\"".to_string())
+        );
+        assert_eq!(
+            error("'é' + 'é'.b"),
+            ("Encoding::CompatibilityError".to_string(), "incompatible character encodings: UTF-8 and ASCII-8BIT".to_string())
+        );
+        assert_eq!(error("'abc'[5] = 'x'"), ("IndexError".to_string(), "index 5 out of string".to_string()));
+        assert_eq!(error("'%d %d' % [1]"), ("ArgumentError".to_string(), "too few arguments".to_string()));
+        assert_eq!(error("'%1000000000000d' % 1"), ("ArgumentError".to_string(), "width too big".to_string()));
+        assert_eq!(error("'a'.center(2 ** 62)"), ("ArgumentError".to_string(), "argument too big".to_string()));
+    }
+
+    #[test]
+    fn test_regexps() {
+        let input = "p \"2024-01-05\"[/\\d+/], \"key=value\"[/(\\w+)=(\\w+)/, 2], \"2024-01\"[/(?<year>\\d+)-(?<month>\\d+)/, \"month\"], \"abc\"[/z/]
+p \"hello\".sub(\"l\", \"L\"), \"hello\".gsub(/l/) { |m| m.upcase }, \"john smith\".sub(/(\\w+) (\\w+)/, '\\2, \\1'), \"cat\".gsub(/[aeiou]/, \"a\" => \"4\")
+p \"hello\" =~ /ll/, \"hello\" !~ /ll/, \"héllo\" =~ /l/, /z/ =~ \"hello\", \"a1b2\".scan(/\\d/), \"a1b2\".scan(/([a-z])(\\d)/)
+p /a\\/b/i, /x/mi.to_s, Regexp.new(\"A.C\", \"i\").match?(\"abc\"), Regexp.escape(\"a.b*c\"), /^b/ =~ \"a\\nb\"
+word = \"Hello\"
+case word
+when /^h/ then p :lower
+when /^H/ then p :upper
+end
+s = \"hello\"
+p s.sub!(/z/, \"\"), s.gsub!(\"l\", \"L\"), s";
+        let expected = "\"2024\"
+\"value\"
+\"01\"
+nil
+\"heLlo\"
+\"heLLo\"
+\"smith, john\"
+\"c4t\"
+2
+false
+2
+nil
+[\"1\", \"2\"]
+[[\"a\", \"1\"], [\"b\", \"2\"]]
+/a\\/b/i
+\"(?mi-x:x)\"
+true
+\"a\\\\.b\\\\*c\"
+2
+:upper
+nil
+\"heLLo\"
+\"heLLo\"
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(error("Regexp.new('a(')"), ("RegexpError".to_string(), "unclosed group: /a(/".to_string()));
+    }
+
+    #[test]
+    fn test_arrays_and_hashes() {
+        let input = "a = [3, 1, 2]
+a << 5
+p a.pop, a[0], a[-1], a[1, 2], a[1..], a[5]
+a[1..2] = [9, 9, 9]
+a[6] = 1
+p a
+p a.pop(2), a.shift, a.unshift(0)
+p [1, [2, [3, [4]]]].flatten, [1, [2, [3, [4]]]].flatten(1), [[1, {a: [7, 8]}]].dig(0, 1, :a, 1)
+p [1, 2].fetch(1), [1, 2].fetch(5, :none), [1, 2].fetch(-9) { |i| i * 2 }
+h = {b: 1, a: 2}
+h[:c] = 3
+p h, h.fetch(:a), h.fetch(:z, 0), h.fetch(:z) { |k| k.to_s }, {a: {b: [1, 2]}}.dig(:a, :b, 0)
+p h.merge({a: 10, d: 4}), h.merge({a: 10}) { |key, old, new| old + new }, h
+p h.transform_values { |v| v * 2 }, h.select { |k, v| v > 1 }, h.reject { |k, v| v > 1 }";
+        let expected = "5
+3
+2
+[1, 2]
+[1, 2]
+nil
+[3, 9, 9, 9, nil, nil, 1]
+[nil, 1]
+3
+[0, 9, 9, 9, nil]
+[1, 2, 3, 4]
+[1, 2, [3, [4]]]
+8
+2
+:none
+-18
+{b: 1, a: 2, c: 3}
+2
+0
+\"z\"
+1
+{b: 1, a: 10, c: 3, d: 4}
+{b: 1, a: 12, c: 3}
+{b: 1, a: 2, c: 3}
+{b: 2, a: 4, c: 6}
+{a: 2, c: 3}
+{b: 1}
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(error("{a: 1}.fetch(:b)"), ("KeyError".to_string(), "key not found: :b".to_string()));
+        assert_eq!(error("[1].fetch(4)"), ("IndexError".to_string(), "index 4 outside of array bounds: -1...1".to_string()));
+        assert_eq!(error("[1, [2]].dig(0, 1)"), ("TypeError".to_string(), "Integer does not have #dig method".to_string()));
+    }
+
+    #[test]
+    fn test_collections_containing_themselves() {
+        let input = "a = [1]
+a << a
+b = [1]
+b << b
+h = {}
+h[:h] = h
+p a, h, [a, h].to_s
+p a == a, a == b, h == h, { a => :found }[a]
+groups = Hash.new { |hash, key| hash[key] = [] }
+groups[:odd] << 1
+groups[:odd] << 3
+p groups, groups[:even], Hash.new(0)[:missing]";
+        let expected = "[1, [...]]
+{h: {...}}
+\"[[1, [...]], {h: {...}}]\"
+true
+true
+true
+:found
+{odd: [1, 3], even: []}
+[]
+0
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(
+            error("Hash.new(0) { }"),
+            ("ArgumentError".to_string(), "wrong number of arguments (given 1, expected 0)".to_string())
+        );
+    }
+
+    #[test]
+    fn test_enumerable() {
+        let input = "p [1, 2, 3, 4].select { |x| x.even? }, [1, 2, 3, 4].reject { |x| x.even? }, [1, 2, 3].map { |x| x * x }
+p [1, 2, 3].reduce(0) { |sum, x| sum + x }, [1, 2, 3].reduce(:*), [3, 1, 2].sort, [3, 1, 2].max
+p [\"pear\", \"fig\", \"apple\"].sort_by { |w| w.length }, [1, 2, 3, 4, 5].group_by { |x| x % 2 }
+[:a, :b].each_with_index { |x, i| p [x, i] }
+p [1, 2].zip([3, 4], [5]), (1..4).select(&:odd?), [[:x, 1], [:y, 2]].to_h
+h = {b: 1, a: 2}
+p h.map { |k, v| [k, v * 10] }, h.sort_by { |k, v| v }, h.min_by { |k, v| v }, h.sum { |k, v| v }
+class NumberList
+  include Enumerable
+  def initialize(*numbers)
+    @numbers = numbers
+  end
+  def each
+    @numbers.each { |n| yield n }
+    self
+  end
+end
+list = NumberList.new(5, 3, 8)
+p list.map { |x| x + 1 }, list.sort, list.include?(3), list.first, list.count { |x| x > 4 }, list.to_a";
+        let expected = "[2, 4]
+[1, 3]
+[1, 4, 9]
+6
+6
+[1, 2, 3]
+3
+[\"fig\", \"pear\", \"apple\"]
+{1 => [1, 3, 5], 0 => [2, 4]}
+[:a, 0]
+[:b, 1]
+[[1, 3, 5], [2, 4, nil]]
+[1, 3]
+{x: 1, y: 2}
+[[:b, 10], [:a, 20]]
+[[:b, 1], [:a, 2]]
+[:b, 1]
+3
+[6, 4, 9]
+[3, 5, 8]
+true
+5
+2
+[5, 3, 8]
+";
+        assert_eq!(run(input), expected);
+        assert_eq!(error("[1, \"a\"].sort"), ("ArgumentError".to_string(), "comparison of String with 1 failed".to_string()));
+    }
+
+    #[test]
+    fn test_enumerable_stops_early() {
+        let input = "class Counter
+  include Enumerable
+  def each
+    n = 0
+    loop do
+      n += 1
+      yield n
+    end
+  end
+end
+c = Counter.new
+p c.first, c.first(3), c.find { |x| x * x > 50 }, c.take_while { |x| x < 4 }
+p c.any? { |x| x > 5 }, c.all? { |x| x < 5 }, c.include?(7)
+p (1..).find { |x| x > 3 }, (1..).each_with_index { |x, i| break x if x > 3 }
+p (1..10).first(3), (1..).first(2), (1..10).first
+class Two
+  include Enumerable
+  def each
+    puts 'a'
+    yield 1, :one
+    puts 'b'
+    yield 2, :two
+  end
+end
+p Two.new.map { |x, name| puts name; x * 2 }
+p Two.new.to_a";
+        let expected = "1
+[1, 2, 3]
+8
+[1, 2, 3]
+true
+false
+true
+4
+4
+[1, 2, 3]
+[1, 2]
+1
+a
+one
+b
+two
+[2, 4]
+a
+b
+[[1, :one], [2, :two]]
+";
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let input = "GC.start
+before = GC.stat(:total_freed_objects)
+i = 0
+while i < 100
+  s = [i, 'garbage']
+  i += 1
+end
+GC.start
+p GC.stat(:total_freed_objects) - before
+kept = [1, [2, 3]]
+o = Object.new
+ObjectSpace.define_finalizer(o, proc { |id| puts 'finalized' })
+o = nil
+GC.start
+p kept, GC.stat.keys, GC.stress";
+        let expected = "198
+finalized
+[1, [2, 3]]
+[:count, :heap_live_slots, :heap_free_slots, :total_allocated_objects, :total_freed_objects]
+false
+";
+        assert_eq!(run(input), expected);
+    }
+
+    #[test]
+    fn test_gc_stress() {
+        let input = "class Base
+  def describe(items) = items.map { |item| item.to_s + '!' }
+end
+class Child < Base
+  define_method(:describe) { |items| super(items) + ['done'] }
+end
+pairs = {}
+[3, 1, 2].each { |n| pairs[n] = [n, n.to_s] }
+p Child.new.describe([1, [2]])
+p pairs
+def nested(n) = n == 0 ? [] : [n, nested(n - 1)]
+p nested(3)
+begin
+  raise ArgumentError, 'boom'
+rescue => e
+  p e.message
+ensure
+  p [:ensure]
+end";
+        let output = run(input);
+        for (_, mut backend) in backends() {
+            backend.runtime().heap.stress = true;
+            backend.runtime().capture_output();
+            backend.eval(input).unwrap();
+            assert_eq!(backend.runtime().take_output(), output);
+            assert!(backend.runtime().heap.stats.count > 10, "{:?}", backend.runtime().heap.stats);
+        }
+    }
+}
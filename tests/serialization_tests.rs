@@ -0,0 +1,95 @@
+#[cfg(test)]
+mod serialization_tests {
+    use chimiaguin::lexer::Lexer;
+    use chimiaguin::parser::Parser;
+    use chimiaguin::sexp::to_sexp;
+
+    // The input of each test in lexer_tests.rs, in the same order, so every kind of token has
+    // its lexemes in a snapshot. Tests going through several inputs are represented by one.
+    const LEXER_INPUTS: &[(&str, &str)] = &[
+        ("plus", "+"),
+        ("minus", "-"),
+        ("identifier", "a"),
+        ("number", "5"),
+        ("expression", "a+5-"),
+        ("empty", ""),
+        ("line_break", "test \n"),
+        ("multi_digit_number", "123"),
+        ("multi_letter_identifier", "abc"),
+        ("command_with_string", "puts 'Hello, World!'"),
+        ("call_with_numbers", "add(1, 2)"),
+        ("call_with_strings", "add('hello', 'world')"),
+        ("call_with_mixed_arguments", "add('hello', 5)"),
+        ("call_with_three_arguments", "add('hello', 5, 'world')"),
+        ("def_without_params", "def add\nend"),
+        ("def_with_params", "def add(a, b)\nend"),
+        ("class", "class Dog\nend"),
+        ("class_with_superclass", "class Dog < Animal\nend"),
+        ("equal_equal", "=="),
+        ("case_equality", "==="),
+        ("hash_arrow", "=>"),
+        ("equal", "="),
+        ("comparisons", "<= >= < >"),
+        ("arithmetic", "+ - * / %"),
+        ("interpolation", "\"Hello, #{name}!\""),
+        ("escapes_that_are_not_utf8", "\"\\xff\\u00e9\" '\\xff'"),
+        ("symbol", ":symbol"),
+        ("method_name_symbols", ":odd? :name= :[]= :\"two words\""),
+        ("empty_braces", "{ }"),
+        ("hash_with_arrow", "{ :key => 'value' }"),
+        ("def_with_yield", "def foo\n  yield\nend"),
+        ("hash_with_labels", "a = { b: 1, c: 'hello' }"),
+        ("block_with_locals", "list.each { |x; tmp| }"),
+        ("underscores", "_1 each_with_index"),
+        ("splats", "*a **b &c"),
+        ("scope_and_shift", "A::B << self"),
+        ("conditional_operators", "empty? ? !a : b != c || d && e"),
+        ("ranges", "[1..2, 3...4]"),
+        ("unary_minus", "foo -1 - 2"),
+        ("operator_assignment", "@count+=1 ||= &&= <<="),
+        ("lambda", "->(x) -1"),
+        ("float", "1.5 2e3 1.times"),
+        ("large_numbers_and_shifts", "1_000 99999999999999999999 a >> 1 <=> b >>= 2"),
+        ("string_escapes", r#""a\tb\n\"c\" \\ \e \x41é\u{1F600} \#{x}" 'it\'s \n'"#),
+        ("regexp", r"x = /a\/b\d/mi"),
+        ("def_on_several_lines", "def foo\n  bar\nend"),
+    ];
+
+    #[test]
+    fn test_token_stream_json() {
+        for (name, input) in LEXER_INPUTS {
+            let lexemes = Lexer::new(input).tokenize();
+            insta::assert_json_snapshot!(format!("tokens_{}", name), lexemes);
+        }
+    }
+
+    #[test]
+    fn test_ast_json() {
+        let program = Parser::new("def add(a, b = 1)\n  a + b\nend\nadd(2) { |x| x }").parse_program().unwrap();
+
+        insta::assert_json_snapshot!(program);
+    }
+
+    fn sexp(input: &str) -> String {
+        to_sexp(&Parser::new(input).parse_program().expect("program should parse"))
+    }
+
+    #[test]
+    fn test_sexp_of_expressions() {
+        insta::assert_snapshot!(sexp("x = 1 + 2 * 3\nputs x, -x\n[1, *rest]\n{ a: 1, **opts }\n1..\n@a ||= b"));
+    }
+
+    #[test]
+    fn test_sexp_of_definitions() {
+        insta::assert_snapshot!(sexp(
+            "module Shapes\n  class Circle < Shape\n    def self.unit = new(1)\n    def area(scale = 1, *, key:, &blk)\n      PI * r ** 2\n    end\n  end\nend"
+        ));
+    }
+
+    #[test]
+    fn test_sexp_of_control_flow() {
+        insta::assert_snapshot!(sexp(
+            "items.each do |item|\n  next unless item\n  begin\n    work(item)\n  rescue IOError => e\n    retry\n  ensure\n    done\n  end\nend\ncase value\nin [Integer => n, *] if n > 0 then n\nin { name: String => name } then name\nelse nil\nend\na, (b, *c) = list\nf = -> { it * 2 }"
+        ));
+    }
+}
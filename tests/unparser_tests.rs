@@ -0,0 +1,76 @@
+#[cfg(test)]
+mod unparser_tests {
+    use chimiaguin::ast::Node;
+    use chimiaguin::parser::Parser;
+    use chimiaguin::unparser::unparse;
+
+    fn parse(input: &str) -> Vec<Node> {
+        Parser::new(input).parse_program().expect("program should parse")
+    }
+
+    // Programs exercising every construct the parser knows about
+    const CORPUS: &[&str] = &[
+        "x = 1\ny = x + 2 * 3\nz = (x + 2) * 3",
+        "a - (b - c)\n(a - b) - c\n2 ** 3 ** 2\n(2 ** 3) ** 2\n-2 ** 2\n(-2).abs",
+        "a && b || c\na && (b || c)\n!a == b\n!(a == b)\na < b == c < d",
+        "a | b ^ c & d << 1\n(a | b) & c\na & (b << c | d)\nlist.map { |x, y = 1| x | y }",
+        "x = [1, 'two', :three, nil, true, false, self]\nh = { a: 1, 'b' => 2, **rest, 3 => [] }\n{}",
+        "s = \"it's\"\nt = 'say \"hi\"'",
+        "n = 1\ns = \"a#{n}\\t#{\"b#{n + 1}\"}\\#{c}\"",
+        "r = 1..10\ne = 1...x\n(1..)\n(..5)\nfoo(1.., ..2)\n(a + 1)..(b * 2)",
+        "puts\nputs 1, 2\nfoo.bar(1).baz\nfoo.bar = 2\nfoo[1, 2]\nfoo[1] = 3\nfoo.()\n-foo.bar",
+        "foo = 1\nfoo()\nfoo.bar\nbar",
+        "list.each { |x| puts(x) }\nlist.map do |a, (b, c), *d, e: 1, **f, &g|\n  a\n  b\nend",
+        "list.map { _1 + _2 }\nlist.map { it * 2 }\nlist.each { |x; y| y = x }",
+        "f = ->(x, y = 2) { x + y }\ng = -> { 1 }\nh = -> x do\n  x\nend\nf.(1)\nlist.each(&f)\nfoo(*args, **opts, &blk)",
+        "def foo\nend\ndef bar(a, b = 1, *c, d:, e: 2, **f, &g)\n  a + b\nend\ndef self.baz = 42\ndef ==(other)\n  true\nend\ndef name=(value)\n  @name = value\nend",
+        "class Foo < Bar::Baz\n  def initialize(x)\n    @x = x\n  end\nend\nmodule A::B\n  class << self\n    def c\n    end\n  end\nend\n::Top",
+        "if a\n  1\nelsif b\n  2\nelse\n  3\nend\nunless c\n  4\nend\nx = 1 if y\nz = a ? b : c",
+        "while x < 10\n  x += 1\nend\nuntil done\n  work\nend\nbegin\n  step\nend while more\nfor a, b in pairs\n  next if a\n  break b\nend",
+        "begin\n  risky\nrescue ArgumentError, TypeError => e\n  retry\nrescue => e\n  raise\nelse\n  ok\nensure\n  done\nend\nvalue = compute rescue nil",
+        "def each\n  return\n  return 1, 2\n  redo\nend",
+        "def each\n  yield\n  yield 1, key: 2\n  x = yield(3) + 1\nend",
+        "def initialize(a)\n  super\n  super()\n  super(a, 1) { |x| x }\n  super.upcase\nend",
+        "case x\nwhen 1, 2\n  :small\nwhen String\nelse\n  :other\nend\ncase\nwhen a\nend",
+        "expected = 0\ncase value\nin Integer | Float => n if n > 0\n  n\nin [1, *rest]\n  rest\nin [*, 3, *post]\n  post\nin { name:, age: 18.. }\n  name\nin Point(x:, **nil)\n  x\nin Point[a, b]\n  a\nin ^expected\nin -1..1\nelse\n  nil\nend",
+        "LIMIT = 10\nConfig::SIZE = LIMIT * 2\n::TOP = :top",
+        "f({ a: 1 })\nf(a: 1)\nf({ a: 1 }, b: 2, &blk)\ndef ==(other) = true\ndef <=(other) = false",
+        "a, b = 1, 2\na, (b, *c), @d = list\nx.y, z[0] = pair\nfirst, * = list\n@count ||= 0\nh[:k] += 1\nobj.size *= 2\nflag &&= ready",
+        "x = [:\"a b\", :+, :[]=, :@x, :a?, :\"9x\", :\"\", :\"a\\nb\"]\nh = { \"a b\": 1, a?: 2, \"@x\": 3 }\nf(\"x-y\": 1)",
+        "n = Integer('12') + Integer '3'\nFoo()\nFoo(1) { |x| x }\nFoo::Bar\nFoo.new(1)",
+    ];
+
+    #[test]
+    fn test_round_trip_corpus() {
+        for source in CORPUS {
+            let program = parse(source);
+            let text = unparse(&program);
+            let reparsed = Parser::new(&text)
+                .parse_program()
+                .unwrap_or_else(|errors| panic!("{:?} unparsed to\n{}\nwhich fails with {:?}", source, text, errors));
+            assert_eq!(reparsed, program, "{:?} unparsed to\n{}", source, text);
+            assert_eq!(unparse(&reparsed), text, "the output of {:?} is not canonical", source);
+        }
+    }
+
+    #[test]
+    fn test_canonical_layout() {
+        let program = parse("class Foo\ndef bar x\nlist.each do |y| puts y end\nend\nend");
+        assert_eq!(
+            unparse(&program),
+            "class Foo\n  def bar(x)\n    list.each { |y| puts(y) }\n  end\nend"
+        );
+    }
+
+    #[test]
+    fn test_quoted_symbols() {
+        let program = parse("[:\"a b\", :foo=]\n{ \"a b\": 1, c: 2 }");
+        assert_eq!(unparse(&program), "[:\"a b\", :foo=]\n{ :\"a b\" => 1, c: 2 }");
+    }
+
+    #[test]
+    fn test_minimal_parentheses() {
+        let program = parse("(a + (b * c)) - (d - e)\nx = (y = 1)");
+        assert_eq!(unparse(&program), "a + b * c - (d - e)\nx = y = 1");
+    }
+}
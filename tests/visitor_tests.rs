@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod visitor_tests {
+    use chimiaguin::ast::{Call, Def, Node};
+    use chimiaguin::parser::Parser;
+    use chimiaguin::visitor::{walk_call, walk_node_mut, Visitor, VisitorMut};
+
+    fn parse(input: &str) -> Vec<Node> {
+        Parser::new(input).parse_program().expect("program should parse")
+    }
+
+    #[derive(Default)]
+    struct MethodCalls {
+        names: Vec<String>,
+    }
+
+    impl Visitor for MethodCalls {
+        fn visit_call(&mut self, call: &Call) {
+            self.names.push(call.method.clone());
+            walk_call(self, call);
+        }
+    }
+
+    #[derive(Default)]
+    struct MethodDefinitions {
+        names: Vec<String>,
+    }
+
+    // Doesn't walk into method bodies, so nested definitions are skipped
+    impl Visitor for MethodDefinitions {
+        fn visit_def(&mut self, def: &Def) {
+            self.names.push(def.name.clone());
+        }
+    }
+
+    // Folds additions of integer literals
+    struct ConstantFolder;
+
+    impl VisitorMut for ConstantFolder {
+        fn visit_node(&mut self, node: &mut Node) {
+            walk_node_mut(self, node);
+            if let Node::Call(Call { receiver: Some(receiver), method, args, block: None, .. }) = node {
+                if let (Node::Integer(left), "+", [Node::Integer(right)]) = (&**receiver, method.as_str(), &args[..]) {
+                    *node = Node::Integer(left + right);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_visitor_reaches_nested_calls() {
+        let program = parse("def run(x = setup)\n  items.each { |item| process(item) } if ready?\nrescue => e\n  log(e)\nend");
+
+        let mut calls = MethodCalls::default();
+        for node in &program {
+            calls.visit_node(node);
+        }
+
+        assert_eq!(calls.names, vec!["setup", "ready?", "each", "items", "process", "log"]);
+    }
+
+    #[test]
+    fn test_overridden_method_controls_recursion() {
+        let program = parse("class Shape\n  def area\n    def helper\n    end\n  end\n  def name\n  end\nend");
+
+        let mut definitions = MethodDefinitions::default();
+        for node in &program {
+            definitions.visit_node(node);
+        }
+
+        assert_eq!(definitions.names, vec!["area", "name"]);
+    }
+
+    #[test]
+    fn test_mutable_visitor_rewrites_tree() {
+        let mut program = parse("x = 1 + 2 + 3\ncase x\nwhen 2 + 2 then [1 + 1]\nend");
+
+        for node in &mut program {
+            ConstantFolder.visit_node(node);
+        }
+
+        assert_eq!(program, parse("x = 6\ncase x\nwhen 4 then [2]\nend"));
+    }
+}
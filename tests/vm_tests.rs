@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod vm_tests {
+    use chimiaguin::vm::{self, Vm};
+
+    #[test]
+    fn test_disassembly() {
+        let iseq = vm::compile("def add(a, b)\n  a + b\nend\nadd(1, 2)").unwrap();
+        let listing = iseq.disassemble();
+        assert!(listing.starts_with("== disasm: <main> (Top"), "{}", listing);
+        assert!(listing.contains("definemethod :add"), "{}", listing);
+        assert!(listing.contains("== disasm: add (Method"), "{}", listing);
+        assert!(listing.contains("send :+, argc: 1"), "{}", listing);
+        assert!(listing.contains("send :add, argc: 2, fcall"), "{}", listing);
+        assert!(listing.contains("leave"), "{}", listing);
+    }
+
+    #[test]
+    fn test_inline_caches() {
+        let mut vm = Vm::new();
+        vm.runtime().capture_output();
+        let input = "class A
+  def value = 1
+end
+class B
+  def value = 2
+end
+total = 0
+[A.new, B.new, A.new, B.new].each { |object| total += object.value }
+p total";
+        vm.eval(input).unwrap();
+        let before = vm.cache_stats();
+        vm.eval("i = 0\nwhile i < 10\n  i += 1\nend").unwrap();
+        let after = vm.cache_stats();
+        assert_eq!(after.misses - before.misses, 2, "{:?}", after);
+        assert_eq!(after.hits - before.hits, 19, "{:?}", after);
+        assert!(after.hit_rate() > before.hit_rate());
+        assert_eq!(vm.runtime().take_output(), "6\n");
+    }
+}
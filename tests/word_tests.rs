@@ -0,0 +1,79 @@
+#[cfg(test)]
+mod word_tests {
+    use chimiaguin::llvm::{self, CodeGen};
+    use chimiaguin::runtime::{Unpacked, Value};
+    use chimiaguin::vm::Vm;
+    use chimiaguin::word::{FIXNUM_MAX, FIXNUM_MIN};
+    use inkwell::context::Context;
+
+    #[test]
+    fn test_immediate_layout() {
+        let mut vm = Vm::new();
+        let symbol = vm.runtime().symbol("name");
+
+        assert_eq!(Value::FALSE.bits(), 0x00);
+        assert_eq!(Value::NIL.bits(), 0x08);
+        assert_eq!(Value::TRUE.bits(), 0x14);
+        assert_eq!(Value::fixnum(-3).map(Value::bits), Some(-5i64 as u64));
+        assert_eq!(Value::flonum(1.0).map(Value::bits), Some(0xff80_0000_0000_0002));
+        assert_eq!(symbol.bits() & 0xff, 0x0c);
+        assert_eq!(Value::fixnum(FIXNUM_MAX + 1), None);
+        assert_eq!(Value::flonum(1e300), None);
+        assert_eq!(Value::UNDEF.unpack(), None);
+    }
+
+    #[test]
+    fn test_numbers_round_trip() {
+        let mut vm = Vm::new();
+        let runtime = vm.runtime();
+        for value in [0, 1, -1, FIXNUM_MAX, FIXNUM_MIN, i64::MAX, i64::MIN] {
+            let word = runtime.int(value);
+            assert_eq!(word.is_fixnum(), (FIXNUM_MIN..=FIXNUM_MAX).contains(&value), "{}", value);
+            assert_eq!(runtime.unpack(word), Unpacked::Integer(value));
+        }
+        for value in [0.0, -0.0, -2.5, 0.1, 1e300, f64::INFINITY] {
+            let word = runtime.float(value);
+            assert!(matches!(runtime.unpack(word), Unpacked::Float(float) if float.to_bits() == value.to_bits()), "{}", value);
+        }
+        let nan = runtime.float(f64::NAN);
+        assert!(matches!(runtime.unpack(nan), Unpacked::Float(float) if float.is_nan()));
+
+        let string = runtime.string("text");
+        assert!(matches!(runtime.unpack(string), Unpacked::Object(_)));
+    }
+
+    #[test]
+    fn test_jit_sum() {
+        let context = Context::create();
+        let codegen = CodeGen::new(&context).unwrap();
+        let sum = codegen.jit_compile_sum().expect("compiles");
+        let mut vm = Vm::new();
+        let fixnums = |values: [i64; 3]| values.map(|value| Value::fixnum(value).unwrap());
+
+        let [x, y, z] = fixnums([1, 2, 3]);
+        assert_eq!(unsafe { sum.call(x, y, z) }, Value::fixnum(6).unwrap());
+        let [x, y, z] = fixnums([FIXNUM_MAX, 1, 0]);
+        assert_eq!(unsafe { sum.call(x, y, z) }, Value::UNDEF);
+
+        let mut add = |values: [Unpacked; 3]| {
+            let runtime = vm.runtime();
+            let values = values.map(|value| match value {
+                Unpacked::Integer(value) => runtime.int(value),
+                Unpacked::Float(value) => runtime.float(value),
+                _ => unreachable!("only numbers are added"),
+            });
+            let result = llvm::sum(&mut vm, &sum, values).unwrap();
+            vm.runtime().unpack(result)
+        };
+        use Unpacked::{Float, Integer};
+        assert_eq!(add([Integer(-4), Integer(2), Integer(1)]), Integer(-1));
+        assert_eq!(add([Float(0.5), Float(1.25), Float(-3.0)]), Float(-1.25));
+        assert_eq!(add([Float(1e300), Float(1e300), Float(0.0)]), Float(2e300));
+        assert_eq!(add([Integer(1), Float(2.5), Integer(1)]), Float(4.5));
+        assert_eq!(add([Integer(FIXNUM_MAX), Integer(1), Integer(0)]), Integer(FIXNUM_MAX + 1));
+
+        let strings = ["a", "b", "c"].map(|text| vm.runtime().string(text));
+        let joined = llvm::sum(&mut vm, &sum, strings).unwrap();
+        assert_eq!(vm.runtime().string_value(joined), Some("abc"));
+    }
+}